@@ -0,0 +1,32 @@
+//! parsing of the quantities (read from every resource of the cluster) should never panic,
+//! only return an error; run with `cargo +nightly fuzz run qty_from_str`
+#![no_main]
+
+use kubectl_view_allocations::qty::Qty;
+use libfuzzer_sys::fuzz_target;
+use std::str::FromStr;
+
+fuzz_target!(|data: &[u8]| {
+    let s = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    for qty in [Qty::from_str(s).ok(), Qty::parse_for_kind(s, "pods").ok()]
+        .into_iter()
+        .flatten()
+    {
+        let _ = qty.adjust_scale().to_string();
+        let _ = qty.to_compact_string();
+        // lossless while the value is exact as f64 (used to parse)
+        if qty.value.abs() < (1 << 53) {
+            let canonical = qty.to_canonical_string();
+            assert_eq!(
+                Qty::from_str(&canonical).map(|q| q.value).ok(),
+                Some(qty.value),
+                "round trip of {:?} by {:?}",
+                s,
+                canonical
+            );
+        }
+    }
+});
@@ -0,0 +1,88 @@
+//! module to copy a text to the clipboard of the desktop (see `--clipboard`), with the clipboard tool
+//! of the platform (`pbcopy`, `clip`, `wl-copy`, `xclip` or `xsel`), run in the background: on X11 the text
+//! is served by the tool after the exit of the cli.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// a clipboard tool: the program and its arguments to read the text to copy from stdin
+pub type Tool = (&'static str, &'static [&'static str]);
+
+/// the clipboard tools to try (in order) on the platform `os` (like `std::env::consts::OS`),
+/// the tools of Wayland & X11 only with their display
+pub fn tools_of(os: &str, wayland: bool, x11: bool) -> Vec<Tool> {
+    match os {
+        "macos" => vec![("pbcopy", &[])],
+        "windows" => vec![("clip", &[])],
+        _ => {
+            let mut tools: Vec<Tool> = vec![];
+            if wayland {
+                tools.push(("wl-copy", &[]));
+            }
+            if x11 {
+                tools.push(("xclip", &["-selection", "clipboard"]));
+                tools.push(("xsel", &["--clipboard", "--input"]));
+            }
+            tools
+        }
+    }
+}
+
+/// copy the `text` with the first clipboard tool that succeeds, return the program of the tool,
+/// none if no clipboard is available (eg no display, no tool installed)
+pub fn copy(text: &[u8]) -> Option<&'static str> {
+    let has_var = |name| std::env::var_os(name).is_some_and(|v| !v.is_empty());
+    tools_of(
+        std::env::consts::OS,
+        has_var("WAYLAND_DISPLAY"),
+        has_var("DISPLAY"),
+    )
+    .into_iter()
+    .find(|tool| copy_with(tool, text).is_ok())
+    .map(|(program, _)| program)
+}
+
+fn copy_with((program, args): &Tool, text: &[u8]) -> std::io::Result<()> {
+    let mut child = Command::new(program)
+        .args(args.iter())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text)?;
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{} failed: {}",
+            program, status
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tools_of() {
+        let programs = |os, wayland, x11| {
+            tools_of(os, wayland, x11)
+                .into_iter()
+                .map(|(program, _)| program)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(programs("macos", false, false), vec!["pbcopy"]);
+        assert_eq!(programs("windows", false, false), vec!["clip"]);
+        assert_eq!(
+            programs("linux", true, true),
+            vec!["wl-copy", "xclip", "xsel"]
+        );
+        assert_eq!(programs("linux", false, true), vec!["xclip", "xsel"]);
+        // a server without desktop
+        assert!(programs("linux", false, false).is_empty());
+    }
+}
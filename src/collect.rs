@@ -0,0 +1,3851 @@
+//! module to collect the resources from the cluster (nodes, pods, jobs, metrics, resource claims,...)
+//! and extract them as `Resource`
+
+use crate::*;
+
+/// The priority value of `class`: a number or the name of a `PriorityClass`
+pub async fn resolve_priority(client: kube::Client, class: &str) -> Result<i32, Error> {
+    if let Ok(value) = class.parse::<i32>() {
+        return Ok(value);
+    }
+    let api: Api<PriorityClass> = Api::all(client);
+    let priority_class = api.get(class).await.map_err(|source| Error::KubeError {
+        context: format!("get priorityclass '{}'", class),
+        source,
+    })?;
+    Ok(priority_class.value)
+}
+
+/// Token bucket to throttle the requests sent to the API server
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    qps: f32,
+    burst: f32,
+    tokens: f32,
+    last: tokio::time::Instant,
+}
+
+impl RateLimiter {
+    /// `qps` <= 0 disables the throttling
+    pub fn new(qps: f32, burst: u32) -> Self {
+        let burst = burst.max(1) as f32;
+        RateLimiter {
+            qps,
+            burst,
+            tokens: burst,
+            last: tokio::time::Instant::now(),
+        }
+    }
+
+    /// wait until a request can be sent
+    pub async fn acquire(&mut self) {
+        if self.qps <= 0.0 {
+            return;
+        }
+        let now = tokio::time::Instant::now();
+        self.tokens =
+            (self.tokens + now.duration_since(self.last).as_secs_f32() * self.qps).min(self.burst);
+        self.last = now;
+        if self.tokens < 1.0 {
+            // eg a tiny `qps`, the wait overflows a `Duration`
+            let wait = std::time::Duration::try_from_secs_f32((1.0 - self.tokens) / self.qps)
+                .unwrap_or(std::time::Duration::MAX);
+            debug!(?wait, "throttle request to the API server");
+            tokio::time::sleep(wait).await;
+            self.tokens = 1.0;
+            self.last = tokio::time::Instant::now();
+        }
+        self.tokens -= 1.0;
+    }
+}
+
+/// List objects from the API server by pages of `chunk_size` (0 for a single page),
+/// every page is throttled by the `limiter`.
+#[derive(Debug, Clone)]
+pub struct Lister {
+    pub chunk_size: u32,
+    pub limiter: RateLimiter,
+}
+
+impl Default for Lister {
+    fn default() -> Self {
+        Lister {
+            chunk_size: 500,
+            limiter: RateLimiter::new(5.0, 10),
+        }
+    }
+}
+
+impl Lister {
+    pub async fn list<K>(&mut self, api: &Api<K>, lp: &ListParams) -> kube::Result<ObjectList<K>>
+    where
+        K: Clone + serde::de::DeserializeOwned + std::fmt::Debug + kube::Resource,
+    {
+        self.list_pages(lp, |lp| async move { api.list(&lp).await })
+            .await
+    }
+
+    /// like `list`, the pages fetched by `fetch` (with the limit & the continue token of the page)
+    pub async fn list_pages<K, F, Fut>(
+        &mut self,
+        lp: &ListParams,
+        mut fetch: F,
+    ) -> kube::Result<ObjectList<K>>
+    where
+        K: Clone + kube::Resource,
+        F: FnMut(ListParams) -> Fut,
+        Fut: std::future::Future<Output = kube::Result<ObjectList<K>>>,
+    {
+        let mut lp = lp.clone();
+        if self.chunk_size > 0 {
+            lp = lp.limit(self.chunk_size);
+        }
+        let mut items = vec![];
+        let mut pages = 0;
+        loop {
+            self.limiter.acquire().await;
+            let page = fetch(lp.clone()).await?;
+            pages += 1;
+            items.extend(page.items);
+            match page.metadata.continue_.as_deref() {
+                Some(token) if !token.is_empty() => lp = lp.continue_token(token),
+                _ => {
+                    debug!(pages, "list done");
+                    // the cluster can change between the pages (eg a node updated, moved to a later page)
+                    let (items, duplicates) = dedup_objects(items);
+                    if duplicates > 0 {
+                        warn!(
+                            duplicates,
+                            pages, "objects listed on several pages, counted once"
+                        );
+                    }
+                    return Ok(ObjectList {
+                        metadata: page.metadata,
+                        items,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// the objects without the duplicates (same namespace & name), in the order of their first listing,
+/// keeping the last listed (the `resourceVersion` is opaque, it can't tell the most recent);
+/// return the count of the duplicates removed
+pub fn dedup_objects<K: kube::Resource>(objects: Vec<K>) -> (Vec<K>, usize) {
+    let mut positions: HashMap<(Option<String>, Option<String>), usize> = HashMap::new();
+    let mut kept: Vec<K> = Vec::with_capacity(objects.len());
+    let mut duplicates = 0;
+    for o in objects {
+        let key = (o.meta().namespace.clone(), o.meta().name.clone());
+        match positions.get(&key) {
+            Some(&i) => {
+                duplicates += 1;
+                kept[i] = o;
+            }
+            None => {
+                positions.insert(key, kept.len());
+                kept.push(o);
+            }
+        }
+    }
+    (kept, duplicates)
+}
+
+#[instrument(skip(client, lister, resources))]
+pub async fn collect_from_nodes(
+    client: kube::Client,
+    lister: &mut Lister,
+    resources: &mut Vec<Resource>,
+) -> Result<(Vec<Skipped>, FleetSummary), Error> {
+    let start = std::time::Instant::now();
+    let api_nodes: Api<Node> = Api::all(client);
+    let nodes = lister
+        .list(&api_nodes, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: "list nodes".to_string(),
+            source,
+        })?;
+    debug!(nodes = nodes.items.len(), elapsed = ?start.elapsed(), "nodes listed");
+    collect_from_node_list(nodes, resources).await
+}
+
+/// the part of `collect_from_nodes` after the listing, on the listed `nodes` (eg synthetic ones in the tests)
+pub async fn collect_from_node_list(
+    nodes: ObjectList<Node>,
+    resources: &mut Vec<Resource>,
+) -> Result<(Vec<Skipped>, FleetSummary), Error> {
+    let fleet = summarize_nodes(&nodes.items);
+    let skipped = extract_allocatable_from_nodes(nodes, resources).await?;
+    Ok((skipped, fleet))
+}
+
+/// The size of the fleet (see `--node-count`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FleetSummary {
+    pub nodes: usize,
+    /// the nodes with the condition `Ready`
+    pub ready: usize,
+    /// the nodes not cordoned (`spec.unschedulable`)
+    pub schedulable: usize,
+    /// the pods contributing to the resources (after the filters)
+    pub pods: usize,
+}
+
+impl std::fmt::Display for FleetSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Nodes: {} (ready: {}, schedulable: {}), Pods: {}",
+            self.nodes, self.ready, self.schedulable, self.pods
+        )
+    }
+}
+
+fn is_node_ready(node: &Node) -> bool {
+    node.status
+        .iter()
+        .flat_map(|status| status.conditions.iter().flatten())
+        .any(|c| c.type_ == "Ready" && c.status == "True")
+}
+
+fn is_node_schedulable(node: &Node) -> bool {
+    !node
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.unschedulable)
+        .unwrap_or(false)
+}
+
+pub fn node_state_of(node: &Node) -> NodeState {
+    if !is_node_ready(node) {
+        NodeState::not_ready
+    } else if is_node_schedulable(node) {
+        NodeState::ready
+    } else {
+        NodeState::cordoned
+    }
+}
+
+/// the counts of the nodes (the pods are counted later, see `count_pods`)
+pub fn summarize_nodes(nodes: &[Node]) -> FleetSummary {
+    FleetSummary {
+        nodes: nodes.len(),
+        ready: nodes.iter().filter(|node| is_node_ready(node)).count(),
+        schedulable: nodes
+            .iter()
+            .filter(|node| is_node_schedulable(node))
+            .count(),
+        pods: 0,
+    }
+}
+
+/// the number of pods with at least one resource
+pub fn count_pods(resources: &[Resource]) -> usize {
+    resources
+        .iter()
+        .filter_map(|r| {
+            r.location
+                .pod_name
+                .as_ref()
+                .map(|pod| (r.location.namespace.as_ref(), pod))
+        })
+        .unique()
+        .count()
+}
+
+/// A field of custom resources read as the allocatable of a kind (see `--extra-allocatable-from`),
+/// parsed from `KIND=API_VERSION/PLURAL:PATH[@NODE_PATH]`, eg
+/// `example.com/licenses=licensing.example.com/v1/licensepools:.status.capacity@.spec.nodeName`
+/// - `API_VERSION` is `GROUP/VERSION` (or `VERSION` for the core group), `PLURAL` the name of the resource in the urls
+/// - `PATH` the fields (separated by `.`) from the root of the object to the quantity (a string or a number)
+/// - `NODE_PATH` (optional) the fields to the name of the node of the allocatable, without it the allocatable
+///   is not on a node (only in the total of the kind)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraAllocatable {
+    pub kind: String,
+    pub api_version: String,
+    pub plural: String,
+    pub path: Vec<String>,
+    pub node_path: Option<Vec<String>>,
+}
+
+fn parse_field_path(s: &str) -> Vec<String> {
+    s.split('.')
+        .filter(|f| !f.is_empty())
+        .map(|f| f.to_string())
+        .collect()
+}
+
+/// parse an `ExtraAllocatable` from `KIND=API_VERSION/PLURAL:PATH[@NODE_PATH]`
+pub fn parse_extra_allocatable(s: &str) -> Result<ExtraAllocatable, String> {
+    let invalid = || format!("invalid KIND=API_VERSION/PLURAL:PATH[@NODE_PATH]: `{}`", s);
+    let (kind, source) = parse_key_value(s)?;
+    let (resource, paths) = source.split_once(':').ok_or_else(invalid)?;
+    let (api_version, plural) = resource
+        .rsplit_once('/')
+        .filter(|(api_version, plural)| !api_version.is_empty() && !plural.is_empty())
+        .ok_or_else(invalid)?;
+    let (path, node_path) = match paths.split_once('@') {
+        Some((path, node_path)) => (path, Some(parse_field_path(node_path))),
+        None => (paths, None),
+    };
+    let path = parse_field_path(path);
+    if path.is_empty() || node_path.as_ref().map(|p| p.is_empty()).unwrap_or(false) {
+        return Err(invalid());
+    }
+    Ok(ExtraAllocatable {
+        kind,
+        api_version: api_version.to_string(),
+        plural: plural.to_string(),
+        path,
+        node_path,
+    })
+}
+
+impl ExtraAllocatable {
+    pub fn api_resource(&self) -> ApiResource {
+        let (group, version) = match self.api_version.split_once('/') {
+            Some((group, version)) => (group.to_string(), version.to_string()),
+            None => (String::new(), self.api_version.clone()),
+        };
+        ApiResource {
+            group,
+            version,
+            api_version: self.api_version.clone(),
+            // not used to list
+            kind: String::new(),
+            plural: self.plural.clone(),
+        }
+    }
+
+    /// the path of the list in the urls, eg `/apis/licensing.example.com/v1/licensepools`
+    pub fn url_path(&self) -> String {
+        let prefix = if self.api_version.contains('/') {
+            "apis"
+        } else {
+            "api"
+        };
+        format!("/{}/{}/{}", prefix, self.api_version, self.plural)
+    }
+}
+
+#[instrument(skip(client, lister, resources))]
+pub async fn collect_extra_allocatable(
+    client: kube::Client,
+    lister: &mut Lister,
+    extra: &ExtraAllocatable,
+    resources: &mut Vec<Resource>,
+) -> Result<Vec<Skipped>, Error> {
+    let api: Api<DynamicObject> = Api::all_with(client, &extra.api_resource());
+    let objects = lister
+        .list(&api, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: format!("list {}", extra.url_path()),
+            source,
+        })?;
+    debug!(objects = objects.items.len(), "custom resources listed");
+    extract_extra_allocatable(extra, objects.items, resources)
+}
+
+/// the input of a quantity from a json value, a non-scalar (eg a list) is kept as json to be rejected
+/// by the parser of `Qty` (and reported as skipped), not coerced nor ignored
+fn quantity_of_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Add the field of every object as an allocatable of `extra.kind`,
+/// the objects without the field are ignored (eg not yet reconciled)
+pub fn extract_extra_allocatable(
+    extra: &ExtraAllocatable,
+    objects: Vec<DynamicObject>,
+    resources: &mut Vec<Resource>,
+) -> Result<Vec<Skipped>, Error> {
+    let mut skipped = vec![];
+    let get = |value: &serde_json::Value, path: &[String]| {
+        path.iter()
+            .try_fold(value.clone(), |v, field| v.get(field).cloned())
+            .and_then(|v| quantity_of_value(&v))
+    };
+    for object in objects {
+        let value = serde_json::to_value(&object)?;
+        let input = match get(&value, &extra.path) {
+            Some(input) => input,
+            None => {
+                debug!(name = ?object.metadata.name, "no allocatable field");
+                continue;
+            }
+        };
+        let location = Location {
+            node_name: extra
+                .node_path
+                .as_ref()
+                .and_then(|node_path| get(&value, node_path))
+                .filter(|node_name| !node_name.starts_with(['[', '{'])),
+            ..Location::default()
+        };
+        let qualifier = ResourceQualifier::Allocatable;
+        match Qty::parse_for_kind(&input, &extra.kind) {
+            Ok(quantity) => resources.push(Resource {
+                kind: extra.kind.clone(),
+                qualifier,
+                quantity,
+                location,
+            }),
+            Err(err) => skipped.push(Skipped::new(
+                &location,
+                &qualifier,
+                &extra.kind,
+                &input,
+                err,
+            )),
+        }
+    }
+    Ok(skipped)
+}
+
+#[instrument(skip(node_list, resources))]
+pub async fn extract_allocatable_from_nodes(
+    node_list: ObjectList<Node>,
+    resources: &mut Vec<Resource>,
+) -> Result<Vec<Skipped>, Error> {
+    let resources_before = resources.len();
+    let mut skipped = vec![];
+    // index of the resource by (node, kind, qualifier), to keep only the last one of a node listed twice
+    let mut extracted = HashMap::new();
+    for node in node_list.items {
+        let node_state = Some(node_state_of(&node));
+        let location = Location {
+            node_name: node.metadata.name,
+            node_state,
+            node_labels: Arc::new(node.metadata.labels.unwrap_or_default()),
+            node_created: node.metadata.creation_timestamp.map(|t| t.0),
+            ..Location::default()
+        };
+        let status = node.status.unwrap_or_default();
+        for (qualifier_index, (qualifier, qtys)) in [
+            (ResourceQualifier::Allocatable, status.allocatable),
+            (ResourceQualifier::Capacity, status.capacity),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            for (kind, value) in qtys.unwrap_or_default().iter() {
+                let quantity = match Qty::parse_for_kind(&(value).0, kind) {
+                    Ok(quantity) => quantity,
+                    Err(err) => {
+                        skipped.push(Skipped::new(&location, &qualifier, kind, &value.0, err));
+                        continue;
+                    }
+                };
+                let resource = Resource {
+                    kind: kind.clone(),
+                    qualifier: qualifier.clone(),
+                    quantity,
+                    location: location.clone(),
+                };
+                let key = (location.node_name.clone(), kind.clone(), qualifier_index);
+                if let Some(&i) = extracted.get(&key) {
+                    let previous: &mut Resource = &mut resources[i];
+                    if previous.quantity.value != resource.quantity.value {
+                        warn!(
+                            node = location.node_name.as_deref().unwrap_or_default(),
+                            kind,
+                            previous = %previous.quantity,
+                            last = %resource.quantity,
+                            "node listed twice with different {:?}, keep the last",
+                            qualifier
+                        );
+                    }
+                    *previous = resource;
+                } else {
+                    extracted.insert(key, resources.len());
+                    resources.push(resource);
+                }
+            }
+        }
+    }
+    debug!(
+        resources = resources.len() - resources_before,
+        "allocatable extracted from nodes"
+    );
+    Ok(skipped)
+}
+
+/// A what-if allocatable of a node (see `--node-allocatable-override`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeAllocatableOverride {
+    pub node: String,
+    pub allocatable: Vec<(String, Qty)>,
+}
+
+/// parse `NODE:KIND=QTY,KIND=QTY...`, eg `node-1:cpu=8,memory=32Gi`
+pub fn parse_node_allocatable_override(s: &str) -> Result<NodeAllocatableOverride, String> {
+    let (node, qtys) = s
+        .split_once(':')
+        .filter(|(node, _)| !node.is_empty())
+        .ok_or_else(|| format!("invalid NODE:KIND=QTY,...: no `:` found in `{}`", s))?;
+    let allocatable = qtys
+        .split(',')
+        .map(parse_threshold)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(NodeAllocatableOverride {
+        node: node.to_string(),
+        allocatable,
+    })
+}
+
+/// Replace the allocatable of the kinds of the overrides on their nodes (the ones collected from the cluster),
+/// the capacity of these kinds is removed (the reserved of a modeled node is unknown).
+/// Fails if a node of an override is not collected (eg a typo).
+pub fn apply_node_allocatable_overrides(
+    resources: &mut Vec<Resource>,
+    overrides: &[NodeAllocatableOverride],
+) -> Result<(), Error> {
+    for o in overrides {
+        let location = resources
+            .iter()
+            .find(|r| {
+                r.location.node_name.as_deref() == Some(&o.node)
+                    && matches!(r.qualifier, ResourceQualifier::Allocatable)
+            })
+            .map(|r| r.location.clone())
+            .ok_or_else(|| Error::NodeNotFound {
+                node: o.node.clone(),
+            })?;
+        resources.retain(|r| {
+            !(r.location.node_name.as_deref() == Some(&o.node)
+                && matches!(
+                    r.qualifier,
+                    ResourceQualifier::Allocatable | ResourceQualifier::Capacity
+                )
+                && o.allocatable.iter().any(|(kind, _)| kind == &r.kind))
+        });
+        for (kind, quantity) in &o.allocatable {
+            info!(node = o.node, kind, %quantity, "override allocatable");
+            resources.push(Resource {
+                kind: kind.clone(),
+                quantity: quantity.clone(),
+                location: location.clone(),
+                qualifier: ResourceQualifier::Allocatable,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Subtract the quantity reserved (by kind) from the allocatable of every node (see `--reserve-for-system`),
+/// 0 if the reserve is over the allocatable
+pub fn apply_reserve_for_system(resources: &mut [Resource], reserve: &[(String, Qty)]) {
+    if reserve.is_empty() {
+        return;
+    }
+    for r in resources.iter_mut().filter(|r| {
+        r.location.node_name.is_some() && matches!(r.qualifier, ResourceQualifier::Allocatable)
+    }) {
+        if let Some((_, reserved)) = reserve.iter().find(|(kind, _)| kind == &r.kind) {
+            r.quantity = r.quantity.checked_sub(reserved).unwrap_or_default();
+        }
+    }
+}
+
+/*
+The phase of a Pod is a simple, high-level summary of where the Pod is in its lifecycle. The conditions array, the reason and message fields, and the individual container status arrays contain more detail about the pod's status.
+
+There are five possible phase values:
+Pending: The pod has been accepted by the Kubernetes system, but one or more of the container images has not been created. This includes time before being scheduled as well as time spent downloading images over the network, which could take a while.
+Running: The pod has been bound to a node, and all of the containers have been created. At least one container is still running, or is in the process of starting or restarting.
+Succeeded: All containers in the pod have terminated in success, and will not be restarted.
+Failed: All containers in the pod have terminated, and at least one container has terminated in failure. The container either exited with non-zero status or was terminated by the system.
+Unknown: For some reason the state of the pod could not be obtained, typically due to an error in communicating with the host of the pod.
+
+More info: https://kubernetes.io/docs/concepts/workloads/pods/pod-lifecycle#pod-phase
+*/
+
+pub fn is_scheduled(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|ps| {
+            ps.phase.as_ref().and_then(|phase| {
+                match &phase[..] {
+                    "Succeeded" | "Failed" => Some(false),
+                    "Running" => Some(true),
+                    "Unknown" => None, // this is the case when a node is down (kubelet is not responding)
+                    "Pending" => ps.conditions.as_ref().map(|o| {
+                        o.iter()
+                            .any(|c| c.type_ == "PodScheduled" && c.status == "True")
+                    }),
+                    &_ => None, // should not happen
+                }
+            })
+        })
+        .unwrap_or(false)
+}
+
+fn find_qty(resource_list: &Option<BTreeMap<String, Quantity>>, kind: &str) -> Option<Qty> {
+    resource_list
+        .as_ref()
+        .and_then(|l| l.get(kind))
+        .and_then(|q| Qty::parse_for_kind(&q.0, kind).ok())
+}
+
+/// Derive the QoS class of a pod from the requests and limits (cpu & memory) of its containers
+/// (like the kubelet does), a missing request defaults to the limit.
+pub fn qos_class_of(spec: &PodSpec) -> QosClass {
+    let containers = spec
+        .containers
+        .iter()
+        .chain(spec.init_containers.iter().flatten());
+    let mut is_best_effort = true;
+    let mut is_guaranteed = true;
+    for container in containers {
+        let requirements = container.resources.as_ref();
+        for kind in ["cpu", "memory"] {
+            let request = requirements.and_then(|r| find_qty(&r.requests, kind));
+            let limit = requirements.and_then(|r| find_qty(&r.limits, kind));
+            if request.iter().chain(limit.iter()).any(|q| !q.is_zero()) {
+                is_best_effort = false;
+            }
+            match (request, limit) {
+                (_, None) => is_guaranteed = false,
+                (Some(request), Some(limit)) if request.value != limit.value => {
+                    is_guaranteed = false
+                }
+                _ => {}
+            }
+        }
+    }
+    if is_best_effort {
+        QosClass::BestEffort
+    } else if is_guaranteed {
+        QosClass::Guaranteed
+    } else {
+        QosClass::Burstable
+    }
+}
+
+fn push_resources(
+    resources: &mut Vec<Resource>,
+    location: &Location,
+    qualifier: ResourceQualifier,
+    resource_list: &BTreeMap<String, Qty>,
+) {
+    for (key, quantity) in resource_list.iter() {
+        resources.push(Resource {
+            kind: key.clone(),
+            qualifier: qualifier.clone(),
+            quantity: quantity.clone(),
+            location: location.clone(),
+        });
+    }
+}
+
+/// the hugepages (`hugepages-<size>`) requested with a limit different of the request
+/// (kubernetes requires them to be equal, a missing request defaults to the limit)
+pub fn hugepages_mismatches(requirements: &ResourceRequirements) -> Vec<String> {
+    requirements
+        .requests
+        .iter()
+        .flat_map(|r| r.keys())
+        .filter(|kind| kind.starts_with("hugepages-"))
+        .filter(|kind| {
+            find_qty(&requirements.requests, kind).map(|q| q.value)
+                != find_qty(&requirements.limits, kind).map(|q| q.value)
+        })
+        .cloned()
+        .collect()
+}
+
+fn warn_hugepages_mismatches(location: &Location, requirements: &ResourceRequirements) {
+    for kind in hugepages_mismatches(requirements) {
+        warn!(
+            namespace = location.namespace.as_deref().unwrap_or_default(),
+            pod = location.pod_name.as_deref().unwrap_or_default(),
+            container = location.container_name.as_deref().unwrap_or_default(),
+            "{}: requested is not equal to limit",
+            kind
+        );
+    }
+}
+
+/// push a `NoLimit` marker for every resource requested without limit
+fn push_no_limits(
+    resources: &mut Vec<Resource>,
+    location: &Location,
+    requirements: &ResourceRequirements,
+) {
+    if let Some(requests) = &requirements.requests {
+        for kind in requests.keys() {
+            if !requirements
+                .limits
+                .as_ref()
+                .map(|l| l.contains_key(kind))
+                .unwrap_or(false)
+            {
+                resources.push(Resource {
+                    kind: kind.clone(),
+                    qualifier: ResourceQualifier::NoLimit,
+                    quantity: Qty::default(),
+                    location: location.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// A container that sets no request for some kinds (see `--no-requests`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerWithoutRequests {
+    pub namespace: Option<String>,
+    pub pod: String,
+    pub container: String,
+    /// the kinds (of the checked ones) without request
+    pub kinds: Vec<String>,
+}
+
+/// The containers (of the scheduled pods, init containers excluded) without request for some `kinds`
+pub fn find_containers_without_requests(
+    pods: &[Pod],
+    kinds: &[String],
+) -> Vec<ContainerWithoutRequests> {
+    let mut out = vec![];
+    for pod in pods.iter().filter(|pod| is_scheduled(pod)) {
+        let containers = pod
+            .spec
+            .as_ref()
+            .map(|s| &s.containers[..])
+            .unwrap_or_default();
+        for container in containers {
+            let requests = container
+                .resources
+                .as_ref()
+                .and_then(|r| r.requests.as_ref());
+            let missing = kinds
+                .iter()
+                .filter(|kind| !requests.map(|r| r.contains_key(*kind)).unwrap_or(false))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                out.push(ContainerWithoutRequests {
+                    namespace: pod.metadata.namespace.clone(),
+                    pod: pod.metadata.name.clone().unwrap_or_default(),
+                    container: container.name.clone(),
+                    kinds: missing,
+                });
+            }
+        }
+    }
+    out
+}
+
+/// A pending pod that the scheduler failed to place for lack of some kinds (see `--pending-pods`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockedPod {
+    pub namespace: Option<String>,
+    pub pod: String,
+    /// the kinds reported as insufficient
+    pub kinds: Vec<String>,
+}
+
+/// The kinds of the `Insufficient <kind>` of a message of the scheduler, eg
+/// "0/3 nodes are available: 1 Insufficient cpu, 2 Insufficient nvidia.com/gpu."
+pub fn insufficient_kinds(message: &str) -> Vec<String> {
+    message
+        .split("Insufficient ")
+        .skip(1)
+        .filter_map(|rest| {
+            let kind = rest
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches('.');
+            (!kind.is_empty()).then(|| kind.to_string())
+        })
+        .unique()
+        .collect()
+}
+
+/// The pending pods not scheduled for lack of resources, from the `PodScheduled` condition set by the scheduler
+/// (reason `Unschedulable`)
+pub fn find_blocked_pods(pods: &[Pod]) -> Vec<BlockedPod> {
+    pods.iter()
+        .filter(|pod| {
+            pod.status.as_ref().and_then(|s| s.phase.as_deref()) == Some("Pending")
+                && !is_scheduled(pod)
+        })
+        .filter_map(|pod| {
+            let kinds = pod
+                .status
+                .as_ref()
+                .and_then(|s| s.conditions.as_ref())
+                .and_then(|conditions| {
+                    conditions.iter().find(|c| {
+                        c.type_ == "PodScheduled"
+                            && c.status == "False"
+                            && c.reason.as_deref() == Some("Unschedulable")
+                    })
+                })
+                .and_then(|c| c.message.as_deref())
+                .map(insufficient_kinds)
+                .unwrap_or_default();
+            (!kinds.is_empty()).then(|| BlockedPod {
+                namespace: pod.metadata.namespace.clone(),
+                pod: pod.metadata.name.clone().unwrap_or_default(),
+                kinds,
+            })
+        })
+        .collect()
+}
+
+/// the number of blocked pods by kind (a pod blocked on several kinds is counted for each)
+pub fn count_blocked_by_kind(pods: &[BlockedPod]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for kind in pods.iter().flat_map(|p| &p.kinds) {
+        *counts.entry(kind.clone()).or_default() += 1;
+    }
+    counts
+}
+
+/// the lines of the notes of `--no-requests`, the first one is the summary
+pub fn no_requests_summary(
+    containers: &[ContainerWithoutRequests],
+    kinds: &[String],
+) -> Vec<String> {
+    if containers.is_empty() {
+        return vec![format!(
+            "Containers without requests ({}): none",
+            kinds.join(", ")
+        )];
+    }
+    let mut lines = vec![format!(
+        "Containers without requests ({}): {}",
+        kinds.join(", "),
+        containers.len()
+    )];
+    for c in containers {
+        lines.push(format!(
+            "  {}/{}/{}: {}",
+            c.namespace.as_deref().unwrap_or_default(),
+            c.pod,
+            c.container,
+            c.kinds.join(", ")
+        ));
+    }
+    lines
+}
+
+/// A pod with a container waiting in `CrashLoopBackOff`: its requests stay reserved on its node while it
+/// provides no service (see `--crashloop-waste`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashLoopPod {
+    pub namespace: Option<String>,
+    pub pod: String,
+    /// the containers waiting in `CrashLoopBackOff` (the native sidecars with the init containers)
+    pub containers: Vec<String>,
+    /// the effective requests of the pod (see `effective_requests_of`)
+    pub requested: BTreeMap<String, Qty>,
+}
+
+/// The pods with requests and a container waiting in `CrashLoopBackOff` (from the statuses of the containers)
+pub fn find_crashlooping_pods(pods: &[Pod]) -> Vec<CrashLoopPod> {
+    pods.iter()
+        .filter_map(|pod| {
+            let status = pod.status.as_ref()?;
+            let containers = status
+                .container_statuses
+                .iter()
+                .chain(status.init_container_statuses.iter())
+                .flatten()
+                .filter(|c| {
+                    c.state
+                        .as_ref()
+                        .and_then(|s| s.waiting.as_ref())
+                        .and_then(|w| w.reason.as_deref())
+                        == Some("CrashLoopBackOff")
+                })
+                .map(|c| c.name.clone())
+                .collect::<Vec<_>>();
+            if containers.is_empty() {
+                return None;
+            }
+            let requested = pod
+                .spec
+                .as_ref()
+                .and_then(|spec| effective_requests_of(spec).ok())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(_, qty)| !qty.is_zero())
+                .collect::<BTreeMap<_, _>>();
+            (!requested.is_empty()).then(|| CrashLoopPod {
+                namespace: pod.metadata.namespace.clone(),
+                pod: pod.metadata.name.clone().unwrap_or_default(),
+                containers,
+                requested,
+            })
+        })
+        .collect()
+}
+
+/// the lines of the notes about the pods in `CrashLoopBackOff`, the first one is the summary with the requests
+/// held by kind (see `--crashloop-waste`)
+pub fn crashloop_waste_summary(pods: &[CrashLoopPod]) -> Vec<String> {
+    if pods.is_empty() {
+        return vec!["Pods in CrashLoopBackOff holding requests: none".to_string()];
+    }
+    let format_requests = |requests: &BTreeMap<String, Qty>| {
+        requests
+            .iter()
+            .map(|(kind, qty)| format!("{} {}", kind, qty.adjust_scale()))
+            .join(", ")
+    };
+    let mut held: BTreeMap<String, Qty> = BTreeMap::new();
+    for (kind, qty) in pods.iter().flat_map(|p| p.requested.iter()) {
+        let total = held.entry(kind.clone()).or_default();
+        *total = &*total + qty;
+    }
+    std::iter::once(format!(
+        "Pods in CrashLoopBackOff holding requests: {} ({})",
+        pods.len(),
+        format_requests(&held)
+    ))
+    .chain(pods.iter().map(|p| {
+        format!(
+            "  {}/{} ({}): {}",
+            p.namespace.as_deref().unwrap_or_default(),
+            p.pod,
+            p.containers.join(", "),
+            format_requests(&p.requested)
+        )
+    }))
+    .collect()
+}
+
+/// the positive part of `effective - base` (by resource)
+fn excess_of(
+    effective: &BTreeMap<String, Qty>,
+    base: &BTreeMap<String, Qty>,
+) -> BTreeMap<String, Qty> {
+    effective
+        .iter()
+        .filter_map(|(key, qty)| {
+            let excess = match base.get(key) {
+                Some(b) => qty - b,
+                None => qty.clone(),
+            };
+            (excess.value > 0).then_some((key.clone(), excess))
+        })
+        .collect()
+}
+
+fn process_resources<F>(
+    effective_resources: &mut BTreeMap<String, Qty>,
+    resource_list: &BTreeMap<String, k8s_openapi::apimachinery::pkg::api::resource::Quantity>,
+    op: F,
+) -> Result<(), Error>
+where
+    F: Fn(Qty, Qty) -> Qty,
+{
+    for (key, value) in resource_list.iter() {
+        let quantity = Qty::parse_for_kind(&(value).0, key)?;
+        if let Some(current_quantity) = effective_resources.get_mut(key) {
+            *current_quantity = op(current_quantity.clone(), quantity).clone();
+        } else {
+            effective_resources.insert(key.clone(), quantity.clone());
+        }
+    }
+    Ok(())
+}
+
+/// What is found while collecting the pods, besides their resources
+#[derive(Debug, Clone, Default)]
+pub struct PodsFindings {
+    pub skipped: Vec<Skipped>,
+    /// see `--no-requests`
+    pub without_requests: Vec<ContainerWithoutRequests>,
+    /// see `--pending-pods`
+    pub blocked: Vec<BlockedPod>,
+    /// the pods with an in-place resize not completed (see `--allocated-resources`)
+    pub resizing: Vec<ResizingPod>,
+    /// see `--crashloop-waste`
+    pub crashlooping: Vec<CrashLoopPod>,
+}
+
+#[instrument(skip(client, lister, resources))]
+pub async fn collect_from_pods(
+    client: kube::Client,
+    lister: &mut Lister,
+    resources: &mut Vec<Resource>,
+    namespace: &Option<String>,
+    allocated_resources: bool,
+    no_requests_kinds: &[String],
+) -> Result<PodsFindings, Error> {
+    let start = std::time::Instant::now();
+    // untyped, the restart policy of the containers (kubernetes >= 1.28) and the resources of the container statuses
+    // (kubernetes >= 1.27) are unknown of k8s-openapi
+    let ar = ApiResource::erase::<Pod>(&());
+    let api_pods: Api<DynamicObject> = if let Some(ns) = namespace {
+        Api::namespaced_with(client, ns, &ar)
+    } else {
+        Api::all_with(client, &ar)
+    };
+    let objects = lister
+        .list(&api_pods, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: "list pods".to_string(),
+            source,
+        })?;
+    debug!(pods = objects.items.len(), elapsed = ?start.elapsed(), "pods listed");
+    let values = objects
+        .items
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    collect_from_pod_values(values, resources, allocated_resources, no_requests_kinds).await
+}
+
+/// the typed pods of the untyped `objects` (eg watched), with their native sidecars hoisted (see `hoist_sidecars`)
+pub fn typed_pods(objects: Vec<DynamicObject>) -> Result<Vec<Pod>, Error> {
+    objects
+        .into_iter()
+        .map(|object| {
+            let value = serde_json::to_value(object)?;
+            let mut pod: Pod = serde_json::from_value(value.clone())?;
+            hoist_sidecars(&mut pod, &value);
+            Ok(pod)
+        })
+        .collect()
+}
+
+/// the part of `collect_from_pods` after the listing, on the untyped listed `pods` (eg synthetic ones in the tests)
+pub async fn collect_from_pod_values(
+    pods: Vec<serde_json::Value>,
+    resources: &mut Vec<Resource>,
+    allocated_resources: bool,
+    no_requests_kinds: &[String],
+) -> Result<PodsFindings, Error> {
+    let mut items = vec![];
+    let mut resizing = vec![];
+    let mut sidecars = 0;
+    let mut resized = 0;
+    for value in pods {
+        let mut pod: Pod = serde_json::from_value(value.clone())?;
+        sidecars += hoist_sidecars(&mut pod, &value);
+        if allocated_resources {
+            resized += apply_status_resources(&mut pod, status_resources_of(&value));
+            resizing.extend(resizing_pod_of(&value));
+        }
+        items.push(pod);
+    }
+    debug!(
+        sidecars,
+        resized, "containers with sidecars hoisted, with allocated resources != spec"
+    );
+    let pods = ObjectList {
+        metadata: Default::default(),
+        items,
+    };
+    let without_requests = find_containers_without_requests(&pods.items, no_requests_kinds);
+    let blocked = find_blocked_pods(&pods.items);
+    let crashlooping = find_crashlooping_pods(&pods.items);
+    let skipped = extract_allocatable_from_pods(pods, resources).await?;
+    Ok(PodsFindings {
+        skipped,
+        without_requests,
+        blocked,
+        resizing,
+        crashlooping,
+    })
+}
+
+/// Move the native sidecars of the pod (the init containers with `restartPolicy: Always`) to its regular containers,
+/// as they keep running along them, and add the requests (and limits) of the sidecars started before an init
+/// container to the ones of the init container, as they run along it: the effective requests of the pod are
+/// `max(max(init[i] + sum(sidecars before i)), sum(regular) + sum(sidecars)) + overhead`
+/// (see https://kubernetes.io/docs/concepts/workloads/pods/sidecar-containers/#resource-sharing-within-containers).
+/// The restart policy of a container is unknown of k8s-openapi, so it is read from the untyped `value` of the pod.
+/// Return the number of moved containers.
+pub fn hoist_sidecars(pod: &mut Pod, value: &serde_json::Value) -> usize {
+    let names = value
+        .pointer("/spec/initContainers")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|c| c.get("restartPolicy").and_then(|p| p.as_str()) == Some("Always"))
+        .filter_map(|c| c.get("name").and_then(|n| n.as_str()))
+        .collect::<BTreeSet<_>>();
+    match pod.spec.as_mut() {
+        Some(spec) if !names.is_empty() => {
+            let mut sidecars: Vec<Container> = vec![];
+            let mut init_containers = vec![];
+            for mut container in spec.init_containers.take().unwrap_or_default() {
+                if names.contains(container.name.as_str()) {
+                    sidecars.push(container);
+                } else {
+                    add_started_sidecars(&mut container, &sidecars);
+                    init_containers.push(container);
+                }
+            }
+            spec.init_containers = Some(init_containers);
+            let moved = sidecars.len();
+            spec.containers.extend(sidecars);
+            moved
+        }
+        _ => 0,
+    }
+}
+
+/// add to the requests & limits of the init `container` the ones of the `sidecars` (started before it), only for
+/// the kinds it requests or limits (else the sidecars are under their sum with the regular containers)
+fn add_started_sidecars(container: &mut Container, sidecars: &[Container]) {
+    let resources = match container.resources.as_mut() {
+        Some(resources) => resources,
+        None => return,
+    };
+    let sidecar_resources = sidecars
+        .iter()
+        .filter_map(|c| c.resources.as_ref())
+        .collect::<Vec<_>>();
+    for (list, others) in [
+        (
+            &mut resources.requests,
+            sidecar_resources
+                .iter()
+                .filter_map(|r| r.requests.as_ref())
+                .collect::<Vec<_>>(),
+        ),
+        (
+            &mut resources.limits,
+            sidecar_resources
+                .iter()
+                .filter_map(|r| r.limits.as_ref())
+                .collect::<Vec<_>>(),
+        ),
+    ] {
+        for (kind, quantity) in list.iter_mut().flatten() {
+            // unreadable: the pod is skipped (see `unreadable_qtys_of`)
+            let qtys = std::iter::once(&*quantity)
+                .chain(others.iter().filter_map(|o| o.get(kind)))
+                .map(|q| Qty::parse_for_kind(&q.0, kind))
+                .collect::<Result<Vec<_>, _>>();
+            if let Ok(qtys) = qtys {
+                if qtys.len() > 1 {
+                    let sum = qtys.iter().fold(Qty::default(), |sum, q| &sum + q);
+                    *quantity = Quantity(sum.to_canonical_string());
+                }
+            }
+        }
+    }
+}
+
+/// The resources of the containers reported by the status of the pod (see `--allocated-resources`), by container:
+/// the requests allocated by the node (`allocatedResources`), else the requests of the `resources` of the status,
+/// and the limits of the `resources` of the status
+pub fn status_resources_of(pod: &serde_json::Value) -> BTreeMap<String, ResourceRequirements> {
+    // the statuses of the native sidecars are with the ones of the init containers
+    let statuses = ["/status/containerStatuses", "/status/initContainerStatuses"]
+        .iter()
+        .filter_map(|pointer| pod.pointer(pointer).and_then(|s| s.as_array()))
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    let field = |status: &serde_json::Value, pointer: &str| {
+        status
+            .pointer(pointer)
+            .and_then(|v| v.as_object())
+            .map(|m| {
+                m.iter()
+                    .filter_map(|(kind, v)| Some((kind.clone(), Quantity(quantity_of_value(v)?))))
+                    .collect::<BTreeMap<_, _>>()
+            })
+    };
+    statuses
+        .iter()
+        .filter_map(|status| {
+            let name = status.get("name")?.as_str()?.to_string();
+            let requests = field(status, "/allocatedResources")
+                .or_else(|| field(status, "/resources/requests"));
+            let limits = field(status, "/resources/limits");
+            (requests.is_some() || limits.is_some())
+                .then_some((name, ResourceRequirements { requests, limits }))
+        })
+        .collect()
+}
+
+/// A pod with an in-place resize not completed (see `--allocated-resources`): its resources are the allocated ones,
+/// not the target of the spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResizingPod {
+    pub namespace: Option<String>,
+    pub pod: String,
+    /// `Proposed`, `InProgress`, `Deferred` or `Infeasible`
+    pub state: String,
+}
+
+/// the resize in progress of a pod: from `status.resize` (kubernetes < 1.33), else from the conditions
+/// `PodResizePending` (with the reason `Deferred` or `Infeasible`) & `PodResizeInProgress`
+pub fn resizing_pod_of(pod: &serde_json::Value) -> Option<ResizingPod> {
+    let state = pod
+        .pointer("/status/resize")
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .or_else(|| {
+            pod.pointer("/status/conditions")
+                .and_then(|c| c.as_array())?
+                .iter()
+                .filter(|c| c.get("status").and_then(|s| s.as_str()) == Some("True"))
+                .find_map(|c| match c.get("type").and_then(|t| t.as_str()) {
+                    Some("PodResizePending") => Some(
+                        c.get("reason")
+                            .and_then(|r| r.as_str())
+                            .unwrap_or("Proposed")
+                            .to_string(),
+                    ),
+                    Some("PodResizeInProgress") => Some("InProgress".to_string()),
+                    _ => None,
+                })
+        })?;
+    let text = |pointer: &str| {
+        pod.pointer(pointer)
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    };
+    Some(ResizingPod {
+        namespace: text("/metadata/namespace"),
+        pod: text("/metadata/name").unwrap_or_default(),
+        state,
+    })
+}
+
+/// the lines of the notes about the pods with a resize in progress, the first one is the summary
+pub fn resizing_summary(pods: &[ResizingPod]) -> Vec<String> {
+    std::iter::once(format!(
+        "Pods with an in-place resize in progress (counted with their allocated resources): {}",
+        pods.len()
+    ))
+    .chain(pods.iter().map(|p| {
+        format!(
+            "  {}/{}: {}",
+            p.namespace.as_deref().unwrap_or_default(),
+            p.pod,
+            p.state
+        )
+    }))
+    .collect()
+}
+
+/// Replace the requests & limits of the containers of the spec by the ones of the status (when set),
+/// the spec is the desired state of a pending in-place resize. Returns the number of containers changed.
+pub fn apply_status_resources(
+    pod: &mut Pod,
+    status_resources: BTreeMap<String, ResourceRequirements>,
+) -> usize {
+    let mut changed = 0;
+    let containers = pod
+        .spec
+        .iter_mut()
+        .flat_map(|spec| spec.containers.iter_mut());
+    for container in containers {
+        let status = match status_resources.get(&container.name) {
+            Some(status) => status,
+            None => continue,
+        };
+        let resources = container.resources.get_or_insert_with(Default::default);
+        if status.requests.is_some() && status.requests != resources.requests {
+            resources.requests = status.requests.clone();
+            changed += 1;
+        }
+        if status.limits.is_some() && status.limits != resources.limits {
+            resources.limits = status.limits.clone();
+        }
+    }
+    changed
+}
+
+/// the resources of the container statuses (in-place resize) are only reported since kubernetes 1.27
+pub fn supports_allocated_resources(info: &k8s_openapi::apimachinery::pkg::version::Info) -> bool {
+    let number = |s: &str| {
+        s.chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect::<String>()
+            .parse::<u32>()
+            .unwrap_or(0)
+    };
+    (number(&info.major), number(&info.minor)) >= (1, 27)
+}
+
+#[instrument(skip(client, lister, resources))]
+pub async fn collect_from_jobs(
+    client: kube::Client,
+    lister: &mut Lister,
+    resources: &mut Vec<Resource>,
+    namespace: &Option<String>,
+) -> Result<(), Error> {
+    let start = std::time::Instant::now();
+    let (api_jobs, api_cron_jobs): (Api<Job>, Api<CronJob>) = if let Some(ns) = namespace {
+        (
+            Api::namespaced(client.clone(), ns),
+            Api::namespaced(client, ns),
+        )
+    } else {
+        (Api::all(client.clone()), Api::all(client))
+    };
+    let jobs = lister
+        .list(&api_jobs, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: "list jobs".to_string(),
+            source,
+        })?;
+    let cron_jobs = lister
+        .list(&api_cron_jobs, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: "list cronjobs".to_string(),
+            source,
+        })?;
+    debug!(jobs = jobs.items.len(), cron_jobs = cron_jobs.items.len(), elapsed = ?start.elapsed(), "jobs listed");
+    extract_burst_from_jobs(jobs, cron_jobs, resources)?;
+    Ok(())
+}
+
+#[instrument(skip(client, lister))]
+pub async fn collect_limit_ranges(
+    client: kube::Client,
+    lister: &mut Lister,
+    namespace: &Option<String>,
+) -> Result<Vec<LimitRange>, Error> {
+    let api_limit_ranges: Api<LimitRange> = if let Some(ns) = namespace {
+        Api::namespaced(client, ns)
+    } else {
+        Api::all(client)
+    };
+    let limit_ranges = lister
+        .list(&api_limit_ranges, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: "list limitranges".to_string(),
+            source,
+        })?;
+    debug!(
+        limit_ranges = limit_ranges.items.len(),
+        "limitranges listed"
+    );
+    Ok(limit_ranges.items)
+}
+
+#[instrument(skip(client, lister))]
+pub async fn collect_resource_quotas(
+    client: kube::Client,
+    lister: &mut Lister,
+    namespace: &Option<String>,
+) -> Result<Vec<ResourceQuota>, Error> {
+    let api_quotas: Api<ResourceQuota> = if let Some(ns) = namespace {
+        Api::namespaced(client, ns)
+    } else {
+        Api::all(client)
+    };
+    let quotas = lister
+        .list(&api_quotas, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: "list resourcequotas".to_string(),
+            source,
+        })?;
+    debug!(quotas = quotas.items.len(), "resourcequotas listed");
+    Ok(quotas.items)
+}
+
+/// The requested of a namespace compared to its quota (hard) and to the free of the cluster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaRow {
+    pub namespace: String,
+    pub kind: String,
+    pub requested: Qty,
+    /// the lowest hard limit of the quotas of the namespace
+    pub hard: Qty,
+    pub cluster_free: Option<Qty>,
+}
+
+impl QuotaRow {
+    pub fn quota_free(&self) -> Qty {
+        self.hard.checked_sub(&self.requested).unwrap_or_default()
+    }
+
+    /// what limits the next requests of the namespace: "quota" or "capacity" (of the cluster)
+    pub fn limited_by(&self) -> &'static str {
+        match &self.cluster_free {
+            Some(cluster_free) if cluster_free < &self.quota_free() => "capacity",
+            _ => "quota",
+        }
+    }
+}
+
+/// the kind of a resource limited by a key of `ResourceQuota.spec.hard` on requests
+/// (`requests.cpu`, `cpu`, `requests.nvidia.com/gpu`, `pods`), none for limits, counts of objects,...
+fn quota_kind(key: &str) -> Option<&str> {
+    if let Some(kind) = key.strip_prefix("requests.") {
+        return Some(kind);
+    }
+    match key {
+        "cpu" | "memory" | "ephemeral-storage" | "pods" => Some(key),
+        _ => None,
+    }
+}
+
+/// Join the quotas of the namespaces with the requested (by namespace & kind)
+/// and the free of the cluster (by kind), see `--compare-to-quota`
+pub fn make_quota_rows(
+    quotas: &[ResourceQuota],
+    sums: &ResourceSums,
+    headroom_pct: f64,
+) -> Vec<QuotaRow> {
+    let mut hards: BTreeMap<(String, String), Qty> = BTreeMap::new();
+    for quota in quotas {
+        let namespace = quota.metadata.namespace.clone().unwrap_or_default();
+        for (key, value) in quota
+            .spec
+            .iter()
+            .flat_map(|spec| spec.hard.iter().flatten())
+        {
+            let kind = match quota_kind(key) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let hard = match Qty::parse_for_kind(&value.0, kind) {
+                Ok(hard) => hard,
+                Err(err) => {
+                    warn!(namespace, key, ?err, "skip unreadable quota");
+                    continue;
+                }
+            };
+            let entry = hards
+                .entry((namespace.clone(), kind.to_string()))
+                .or_insert_with(|| hard.clone());
+            *entry = std::cmp::min(entry.clone(), hard);
+        }
+    }
+    let requested: BTreeMap<(String, String), Qty> = sums
+        .make_qualifiers(&[GroupBy::namespace, GroupBy::resource], &[])
+        .into_iter()
+        .filter_map(|(k, oqtys)| match k.as_slice() {
+            [namespace, kind] => oqtys
+                .and_then(|qtys| qtys.requested)
+                .map(|requested| ((namespace.clone(), kind.clone()), requested)),
+            _ => None,
+        })
+        .collect();
+    let mut by_kind = sums.make_qualifiers(&[GroupBy::resource], &[]);
+    apply_free_headroom(&mut by_kind, headroom_pct);
+    let cluster_free: BTreeMap<String, Qty> = by_kind
+        .into_iter()
+        .filter_map(|(k, oqtys)| {
+            let qtys = oqtys?;
+            // nothing requested, everything is free
+            let free = QtyByQualifier {
+                requested: qtys.requested.clone().or_else(|| Some(Qty::default())),
+                ..qtys
+            }
+            .calc_free()?;
+            Some((k[0].clone(), free))
+        })
+        .collect();
+    hards
+        .into_iter()
+        .map(|((namespace, kind), hard)| QuotaRow {
+            requested: requested
+                .get(&(namespace.clone(), kind.clone()))
+                .cloned()
+                .unwrap_or_default(),
+            cluster_free: cluster_free.get(&kind).cloned(),
+            namespace,
+            kind,
+            hard,
+        })
+        .collect()
+}
+
+/// annotation set by the LimitRanger admission plugin on the pods it set defaults to,
+/// eg `LimitRanger plugin set: cpu, memory request for container app`
+pub const LIMIT_RANGER_ANNOTATION: &str = "kubernetes.io/limit-ranger";
+
+/// explain (by namespace) the requests set by the defaults of the LimitRanges
+/// on the containers without explicit requests
+pub fn explain_limit_ranges(limit_ranges: &[LimitRange], resources: &[Resource]) -> Vec<String> {
+    let mut defaulted_pods: BTreeMap<String, usize> = BTreeMap::new();
+    for r in resources
+        .iter()
+        .filter(|r| r.kind == "pods" && matches!(r.qualifier, ResourceQualifier::Requested))
+    {
+        let defaulted = r
+            .location
+            .pod_annotations
+            .get(LIMIT_RANGER_ANNOTATION)
+            .map(|v| v.contains("request"))
+            .unwrap_or(false);
+        if defaulted {
+            *defaulted_pods
+                .entry(r.location.namespace.clone().unwrap_or_default())
+                .or_default() += 1;
+        }
+    }
+    let mut lines = vec![];
+    for limit_range in limit_ranges {
+        let namespace = limit_range.metadata.namespace.clone().unwrap_or_default();
+        let defaults = limit_range
+            .spec
+            .iter()
+            .flat_map(|spec| spec.limits.iter())
+            .filter(|item| item.type_ == "Container")
+            .flat_map(|item| item.default_request.iter().flatten())
+            .map(|(kind, qty)| format!("{}={}", kind, qty.0))
+            .collect::<Vec<_>>();
+        if defaults.is_empty() {
+            continue;
+        }
+        lines.push(format!(
+            "{}/{}: default requests {} (set on {} pods)",
+            namespace,
+            limit_range.metadata.name.clone().unwrap_or_default(),
+            defaults.join(", "),
+            defaulted_pods.remove(&namespace).unwrap_or_default(),
+        ));
+    }
+    // the LimitRange could have been deleted since (or not readable)
+    for (namespace, count) in defaulted_pods {
+        lines.push(format!(
+            "{}: requests set by a LimitRange on {} pods",
+            namespace, count
+        ));
+    }
+    lines
+}
+
+/// Estimate the peak of requests of the pods not yet created by jobs and cronjobs
+/// (parallelism x requests of the pod template), pushed as `Requested` of a pseudo pod
+/// `job/<name>` or `cronjob/<name>` (without node).
+/// For a job, the running (active) pods are already collected with pods, only the remaining are counted.
+pub fn extract_burst_from_jobs(
+    jobs: ObjectList<Job>,
+    cron_jobs: ObjectList<CronJob>,
+    resources: &mut Vec<Resource>,
+) -> Result<(), Error> {
+    for job in jobs.items {
+        let status = job.status.unwrap_or_default();
+        // a failed job (eg backoffLimit or activeDeadlineSeconds reached) creates no more pods
+        let finished = status
+            .conditions
+            .iter()
+            .flatten()
+            .any(|c| (c.type_ == "Complete" || c.type_ == "Failed") && c.status == "True");
+        if status.completion_time.is_some() || finished {
+            continue;
+        }
+        if let Some(spec) = job.spec {
+            if spec.suspend == Some(true) {
+                continue;
+            }
+            let remaining = spec
+                .completions
+                .map(|c| (c - status.succeeded.unwrap_or(0)).max(0))
+                .unwrap_or(i32::MAX);
+            let pods = spec.parallelism.unwrap_or(1).min(remaining) - status.active.unwrap_or(0);
+            let location = Location {
+                namespace: job.metadata.namespace.clone(),
+                pod_name: job.metadata.name.map(|n| format!("job/{}", n)),
+                ..Location::default()
+            };
+            push_burst(resources, &location, &spec, pods)?;
+        }
+    }
+    for cron_job in cron_jobs.items {
+        if let Some(spec) = cron_job.spec {
+            if spec.suspend == Some(true) {
+                continue;
+            }
+            if let Some(job_spec) = spec.job_template.spec {
+                let pods = job_spec
+                    .parallelism
+                    .unwrap_or(1)
+                    .min(job_spec.completions.unwrap_or(i32::MAX));
+                let location = Location {
+                    namespace: cron_job.metadata.namespace.clone(),
+                    pod_name: cron_job.metadata.name.map(|n| format!("cronjob/{}", n)),
+                    ..Location::default()
+                };
+                push_burst(resources, &location, &job_spec, pods)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn push_burst(
+    resources: &mut Vec<Resource>,
+    location: &Location,
+    job_spec: &JobSpec,
+    pods: i32,
+) -> Result<(), Error> {
+    if pods <= 0 {
+        return Ok(());
+    }
+    let mut requests = job_spec
+        .template
+        .spec
+        .as_ref()
+        .map(effective_requests_of)
+        .transpose()?
+        .unwrap_or_default();
+    requests.insert("pods".to_string(), Qty::parse_for_kind("1", "pods")?);
+    let requests = requests
+        .into_iter()
+        .map(|(kind, qty)| (kind, &qty * i64::from(pods)))
+        .collect();
+    push_resources(resources, location, ResourceQualifier::Requested, &requests);
+    Ok(())
+}
+
+/// the effective requests of a pod: max(sum of containers, max of init containers) + overhead,
+/// the native sidecars are counted with the containers & the init containers started after them
+/// (see `hoist_sidecars`)
+/// see https://kubernetes.io/docs/concepts/workloads/pods/init-containers/#resources
+pub fn effective_requests_of(spec: &PodSpec) -> Result<BTreeMap<String, Qty>, Error> {
+    let mut requests = BTreeMap::new();
+    for r in spec
+        .containers
+        .iter()
+        .filter_map(|c| c.resources.as_ref().and_then(|r| r.requests.as_ref()))
+    {
+        process_resources(&mut requests, r, std::ops::Add::add)?;
+    }
+    for r in spec
+        .init_containers
+        .iter()
+        .flatten()
+        .filter_map(|c| c.resources.as_ref().and_then(|r| r.requests.as_ref()))
+    {
+        process_resources(&mut requests, r, std::cmp::max)?;
+    }
+    if let Some(overhead) = &spec.overhead {
+        process_resources(&mut requests, overhead, std::ops::Add::add)?;
+    }
+    Ok(requests)
+}
+
+/// the label set by the controller of the deployments on its replicasets & their pods
+const POD_TEMPLATE_HASH_LABEL: &str = "pod-template-hash";
+
+/// The top-level owner of a pod as `<Kind>/<name>` (eg `Deployment/web`), from its controller: the replicaset of a
+/// deployment is folded into the deployment (the name of the replicaset is the one of the deployment suffixed by
+/// the `pod-template-hash`, no need to list the replicasets), `None` for a pod without controller
+pub fn workload_of(metadata: &ObjectMeta) -> Option<String> {
+    let owner = metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .find(|o| o.controller == Some(true))?;
+    let hash = metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(POD_TEMPLATE_HASH_LABEL));
+    match (owner.kind.as_str(), hash) {
+        ("ReplicaSet", Some(hash)) => match owner.name.strip_suffix(&format!("-{}", hash)) {
+            Some(deployment) => Some(format!("Deployment/{}", deployment)),
+            None => Some(format!("ReplicaSet/{}", owner.name)),
+        },
+        (kind, _) => Some(format!("{}/{}", kind, owner.name)),
+    }
+}
+
+/// the unreadable quantities of the requests, limits & overhead of the pod
+fn unreadable_qtys_of(location: &Location, spec: &PodSpec) -> Vec<Skipped> {
+    let containers = spec
+        .containers
+        .iter()
+        .chain(spec.init_containers.iter().flatten())
+        .map(|c| {
+            (
+                Some(c.name.clone()),
+                c.resources.clone().unwrap_or_default(),
+            )
+        });
+    let overhead = spec.overhead.iter().map(|o| {
+        (
+            Some(OVERHEAD_CONTAINER.to_string()),
+            ResourceRequirements {
+                requests: Some(o.clone()),
+                ..ResourceRequirements::default()
+            },
+        )
+    });
+    let mut skipped = vec![];
+    for (container_name, requirements) in containers.chain(overhead) {
+        let location = Location {
+            container_name,
+            ..location.clone()
+        };
+        for (qualifier, qtys) in [
+            (ResourceQualifier::Requested, requirements.requests),
+            (ResourceQualifier::Limit, requirements.limits),
+        ] {
+            for (kind, value) in qtys.unwrap_or_default().iter() {
+                if let Err(err) = Qty::parse_for_kind(&value.0, kind) {
+                    skipped.push(Skipped::new(&location, &qualifier, kind, &value.0, err));
+                }
+            }
+        }
+    }
+    skipped
+}
+
+#[instrument(skip(pod_list, resources))]
+pub async fn extract_allocatable_from_pods(
+    pod_list: ObjectList<Pod>,
+    resources: &mut Vec<Resource>,
+) -> Result<Vec<Skipped>, Error> {
+    let resources_before = resources.len();
+    let mut skipped = vec![];
+    let node_labels = extract_node_labels(resources);
+    let node_states = extract_node_states(resources);
+    let pods_count = pod_list.items.len();
+    let scheduled_pods = pod_list
+        .items
+        .into_iter()
+        .filter(is_scheduled)
+        .collect::<Vec<_>>();
+    debug!(
+        scheduled = scheduled_pods.len(),
+        skipped = pods_count - scheduled_pods.len(),
+        "pods filtered on phase"
+    );
+    for pod in scheduled_pods {
+        let spec = pod.spec.as_ref();
+        let node_name = spec.and_then(|s| s.node_name.clone());
+        let metadata = &pod.metadata;
+        let location = Location {
+            node_name: node_name.clone(),
+            namespace: metadata.namespace.clone(),
+            pod_name: metadata.name.clone(),
+            qos_class: spec.map(qos_class_of),
+            node_labels: node_name
+                .as_ref()
+                .and_then(|n| node_labels.get(n))
+                .cloned()
+                .unwrap_or_default(),
+            pod_annotations: Arc::new(metadata.annotations.clone().unwrap_or_default()),
+            priority: spec.and_then(|s| s.priority),
+            node_state: node_name.as_ref().and_then(|n| node_states.get(n)).copied(),
+            workload: workload_of(metadata),
+            ..Location::default()
+        };
+        // skip the whole pod, a partial pod would mislead the sums
+        let unreadable = spec
+            .map(|s| unreadable_qtys_of(&location, s))
+            .unwrap_or_default();
+        if !unreadable.is_empty() {
+            skipped.extend(unreadable);
+            continue;
+        }
+        // compute the effective resource qualifier
+        // see https://kubernetes.io/docs/concepts/workloads/pods/init-containers/#resources
+        // resources are pushed by container, the part of the effective requests (and limits)
+        // due to init containers and overhead are pushed as pseudo containers "(init)" & "(overhead)"
+        // so the sum over containers is always the effective requests of the pod
+        let mut resource_requests: BTreeMap<String, Qty> = BTreeMap::new();
+        let mut resource_limits: BTreeMap<String, Qty> = BTreeMap::new();
+        // handle regular containers
+        let containers = spec.map(|s| s.containers.clone()).unwrap_or_default();
+        for container in containers.into_iter() {
+            let container_location = Location {
+                container_name: Some(container.name.clone()),
+                ..location.clone()
+            };
+            // a single (optional) block of requirements by container, none: nothing requested nor limited
+            if let Some(requirements) = container.resources {
+                push_no_limits(resources, &container_location, &requirements);
+                warn_hugepages_mismatches(&container_location, &requirements);
+                if let Some(r) = requirements.requests {
+                    let mut container_requests = BTreeMap::new();
+                    process_resources(&mut container_requests, &r, std::ops::Add::add)?;
+                    process_resources(&mut resource_requests, &r, std::ops::Add::add)?;
+                    push_resources(
+                        resources,
+                        &container_location,
+                        ResourceQualifier::Requested,
+                        &container_requests,
+                    );
+                }
+                if let Some(r) = requirements.limits {
+                    let mut container_limits = BTreeMap::new();
+                    process_resources(&mut container_limits, &r, std::ops::Add::add)?;
+                    process_resources(&mut resource_limits, &r, std::ops::Add::add)?;
+                    push_resources(
+                        resources,
+                        &container_location,
+                        ResourceQualifier::Limit,
+                        &container_limits,
+                    );
+                }
+            }
+        }
+        // handle initContainers
+        let init_location = Location {
+            container_name: Some(INIT_CONTAINER.to_string()),
+            ..location.clone()
+        };
+        let mut effective_requests = resource_requests.clone();
+        let mut effective_limits = resource_limits.clone();
+        let init_containers = spec
+            .and_then(|s| s.init_containers.clone())
+            .unwrap_or_default();
+        for container in init_containers.into_iter() {
+            if let Some(requirements) = container.resources {
+                push_no_limits(resources, &init_location, &requirements);
+                warn_hugepages_mismatches(&init_location, &requirements);
+                if let Some(r) = requirements.requests {
+                    process_resources(&mut effective_requests, &r, std::cmp::max)?;
+                }
+                if let Some(r) = requirements.limits {
+                    process_resources(&mut effective_limits, &r, std::cmp::max)?;
+                }
+            }
+        }
+        push_resources(
+            resources,
+            &init_location,
+            ResourceQualifier::Requested,
+            &excess_of(&effective_requests, &resource_requests),
+        );
+        push_resources(
+            resources,
+            &init_location,
+            ResourceQualifier::Limit,
+            &excess_of(&effective_limits, &resource_limits),
+        );
+        // handler overhead (add to both requests and limits)
+        if let Some(ref overhead) = spec.and_then(|s| s.overhead.clone()) {
+            let overhead_location = Location {
+                container_name: Some(OVERHEAD_CONTAINER.to_string()),
+                ..location.clone()
+            };
+            let mut overhead_qtys = BTreeMap::new();
+            process_resources(&mut overhead_qtys, overhead, std::ops::Add::add)?;
+            push_resources(
+                resources,
+                &overhead_location,
+                ResourceQualifier::Requested,
+                &overhead_qtys,
+            );
+            push_resources(
+                resources,
+                &overhead_location,
+                ResourceQualifier::Limit,
+                &overhead_qtys,
+            );
+        }
+        // add a "pods" resource as well
+        for qualifier in [ResourceQualifier::Requested, ResourceQualifier::Limit] {
+            resources.push(Resource {
+                kind: "pods".to_string(),
+                qualifier,
+                quantity: Qty::parse_for_kind("1", "pods")?,
+                location: location.clone(),
+            });
+        }
+    }
+    debug!(
+        resources = resources.len() - resources_before,
+        "requests and limits extracted from pods"
+    );
+    Ok(skipped)
+}
+
+/// label of nodes used to merge similar nodes (see `--merge-similar-nodes`)
+pub const INSTANCE_TYPE_LABEL: &str = "node.kubernetes.io/instance-type";
+
+/// labels of nodes (by node's name) from the resources collected on nodes
+pub fn extract_node_labels(
+    resources: &[Resource],
+) -> std::collections::HashMap<String, Arc<BTreeMap<String, String>>> {
+    resources
+        .iter()
+        .filter(|resource| resource.location.pod_name.is_none())
+        .filter_map(|resource| {
+            let loc = &resource.location;
+            loc.node_name
+                .as_ref()
+                .map(|n| (n.clone(), loc.node_labels.clone()))
+        })
+        .collect()
+}
+
+/// states of nodes (by node's name) from the resources collected on nodes
+pub fn extract_node_states(resources: &[Resource]) -> HashMap<String, NodeState> {
+    resources
+        .iter()
+        .filter(|resource| resource.location.pod_name.is_none())
+        .filter_map(|resource| {
+            let loc = &resource.location;
+            Some((loc.node_name.clone()?, loc.node_state?))
+        })
+        .collect()
+}
+
+pub fn extract_locations(
+    resources: &[Resource],
+) -> std::collections::HashMap<(String, String), Location> {
+    resources
+        .iter()
+        .filter_map(|resource| {
+            let loc = &resource.location;
+            loc.pod_name.as_ref().map(|n| {
+                (
+                    (loc.namespace.clone().unwrap_or_default(), n.to_owned()),
+                    loc.clone(),
+                )
+            })
+        })
+        .collect()
+}
+
+//TODO need location of pods (aka node because its not part of metrics)
+#[instrument(skip(client, lister, resources))]
+pub async fn collect_from_metrics(
+    client: kube::Client,
+    lister: &mut Lister,
+    resources: &mut Vec<Resource>,
+    api_version: &str,
+) -> Result<Vec<Skipped>, Error> {
+    let start = std::time::Instant::now();
+    // untyped, the version of the Metrics API depends of the cluster
+    let (group, version) = api_version
+        .split_once('/')
+        .unwrap_or(("metrics.k8s.io", api_version));
+    let ar = ApiResource {
+        group: group.to_string(),
+        version: version.to_string(),
+        api_version: format!("{}/{}", group, version),
+        kind: "PodMetrics".to_string(),
+        plural: "pods".to_string(),
+    };
+    let api_pod_metrics: Api<DynamicObject> = Api::all_with(client, &ar);
+    let objects = lister
+        .list(&api_pod_metrics, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: format!(
+                "list podmetrics ({}), maybe Metrics API not available",
+                ar.api_version
+            ),
+            source,
+        })?;
+    let pod_metrics = ObjectList {
+        metadata: objects.metadata,
+        items: objects
+            .items
+            .into_iter()
+            .map(|object| serde_json::to_value(object).and_then(serde_json::from_value))
+            .collect::<Result<Vec<metrics::PodMetrics>, _>>()?,
+    };
+    debug!(pod_metrics = pod_metrics.items.len(), elapsed = ?start.elapsed(), "podmetrics listed");
+
+    extract_utilizations_from_pod_metrics(pod_metrics, resources).await
+}
+
+/// the group of the APIs of the dynamic resource allocation (DRA), see `--resource-claims`
+pub const RESOURCE_CLAIM_GROUP: &str = "resource.k8s.io";
+
+/// the allocated devices are in the status of the ResourceClaims since `v1alpha3` (kubernetes 1.31),
+/// the previous versions only provide opaque handles of the drivers
+pub fn supports_resource_claims(api_version: &str) -> bool {
+    !matches!(
+        api_version.rsplit('/').next(),
+        Some("v1alpha1") | Some("v1alpha2")
+    )
+}
+
+/// the group/version of the ResourceClaims preferred by the cluster, `None` if DRA is not served
+pub async fn resolve_resource_claims_api_version(client: &kube::Client) -> Option<String> {
+    match client.list_api_groups().await {
+        Ok(groups) => preferred_group_version(&groups, RESOURCE_CLAIM_GROUP),
+        Err(err) => {
+            debug!(?err, "failed to discover the api groups");
+            None
+        }
+    }
+}
+
+#[instrument(skip(client, lister, resources))]
+pub async fn collect_from_resource_claims(
+    client: kube::Client,
+    lister: &mut Lister,
+    resources: &mut Vec<Resource>,
+    namespace: &Option<String>,
+    api_version: &str,
+) -> Result<(), Error> {
+    let (group, version) = api_version
+        .split_once('/')
+        .unwrap_or((RESOURCE_CLAIM_GROUP, api_version));
+    let ar = ApiResource {
+        group: group.to_string(),
+        version: version.to_string(),
+        api_version: api_version.to_string(),
+        kind: "ResourceClaim".to_string(),
+        plural: "resourceclaims".to_string(),
+    };
+    let api: Api<DynamicObject> = match namespace {
+        Some(ns) => Api::namespaced_with(client, ns, &ar),
+        None => Api::all_with(client, &ar),
+    };
+    let claims = lister
+        .list(&api, &ListParams::default())
+        .await
+        .map_err(|source| Error::KubeError {
+            context: format!("list resourceclaims ({})", api_version),
+            source,
+        })?;
+    debug!(claims = claims.items.len(), "resourceclaims listed");
+    let claims = claims
+        .items
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    extract_from_resource_claims(&claims, resources);
+    Ok(())
+}
+
+/// Add the devices allocated to the ResourceClaims (including the ones generated from ResourceClaimTemplates)
+/// as requested & limit of the pod reserving the claim, by driver (eg `gpu.nvidia.com`), on the pseudo container "(claims)".
+/// A claim shared by several pods is counted once (for the first pod), the claims not allocated or
+/// reserved for a pod not collected (eg filtered out) are ignored. Returns the number of devices added.
+pub fn extract_from_resource_claims(
+    claims: &[serde_json::Value],
+    resources: &mut Vec<Resource>,
+) -> usize {
+    let locations = extract_locations(resources);
+    let mut added = 0;
+    for claim in claims {
+        let namespace = claim
+            .pointer("/metadata/namespace")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        let pod = claim
+            .pointer("/status/reservedFor")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter(|consumer| consumer.get("resource").and_then(|v| v.as_str()) == Some("pods"))
+            .find_map(|consumer| consumer.get("name").and_then(|v| v.as_str()));
+        let location = match pod
+            .and_then(|pod| locations.get(&(namespace.to_string(), pod.to_string())))
+        {
+            Some(location) => Location {
+                container_name: Some(CLAIMS_CONTAINER.to_string()),
+                ..location.clone()
+            },
+            None => {
+                debug!(claim = ?claim.pointer("/metadata/name"), "claim not reserved for a collected pod");
+                continue;
+            }
+        };
+        let mut devices: BTreeMap<String, i64> = BTreeMap::new();
+        for result in claim
+            .pointer("/status/allocation/devices/results")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+        {
+            if let Some(driver) = result.get("driver").and_then(|v| v.as_str()) {
+                *devices.entry(driver.to_string()).or_default() += 1;
+            }
+        }
+        for (kind, count) in devices {
+            let quantity = Qty::from_base_value(i128::from(count), UnitFamily::Count);
+            // a device is allocated exclusively: the limit is the request
+            for qualifier in [ResourceQualifier::Requested, ResourceQualifier::Limit] {
+                resources.push(Resource {
+                    kind: kind.clone(),
+                    quantity: quantity.clone(),
+                    location: location.clone(),
+                    qualifier,
+                });
+            }
+            added += count as usize;
+        }
+    }
+    added
+}
+
+/// the group/version of the Metrics API used when not set and not discovered
+pub const DEFAULT_METRICS_API_VERSION: &str = "metrics.k8s.io/v1beta1";
+
+/// the preferred version (`group/version`) of the `group` served by the API server, else its first version
+pub fn preferred_group_version(
+    groups: &k8s_openapi::apimachinery::pkg::apis::meta::v1::APIGroupList,
+    group: &str,
+) -> Option<String> {
+    let api_group = groups.groups.iter().find(|g| g.name == group)?;
+    api_group
+        .preferred_version
+        .as_ref()
+        .or_else(|| api_group.versions.first())
+        .map(|v| v.group_version.clone())
+}
+
+/// the group/version of the Metrics API: the one of `--metrics-api-version`, else the preferred one served
+/// (discovery), else `DEFAULT_METRICS_API_VERSION`
+pub async fn resolve_metrics_api_version(client: &kube::Client, cli_opts: &CliOpts) -> String {
+    if let Some(api_version) = &cli_opts.metrics_api_version {
+        return api_version.clone();
+    }
+    match client.list_api_groups().await {
+        Ok(groups) => preferred_group_version(&groups, "metrics.k8s.io").unwrap_or_else(|| {
+            debug!("metrics.k8s.io not discovered");
+            DEFAULT_METRICS_API_VERSION.to_string()
+        }),
+        Err(err) => {
+            debug!(?err, "failed to discover the api groups");
+            DEFAULT_METRICS_API_VERSION.to_string()
+        }
+    }
+}
+
+#[instrument(skip(pod_metrics, resources))]
+pub async fn extract_utilizations_from_pod_metrics(
+    pod_metrics: ObjectList<metrics::PodMetrics>,
+    resources: &mut Vec<Resource>,
+) -> Result<Vec<Skipped>, Error> {
+    let mut skipped = vec![];
+    let cpu_kind = "cpu";
+    let memory_kind = "memory";
+    let locations = extract_locations(resources);
+    let mut without_location = 0;
+    // the pods without metrics (eg just started) keep their requests, without utilization (not 0)
+    let with_metrics = pod_metrics
+        .items
+        .iter()
+        .map(|m| {
+            (
+                m.metadata.namespace.clone().unwrap_or_default(),
+                m.metadata.name.clone().unwrap_or_default(),
+            )
+        })
+        .collect::<std::collections::HashSet<_>>();
+    let without_metrics = locations
+        .keys()
+        .filter(|key| !with_metrics.contains(*key))
+        .count();
+    for pod_metric in pod_metrics.items {
+        let metadata = &pod_metric.metadata;
+        let key = (
+            metadata.namespace.clone().unwrap_or_default(),
+            metadata.name.clone().unwrap_or_default(),
+        );
+        if !locations.contains_key(&key) {
+            without_location += 1;
+        }
+        let location = locations.get(&key).cloned().unwrap_or_else(|| Location {
+            // node_name: node_name.clone(),
+            namespace: metadata.namespace.clone(),
+            pod_name: metadata.name.clone(),
+            ..Location::default()
+        });
+        for container in pod_metric.containers.into_iter() {
+            let location = Location {
+                container_name: Some(container.name.clone()),
+                ..location.clone()
+            };
+            let parse = |input: &str, kind: &str| {
+                Qty::parse_for_kind(input, kind)
+                    .map(|qty| qty.max(Qty::lowest_positive()))
+                    .map_err(|err| {
+                        Skipped::new(&location, &ResourceQualifier::Utilization, kind, input, err)
+                    })
+            };
+            let (cpu_utilization, memory_utilization) = match (
+                parse(&container.usage.cpu, cpu_kind),
+                parse(&container.usage.memory, memory_kind),
+            ) {
+                (Ok(cpu), Ok(memory)) => (cpu, memory),
+                (cpu, memory) => {
+                    skipped.extend(cpu.err());
+                    skipped.extend(memory.err());
+                    continue;
+                }
+            };
+            resources.push(Resource {
+                kind: cpu_kind.to_string(),
+                qualifier: ResourceQualifier::Utilization,
+                quantity: cpu_utilization,
+                location: location.clone(),
+            });
+            resources.push(Resource {
+                kind: memory_kind.to_string(),
+                qualifier: ResourceQualifier::Utilization,
+                quantity: memory_utilization,
+                location,
+            });
+        }
+    }
+    debug!(
+        without_location,
+        without_metrics,
+        "utilizations extracted from podmetrics (pods without location are not on a known node)"
+    );
+    Ok(skipped)
+}
+
+/// Collect from the `context` (see `collect`), the resources are tagged with the context
+/// and the pods under `--for-priority` are removed (the priority classes are by cluster)
+pub(crate) async fn collect_context(
+    cli_opts: &CliOpts,
+    context: &str,
+) -> Result<(ClusterInfo, Collected), Error> {
+    let cli_opts = CliOpts {
+        context: Some(context.to_string()),
+        ..cli_opts.clone()
+    };
+    let (client, cluster) = new_client_with_info(&cli_opts).await?;
+    let mut lister = Lister {
+        chunk_size: cli_opts.chunk_size,
+        limiter: RateLimiter::new(cli_opts.qps, cli_opts.burst),
+    };
+    let mut collected = collect(client.clone(), &mut lister, &cli_opts).await?;
+    if let Some(class) = &cli_opts.for_priority {
+        let min_priority = resolve_priority(client, class).await?;
+        retain_for_priority(&mut collected.resources, min_priority);
+    }
+    collected.tag_cluster(context);
+    Ok((cluster, collected))
+}
+
+/// Collect the resources of the cluster (nodes, pods and the optional ones requested by the cli),
+/// the failures of the optional collects are recorded into the report
+pub async fn collect(
+    client: kube::Client,
+    lister: &mut Lister,
+    cli_opts: &CliOpts,
+) -> Result<Collected, Error> {
+    let mut burst: Vec<Resource> = vec![];
+    let mut resources: Vec<Resource> = vec![];
+    let mut report = CollectReport::default();
+    let fleet = if cli_opts.collects_nodes() {
+        let (skipped, fleet) = collect_from_nodes(client.clone(), lister, &mut resources).await?;
+        report.skipped.extend(skipped);
+        apply_node_allocatable_overrides(&mut resources, &cli_opts.node_allocatable_override)?;
+        apply_reserve_for_system(&mut resources, &cli_opts.reserve_for_system);
+        fleet
+    } else {
+        FleetSummary::default()
+    };
+    let allocated_resources = cli_opts.collects_pods()
+        && cli_opts.allocated_resources
+        && match client.apiserver_version().await {
+            Ok(info) if supports_allocated_resources(&info) => true,
+            Ok(info) => {
+                warn!(
+                    version = info.git_version,
+                    "--allocated-resources requires kubernetes >= 1.27, the requests of the spec are used"
+                );
+                false
+            }
+            Err(err) => {
+                warn!(?err, "failed to get the version of the API server, the requests of the spec are used");
+                false
+            }
+        };
+    let findings = if cli_opts.collects_pods() {
+        collect_from_pods(
+            client.clone(),
+            lister,
+            &mut resources,
+            &cli_opts.namespace,
+            allocated_resources,
+            cli_opts.no_requests.as_deref().unwrap_or_default(),
+        )
+        .await?
+    } else {
+        PodsFindings::default()
+    };
+    report.skipped.extend(findings.skipped);
+    let extra_allocatable_from = if cli_opts.collects_nodes() {
+        &cli_opts.extra_allocatable_from[..]
+    } else {
+        &[]
+    };
+    for extra in extra_allocatable_from {
+        match collect_extra_allocatable(client.clone(), lister, extra, &mut resources).await {
+            Ok(skipped) => report.skipped.extend(skipped),
+            Err(err) => report.record_error(&extra.url_path(), &err),
+        }
+    }
+
+    if cli_opts.resource_claims && cli_opts.collects_pods() {
+        match resolve_resource_claims_api_version(&client).await {
+            Some(api_version) if supports_resource_claims(&api_version) => {
+                if let Err(err) = collect_from_resource_claims(
+                    client.clone(),
+                    lister,
+                    &mut resources,
+                    &cli_opts.namespace,
+                    &api_version,
+                )
+                .await
+                {
+                    report.record_error("resourceclaims", &err);
+                }
+            }
+            Some(api_version) => warn!(
+                api_version,
+                "the allocated devices are not provided by this version of the ResourceClaims, they are ignored"
+            ),
+            None => warn!(
+                "{} (dynamic resource allocation) is not served by the cluster, the resource claims are ignored",
+                RESOURCE_CLAIM_GROUP
+            ),
+        }
+    }
+
+    let show_utilization = if cli_opts.utilization && cli_opts.collects_pods() {
+        let api_version = resolve_metrics_api_version(&client, cli_opts).await;
+        match collect_from_metrics(client.clone(), lister, &mut resources, &api_version).await {
+            Ok(skipped) => {
+                report.skipped.extend(skipped);
+                true
+            }
+            Err(err) => {
+                report.record_error("utilization (podmetrics)", &err);
+                false
+            }
+        }
+    } else {
+        false
+    };
+
+    if cli_opts.include_jobs && cli_opts.collects_pods() {
+        if let Err(err) =
+            collect_from_jobs(client.clone(), lister, &mut burst, &cli_opts.namespace).await
+        {
+            report.record_error("jobs & cronjobs", &err);
+        }
+    }
+    let limit_ranges = if cli_opts.limit_ranges {
+        collect_limit_ranges(client.clone(), lister, &cli_opts.namespace)
+            .await
+            .unwrap_or_else(|err| {
+                report.record_error("limitranges", &err);
+                vec![]
+            })
+    } else {
+        vec![]
+    };
+    let quotas = if cli_opts.compare_to_quota {
+        collect_resource_quotas(client.clone(), lister, &cli_opts.namespace)
+            .await
+            .unwrap_or_else(|err| {
+                report.record_error("resourcequotas", &err);
+                vec![]
+            })
+    } else {
+        vec![]
+    };
+    Ok(Collected {
+        resources,
+        burst,
+        show_utilization,
+        limit_ranges,
+        quotas,
+        fleet,
+        without_requests: findings.without_requests,
+        blocked: findings.blocked,
+        resizing: findings.resizing,
+        crashlooping: findings.crashlooping,
+        report,
+    })
+}
+
+/// The resources collected from the cluster, before filtering and grouping
+#[derive(Debug, Clone, Default)]
+pub struct Collected {
+    pub resources: Vec<Resource>,
+    /// estimated requests of the pods not yet created by jobs & cronjobs (see `--include-jobs`)
+    pub burst: Vec<Resource>,
+    pub show_utilization: bool,
+    /// the LimitRanges, to explain the requests set by their defaults (see `--limit-ranges`)
+    pub limit_ranges: Vec<LimitRange>,
+    /// the ResourceQuotas of the namespaces (see `--compare-to-quota`)
+    pub quotas: Vec<ResourceQuota>,
+    /// the counts of the nodes (see `--node-count`)
+    pub fleet: FleetSummary,
+    /// the containers without requests (see `--no-requests`)
+    pub without_requests: Vec<ContainerWithoutRequests>,
+    /// the pending pods blocked by a lack of resources (see `--pending-pods`)
+    pub blocked: Vec<BlockedPod>,
+    /// the pods with an in-place resize not completed (see `--allocated-resources`)
+    pub resizing: Vec<ResizingPod>,
+    /// the pods in `CrashLoopBackOff` holding requests (see `--crashloop-waste`)
+    pub crashlooping: Vec<CrashLoopPod>,
+    /// what was not collected (see `CollectReport::summary`)
+    pub report: CollectReport,
+}
+
+impl Collected {
+    /// set the `cluster` of the locations, and prefix the failed requests of the report by it
+    pub fn tag_cluster(&mut self, cluster: &str) {
+        let locations = self
+            .resources
+            .iter_mut()
+            .chain(self.burst.iter_mut())
+            .map(|r| &mut r.location)
+            .chain(self.report.skipped.iter_mut().map(|s| &mut s.location));
+        for location in locations {
+            location.cluster = Some(cluster.to_string());
+        }
+        for what in self
+            .report
+            .denied
+            .iter_mut()
+            .chain(self.report.failed.iter_mut())
+        {
+            *what = format!("{}: {}", cluster, what);
+        }
+    }
+
+    /// add the collected of another cluster (see `--batch`)
+    pub fn merge(&mut self, other: Collected) {
+        self.resources.extend(other.resources);
+        self.burst.extend(other.burst);
+        self.show_utilization |= other.show_utilization;
+        self.limit_ranges.extend(other.limit_ranges);
+        self.quotas.extend(other.quotas);
+        self.fleet.nodes += other.fleet.nodes;
+        self.fleet.ready += other.fleet.ready;
+        self.fleet.schedulable += other.fleet.schedulable;
+        self.without_requests.extend(other.without_requests);
+        self.blocked.extend(other.blocked);
+        self.resizing.extend(other.resizing);
+        self.crashlooping.extend(other.crashlooping);
+        self.report.skipped.extend(other.report.skipped);
+        self.report.denied.extend(other.report.denied);
+        self.report.failed.extend(other.report.failed);
+    }
+}
+
+/// A quantity not collected because it was unreadable
+#[derive(Debug, Clone)]
+pub struct Skipped {
+    pub location: Location,
+    pub qualifier: ResourceQualifier,
+    pub kind: String,
+    pub input: String,
+}
+
+impl Skipped {
+    /// log the skip as a warning (the details are only in the log)
+    pub fn new(
+        location: &Location,
+        qualifier: &ResourceQualifier,
+        kind: &str,
+        input: &str,
+        err: qty::Error,
+    ) -> Skipped {
+        warn!(
+            ?location,
+            ?qualifier,
+            kind,
+            input,
+            ?err,
+            "skip unreadable quantity"
+        );
+        Skipped {
+            location: location.clone(),
+            qualifier: qualifier.clone(),
+            kind: kind.to_string(),
+            input: input.to_string(),
+        }
+    }
+}
+
+/// What was not collected (unreadable quantities, denied or failed requests),
+/// so the completeness of the displayed numbers can be checked
+#[derive(Debug, Clone, Default)]
+pub struct CollectReport {
+    pub skipped: Vec<Skipped>,
+    /// the requests denied by the RBAC (403)
+    pub denied: Vec<String>,
+    /// the other failed (optional) requests
+    pub failed: Vec<String>,
+}
+
+impl CollectReport {
+    pub fn is_empty(&self) -> bool {
+        self.skipped.is_empty() && self.denied.is_empty() && self.failed.is_empty()
+    }
+
+    /// record the error of an optional collect (eg metrics, jobs) and log it
+    pub fn record_error(&mut self, what: &str, err: &Error) {
+        warn!(?err);
+        let denied = matches!(
+            err,
+            Error::KubeError {
+                source: kube::Error::Api(response),
+                ..
+            } if response.code == 403
+        );
+        if denied {
+            self.denied.push(what.to_string());
+        } else {
+            self.failed.push(what.to_string());
+        }
+    }
+
+    /// the lines to display after the output,
+    /// eg "skipped 3 resources on 2 nodes; see the warnings above"
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![];
+        let (on_nodes, on_pods): (Vec<_>, Vec<_>) = self
+            .skipped
+            .iter()
+            .partition(|s| s.location.pod_name.is_none());
+        if !on_nodes.is_empty() {
+            let nodes = on_nodes
+                .iter()
+                .map(|s| &s.location.node_name)
+                .unique()
+                .count();
+            lines.push(format!(
+                "skipped {} resources on {} nodes",
+                on_nodes.len(),
+                nodes
+            ));
+        }
+        if !on_pods.is_empty() {
+            let pods = on_pods
+                .iter()
+                .map(|s| (&s.location.namespace, &s.location.pod_name))
+                .unique()
+                .count();
+            lines.push(format!(
+                "skipped {} resources of {} pods",
+                on_pods.len(),
+                pods
+            ));
+        }
+        if !self.denied.is_empty() {
+            lines.push(format!("denied (403): {}", self.denied.join(", ")));
+        }
+        if !self.failed.is_empty() {
+            lines.push(format!("failed: {}", self.failed.join(", ")));
+        }
+        if let Some(last) = lines.last_mut() {
+            last.push_str("; see the warnings above");
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    // the clock is paused (advanced only by the sleeps), so the elapsed times are exact
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_allows_burst_then_throttles() {
+        let mut limiter = RateLimiter::new(100.0, 2);
+        let start = tokio::time::Instant::now();
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), std::time::Duration::ZERO);
+        limiter.acquire().await;
+        assert!(start.elapsed() >= std::time::Duration::from_millis(10));
+        assert!(start.elapsed() < std::time::Duration::from_millis(11));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_tiny_qps() {
+        // the wait overflows a `Duration`, waits "forever" without panic
+        let mut limiter = RateLimiter::new(1e-38, 1);
+        limiter.acquire().await;
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_secs(3600), limiter.acquire())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dedup_objects_across_pages() {
+        let cpu = |cpu: &str| serde_json::json!({ "cpu": cpu });
+        // n1 updated between the pages: listed on the first, then again (moved) on the second
+        let mut pages = vec![
+            (
+                vec![
+                    make_node("n1", serde_json::json!({}), cpu("4")),
+                    make_node("n2", serde_json::json!({}), cpu("2")),
+                ],
+                Some("page-2"),
+            ),
+            (
+                vec![
+                    make_node("n1", serde_json::json!({}), cpu("8")),
+                    make_node("n3", serde_json::json!({}), cpu("1")),
+                ],
+                None,
+            ),
+        ]
+        .into_iter();
+        let mut tokens = vec![];
+        let mut lister = Lister {
+            chunk_size: 2,
+            limiter: RateLimiter::new(0.0, 1),
+        };
+        let nodes = lister
+            .list_pages(&ListParams::default(), |lp| {
+                tokens.push(lp.continue_token.clone());
+                let (items, next) = pages.next().expect("a page after the last");
+                let mut page = make_node_list(items);
+                page.metadata.continue_ = next.map(|token| token.to_string());
+                async move { Ok(page) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(tokens, vec![None, Some("page-2".to_string())]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::node],
+            &["cpu".to_string()],
+        );
+        assert_eq!(keys(&res), vec!["cpu", "cpu/n1", "cpu/n2", "cpu/n3"]);
+        let allocatable_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.as_ref())
+                .and_then(|q| q.allocatable.as_ref())
+                .map(|q| q.to_canonical_string())
+        };
+        assert_eq!(allocatable_of("cpu/n1"), Some("8".to_string()));
+        assert_eq!(allocatable_of("cpu"), Some("11".to_string()));
+
+        // the pods (other namespaces) are deduped too
+        let mut other = make_pod("p1", "n1", vec![]);
+        other.metadata.namespace = Some("other".to_string());
+        let (pods, duplicates) = dedup_objects(vec![
+            make_pod("p1", "n1", vec![]),
+            other,
+            make_pod("p1", "n1", vec![]),
+        ]);
+        assert_eq!((pods.len(), duplicates), (2, 1));
+    }
+
+    #[test]
+    fn test_find_containers_without_requests() {
+        let pods = vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("cpu", "1"), ("memory", "1Gi")], &[])],
+            ),
+            make_pod(
+                "p2",
+                "n1",
+                vec![
+                    make_named_container("a", &[("cpu", "1")], &[("memory", "1Gi")]),
+                    serde_json::json!({ "name": "b" }),
+                ],
+            ),
+        ];
+        let kinds = vec!["cpu".to_string(), "memory".to_string()];
+        let without_requests = find_containers_without_requests(&pods, &kinds);
+        assert_eq!(
+            no_requests_summary(&without_requests, &kinds),
+            vec![
+                "Containers without requests (cpu, memory): 2",
+                "  default/p2/a: memory",
+                "  default/p2/b: cpu, memory",
+            ]
+        );
+        assert_eq!(
+            no_requests_summary(&[], &kinds),
+            vec!["Containers without requests (cpu, memory): none"]
+        );
+        let cli_opts = CliOpts::parse_from(["test", "--no-requests"]);
+        assert_eq!(cli_opts.no_requests, Some(kinds));
+        let cli_opts = CliOpts::parse_from(["test", "--no-requests", "nvidia.com/gpu"]);
+        assert_eq!(
+            cli_opts.no_requests,
+            Some(vec!["nvidia.com/gpu".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_find_blocked_pods() {
+        assert_eq!(
+            insufficient_kinds(
+                "0/3 nodes are available: 1 Insufficient cpu, 2 Insufficient nvidia.com/gpu. preemption: 0/3 nodes are available: 3 No preemption victims found for incoming pod."
+            ),
+            vec!["cpu", "nvidia.com/gpu"]
+        );
+        assert!(
+            insufficient_kinds("0/3 nodes are available: 3 node(s) had untolerated taint")
+                .is_empty()
+        );
+
+        let pending = |name: &str, message: &str| -> Pod {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": name, "namespace": "default" },
+                "spec": { "containers": [] },
+                "status": {
+                    "phase": "Pending",
+                    "conditions": [{
+                        "type": "PodScheduled",
+                        "status": "False",
+                        "reason": "Unschedulable",
+                        "message": message,
+                    }],
+                },
+            }))
+            .unwrap()
+        };
+        let pods = vec![
+            pending("p1", "0/2 nodes are available: 2 Insufficient cpu."),
+            pending(
+                "p2",
+                "0/2 nodes are available: 1 Insufficient cpu, 1 Insufficient memory.",
+            ),
+            pending(
+                "p3",
+                "0/2 nodes are available: 2 node(s) didn't match Pod's node affinity.",
+            ),
+            make_pod("p4", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+        ];
+        let blocked = find_blocked_pods(&pods);
+        assert_eq!(
+            blocked.iter().map(|p| p.pod.as_str()).collect::<Vec<_>>(),
+            vec!["p1", "p2"]
+        );
+        let counts = count_blocked_by_kind(&blocked);
+        assert_eq!(counts.get("cpu"), Some(&2));
+        assert_eq!(counts.get("memory"), Some(&1));
+
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node],
+            pending: Some(counts),
+            ..DisplayOpts::default()
+        };
+        assert_eq!(opts.pending_of(&["cpu".to_string()]), Some(2));
+        assert_eq!(opts.pending_of(&["nvidia.com/gpu".to_string()]), Some(0));
+        assert_eq!(
+            opts.pending_of(&["cpu".to_string(), "n1".to_string()]),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_containers_sum_to_the_pod() {
+        let mut pod = make_pod(
+            "p1",
+            "n1",
+            vec![
+                make_named_container("app", &[("cpu", "1")], &[]),
+                make_named_container("sidecar", &[("cpu", "500m")], &[]),
+            ],
+        );
+        pod.spec.as_mut().unwrap().init_containers = Some(vec![serde_json::from_value(
+            make_named_container("setup", &[("cpu", "2")], &[]),
+        )
+        .unwrap()]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(vec![pod]), &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[
+                GroupBy::resource,
+                GroupBy::node,
+                GroupBy::pod,
+                GroupBy::container,
+            ],
+            &[],
+        );
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        let requested_of = |key: &str| requested_of(&res, key).map(|q| q.value);
+        assert_eq!(requested_of("cpu/n1/p1"), qty("2"));
+        assert_eq!(requested_of("cpu/n1/p1/app"), qty("1"));
+        assert_eq!(requested_of("cpu/n1/p1/sidecar"), qty("500m"));
+        assert_eq!(requested_of("cpu/n1/p1/(init)"), qty("500m"));
+        // "pods" is counted once by pod, and not displayed by pod or container
+        assert_eq!(requested_of("pods/n1"), qty("1"));
+        assert!(!res.iter().any(|(k, _)| k.len() > 2 && k[0] == "pods"));
+    }
+
+    #[tokio::test]
+    async fn test_native_sidecars() {
+        let make_pod = |name: &str, init_containers: Vec<serde_json::Value>, overhead| {
+            let value = serde_json::json!({
+                "metadata": { "name": name, "namespace": "default" },
+                "spec": {
+                    "nodeName": "n1",
+                    "containers": [make_named_container("app", &[("cpu", "1")], &[])],
+                    "initContainers": init_containers,
+                    "overhead": overhead,
+                },
+                "status": { "phase": "Running" },
+            });
+            let mut pod: Pod = serde_json::from_value(value.clone()).unwrap();
+            (hoist_sidecars(&mut pod, &value), pod)
+        };
+        let sidecar = |cpu| {
+            let mut c = make_named_container("proxy", &[("cpu", cpu)], &[]);
+            c["restartPolicy"] = "Always".into();
+            c
+        };
+        // the init container is over the regular containers + the sidecar: max(2, 1 + 0.5)
+        let (moved, p1) = make_pod(
+            "p1",
+            vec![
+                make_named_container("setup", &[("cpu", "2")], &[]),
+                sidecar("500m"),
+            ],
+            serde_json::Value::Null,
+        );
+        assert_eq!(moved, 1);
+        // the sidecar runs along the regular containers, not only during the init: max(1, 1 + 1) + 100m
+        let (_, p2) = make_pod(
+            "p2",
+            vec![
+                sidecar("1"),
+                make_named_container("setup", &[("cpu", "1")], &[]),
+            ],
+            serde_json::json!({ "cpu": "100m" }),
+        );
+        let spec = p2.spec.as_ref().unwrap();
+        assert_eq!(spec.containers.len(), 2);
+        assert_eq!(spec.init_containers.as_ref().map(|c| c.len()), Some(1));
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        let effective = effective_requests_of(spec).unwrap();
+        assert_eq!(effective.get("cpu").map(|q| q.value), qty("2100m"));
+        // a classic init container is not a sidecar
+        let (moved, _) = make_pod(
+            "p3",
+            vec![make_named_container("setup", &[("cpu", "1")], &[])],
+            serde_json::Value::Null,
+        );
+        assert_eq!(moved, 0);
+        // the sidecar started before the init container runs along it (the example of the docs):
+        // max(2 + 1, 100m + 1), untyped like the watched pods
+        let object: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": { "name": "p4", "namespace": "default" },
+            "spec": {
+                "nodeName": "n1",
+                "containers": [make_named_container("app", &[("cpu", "100m")], &[])],
+                "initContainers": [
+                    sidecar("1"),
+                    make_named_container("setup", &[("cpu", "2")], &[]),
+                ],
+            },
+            "status": { "phase": "Running" },
+        }))
+        .unwrap();
+        let p4 = typed_pods(vec![object]).unwrap().remove(0);
+        let effective = effective_requests_of(p4.spec.as_ref().unwrap()).unwrap();
+        assert_eq!(effective.get("cpu").map(|q| q.value), qty("3"));
+
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(vec![p1, p2, p4]), &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[
+                GroupBy::resource,
+                GroupBy::node,
+                GroupBy::pod,
+                GroupBy::container,
+            ],
+            &[],
+        );
+        let requested_of = |key: &str| requested_of(&res, key).map(|q| q.value);
+        assert_eq!(requested_of("cpu/n1/p1"), qty("2"));
+        assert_eq!(requested_of("cpu/n1/p1/proxy"), qty("500m"));
+        assert_eq!(requested_of("cpu/n1/p1/(init)"), qty("500m"));
+        assert_eq!(requested_of("cpu/n1/p2"), qty("2100m"));
+        assert_eq!(requested_of("cpu/n1/p2/proxy"), qty("1"));
+        assert_eq!(requested_of("cpu/n1/p2/(init)"), None);
+        assert_eq!(requested_of("cpu/n1/p4"), qty("3"));
+        assert_eq!(requested_of("cpu/n1/p4/proxy"), qty("1"));
+        assert_eq!(requested_of("cpu/n1/p4/(init)"), qty("1900m"));
+    }
+
+    #[tokio::test]
+    async fn test_node_requested_with_init_containers_and_overhead() {
+        let init = |containers: Vec<serde_json::Value>| {
+            Some(
+                containers
+                    .into_iter()
+                    .map(|c| serde_json::from_value(c).unwrap())
+                    .collect(),
+            )
+        };
+        // init containers over the regular containers for cpu, under for memory
+        let mut p1 = make_pod(
+            "p1",
+            "n1",
+            vec![
+                make_named_container("app", &[("cpu", "1"), ("memory", "1Gi")], &[]),
+                make_named_container("sidecar", &[("cpu", "500m"), ("memory", "1Gi")], &[]),
+            ],
+        );
+        let spec = p1.spec.as_mut().unwrap();
+        spec.init_containers = init(vec![
+            make_named_container("setup", &[("cpu", "3"), ("memory", "1Gi")], &[]),
+            make_named_container("migrate", &[("cpu", "2"), ("memory", "512Mi")], &[]),
+        ]);
+        spec.overhead = Some(
+            [("cpu", "250m"), ("memory", "128Mi")]
+                .iter()
+                .map(|(k, v)| (k.to_string(), Quantity(v.to_string())))
+                .collect(),
+        );
+        // init containers under the regular containers
+        let mut p2 = make_pod("p2", "n1", vec![make_container(&[("cpu", "2")], &[])]);
+        p2.spec.as_mut().unwrap().init_containers =
+            init(vec![make_container(&[("cpu", "1")], &[])]);
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        // max(init, sum(regular)) + overhead
+        let effective = effective_requests_of(p1.spec.as_ref().unwrap()).unwrap();
+        assert_eq!(effective.get("cpu").map(|q| q.value), qty("3250m"));
+        assert_eq!(effective.get("memory").map(|q| q.value), qty("2176Mi"));
+
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(vec![p1, p2]), &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::node, GroupBy::pod],
+            &[],
+        );
+        let requested_of = |key: &str| requested_of(&res, key).map(|q| q.value);
+        assert_eq!(requested_of("cpu/n1/p1"), qty("3250m"));
+        assert_eq!(requested_of("memory/n1/p1"), qty("2176Mi"));
+        assert_eq!(requested_of("cpu/n1/p2"), qty("2"));
+        assert_eq!(requested_of("cpu/n1"), qty("5250m"));
+        assert_eq!(requested_of("memory/n1"), qty("2176Mi"));
+        // the same without grouping (every source is counted once)
+        let cpu = resources
+            .iter()
+            .filter(|r| r.kind == "cpu")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sum_by_qualifier(&cpu)
+                .and_then(|q| q.requested)
+                .map(|q| q.value),
+            qty("5250m")
+        );
+    }
+
+    #[test]
+    fn test_extract_burst_from_jobs() {
+        let pod_template = serde_json::json!({
+            "spec": {
+                "containers": [make_container(&[("cpu", "500m"), ("memory", "1Gi")], &[])],
+                "initContainers": [make_container(&[("cpu", "2")], &[])],
+            }
+        });
+        let jobs: Vec<Job> = serde_json::from_value(serde_json::json!([
+            {
+                "metadata": { "name": "j1", "namespace": "batch" },
+                "spec": { "parallelism": 4, "completions": 10, "template": pod_template },
+                "status": { "active": 1, "succeeded": 8 },
+            },
+            {
+                "metadata": { "name": "done", "namespace": "batch" },
+                "spec": { "template": pod_template },
+                "status": { "completionTime": "2022-01-01T00:00:00Z", "succeeded": 1 },
+            },
+            {
+                "metadata": { "name": "failed", "namespace": "batch" },
+                "spec": { "parallelism": 2, "template": pod_template },
+                "status": {
+                    "failed": 6,
+                    "conditions": [{ "type": "Failed", "status": "True", "reason": "BackoffLimitExceeded" }],
+                },
+            },
+            {
+                "metadata": { "name": "completing", "namespace": "batch" },
+                "spec": { "parallelism": 2, "template": pod_template },
+                "status": { "conditions": [{ "type": "Complete", "status": "True" }] },
+            },
+            {
+                "metadata": { "name": "not-failed", "namespace": "batch" },
+                "spec": { "template": pod_template },
+                "status": { "conditions": [{ "type": "Failed", "status": "False" }] },
+            },
+            {
+                "metadata": { "name": "suspended", "namespace": "batch" },
+                "spec": { "parallelism": 2, "suspend": true, "template": pod_template },
+            },
+        ]))
+        .unwrap();
+        let cron_jobs: Vec<CronJob> = serde_json::from_value(serde_json::json!([
+            {
+                "metadata": { "name": "c1", "namespace": "batch" },
+                "spec": {
+                    "schedule": "0 * * * *",
+                    "jobTemplate": { "spec": { "parallelism": 3, "template": pod_template } },
+                },
+            },
+            {
+                "metadata": { "name": "suspended", "namespace": "batch" },
+                "spec": {
+                    "schedule": "0 * * * *",
+                    "suspend": true,
+                    "jobTemplate": { "spec": { "parallelism": 3, "template": pod_template } },
+                },
+            },
+        ]))
+        .unwrap();
+        let mut resources = vec![];
+        extract_burst_from_jobs(
+            ObjectList {
+                metadata: Default::default(),
+                items: jobs,
+            },
+            ObjectList {
+                metadata: Default::default(),
+                items: cron_jobs,
+            },
+            &mut resources,
+        )
+        .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::namespace, GroupBy::pod],
+            &[],
+        );
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        // j1: min(4, 10 - 8) - 1 active = 1 pod, not-failed: 1 pod, c1: 3 pods,
+        // cpu of the pod is 2 (init container)
+        assert_eq!(
+            requested_of(&res, "cpu/batch/job/j1").map(|q| q.value),
+            qty("2")
+        );
+        assert_eq!(
+            requested_of(&res, "cpu/batch/cronjob/c1").map(|q| q.value),
+            qty("6")
+        );
+        assert_eq!(
+            requested_of(&res, "memory/batch").map(|q| q.value),
+            qty("5Gi")
+        );
+        assert_eq!(requested_of(&res, "pods/batch").map(|q| q.value), qty("5"));
+        assert_eq!(requested_of(&res, "cpu/batch/job/done"), None);
+        assert_eq!(requested_of(&res, "cpu/batch/job/failed"), None);
+        assert_eq!(requested_of(&res, "cpu/batch/job/completing"), None);
+        assert_eq!(requested_of(&res, "cpu/batch/job/suspended"), None);
+        assert_eq!(
+            requested_of(&res, "cpu/batch/job/not-failed").map(|q| q.value),
+            qty("2")
+        );
+        assert_eq!(requested_of(&res, "cpu/batch/cronjob/suspended"), None);
+    }
+
+    #[test]
+    fn test_hugepages_mismatches() {
+        let requirements = |requests: serde_json::Value, limits: serde_json::Value| {
+            serde_json::from_value::<ResourceRequirements>(
+                serde_json::json!({ "requests": requests, "limits": limits }),
+            )
+            .unwrap()
+        };
+        let equal = requirements(
+            serde_json::json!({ "hugepages-2Mi": "512Mi", "memory": "1Gi" }),
+            serde_json::json!({ "hugepages-2Mi": "0.5Gi", "memory": "2Gi" }),
+        );
+        assert!(hugepages_mismatches(&equal).is_empty());
+        let different = requirements(
+            serde_json::json!({ "hugepages-1Gi": "1Gi", "hugepages-2Mi": "512Mi" }),
+            serde_json::json!({ "hugepages-1Gi": "2Gi" }),
+        );
+        assert_eq!(
+            hugepages_mismatches(&different),
+            vec!["hugepages-1Gi".to_string(), "hugepages-2Mi".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_qos_class_of() {
+        let best_effort = make_pod_spec(vec![make_container(&[], &[])]);
+        assert_eq!(qos_class_of(&best_effort), QosClass::BestEffort);
+
+        let guaranteed = make_pod_spec(vec![
+            make_container(
+                &[("cpu", "1"), ("memory", "1Gi")],
+                &[("cpu", "1000m"), ("memory", "1Gi")],
+            ),
+            // requests default to limits
+            make_container(&[], &[("cpu", "500m"), ("memory", "128Mi")]),
+        ]);
+        assert_eq!(qos_class_of(&guaranteed), QosClass::Guaranteed);
+
+        let burstable = make_pod_spec(vec![
+            make_container(&[("cpu", "1"), ("memory", "1Gi")], &[("cpu", "1")]),
+            make_container(&[], &[]),
+        ]);
+        assert_eq!(qos_class_of(&burstable), QosClass::Burstable);
+    }
+
+    #[test]
+    fn test_metrics_api_version() {
+        let groups: k8s_openapi::apimachinery::pkg::apis::meta::v1::APIGroupList =
+            serde_json::from_value(serde_json::json!({
+                "groups": [
+                    {
+                        "name": "apps",
+                        "versions": [{ "groupVersion": "apps/v1", "version": "v1" }],
+                    },
+                    {
+                        "name": "metrics.k8s.io",
+                        "versions": [
+                            { "groupVersion": "metrics.k8s.io/v1beta1", "version": "v1beta1" },
+                            { "groupVersion": "metrics.k8s.io/v1", "version": "v1" },
+                        ],
+                        "preferredVersion": { "groupVersion": "metrics.k8s.io/v1", "version": "v1" },
+                    },
+                ],
+            }))
+            .unwrap();
+        assert_eq!(
+            preferred_group_version(&groups, "metrics.k8s.io"),
+            Some("metrics.k8s.io/v1".to_string())
+        );
+        assert_eq!(
+            preferred_group_version(&groups, "apps"),
+            Some("apps/v1".to_string())
+        );
+        assert_eq!(
+            preferred_group_version(&groups, "custom.metrics.k8s.io"),
+            None
+        );
+
+        let cli_opts = CliOpts::parse_from([
+            "kubectl-view-allocations",
+            "-u",
+            "--metrics-api-version",
+            "metrics.k8s.io/v1",
+        ]);
+        assert!(describe_query_plan(&cli_opts).contains(
+            &"list pod metrics: GET /apis/metrics.k8s.io/v1/pods?limit=500 (paginated)".to_string()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_collect_from_lists() {
+        let (skipped, fleet) = collect_from_node_list(
+            make_node_list(vec![make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "8" }),
+            )]),
+            &mut vec![],
+        )
+        .await
+        .unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(fleet.nodes, 1);
+
+        let pod = |name: &str, containers| {
+            serde_json::to_value(make_pod(name, "n1", containers)).unwrap()
+        };
+        // a native sidecar, resized in place to 500m (not yet allocated)
+        let mut p1 = pod(
+            "p1",
+            vec![make_named_container("app", &[("cpu", "1")], &[])],
+        );
+        p1["spec"]["initContainers"] = serde_json::json!([{
+            "name": "proxy",
+            "restartPolicy": "Always",
+            "resources": { "requests": { "cpu": "200m" } },
+        }]);
+        p1["status"]["resize"] = serde_json::json!("InProgress");
+        p1["status"]["initContainerStatuses"] = serde_json::json!([
+            { "name": "proxy", "allocatedResources": { "cpu": "300m" } },
+        ]);
+        // terminated, not counted
+        let mut p2 = pod("p2", vec![make_container(&[("cpu", "4")], &[])]);
+        p2["status"]["phase"] = serde_json::json!("Succeeded");
+        let p3 = pod("p3", vec![make_container(&[], &[])]);
+        let mut p4 = pod("p4", vec![make_container(&[("cpu", "16")], &[])]);
+        p4["spec"]["nodeName"] = serde_json::Value::Null;
+        p4["status"] = serde_json::json!({
+            "phase": "Pending",
+            "conditions": [{
+                "type": "PodScheduled",
+                "status": "False",
+                "reason": "Unschedulable",
+                "message": "0/1 nodes are available: 1 Insufficient cpu.",
+            }],
+        });
+        let mut resources = vec![];
+        let findings = collect_from_pod_values(
+            vec![p1, p2, p3, p4],
+            &mut resources,
+            true,
+            &["cpu".to_string()],
+        )
+        .await
+        .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::pod, GroupBy::container],
+            &["cpu".to_string()],
+        );
+        assert_eq!(
+            keys(&res),
+            vec!["cpu", "cpu/p1", "cpu/p1/app", "cpu/p1/proxy"]
+        );
+        assert_eq!(
+            requested_of(&res, "cpu/p1/proxy").map(|q| q.value),
+            Some(300)
+        );
+        assert_eq!(
+            findings
+                .resizing
+                .iter()
+                .map(|p| p.pod.as_str())
+                .collect::<Vec<_>>(),
+            vec!["p1"]
+        );
+        assert_eq!(
+            findings
+                .without_requests
+                .iter()
+                .map(|c| c.pod.as_str())
+                .collect::<Vec<_>>(),
+            vec!["p3"]
+        );
+        assert_eq!(
+            findings
+                .blocked
+                .iter()
+                .map(|p| p.pod.as_str())
+                .collect::<Vec<_>>(),
+            vec!["p4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resize_in_progress() {
+        let mut value = serde_json::to_value(make_pod(
+            "p1",
+            "n1",
+            vec![make_named_container("app", &[("cpu", "2")], &[])],
+        ))
+        .unwrap();
+        assert_eq!(resizing_pod_of(&value), None);
+        // the target (2) is not yet allocated by the node (1)
+        value["status"]["resize"] = serde_json::json!("InProgress");
+        value["status"]["containerStatuses"] = serde_json::json!([
+            { "name": "app", "allocatedResources": { "cpu": "1" } },
+        ]);
+        let resizing = resizing_pod_of(&value).unwrap();
+        assert_eq!(resizing.state, "InProgress");
+        assert_eq!(
+            resizing_summary(&[resizing]),
+            vec![
+                "Pods with an in-place resize in progress (counted with their allocated resources): 1",
+                "  default/p1: InProgress",
+            ]
+        );
+        let status_resources = status_resources_of(&value);
+        let mut pod: Pod = serde_json::from_value(value.clone()).unwrap();
+        apply_status_resources(&mut pod, status_resources);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(vec![pod]), &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(&resources, &[GroupBy::resource], &["cpu".to_string()]);
+        assert_eq!(requested_of(&res, "cpu").map(|q| q.value), Some(1000));
+
+        // kubernetes >= 1.33: the conditions replace `status.resize`
+        value["status"]["resize"] = serde_json::Value::Null;
+        value["status"]["conditions"] = serde_json::json!([
+            { "type": "PodResizePending", "status": "True", "reason": "Deferred" },
+        ]);
+        assert_eq!(
+            resizing_pod_of(&value).map(|p| p.state),
+            Some("Deferred".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_allocated_resources() {
+        let mut value = serde_json::to_value(make_pod(
+            "p1",
+            "n1",
+            vec![
+                make_named_container("resized", &[("cpu", "2")], &[("cpu", "4")]),
+                make_named_container("sidecar", &[("cpu", "100m")], &[]),
+            ],
+        ))
+        .unwrap();
+        // the spec is the desired state, the resize is pending (eg not enough cpu on the node)
+        value["status"]["containerStatuses"] = serde_json::json!([
+            {
+                "name": "resized",
+                "allocatedResources": { "cpu": "1" },
+                "resources": { "requests": { "cpu": "1" }, "limits": { "cpu": "2" } },
+            },
+            { "name": "sidecar" },
+        ]);
+        let status_resources = status_resources_of(&value);
+        assert_eq!(status_resources.len(), 1);
+        let mut pod: Pod = serde_json::from_value(value).unwrap();
+        assert_eq!(apply_status_resources(&mut pod, status_resources), 1);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(vec![pod]), &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::container],
+            &["cpu".to_string()],
+        );
+        let (requested, limit) = res
+            .iter()
+            .find(|(k, _)| k.join("/") == "cpu/resized")
+            .and_then(|(_, q)| q.as_ref())
+            .map(|q| (q.requested.clone(), q.limit.clone()))
+            .unwrap();
+        assert_eq!(requested.map(|q| q.value), Some(1000));
+        assert_eq!(limit.map(|q| q.value), Some(2000));
+
+        let version = |major: &str, minor: &str| k8s_openapi::apimachinery::pkg::version::Info {
+            major: major.to_string(),
+            minor: minor.to_string(),
+            ..Default::default()
+        };
+        // a non-scalar quantity is kept to be rejected (with the location) by the extraction, not coerced
+        let mut value =
+            serde_json::to_value(make_pod("p2", "n1", vec![make_container(&[], &[])])).unwrap();
+        value["status"]["containerStatuses"] = serde_json::json!([
+            { "name": "c", "allocatedResources": { "cpu": ["1", "2"], "memory": 1024 } },
+        ]);
+        let status_resources = status_resources_of(&value);
+        let requests = status_resources["c"].requests.clone().unwrap();
+        assert_eq!(requests["cpu"], Quantity(r#"["1","2"]"#.to_string()));
+        assert_eq!(requests["memory"], Quantity("1024".to_string()));
+        let mut pod: Pod = serde_json::from_value(value).unwrap();
+        apply_status_resources(&mut pod, status_resources);
+        let mut resources = vec![];
+        let skipped = extract_allocatable_from_pods(make_pod_list(vec![pod]), &mut resources)
+            .await
+            .unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].kind, "cpu");
+
+        assert!(supports_allocated_resources(&version("1", "27")));
+        assert!(supports_allocated_resources(&version("1", "29+")));
+        assert!(!supports_allocated_resources(&version("1", "26")));
+        assert!(!supports_allocated_resources(&version("", "")));
+    }
+
+    #[tokio::test]
+    async fn test_resource_claims() {
+        let pods = make_pod_list(vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n2", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p3", "n2", vec![make_container(&[("cpu", "1")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let claim = |name: &str, reserved_for: &[&str], drivers: &[&str]| {
+            serde_json::json!({
+                "metadata": { "name": name, "namespace": "default" },
+                "status": {
+                    "reservedFor": reserved_for
+                        .iter()
+                        .map(|pod| serde_json::json!({ "resource": "pods", "name": pod }))
+                        .collect::<Vec<_>>(),
+                    "allocation": { "devices": { "results": drivers
+                        .iter()
+                        .map(|driver| serde_json::json!({ "driver": driver, "pool": "n1", "device": "d" }))
+                        .collect::<Vec<_>>() } },
+                },
+            })
+        };
+        let claims = vec![
+            claim(
+                "p1-gpus",
+                &["p1"],
+                &["gpu.nvidia.com", "gpu.nvidia.com", "fpga.example.com"],
+            ),
+            // shared by 2 pods, counted once
+            claim("shared", &["p2", "p3"], &["gpu.nvidia.com"]),
+            // not yet allocated
+            serde_json::json!({ "metadata": { "name": "pending", "namespace": "default" } }),
+            claim("other", &["p4"], &["gpu.nvidia.com"]),
+        ];
+        assert_eq!(extract_from_resource_claims(&claims, &mut resources), 4);
+        let res = make_qualifiers(
+            &resources,
+            &[
+                GroupBy::resource,
+                GroupBy::node,
+                GroupBy::pod,
+                GroupBy::container,
+            ],
+            &[],
+        );
+        let qtys_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.as_ref())
+                .map(|q| (q.requested.clone(), q.limit.clone()))
+        };
+        let count = |n: i128| Some(Qty::from_base_value(n, UnitFamily::Count));
+        assert_eq!(
+            qtys_of("gpu.nvidia.com/n1/p1/(claims)"),
+            Some((count(2), count(2)))
+        );
+        assert_eq!(
+            qtys_of("fpga.example.com/n1/p1"),
+            Some((count(1), count(1)))
+        );
+        assert_eq!(qtys_of("gpu.nvidia.com/n2/p2"), Some((count(1), count(1))));
+        assert_eq!(qtys_of("gpu.nvidia.com/n2/p3"), None);
+
+        assert!(supports_resource_claims("resource.k8s.io/v1"));
+        assert!(supports_resource_claims("resource.k8s.io/v1beta1"));
+        assert!(supports_resource_claims("resource.k8s.io/v1alpha3"));
+        assert!(!supports_resource_claims("resource.k8s.io/v1alpha2"));
+    }
+
+    #[test]
+    fn test_extra_allocatable() {
+        let extra = parse_extra_allocatable(
+            "example.com/licenses=licensing.example.com/v1/licensepools:.status.capacity@.spec.nodeName",
+        )
+        .unwrap();
+        assert_eq!(
+            extra,
+            ExtraAllocatable {
+                kind: "example.com/licenses".to_string(),
+                api_version: "licensing.example.com/v1".to_string(),
+                plural: "licensepools".to_string(),
+                path: vec!["status".to_string(), "capacity".to_string()],
+                node_path: Some(vec!["spec".to_string(), "nodeName".to_string()]),
+            }
+        );
+        assert_eq!(
+            extra.url_path(),
+            "/apis/licensing.example.com/v1/licensepools"
+        );
+        assert_eq!(extra.api_resource().group, "licensing.example.com");
+        assert_eq!(
+            parse_extra_allocatable("slots=v1/configmaps:data.slots")
+                .unwrap()
+                .url_path(),
+            "/api/v1/configmaps"
+        );
+        assert!(parse_extra_allocatable("slots=configmaps:data.slots").is_err());
+        assert!(parse_extra_allocatable("slots=v1/configmaps").is_err());
+        assert!(parse_extra_allocatable("slots=v1/configmaps:.").is_err());
+
+        let object = |name: &str, data: serde_json::Value| -> DynamicObject {
+            let mut value = serde_json::json!({
+                "apiVersion": "licensing.example.com/v1",
+                "kind": "LicensePool",
+                "metadata": { "name": name },
+            });
+            value
+                .as_object_mut()
+                .unwrap()
+                .extend(data.as_object().unwrap().clone());
+            serde_json::from_value(value).unwrap()
+        };
+        let objects = vec![
+            object(
+                "pool1",
+                serde_json::json!({ "spec": { "nodeName": "n1" }, "status": { "capacity": "4" } }),
+            ),
+            object("pool2", serde_json::json!({ "status": { "capacity": 2 } })),
+            object(
+                "pool3",
+                serde_json::json!({ "status": { "capacity": "lots" } }),
+            ),
+            // not yet reconciled
+            object("pool4", serde_json::json!({ "spec": {} })),
+            // a list is rejected, not read as its first item
+            object(
+                "pool5",
+                serde_json::json!({ "spec": { "nodeName": ["n1"] }, "status": { "capacity": ["1", "2"] } }),
+            ),
+        ];
+        let mut resources = vec![];
+        let skipped = extract_extra_allocatable(&extra, objects, &mut resources).unwrap();
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(skipped[0].input, "lots");
+        assert_eq!(skipped[1].input, r#"["1","2"]"#);
+        assert_eq!(skipped[1].location.node_name, None);
+        let extracted = resources
+            .iter()
+            .map(|r| {
+                (
+                    r.kind.as_str(),
+                    r.location.node_name.as_deref(),
+                    r.quantity.to_canonical_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            extracted,
+            vec![
+                ("example.com/licenses", Some("n1"), "4".to_string()),
+                ("example.com/licenses", None, "2".to_string()),
+            ]
+        );
+        assert!(resources
+            .iter()
+            .all(|r| matches!(r.qualifier, ResourceQualifier::Allocatable)));
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_quantities_are_reported() {
+        let mut resources = vec![];
+        let mut report = CollectReport::default();
+        report.skipped.extend(
+            extract_allocatable_from_nodes(
+                make_node_list(vec![
+                    make_node(
+                        "n1",
+                        serde_json::json!({}),
+                        serde_json::json!({ "cpu": "4", "memory": "lots" }),
+                    ),
+                    make_node(
+                        "n2",
+                        serde_json::json!({}),
+                        serde_json::json!({ "cpu": "2" }),
+                    ),
+                ]),
+                &mut resources,
+            )
+            .await
+            .unwrap(),
+        );
+        report.skipped.extend(
+            extract_allocatable_from_pods(
+                make_pod_list(vec![
+                    make_pod(
+                        "p1",
+                        "n1",
+                        vec![serde_json::json!({
+                            "name": "c1",
+                            "resources": { "requests": { "cpu": "1" } },
+                        })],
+                    ),
+                    make_pod(
+                        "p2",
+                        "n2",
+                        vec![serde_json::json!({
+                            "name": "c1",
+                            "resources": { "requests": { "cpu": "1", "memory": "1Zz" } },
+                        })],
+                    ),
+                ]),
+                &mut resources,
+            )
+            .await
+            .unwrap(),
+        );
+        // the readable allocatable of n1 is kept, the whole pod p2 is skipped
+        assert_eq!(
+            resources
+                .iter()
+                .filter(|r| r.location.pod_name.is_none())
+                .count(),
+            2
+        );
+        assert!(resources
+            .iter()
+            .all(|r| r.location.pod_name.as_deref() != Some("p2")));
+        assert_eq!(
+            report.summary(),
+            vec![
+                "skipped 1 resources on 1 nodes".to_string(),
+                "skipped 1 resources of 1 pods; see the warnings above".to_string(),
+            ]
+        );
+        report.failed.push("jobs & cronjobs".to_string());
+        assert_eq!(
+            report.summary().last().unwrap(),
+            "failed: jobs & cronjobs; see the warnings above"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_workload() {
+        let owned = |name: &str, kind: &str, owner: &str, hash: Option<&str>| {
+            let mut pod = make_pod(name, "n1", vec![make_container(&[("cpu", "1")], &[])]);
+            pod.metadata = serde_json::from_value(serde_json::json!({
+                "name": name,
+                "namespace": "default",
+                "labels": hash.map(|h| serde_json::json!({ POD_TEMPLATE_HASH_LABEL: h })),
+                "ownerReferences": [{
+                    "apiVersion": "apps/v1",
+                    "kind": kind,
+                    "name": owner,
+                    "uid": "u1",
+                    "controller": true,
+                }],
+            }))
+            .unwrap();
+            pod
+        };
+        let pods = vec![
+            owned(
+                "web-7d4b9c-abcde",
+                "ReplicaSet",
+                "web-7d4b9c",
+                Some("7d4b9c"),
+            ),
+            owned(
+                "web-7d4b9c-fghij",
+                "ReplicaSet",
+                "web-7d4b9c",
+                Some("7d4b9c"),
+            ),
+            // a replicaset without deployment
+            owned("rs-xyz12", "ReplicaSet", "rs", None),
+            owned("db-0", "StatefulSet", "db", None),
+            make_pod("bare", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+        ];
+        assert_eq!(
+            pods.iter()
+                .map(|p| workload_of(&p.metadata))
+                .collect::<Vec<_>>(),
+            vec![
+                Some("Deployment/web".to_string()),
+                Some("Deployment/web".to_string()),
+                Some("ReplicaSet/rs".to_string()),
+                Some("StatefulSet/db".to_string()),
+                None,
+            ]
+        );
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources)
+            .await
+            .unwrap();
+        assert_eq!(GroupBy::from_str("workload"), Ok(GroupBy::workload));
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::workload],
+            &["cpu".to_string()],
+        );
+        assert_eq!(
+            keys(&res),
+            vec![
+                "cpu",
+                "cpu/(none)",
+                "cpu/Deployment/web",
+                "cpu/ReplicaSet/rs",
+                "cpu/StatefulSet/db"
+            ]
+        );
+        assert_eq!(
+            requested_of(&res, "cpu/Deployment/web").map(|q| q.value),
+            Some(2000)
+        );
+    }
+
+    #[test]
+    fn test_fleet_summary() {
+        let node = |name: &str, ready: &str, unschedulable: bool| -> Node {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": name },
+                "spec": { "unschedulable": unschedulable },
+                "status": { "conditions": [{ "type": "Ready", "status": ready }] },
+            }))
+            .unwrap()
+        };
+        let mut fleet = summarize_nodes(&[
+            node("n1", "True", false),
+            node("n2", "True", true),
+            node("n3", "Unknown", false),
+        ]);
+        let location = |namespace: &str, pod: &str| Location {
+            namespace: Some(namespace.to_string()),
+            pod_name: Some(pod.to_string()),
+            ..Location::default()
+        };
+        let resources = [
+            location("a", "p1"),
+            location("a", "p1"),
+            location("b", "p1"),
+            Location::default(),
+        ]
+        .into_iter()
+        .map(|location| Resource {
+            kind: "cpu".to_string(),
+            quantity: Qty::default(),
+            location,
+            qualifier: ResourceQualifier::Requested,
+        })
+        .collect::<Vec<_>>();
+        fleet.pods = count_pods(&resources);
+        assert_eq!(
+            fleet.to_string(),
+            "Nodes: 3 (ready: 2, schedulable: 2), Pods: 2"
+        );
+        let opts = DisplayOpts {
+            fleet: Some(fleet.clone()),
+            ..DisplayOpts::default()
+        };
+        let output = make_json_output(&[], &opts);
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["fleet"]["schedulable"], 2);
+        // the snapshots without fleet are still readable (see `--baseline`)
+        let mut json = json;
+        json.as_object_mut().unwrap().remove("fleet");
+        let output: JsonOutput = serde_json::from_value(json).unwrap();
+        assert_eq!(output.fleet, None);
+    }
+
+    #[tokio::test]
+    async fn test_make_quota_rows() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+        )]);
+        let mut pods = vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+        ];
+        pods[1].metadata.namespace = Some("big".to_string());
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let quota = |namespace: &str, hard: serde_json::Value| -> ResourceQuota {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": "quota", "namespace": namespace },
+                "spec": { "hard": hard },
+            }))
+            .unwrap()
+        };
+        let quotas = vec![
+            quota(
+                "default",
+                serde_json::json!({ "requests.cpu": "3", "limits.cpu": "8", "count/jobs.batch": "2" }),
+            ),
+            // the lowest hard wins
+            quota("default", serde_json::json!({ "cpu": "1500m" })),
+            quota("big", serde_json::json!({ "requests.cpu": "10" })),
+        ];
+        let rows = make_quota_rows(&quotas, &sums, 0.0);
+        let summary = rows
+            .iter()
+            .map(|row| {
+                format!(
+                    "{}/{}: {} of {}, free {} ({}) by {}",
+                    row.namespace,
+                    row.kind,
+                    row.requested.to_canonical_string(),
+                    row.hard.to_canonical_string(),
+                    row.quota_free().to_canonical_string(),
+                    row.cluster_free
+                        .as_ref()
+                        .map(|q| q.to_canonical_string())
+                        .unwrap_or_default(),
+                    row.limited_by()
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                "big/cpu: 1 of 10, free 9 (2) by capacity".to_string(),
+                "default/cpu: 1 of 1500m, free 500m (2) by quota".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crashloop_waste() {
+        let pod = |name: &str, containers| {
+            serde_json::to_value(make_pod(name, "n1", containers)).unwrap()
+        };
+        let waiting = |name: &str, reason: &str| {
+            serde_json::json!({
+                "name": name, "image": "app", "imageID": "", "ready": false, "restartCount": 12,
+                "state": { "waiting": { "reason": reason } },
+            })
+        };
+        let mut p1 = pod(
+            "p1",
+            vec![
+                make_named_container("app", &[("cpu", "500m"), ("memory", "256Mi")], &[]),
+                make_named_container("proxy", &[("cpu", "100m")], &[]),
+            ],
+        );
+        p1["status"]["containerStatuses"] = serde_json::json!([
+            waiting("app", "CrashLoopBackOff"),
+            { "name": "proxy", "image": "proxy", "imageID": "", "ready": true, "restartCount": 0,
+              "state": { "running": {} } },
+        ]);
+        let mut p2 = pod("p2", vec![make_container(&[("cpu", "1")], &[])]);
+        p2["status"]["containerStatuses"] = serde_json::json!([waiting("app", "CrashLoopBackOff")]);
+        // not crashing (yet)
+        let mut p3 = pod("p3", vec![make_container(&[("cpu", "2")], &[])]);
+        p3["status"]["containerStatuses"] =
+            serde_json::json!([waiting("app", "ContainerCreating")]);
+        // nothing held
+        let mut p4 = pod("p4", vec![make_container(&[], &[])]);
+        p4["status"]["containerStatuses"] = serde_json::json!([waiting("app", "CrashLoopBackOff")]);
+        let findings = collect_from_pod_values(vec![p1, p2, p3, p4], &mut vec![], false, &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            findings
+                .crashlooping
+                .iter()
+                .map(|p| (p.pod.as_str(), p.containers.join(",")))
+                .collect::<Vec<_>>(),
+            vec![("p1", "app".to_string()), ("p2", "app".to_string())]
+        );
+        assert_eq!(
+            crashloop_waste_summary(&findings.crashlooping),
+            vec![
+                "Pods in CrashLoopBackOff holding requests: 2 (cpu 1.6, memory 256.0Mi)",
+                "  default/p1 (app): cpu 600.0m, memory 256.0Mi",
+                "  default/p2 (app): cpu 1.0",
+            ]
+        );
+        assert_eq!(
+            crashloop_waste_summary(&[]),
+            vec!["Pods in CrashLoopBackOff holding requests: none"]
+        );
+    }
+}
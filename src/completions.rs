@@ -0,0 +1,251 @@
+//! module to generate the shell completions (to source) of a command,
+//! from the arguments declared for clap (flags, value names & possible values)
+
+use clap::{ArgAction, Command, ValueEnum};
+use std::io::Write;
+
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Shell {
+    bash,
+    zsh,
+    fish,
+}
+
+/// an option of the command, with what is needed by every shell
+struct Opt {
+    short: Option<char>,
+    long: Option<String>,
+    help: String,
+    value_name: Option<String>,
+    possible_values: Vec<String>,
+    repeatable: bool,
+}
+
+fn opts_of(cmd: &Command) -> Vec<Opt> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !arg.is_positional())
+        .map(|arg| {
+            let takes_value = arg.get_action().takes_values();
+            Opt {
+                short: arg.get_short(),
+                long: arg.get_long().map(|l| l.to_string()),
+                help: arg
+                    .get_help()
+                    .map(|h| h.to_string())
+                    .and_then(|h| h.lines().next().map(|l| l.to_string()))
+                    .unwrap_or_default(),
+                value_name: takes_value.then(|| {
+                    arg.get_value_names()
+                        .and_then(|names| names.first())
+                        .map(|name| name.to_string())
+                        .unwrap_or_else(|| arg.get_id().to_string().to_uppercase())
+                }),
+                possible_values: if takes_value {
+                    arg.get_possible_values()
+                        .iter()
+                        .filter(|v| !v.is_hide_set())
+                        .map(|v| v.get_name().to_string())
+                        .collect()
+                } else {
+                    vec![]
+                },
+                repeatable: matches!(arg.get_action(), ArgAction::Append | ArgAction::Count),
+            }
+        })
+        .collect()
+}
+
+/// write the completion script of `cmd` for the `shell`
+pub fn generate(shell: Shell, mut cmd: Command, out: &mut dyn Write) -> std::io::Result<()> {
+    // add the generated args (help, version)
+    cmd.build();
+    let name = cmd.get_name().to_string();
+    let opts = opts_of(&cmd);
+    match shell {
+        Shell::bash => write_bash(&name, &opts, out),
+        Shell::zsh => write_zsh(&name, &opts, out),
+        Shell::fish => write_fish(&name, &opts, out),
+    }
+}
+
+fn flags_of(opt: &Opt) -> Vec<String> {
+    opt.short
+        .map(|s| format!("-{}", s))
+        .into_iter()
+        .chain(opt.long.iter().map(|l| format!("--{}", l)))
+        .collect()
+}
+
+fn write_bash(name: &str, opts: &[Opt], out: &mut dyn Write) -> std::io::Result<()> {
+    let fname = format!("_{}", name.replace('-', "_"));
+    let all = opts.iter().flat_map(flags_of).collect::<Vec<_>>().join(" ");
+    writeln!(out, "{}() {{", fname)?;
+    writeln!(out, "    local cur prev")?;
+    writeln!(out, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"")?;
+    writeln!(out, "    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+    writeln!(out, "    case \"${{prev}}\" in")?;
+    for opt in opts.iter().filter(|o| o.value_name.is_some()) {
+        let words = opt.possible_values.join(" ");
+        writeln!(out, "        {})", flags_of(opt).join("|"))?;
+        if words.is_empty() {
+            // free value (eg a namespace, a path)
+            writeln!(out, "            COMPREPLY=()")?;
+        } else {
+            writeln!(
+                out,
+                "            COMPREPLY=($(compgen -W \"{}\" -- \"${{cur}}\"))",
+                words
+            )?;
+        }
+        writeln!(out, "            return 0")?;
+        writeln!(out, "            ;;")?;
+    }
+    writeln!(out, "    esac")?;
+    writeln!(
+        out,
+        "    COMPREPLY=($(compgen -W \"{}\" -- \"${{cur}}\"))",
+        all
+    )?;
+    writeln!(out, "}}")?;
+    writeln!(out, "complete -F {} {}", fname, name)
+}
+
+fn write_zsh(name: &str, opts: &[Opt], out: &mut dyn Write) -> std::io::Result<()> {
+    let escape = |s: &str| {
+        s.replace('\'', "'\\''")
+            .replace('[', "\\[")
+            .replace(']', "\\]")
+            .replace(':', "\\:")
+    };
+    writeln!(out, "#compdef {}", name)?;
+    writeln!(out)?;
+    writeln!(out, "_arguments -s \\")?;
+    for opt in opts {
+        let value = match &opt.value_name {
+            None => "".to_string(),
+            Some(value_name) => format!(
+                ":{}:{}",
+                escape(value_name),
+                if opt.possible_values.is_empty() {
+                    " ".to_string()
+                } else {
+                    format!("({})", opt.possible_values.join(" "))
+                }
+            ),
+        };
+        let flags = flags_of(opt);
+        // the short & long flags are exclusive, unless repeatable
+        let exclusion = if opt.repeatable {
+            "*".to_string()
+        } else {
+            format!("({})", flags.join(" "))
+        };
+        for flag in &flags {
+            writeln!(
+                out,
+                "  '{}{}[{}]{}' \\",
+                exclusion,
+                flag,
+                escape(&opt.help),
+                value
+            )?;
+        }
+    }
+    writeln!(out, "  && return 0")
+}
+
+fn write_fish(name: &str, opts: &[Opt], out: &mut dyn Write) -> std::io::Result<()> {
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('\'', "\\'");
+    for opt in opts {
+        let mut line = format!("complete -c {}", name);
+        if let Some(short) = opt.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        if let Some(long) = &opt.long {
+            line.push_str(&format!(" -l {}", long));
+        }
+        if opt.value_name.is_some() {
+            line.push_str(" -r");
+            if !opt.possible_values.is_empty() {
+                line.push_str(&format!(" -f -a '{}'", opt.possible_values.join(" ")));
+            }
+        }
+        line.push_str(&format!(" -d '{}'", escape(&opt.help)));
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn make_cmd() -> Command {
+        Command::new("kubectl-view-allocations")
+            .arg(
+                Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .help("Output format")
+                    .value_parser(["table", "csv"]),
+            )
+            .arg(
+                Arg::new("namespace")
+                    .short('n')
+                    .long("namespace")
+                    .help("Show only pods from this namespace"),
+            )
+            .arg(
+                Arg::new("utilization")
+                    .short('u')
+                    .long("utilization")
+                    .help("Force to retrieve utilization (it's the way to [filter])")
+                    .action(ArgAction::SetTrue),
+            )
+    }
+
+    fn generate_to_string(shell: Shell) -> String {
+        let mut out = vec![];
+        generate(shell, make_cmd(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_bash() {
+        let script = generate_to_string(Shell::bash);
+        assert!(script.contains("        -o|--output)\n"));
+        assert!(script.contains("compgen -W \"table csv\""));
+        assert!(
+            script.contains("compgen -W \"-o --output -n --namespace -u --utilization -h --help\"")
+        );
+        assert!(
+            script.ends_with("complete -F _kubectl_view_allocations kubectl-view-allocations\n")
+        );
+    }
+
+    #[test]
+    fn test_zsh() {
+        let script = generate_to_string(Shell::zsh);
+        assert!(script.starts_with("#compdef kubectl-view-allocations\n"));
+        assert!(script.contains("  '(-o --output)--output[Output format]:OUTPUT:(table csv)' \\\n"));
+        assert!(script.contains(
+            "  '(-u --utilization)-u[Force to retrieve utilization (it'\\''s the way to \\[filter\\])]' \\\n"
+        ));
+    }
+
+    #[test]
+    fn test_fish() {
+        let script = generate_to_string(Shell::fish);
+        assert!(script.contains(
+            "complete -c kubectl-view-allocations -s o -l output -r -f -a 'table csv' -d 'Output format'\n"
+        ));
+        assert!(script.contains(
+            "complete -c kubectl-view-allocations -s n -l namespace -r -d 'Show only pods from this namespace'\n"
+        ));
+        assert!(script.contains(
+            "-s u -l utilization -d 'Force to retrieve utilization (it\\'s the way to [filter])'\n"
+        ));
+    }
+}
@@ -0,0 +1,691 @@
+//! module to filter, group and display the collected resources (like requested by the cli)
+
+use crate::*;
+
+/// display the collected resources on stdout (see `display_collected`), and copy them to the clipboard
+/// with `--clipboard`
+pub(crate) fn display_to_stdout(
+    cli_opts: &CliOpts,
+    cluster: &ClusterInfo,
+    min_priority: Option<i32>,
+    collected: Collected,
+) -> Result<(), Error> {
+    if !cli_opts.clipboard {
+        return display_collected(
+            cli_opts,
+            cluster,
+            min_priority,
+            collected,
+            &mut std::io::stdout().lock(),
+        );
+    }
+    // copied even if invalid (eg `--fail-on-empty`), like it is displayed
+    let mut buffer = vec![];
+    let result = display_collected(cli_opts, cluster, min_priority, collected, &mut buffer);
+    std::io::stdout().lock().write_all(&buffer)?;
+    copy_to_clipboard(&buffer)?;
+    result
+}
+
+/// apply the rules of the hook to the resources (see `--hook`), return the resources kept (relabeled)
+#[cfg(feature = "hook")]
+pub fn apply_hook(resources: Vec<Resource>, program: &HookProgram) -> Result<Vec<Resource>, Error> {
+    let before = resources.len();
+    let resources: Vec<Resource> = resources
+        .into_iter()
+        .filter_map(|r| program.apply(r))
+        .collect();
+    debug!(
+        %program,
+        dropped = before - resources.len(),
+        "resources of the hook"
+    );
+    Ok(resources)
+}
+
+#[cfg(not(feature = "hook"))]
+pub fn apply_hook(
+    _resources: Vec<Resource>,
+    _program: &HookProgram,
+) -> Result<Vec<Resource>, Error> {
+    Err(Error::FeatureNotEnabled {
+        flag: "--hook",
+        feature: "hook",
+    })
+}
+
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(text: &[u8]) -> Result<(), Error> {
+    match clipboard::copy(text) {
+        Some(program) => info!(program, "output copied to the clipboard"),
+        None => warn!(
+            "no clipboard available (no display, or none of pbcopy, clip, wl-copy, xclip, xsel), not copied"
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn copy_to_clipboard(_text: &[u8]) -> Result<(), Error> {
+    Err(Error::FeatureNotEnabled {
+        flag: "--clipboard",
+        feature: "clipboard",
+    })
+}
+
+/// Filter, group and display the collected resources (like requested by the cli)
+/// display the collected resources into `out` (the notes & the report are displayed on stderr)
+pub fn display_collected(
+    cli_opts: &CliOpts,
+    cluster: &ClusterInfo,
+    min_priority: Option<i32>,
+    collected: Collected,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let Collected {
+        mut resources,
+        mut burst,
+        show_utilization,
+        limit_ranges,
+        quotas,
+        mut fleet,
+        mut without_requests,
+        mut blocked,
+        mut resizing,
+        mut crashlooping,
+        report,
+    } = collected;
+    // before anything by kind
+    normalize_kinds(&mut resources, &cli_opts.normalize_kind);
+    normalize_kinds(&mut burst, &cli_opts.normalize_kind);
+    if let Some(program) = &cli_opts.hook {
+        resources = apply_hook(resources, program)?;
+    }
+    if !cli_opts.exclude_namespace.is_empty() {
+        resources.retain(|r| accept_namespace(&r.location.namespace, &cli_opts.exclude_namespace));
+        burst.retain(|r| accept_namespace(&r.location.namespace, &cli_opts.exclude_namespace));
+    }
+    if let Some(regex) = &cli_opts.namespace_regex {
+        resources.retain(|r| regex.accept(&r.location.namespace));
+        burst.retain(|r| regex.accept(&r.location.namespace));
+    }
+    if !cli_opts.exclude_role.is_empty() {
+        retain_without_roles(&mut resources, &cli_opts.exclude_role);
+    }
+    if cli_opts.exclude_static_pods {
+        let before = resources.len();
+        resources.retain(|r| !is_static_pod(&r.location));
+        debug!(
+            removed = before - resources.len(),
+            "resources of static pods"
+        );
+    }
+    // the notes displayed after the output, by section
+    let mut notes: Vec<Vec<String>> = vec![];
+    if let Some(kinds) = &cli_opts.no_requests {
+        without_requests.retain(|c| cli_opts.accepts_namespace(&c.namespace));
+        notes.push(no_requests_summary(&without_requests, kinds));
+    }
+    blocked.retain(|p| cli_opts.accepts_namespace(&p.namespace));
+    resizing.retain(|p| cli_opts.accepts_namespace(&p.namespace));
+    if !resizing.is_empty() {
+        notes.push(resizing_summary(&resizing));
+    }
+    if cli_opts.crashloop_waste {
+        crashlooping.retain(|p| cli_opts.accepts_namespace(&p.namespace));
+        notes.push(crashloop_waste_summary(&crashlooping));
+    }
+    if cli_opts.limit_ranges {
+        // before the filter by kind ("pods" is used to count the pods)
+        let explanations = explain_limit_ranges(&limit_ranges, &resources);
+        notes.push(if explanations.is_empty() {
+            vec!["No requests set by LimitRanges".to_string()]
+        } else {
+            std::iter::once("Requests set by LimitRanges:".to_string())
+                .chain(explanations.into_iter().map(|line| format!("  {}", line)))
+                .collect()
+        });
+    }
+    if !cli_opts.exclude_resource.is_empty() {
+        resources.retain(|r| !exclude_resource(&r.kind, &cli_opts.exclude_resource));
+        burst.retain(|r| !exclude_resource(&r.kind, &cli_opts.exclude_resource));
+    }
+    if let Some(pod) = &cli_opts.pod {
+        retain_pod(&mut resources, pod)?;
+    }
+    if let Some(min_priority) = min_priority {
+        info!(
+            min_priority,
+            "keep only pods with a priority >= min_priority"
+        );
+        retain_for_priority(&mut resources, min_priority);
+    }
+    if !cli_opts.qos.is_empty() {
+        resources.retain(|r| {
+            r.location
+                .qos_class
+                .map(|q| cli_opts.qos.contains(&q))
+                .unwrap_or(true)
+        });
+    }
+
+    for (kind, _) in &cli_opts.precision {
+        if !resources.iter().any(|r| &r.kind == kind) {
+            warn!("{}: no resource of the kind (see --precision)", kind);
+        }
+    }
+    for r in non_integer_quantities(&resources, &cli_opts.integer_kinds) {
+        warn!(
+            location = ?r.location,
+            qualifier = ?r.qualifier,
+            "{}: {} is not an integer (expected a count)",
+            r.kind,
+            r.quantity
+        );
+    }
+    // before the synthetic kinds
+    let mut issues = 0;
+    if cli_opts.validate {
+        let unavailable_kinds = if cli_opts.skip_check.contains(&Check::unavailable_kinds) {
+            vec![]
+        } else {
+            find_unavailable_kinds(&resources)
+        };
+        issues += unavailable_kinds.len();
+        notes.push(validation_summary(&unavailable_kinds));
+    }
+    add_sum_kinds(&mut resources, &cli_opts.sum_kinds);
+    if cli_opts.node_count {
+        fleet.pods = count_pods(&resources);
+        if fleet.nodes == 0 {
+            warn!("0 nodes matched");
+        }
+    }
+    if let Some(threshold_pct) = cli_opts.collapse_namespaces_below {
+        if cli_opts.group_by.contains(&GroupBy::namespace) {
+            let collapsed = collapse_small_namespaces(&mut resources, threshold_pct);
+            debug!(collapsed, "namespaces collapsed into {}", OTHER_NAMESPACE);
+        } else {
+            warn!("--collapse-namespaces-below has no effect without `-g namespace`");
+        }
+    }
+    info!(resources = resources.len(), "resources collected");
+    // summed once, for every grouping
+    let sums = ResourceSums::new(&resources);
+    if !cli_opts.fits.is_empty() {
+        let fits = compute_fits(&sums, &cli_opts.fits, cli_opts.free_headroom);
+        if fits.on_nodes == 0 && fits.in_total > 0 {
+            warn!("the free would fit {} pods of --fits in total, but no node has enough free for one pod (fragmented)", fits.in_total);
+        }
+        notes.push(fits.summary(&cli_opts.fits));
+    }
+    if cli_opts.pod_density {
+        let pod = if cli_opts.fits.is_empty() {
+            average_pod(&sums)
+        } else {
+            cli_opts.fits.clone()
+        };
+        notes.push(pod_densities_summary(
+            &compute_pod_densities(&sums, &pod),
+            &pod,
+        ));
+    }
+    if cli_opts.histogram {
+        for histogram in compute_histograms(&sums, &cli_opts.resource_name) {
+            notes.push(histogram.summary());
+        }
+    }
+    if let Some((node, kind)) = &cli_opts.explain {
+        notes.push(explain_node(
+            &resources,
+            node,
+            kind,
+            cli_opts.free_headroom,
+            cli_opts.used_basis,
+        ));
+    }
+    if let Some(path) = &cli_opts.node_cost {
+        let costs = node_costs(
+            &resources,
+            &sums,
+            &read_node_costs(path)?,
+            cli_opts.used_basis,
+            cli_opts.free_headroom,
+        );
+        let nodes = resources
+            .iter()
+            .filter(|r| matches!(r.qualifier, ResourceQualifier::Allocatable))
+            .filter_map(|r| r.location.node_name.as_ref())
+            .unique()
+            .count();
+        notes.push(node_costs_summary(
+            &costs,
+            nodes.saturating_sub(costs.len()),
+        ));
+    }
+    if !cli_opts.since_snapshot.is_empty() {
+        let snapshots = cli_opts
+            .since_snapshot
+            .iter()
+            .map(|path| Baseline::read(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let now = Utc::now();
+        let growths = estimate_growth(&sums, &cli_opts.resource_name, &snapshots, now);
+        notes.push(growth_summary(&growths, snapshots.len(), now));
+    }
+    if let Some(target_pct) = cli_opts.target_utilization.filter(|pct| *pct > 0.0) {
+        let plans = plan_pools(&sums, &cli_opts.pool_label, target_pct);
+        notes.push(pool_plans_summary(&plans, &cli_opts.pool_label, target_pct));
+    }
+    if let Some(threshold_pct) = cli_opts.detect_reservation_drift {
+        let drifts = reservation_drifts(&sums, threshold_pct);
+        notes.push(reservation_drifts_summary(&drifts, threshold_pct));
+    }
+    for (node, kind, reserved) in reserved_mismatches(&sums, &cli_opts.reserved) {
+        warn!(
+            node,
+            "{}: reserved is {} (capacity - allocatable), not like expected by --reserved",
+            kind,
+            reserved.adjust_scale()
+        );
+    }
+    let plugins_not_ready = find_plugins_not_ready(&resources);
+    let mut res = sums.make_qualifiers(&cli_opts.group_by, &cli_opts.resource_name);
+    apply_used_basis(&mut res, cli_opts.used_basis);
+    apply_percent_base(&mut res, cli_opts.percent_base);
+    apply_free_headroom(&mut res, cli_opts.free_headroom);
+    debug!(rows = res.len(), "resources grouped");
+    if !cli_opts.no_warnings {
+        for p in &plugins_not_ready {
+            warn!(
+                node = p.node,
+                "{}: requested ({}) by the pods, but not allocatable by the node: the device plugin is not ready",
+                p.kind,
+                p.requested.adjust_scale()
+            );
+        }
+        // the oversubscription is explained by the nodes of the plugins not ready
+        for (kind, percentage) in find_oversubscribed(&res)
+            .into_iter()
+            .filter(|(kind, _)| !plugins_not_ready.iter().any(|p| &p.kind == kind))
+        {
+            warn!(
+                "{}: requested {:.0}% of allocatable cluster-wide",
+                kind, percentage
+            );
+        }
+    }
+    for (k, qtys) in res
+        .iter()
+        .filter_map(|(k, oqtys)| oqtys.as_ref().map(|qtys| (k, qtys)))
+    {
+        if let Some(threshold) =
+            free_below_threshold(k, qtys, &cli_opts.group_by, &cli_opts.warn_free_below)
+        {
+            warn!(
+                "{}: free is below {} ({})",
+                k.join(&cli_opts.group_separator),
+                threshold.adjust_scale(),
+                qtys.calc_free().unwrap_or_default().adjust_scale()
+            );
+        }
+    }
+    let res = finalize_rows(res, cli_opts);
+    if is_empty_result(&res) {
+        // not an error by default (the filters are valid, nothing matches), unlike a failed collect
+        display_report(&notes, &report, cli_opts.quiet);
+        validated(issues)?;
+        if cli_opts.fail_on_empty {
+            return Err(Error::NoResourcesMatched);
+        }
+        if !cli_opts.quiet {
+            eprintln!("no resources matched the given filters");
+        }
+        return Ok(());
+    }
+    let display_opts = DisplayOpts {
+        group_by: cli_opts.group_by.clone(),
+        kind_aliases: cli_opts.kind_alias.clone(),
+        show_utilization,
+        filter_full_zero: !cli_opts.show_zero,
+        unbounded_limit: cli_opts.unbounded_limit,
+        show_reserved: cli_opts.show_reserved,
+        show_age: cli_opts.show_age,
+        show_bars: cli_opts.bars
+            && cli_opts.split_by_node.is_none()
+            && std::io::stdout().is_terminal(),
+        compact: cli_opts.compact,
+        tree_glyphs: cli_opts
+            .tree_style
+            .unwrap_or_else(TreeStyle::detect)
+            .glyphs(),
+        separators: cli_opts.separators,
+        flatten_single_child: cli_opts.flatten_single_child,
+        combined_req_limit: cli_opts.combined_req_limit,
+        plugins_not_ready: plugins_not_ready
+            .iter()
+            .map(|p| (p.kind.clone(), p.node.clone()))
+            .collect(),
+        free_spread: cli_opts
+            .free_spread
+            .then(|| free_spread_by_kind(&sums, cli_opts.used_basis, cli_opts.free_headroom)),
+        group_separator: cli_opts.group_separator.clone(),
+        align_units: cli_opts.align_units,
+        free_with_pct: cli_opts.free_with_pct,
+        show_nodes: cli_opts.merge_similar_nodes,
+        show_pods: cli_opts.group_by.contains(&GroupBy::namespace),
+        show_ratio: cli_opts.show_ratio,
+        qos_split: cli_opts.qos_split,
+        used_basis: cli_opts.used_basis,
+        free_headroom: cli_opts.free_headroom,
+        pending: cli_opts
+            .pending_pods
+            .then(|| count_blocked_by_kind(&blocked)),
+        free_thresholds: cli_opts.warn_free_below.clone(),
+        cluster: Some(cluster.clone()),
+        fleet: cli_opts.node_count.then_some(fleet),
+        percent_decimals: cli_opts.percent_decimals,
+        precisions: cli_opts.precision.clone(),
+        precision: None,
+        quantities: cli_opts.quantities.clone(),
+        base_values: false,
+        csv: CsvOpts {
+            delimiter: cli_opts.csv_delimiter,
+            quote_keys: cli_opts.csv_quote_keys,
+        },
+        color_thresholds: cli_opts
+            .thresholds_file
+            .as_deref()
+            .map(ThresholdsConfig::read)
+            .transpose()?,
+        colored: std::io::stdout().is_terminal() && !cli_opts.clipboard,
+        usages: cli_opts.usage.clone(),
+        quiet: cli_opts.quiet,
+        now: None,
+    };
+    if let Some(dir) = &cli_opts.split_by_node {
+        write_split_by_node(&resources, cli_opts, &display_opts, dir)?;
+        display_report(&notes, &report, cli_opts.quiet);
+        return validated(issues);
+    }
+    if cli_opts.banner {
+        writeln!(out, "{}", make_banner(&res, &display_opts))?;
+        display_report(&notes, &report, cli_opts.quiet);
+        return validated(issues);
+    }
+    let burst_opts = DisplayOpts {
+        group_by: vec![GroupBy::resource, GroupBy::namespace, GroupBy::pod],
+        cluster: None,
+        ..display_opts.clone()
+    };
+    let baseline = cli_opts
+        .baseline
+        .as_deref()
+        .map(Baseline::read)
+        .transpose()?;
+    if baseline.is_some() && cli_opts.output != Output::table {
+        warn!("the deltas from the baseline are only displayed as table");
+    }
+    if cli_opts.compare_to_quota && cli_opts.output != Output::table {
+        warn!("the comparison to the quotas is only displayed as table");
+    }
+    if cli_opts.efficiency && cli_opts.output != Output::table {
+        warn!("the efficiency of the containers is only displayed as table");
+    }
+    if cli_opts.merge_cpu_memory_into_score.is_some() && cli_opts.output != Output::table {
+        warn!("the scores of the nodes are only displayed as table");
+    }
+    let mut burst_res = make_qualifiers(&burst, &burst_opts.group_by, &cli_opts.resource_name);
+    sort_qualifiers(&mut burst_res, &cli_opts.sort_by, &cli_opts.sort_order());
+    match &cli_opts.output {
+        Output::table => {
+            match &baseline {
+                Some(baseline) => {
+                    display_delta_with_prettytable(&res, &display_opts, baseline, out)?
+                }
+                None => display_with_prettytable(&res, &display_opts, out)?,
+            }
+            if cli_opts.include_jobs {
+                writeln!(out, "\nPending burst (jobs & cronjobs):")?;
+                display_with_prettytable(&burst_res, &burst_opts, out)?;
+            }
+            if cli_opts.compare_to_quota {
+                writeln!(out, "\nResourceQuotas:")?;
+                display_quotas_with_prettytable(
+                    &make_quota_rows(&quotas, &sums, cli_opts.free_headroom),
+                    &display_opts,
+                    out,
+                )?;
+            }
+            if cli_opts.efficiency && show_utilization {
+                writeln!(
+                    out,
+                    "\nEfficiency of the containers (utilization / requested):"
+                )?;
+                display_efficiency_with_prettytable(
+                    &make_efficiency_rows(&resources, &cli_opts.sort_by, &cli_opts.sort_order()),
+                    &display_opts,
+                    out,
+                )?;
+            }
+            if let Some(function) = cli_opts.merge_cpu_memory_into_score {
+                let name = function
+                    .to_possible_value()
+                    .map(|v| v.get_name().to_string())
+                    .unwrap_or_default();
+                writeln!(out, "\nScore of the nodes ({} of the requested%):", name)?;
+                display_scores_with_prettytable(
+                    &compute_node_scores(&sums, function, &cli_opts.score_weights),
+                    &display_opts,
+                    out,
+                )?;
+            }
+        }
+        Output::plain => {
+            write_as_plain(&res, &display_opts, out)?;
+            if cli_opts.include_jobs {
+                writeln!(out, "\nPending burst (jobs & cronjobs):")?;
+                write_as_plain(&burst_res, &burst_opts, out)?;
+            }
+        }
+        Output::csv => {
+            if cli_opts.include_jobs {
+                warn!("pending burst of jobs is not available as csv");
+            }
+            write_as_csv(&res, &display_opts, out)?
+        }
+        Output::influx => {
+            if cli_opts.include_jobs {
+                warn!("pending burst of jobs is not available as influx");
+            }
+            write_as_influx(&res, &display_opts, out)?
+        }
+        Output::prometheus => {
+            if cli_opts.include_jobs {
+                warn!("pending burst of jobs is not available as prometheus");
+            }
+            write_as_prometheus(&res, &display_opts, out)?
+        }
+        Output::json | Output::console_json => {
+            if cli_opts.output == Output::console_json {
+                let mut stderr = std::io::stderr().lock();
+                write_with_prettytable(&res, &display_opts, &mut stderr)?;
+                if cli_opts.include_jobs {
+                    writeln!(stderr, "\nPending burst (jobs & cronjobs):")?;
+                    write_with_prettytable(&burst_res, &burst_opts, &mut stderr)?;
+                }
+            }
+            let mut output = make_json_output(&res, &display_opts);
+            if cli_opts.include_jobs {
+                output.pending_burst = Some(make_json_output(&burst_res, &burst_opts).items);
+            }
+            serde_json::to_writer_pretty(&mut *out, &output)?;
+            writeln!(out)?;
+        }
+        Output::sim_json => {
+            let output = make_sim_output(&resources, &cli_opts.resource_name);
+            serde_json::to_writer_pretty(&mut *out, &output)?;
+            writeln!(out)?;
+        }
+        Output::totals_json => {
+            let output = make_totals_json(&resources, &cli_opts.resource_name, &display_opts);
+            serde_json::to_writer_pretty(&mut *out, &output)?;
+            writeln!(out)?;
+        }
+        Output::resources_json => {
+            let output = make_resources_output(&resources, &cli_opts.resource_name);
+            serde_json::to_writer_pretty(&mut *out, &output)?;
+            writeln!(out)?;
+        }
+        Output::node_free_json => {
+            let output = make_node_free_json(&resources, &cli_opts.resource_name, &display_opts);
+            serde_json::to_writer_pretty(&mut *out, &output)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+    if !cli_opts.also.is_empty() {
+        let file_opts = DisplayOpts {
+            show_bars: false,
+            ..display_opts.clone()
+        };
+        resources.retain(|r| accept_resource(&r.kind, &cli_opts.resource_name));
+        for (output, path) in &cli_opts.also {
+            write_output_file(&resources, &res, &file_opts, output, path)?;
+            info!(?output, ?path, "also written");
+        }
+    }
+    let pushed = match &cli_opts.push_gateway {
+        Some(gateway) => {
+            let mut metrics = vec![];
+            write_as_prometheus(&res, &display_opts, &mut metrics)?;
+            let instance = cli_opts
+                .push_instance
+                .clone()
+                .or_else(|| cluster.context.clone())
+                .unwrap_or_else(|| cluster.server.clone());
+            push_metrics(gateway, &cli_opts.push_job, &instance, metrics)
+        }
+        None => Ok(()),
+    };
+    display_report(&notes, &report, cli_opts.quiet);
+    pushed?;
+    validated(issues)
+}
+
+/// the attempts to push the metrics (see `--push-gateway`)
+#[cfg(feature = "pushgateway")]
+const PUSH_ATTEMPTS: u32 = 3;
+
+#[cfg(feature = "pushgateway")]
+fn push_metrics(gateway: &str, job: &str, instance: &str, metrics: Vec<u8>) -> Result<(), Error> {
+    let url = pushgateway::url_of(gateway, job, instance);
+    pushgateway::push(&url, metrics, PUSH_ATTEMPTS).map_err(|message| Error::PushError {
+        url: url.clone(),
+        attempts: PUSH_ATTEMPTS,
+        message,
+    })?;
+    info!(url, "metrics pushed");
+    Ok(())
+}
+
+#[cfg(not(feature = "pushgateway"))]
+fn push_metrics(
+    _gateway: &str,
+    _job: &str,
+    _instance: &str,
+    _metrics: Vec<u8>,
+) -> Result<(), Error> {
+    Err(Error::FeatureNotEnabled {
+        flag: "--push-gateway",
+        feature: "pushgateway",
+    })
+}
+
+/// the error of `--validate` if some issues are found
+pub(crate) fn validated(issues: usize) -> Result<(), Error> {
+    if issues > 0 {
+        return Err(Error::ValidationFailed { count: issues });
+    }
+    Ok(())
+}
+
+/// display the notes (eg explanations of LimitRanges, fits) and what was not collected,
+/// on stderr to keep the output parsable, nothing if `quiet` (see `--quiet`)
+fn display_report(notes: &[Vec<String>], report: &CollectReport, quiet: bool) {
+    if quiet {
+        return;
+    }
+    for section in notes {
+        eprintln!();
+        for line in section {
+            eprintln!("{}", line);
+        }
+    }
+    if report.is_empty() {
+        return;
+    }
+    eprintln!();
+    for line in report.summary() {
+        eprintln!("{}", line);
+    }
+}
+
+/// sort, keep the top and the summary of the rows (from `make_qualifiers`) like requested by the cli
+/// no row with quantities to display (eg the filters match no kind, no namespace)
+pub fn is_empty_result(res: &[(Vec<String>, Option<QtyByQualifier>)]) -> bool {
+    res.iter().all(|(_, oqtys)| oqtys.is_none())
+}
+
+pub(crate) fn finalize_rows(
+    mut res: Vec<(Vec<String>, Option<QtyByQualifier>)>,
+    cli_opts: &CliOpts,
+) -> Vec<(Vec<String>, Option<QtyByQualifier>)> {
+    sort_qualifiers(&mut res, &cli_opts.sort_by, &cli_opts.sort_order());
+    if let Some(top) = cli_opts.top {
+        res = keep_top_qualifiers(res, top);
+    }
+    if cli_opts.summary_only {
+        res.retain(|(k, _)| k.len() == 1);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[cfg(feature = "hook")]
+    #[tokio::test]
+    async fn test_apply_hook() {
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("cpu", "1"), ("memory", "1Gi")], &[])],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let program = parse_hook("drop if kind=memory; set namespace=team-a if kind=cpu").unwrap();
+        let resources = apply_hook(resources, &program).unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::namespace],
+            &["cpu".to_string(), "memory".to_string()],
+        );
+        assert_eq!(keys(&res), vec!["cpu", "cpu/team-a"]);
+        assert!(CliOpts::try_parse_from(["test", "--hook", "keep if kind=cpu"]).is_err());
+    }
+
+    #[test]
+    fn test_is_empty_result() {
+        assert!(is_empty_result(&[]));
+        assert!(is_empty_result(&[(vec!["cpu".to_string()], None)]));
+        assert!(!is_empty_result(&[(
+            vec!["cpu".to_string()],
+            qtys_requested("1")
+        )]));
+    }
+}
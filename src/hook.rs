@@ -0,0 +1,343 @@
+//! module to drop or relabel the collected resources before the grouping (see `--hook`), with rules
+//! (eg of an organization) evaluated in the process over the fields of the resources: they can't read a file,
+//! run a program or reach the network, and have no loop (so they end).
+//!
+//! The program is a list of rules separated by `;`, applied in order to every resource:
+//! - `drop [if CONDITIONS]`: remove the resource (the next rules are skipped)
+//! - `set FIELD=VALUE... [if CONDITIONS]`: replace the fields (an empty value removes it), the next rules see
+//!   the new values
+//!
+//! The conditions are separated by `and`, each `FIELD=VALUE`, `FIELD!=VALUE`, `FIELD~REGEX` or `FIELD!~REGEX`
+//! (the regex should match the whole value), a missing field is the empty value.
+//! The fields are `kind`, `namespace`, `node_name`, `pod_name`, `container_name` and `workload`.
+//! The values & the regexes can't contain a whitespace or a `;`.
+//!
+//! eg `drop if namespace=kube-system; set namespace=team-a if namespace~team-a-.* and kind!=pods`
+
+use crate::Resource;
+use std::str::FromStr;
+
+/// the fields of a resource that the rules can read & replace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Kind,
+    Namespace,
+    NodeName,
+    PodName,
+    ContainerName,
+    Workload,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kind" => Ok(Field::Kind),
+            "namespace" => Ok(Field::Namespace),
+            "node_name" => Ok(Field::NodeName),
+            "pod_name" => Ok(Field::PodName),
+            "container_name" => Ok(Field::ContainerName),
+            "workload" => Ok(Field::Workload),
+            _ => Err(format!(
+                "unknown field '{}' (expected kind, namespace, node_name, pod_name, container_name or workload)",
+                s
+            )),
+        }
+    }
+}
+
+impl Field {
+    fn get(self, r: &Resource) -> &str {
+        let value = match self {
+            Field::Kind => return &r.kind,
+            Field::Namespace => &r.location.namespace,
+            Field::NodeName => &r.location.node_name,
+            Field::PodName => &r.location.pod_name,
+            Field::ContainerName => &r.location.container_name,
+            Field::Workload => &r.location.workload,
+        };
+        value.as_deref().unwrap_or_default()
+    }
+
+    fn set(self, r: &mut Resource, value: &str) {
+        let field = match self {
+            Field::Kind => {
+                r.kind = value.to_string();
+                return;
+            }
+            Field::Namespace => &mut r.location.namespace,
+            Field::NodeName => &mut r.location.node_name,
+            Field::PodName => &mut r.location.pod_name,
+            Field::ContainerName => &mut r.location.container_name,
+            Field::Workload => &mut r.location.workload,
+        };
+        *field = Some(value.to_string()).filter(|v| !v.is_empty());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Equal(Field, String),
+    NotEqual(Field, String),
+    Match(Field, regex_automata::meta::Regex),
+    NotMatch(Field, regex_automata::meta::Regex),
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let end = s
+            .find(|c: char| !(c.is_ascii_lowercase() || c == '_'))
+            .ok_or_else(|| format!("invalid condition '{}' (expected FIELD=VALUE)", s))?;
+        let (field, rest) = s.split_at(end);
+        let field = field.parse()?;
+        let regex = |pattern: &str| {
+            regex_automata::meta::Regex::new(&format!("^(?:{})$", pattern))
+                .map_err(|e| format!("invalid regex '{}': {}", pattern, e))
+        };
+        if let Some(value) = rest.strip_prefix("!=") {
+            Ok(Condition::NotEqual(field, value.to_string()))
+        } else if let Some(pattern) = rest.strip_prefix("!~") {
+            Ok(Condition::NotMatch(field, regex(pattern)?))
+        } else if let Some(value) = rest.strip_prefix('=') {
+            Ok(Condition::Equal(field, value.to_string()))
+        } else if let Some(pattern) = rest.strip_prefix('~') {
+            Ok(Condition::Match(field, regex(pattern)?))
+        } else {
+            Err(format!(
+                "invalid condition '{}' (expected an operator =, !=, ~ or !~)",
+                s
+            ))
+        }
+    }
+}
+
+impl Condition {
+    fn accept(&self, r: &Resource) -> bool {
+        match self {
+            Condition::Equal(field, value) => field.get(r) == value,
+            Condition::NotEqual(field, value) => field.get(r) != value,
+            Condition::Match(field, regex) => regex.is_match(field.get(r)),
+            Condition::NotMatch(field, regex) => !regex.is_match(field.get(r)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Drop,
+    Set(Vec<(Field, String)>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub action: Action,
+    /// all should be accepted (none: every resource)
+    pub conditions: Vec<Condition>,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let action: Vec<&str> = words.by_ref().take_while(|w| *w != "if").collect();
+        let action = match action.split_first() {
+            Some((&"drop", [])) => Action::Drop,
+            Some((&"set", assignments)) if !assignments.is_empty() => Action::Set(
+                assignments
+                    .iter()
+                    .map(|a| {
+                        let (field, value) = a.split_once('=').ok_or_else(|| {
+                            format!("invalid assignment '{}' (expected FIELD=VALUE)", a)
+                        })?;
+                        Ok((field.parse()?, value.to_string()))
+                    })
+                    .collect::<Result<_, String>>()?,
+            ),
+            _ => {
+                return Err(format!(
+                    "invalid action '{}' (expected drop or set FIELD=VALUE...)",
+                    action.join(" ")
+                ))
+            }
+        };
+        let mut conditions = vec![];
+        let mut expect_condition = false;
+        for (i, word) in words.enumerate() {
+            if i % 2 == 1 {
+                if word != "and" {
+                    return Err(format!("expected 'and' before '{}'", word));
+                }
+                expect_condition = true;
+            } else {
+                conditions.push(word.parse()?);
+                expect_condition = false;
+            }
+        }
+        // `if` (or `and`) without condition
+        if expect_condition || (conditions.is_empty() && s.split_whitespace().any(|w| w == "if")) {
+            return Err("missing condition".to_string());
+        }
+        Ok(Rule { action, conditions })
+    }
+}
+
+/// The rules of `--hook`, parsed once (see the module)
+#[derive(Debug, Clone)]
+pub struct Program {
+    source: String,
+    pub rules: Vec<Rule>,
+}
+
+impl FromStr for Program {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules = s
+            .split(';')
+            .filter(|rule| !rule.trim().is_empty())
+            .enumerate()
+            .map(|(i, rule)| {
+                rule.parse()
+                    .map_err(|e| format!("rule {} '{}': {}", i + 1, rule.trim(), e))
+            })
+            .collect::<Result<Vec<Rule>, _>>()?;
+        if rules.is_empty() {
+            return Err("no rule".to_string());
+        }
+        Ok(Program {
+            source: s.to_string(),
+            rules,
+        })
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Program {
+    /// apply the rules to the resource, `None` if it is dropped
+    pub fn apply(&self, mut r: Resource) -> Option<Resource> {
+        for rule in &self.rules {
+            if !rule.conditions.iter().all(|c| c.accept(&r)) {
+                continue;
+            }
+            match &rule.action {
+                Action::Drop => return None,
+                Action::Set(assignments) => {
+                    for (field, value) in assignments {
+                        field.set(&mut r, value);
+                    }
+                }
+            }
+        }
+        Some(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Location, ResourceQualifier};
+
+    fn resource(kind: &str, namespace: &str) -> Resource {
+        Resource {
+            kind: kind.to_string(),
+            quantity: "1".parse().unwrap(),
+            location: Location {
+                namespace: Some(namespace.to_string()),
+                pod_name: Some("p1".to_string()),
+                ..Location::default()
+            },
+            qualifier: ResourceQualifier::Requested,
+        }
+    }
+
+    fn apply(program: &str, r: Resource) -> Option<(String, Option<String>, Option<String>)> {
+        let program: Program = program.parse().unwrap();
+        program
+            .apply(r)
+            .map(|r| (r.kind, r.location.namespace, r.location.pod_name))
+    }
+
+    #[test]
+    fn test_apply() {
+        let kept = |kind: &str, namespace: Option<&str>| {
+            Some((
+                kind.to_string(),
+                namespace.map(str::to_string),
+                Some("p1".to_string()),
+            ))
+        };
+        assert_eq!(apply("drop", resource("cpu", "a")), None);
+        assert_eq!(
+            apply("drop if namespace=kube-system", resource("cpu", "a")),
+            kept("cpu", Some("a"))
+        );
+        assert_eq!(
+            apply(
+                "drop if namespace=kube-system",
+                resource("cpu", "kube-system")
+            ),
+            None
+        );
+        assert_eq!(
+            apply(
+                "set namespace=team-a if namespace~team-a-.* and kind!=memory",
+                resource("cpu", "team-a-prod")
+            ),
+            kept("cpu", Some("team-a"))
+        );
+        assert_eq!(
+            apply(
+                "set namespace=team-a if namespace~team-a-.* and kind!=memory",
+                resource("memory", "team-a-prod")
+            ),
+            kept("memory", Some("team-a-prod"))
+        );
+        // the whole value should match
+        assert_eq!(
+            apply("drop if namespace~team", resource("cpu", "team-a")),
+            kept("cpu", Some("team-a"))
+        );
+        assert_eq!(
+            apply("drop if namespace!~team-.*", resource("cpu", "a")),
+            None
+        );
+        // in order, the next rules see the new values
+        assert_eq!(
+            apply(
+                "set kind=gpu namespace= if kind=nvidia.com/gpu; drop if kind=nvidia.com/gpu",
+                resource("nvidia.com/gpu", "a")
+            ),
+            kept("gpu", None)
+        );
+        // a missing field is the empty value
+        assert_eq!(apply("drop if workload=", resource("cpu", "a")), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let error = |program: &str| program.parse::<Program>().unwrap_err();
+        assert!(" ; drop if kind=cpu ;".parse::<Program>().is_ok());
+        assert_eq!(error(""), "no rule");
+        assert!(error("keep").contains("invalid action 'keep'"));
+        assert!(error("set").contains("invalid action 'set'"));
+        assert!(error("drop namespace=a").contains("invalid action"));
+        assert!(error("set node=n1").contains("unknown field 'node'"));
+        assert!(error("set namespace").contains("invalid assignment"));
+        assert!(error("drop if").contains("missing condition"));
+        assert!(error("drop if kind=cpu and").contains("missing condition"));
+        assert!(error("drop if kind=cpu namespace=a").contains("expected 'and'"));
+        assert!(error("drop if kind").contains("invalid condition"));
+        assert!(error("drop if kind<cpu").contains("expected an operator"));
+        assert!(error("drop if kind~(cpu").contains("invalid regex"));
+        assert!(error("drop; drop if pod=p1").starts_with("rule 2 'drop if pod=p1'"));
+    }
+}
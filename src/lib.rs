@@ -1,20 +1,51 @@
+#![allow(clippy::result_large_err)]
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+mod collect;
+pub mod completions;
+mod display;
+#[cfg(feature = "hook")]
+pub mod hook;
 pub mod metrics;
+mod output;
+#[cfg(feature = "pushgateway")]
+pub mod pushgateway;
 pub mod qty;
 pub mod tree;
+mod watch;
+
+pub use collect::*;
+pub use display::*;
+pub use output::*;
+pub use watch::*;
 
 // mod human_format;
 use chrono::prelude::*;
 use clap::{Parser, ValueEnum};
 use core::convert::TryFrom;
+use futures::StreamExt;
 use itertools::Itertools;
-use k8s_openapi::api::core::v1::{Node, Pod};
-use kube::api::{Api, ListParams, ObjectList};
+use k8s_openapi::api::batch::v1::{CronJob, Job, JobSpec};
+use k8s_openapi::api::core::v1::{
+    ConfigMap, Container, LimitRange, Node, Pod, PodSpec, ResourceQuota, ResourceRequirements,
+};
+use k8s_openapi::api::scheduling::v1::PriorityClass;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::{
+    Api, ApiResource, DynamicObject, ListParams, ObjectList, Patch, PatchParams, WatchEvent,
+};
 #[cfg(feature = "prettytable")]
-use prettytable::{format, row, Cell, Row, Table};
-use qty::Qty;
-use std::collections::BTreeMap;
+use prettytable::{format, row, Attr, Cell, Row, Table};
+use qty::{Qty, Scale, UnitFamily};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{IsTerminal, Write};
 use std::str::FromStr;
-use tracing::{info, instrument, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, instrument, warn};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -40,12 +71,82 @@ pub enum Error {
         source: qty::Error,
     },
 
+    #[error("Failed to serialize as json")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+    },
+
+    #[error("No pod found matching '{pod}'")]
+    PodNotFound { pod: String },
+
+    #[error("No node '{node}' to override its allocatable")]
+    NodeNotFound { node: String },
+
+    #[error("The validation found {count} issue(s)")]
+    ValidationFailed { count: usize },
+
+    #[error("No resources matched the given filters")]
+    NoResourcesMatched,
+
+    #[error("Failed to write the output")]
+    OutputError {
+        #[from]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to write into '{path}'")]
+    FileError {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Failed to read '{path}'")]
+    ReadFileError {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Failed to parse '{path}'")]
+    ConfigError {
+        path: std::path::PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("Failed to parse '{path}'")]
+    YamlConfigError {
+        path: std::path::PathBuf,
+        source: serde_yaml::Error,
+    },
+
+    #[error("Failed to push the metrics to '{url}' ({attempts} attempts): {message}")]
+    PushError {
+        url: String,
+        attempts: u32,
+        message: String,
+    },
+
+    #[error("'{flag}' needs the feature '{feature}', not enabled in this build")]
+    FeatureNotEnabled {
+        flag: &'static str,
+        feature: &'static str,
+    },
+
+    #[error("Failed to annotate {target}: {message}")]
+    AnnotateError { target: String, message: String },
+
+    #[error("Timed out after {timeout:?}")]
+    Timeout { timeout: Duration },
+
     #[error("Failed to {context}")]
     KubeError {
         context: String,
         source: kube::Error,
     },
 
+    #[error("Impersonation as {target} is forbidden (the current user requires the `impersonate` permission)")]
+    ImpersonationForbidden { target: String, source: kube::Error },
+
     #[error("Failed to {context}")]
     KubeConfigError {
         context: String,
@@ -59,14 +160,97 @@ pub enum Error {
     },
 }
 
-#[derive(Debug, Clone, Default)]
+/// the pseudo container (`Location::container_name`) of the part of the requests of a pod due to its init containers
+pub const INIT_CONTAINER: &str = "(init)";
+/// the pseudo container of the overhead of a pod (of its runtime class)
+pub const OVERHEAD_CONTAINER: &str = "(overhead)";
+/// the pseudo container of the devices allocated to a pod by its resource claims (see `--resource-claims`)
+pub const CLAIMS_CONTAINER: &str = "(claims)";
+
+/// (serialized without the labels & annotations, shared by the resources of the node & the pod)
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Location {
     pub node_name: Option<String>,
     pub namespace: Option<String>,
     pub pod_name: Option<String>,
+    pub container_name: Option<String>,
+    pub qos_class: Option<QosClass>,
+    /// labels of the node (shared by every resource of the node)
+    #[serde(skip)]
+    pub node_labels: Arc<BTreeMap<String, String>>,
+    /// annotations of the pod (shared by every resource of the pod)
+    #[serde(skip)]
+    pub pod_annotations: Arc<BTreeMap<String, String>>,
+    /// priority of the pod (resolved from its priority class by the admission)
+    pub priority: Option<i32>,
+    /// the top-level owner of the pod, as `<Kind>/<name>` (see `workload_of`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workload: Option<String>,
+    /// creation time of the node (only on the resources of the node)
+    #[serde(serialize_with = "serialize_rfc3339")]
+    pub node_created: Option<DateTime<Utc>>,
+    /// state of the node (shared by every resource of the node & of its pods)
+    pub node_state: Option<NodeState>,
+    /// the kubeconfig context of the cluster (see `--batch`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+fn serialize_rfc3339<S: serde::Serializer>(
+    t: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match t {
+        Some(t) => serializer.serialize_some(&t.to_rfc3339()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Quality of Service class of a pod
+/// see https://kubernetes.io/docs/concepts/workloads/pods/pod-qos/
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, ValueEnum, Serialize)]
+pub enum QosClass {
+    Guaranteed,
+    Burstable,
+    BestEffort,
+}
+
+impl std::fmt::Display for QosClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Guaranteed => "Guaranteed",
+            Self::Burstable => "Burstable",
+            Self::BestEffort => "BestEffort",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Whether the allocatable of a node is usable now (see `-g node-state`)
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[allow(non_camel_case_types)]
+pub enum NodeState {
+    /// ready & schedulable
+    ready,
+    /// ready but unschedulable (`kubectl cordon`)
+    cordoned,
+    /// without the condition `Ready` (schedulable or not)
+    not_ready,
+}
+
+impl std::fmt::Display for NodeState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::ready => "ready",
+            Self::cordoned => "cordoned",
+            Self::not_ready => "not-ready",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Resource {
     pub kind: String,
     pub quantity: Qty,
@@ -74,35 +258,155 @@ pub struct Resource {
     pub qualifier: ResourceQualifier,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum ResourceQualifier {
     Limit,
     Requested,
     Allocatable,
+    /// capacity of the node (allocatable + reserved by kube & system)
+    Capacity,
     Utilization,
+    /// marker: the resource is requested by a container without limit
+    NoLimit,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct QtyByQualifier {
     pub limit: Option<Qty>,
     pub requested: Option<Qty>,
+    /// the part of requested by the pods of QoS Guaranteed (firm), the rest is elastic (see `calc_requested_burstable`)
+    pub requested_guaranteed: Option<Qty>,
     pub allocatable: Option<Qty>,
+    pub capacity: Option<Qty>,
     pub utilization: Option<Qty>,
+    /// at least one container requests the resource without limit (so limit is unbounded)
+    pub no_limit: bool,
+    /// number of nodes providing allocatable
+    pub nodes: usize,
+    /// number of pods requesting or limiting the resource
+    pub pods: usize,
+    /// creation time of the most recent node providing allocatable
+    pub node_created: Option<DateTime<Utc>>,
+    /// part of allocatable kept as safety buffer, not counted as free (see `apply_free_headroom`)
+    pub headroom: Option<Qty>,
+    /// the quantity used (subtracted from allocatable) by `calc_free` (see `apply_used_basis`)
+    pub used_basis: UsedBasis,
+    /// the denominator of the percentages (see `apply_percent_base`)
+    pub percent_base: PercentBase,
 }
 
 fn add(lhs: Option<Qty>, rhs: &Qty) -> Option<Qty> {
     lhs.map(|l| &l + rhs).or_else(|| Some(rhs.clone()))
 }
 
+fn add_opt(lhs: Option<Qty>, rhs: &Option<Qty>) -> Option<Qty> {
+    match rhs {
+        Some(rhs) => add(lhs, rhs),
+        None => lhs,
+    }
+}
+
 impl QtyByQualifier {
+    pub fn merge(&self, other: &QtyByQualifier) -> QtyByQualifier {
+        QtyByQualifier {
+            limit: add_opt(self.limit.clone(), &other.limit),
+            requested: add_opt(self.requested.clone(), &other.requested),
+            requested_guaranteed: add_opt(
+                self.requested_guaranteed.clone(),
+                &other.requested_guaranteed,
+            ),
+            allocatable: add_opt(self.allocatable.clone(), &other.allocatable),
+            capacity: add_opt(self.capacity.clone(), &other.capacity),
+            utilization: add_opt(self.utilization.clone(), &other.utilization),
+            no_limit: self.no_limit || other.no_limit,
+            nodes: self.nodes + other.nodes,
+            pods: self.pods + other.pods,
+            node_created: std::cmp::max(self.node_created, other.node_created),
+            headroom: add_opt(self.headroom.clone(), &other.headroom),
+            used_basis: self.used_basis,
+            percent_base: self.percent_base,
+        }
+    }
+
+    /// the denominator of the percentages (allocatable, effective or capacity), `None` if zero
+    pub fn calc_percent_base(&self) -> Option<Qty> {
+        let base = match self.percent_base {
+            PercentBase::allocatable => self.allocatable.clone(),
+            // like `calc_free`
+            PercentBase::effective => match &self.headroom {
+                Some(headroom) => self
+                    .allocatable
+                    .as_ref()
+                    .map(|allocatable| allocatable.checked_sub(headroom).unwrap_or_default()),
+                None => self.allocatable.clone(),
+            },
+            PercentBase::capacity => self.capacity.clone(),
+        };
+        base.filter(|q| !q.is_zero())
+    }
+
+    /// the part of requested by the pods Guaranteed, firm (see `--qos-split`)
+    pub fn calc_requested_guaranteed(&self) -> Option<Qty> {
+        self.requested
+            .as_ref()
+            .map(|_| self.requested_guaranteed.clone().unwrap_or_default())
+    }
+
+    /// the part of requested by the pods not Guaranteed (Burstable & BestEffort), elastic (see `--qos-split`)
+    pub fn calc_requested_burstable(&self) -> Option<Qty> {
+        self.requested
+            .as_ref()
+            .map(|requested| match &self.requested_guaranteed {
+                Some(guaranteed) => requested.checked_sub(guaranteed).unwrap_or_default(),
+                None => requested.clone(),
+            })
+    }
+
+    /// the part of the capacity reserved (for kube & system), not allocatable to pods
+    pub fn calc_reserved(&self) -> Option<Qty> {
+        self.capacity
+            .as_ref()
+            .zip(self.allocatable.as_ref())
+            .map(|(capacity, allocatable)| {
+                if capacity > allocatable {
+                    capacity - allocatable
+                } else {
+                    Qty::default()
+                }
+            })
+    }
+
+    /// the ratio requested / limit, `None` when the limit is unbounded (or zero)
+    pub fn calc_request_limit_ratio(&self) -> Option<f64> {
+        if self.no_limit {
+            return None;
+        }
+        self.requested
+            .as_ref()
+            .zip(self.limit.as_ref())
+            .and_then(|(requested, limit)| requested.ratio(limit))
+    }
+
+    /// allocatable (minus the headroom) - the used (see `UsedBasis`), 0 if negative,
+    /// `None` without allocatable or without requested & limit
     pub fn calc_free(&self) -> Option<Qty> {
-        let total_used = std::cmp::max(self.limit.as_ref(), self.requested.as_ref());
+        let total_used = match self.used_basis {
+            UsedBasis::requested => self.requested.clone(),
+            UsedBasis::limit => self.limit.clone(),
+            UsedBasis::max => std::cmp::max(self.limit.as_ref(), self.requested.as_ref()).cloned(),
+        }
+        // only limited (or only requested): nothing used by this basis
+        .or_else(|| (self.requested.is_some() || self.limit.is_some()).then(Qty::default));
         self.allocatable
             .as_ref()
-            .zip(total_used)
+            .zip(total_used.as_ref())
             .map(|(allocatable, total_used)| {
-                if allocatable > total_used {
-                    allocatable - total_used
+                let available = match &self.headroom {
+                    Some(headroom) => allocatable.checked_sub(headroom).unwrap_or_default(),
+                    None => allocatable.clone(),
+                };
+                if &available > total_used {
+                    &available - total_used
                 } else {
                     Qty::default()
                 }
@@ -110,25 +414,78 @@ impl QtyByQualifier {
     }
 }
 
+/// use `basis` to compute the free of every row (see `QtyByQualifier::calc_free`)
+pub fn apply_used_basis(data: &mut [(Vec<String>, Option<QtyByQualifier>)], basis: UsedBasis) {
+    for qtys in data.iter_mut().filter_map(|(_, oqtys)| oqtys.as_mut()) {
+        qtys.used_basis = basis;
+    }
+}
+
+/// use `base` as the denominator of the percentages of every row (see `QtyByQualifier::calc_percent_base`)
+pub fn apply_percent_base(data: &mut [(Vec<String>, Option<QtyByQualifier>)], base: PercentBase) {
+    for qtys in data.iter_mut().filter_map(|(_, oqtys)| oqtys.as_mut()) {
+        qtys.percent_base = base;
+    }
+}
+
+/// keep `percentage` of the allocatable of every row as headroom (so free is reduced)
+pub fn apply_free_headroom(data: &mut [(Vec<String>, Option<QtyByQualifier>)], percentage: f64) {
+    if percentage <= 0.0 {
+        return;
+    }
+    for qtys in data.iter_mut().filter_map(|(_, oqtys)| oqtys.as_mut()) {
+        qtys.headroom = qtys.allocatable.as_ref().map(|allocatable| Qty {
+            value: (allocatable.value as f64 * percentage / 100.0).round() as i128,
+            micro: 0,
+            scale: allocatable.scale.clone(),
+        });
+    }
+}
+
 pub fn sum_by_qualifier(rsrcs: &[&Resource]) -> Option<QtyByQualifier> {
     if !rsrcs.is_empty() {
         let kind = rsrcs
-            .get(0)
+            .first()
             .expect("group contains at least 1 element")
             .kind
             .clone();
 
         if rsrcs.iter().all(|i| i.kind == kind) {
-            let sum = rsrcs.iter().fold(QtyByQualifier::default(), |mut acc, v| {
+            let nodes = rsrcs
+                .iter()
+                .filter(|v| matches!(v.qualifier, ResourceQualifier::Allocatable))
+                .filter_map(|v| v.location.node_name.as_ref())
+                .unique()
+                .count();
+            let pods = rsrcs
+                .iter()
+                .filter(|v| v.location.pod_name.is_some())
+                .map(|v| (&v.location.namespace, &v.location.pod_name))
+                .unique()
+                .count();
+            let init = QtyByQualifier {
+                nodes,
+                pods,
+                ..QtyByQualifier::default()
+            };
+            let sum = rsrcs.iter().fold(init, |mut acc, v| {
                 match &v.qualifier {
                     ResourceQualifier::Limit => acc.limit = add(acc.limit, &v.quantity),
-                    ResourceQualifier::Requested => acc.requested = add(acc.requested, &v.quantity),
+                    ResourceQualifier::Requested => {
+                        acc.requested = add(acc.requested, &v.quantity);
+                        if v.location.qos_class == Some(QosClass::Guaranteed) {
+                            acc.requested_guaranteed = add(acc.requested_guaranteed, &v.quantity);
+                        }
+                    }
                     ResourceQualifier::Allocatable => {
-                        acc.allocatable = add(acc.allocatable, &v.quantity)
+                        acc.allocatable = add(acc.allocatable, &v.quantity);
+                        acc.node_created = std::cmp::max(acc.node_created, v.location.node_created);
                     }
+                    ResourceQualifier::Capacity => acc.capacity = add(acc.capacity, &v.quantity),
                     ResourceQualifier::Utilization => {
                         acc.utilization = add(acc.utilization, &v.quantity)
                     }
+                    ResourceQualifier::NoLimit => acc.no_limit = true,
                 };
                 acc
             });
@@ -146,40 +503,118 @@ pub fn make_qualifiers(
     group_by: &[GroupBy],
     resource_names: &[String],
 ) -> Vec<(Vec<String>, Option<QtyByQualifier>)> {
-    let group_by_fct = group_by.iter().map(GroupBy::to_fct).collect::<Vec<_>>();
-    let mut out = make_group_x_qualifier(
-        &(rsrcs
-            .iter()
-            .filter(|a| accept_resource(&a.kind, resource_names))
-            .collect::<Vec<_>>()),
-        &[],
-        &group_by_fct,
-        0,
-    );
-    out.sort_by_key(|i| i.0.clone());
-    out
+    ResourceSums::new(rsrcs).make_qualifiers(group_by, resource_names)
+}
+
+/// a resource (representative of its kind & location) with the sum of the resources of the same kind & location
+type Leaf<'a> = (&'a Resource, QtyByQualifier);
+
+/// The resources summed once by kind & location (the finest grouping: every `GroupBy` extracts
+/// the same key from the resources of a leaf), so they can be grouped by several `group_by`
+/// without summing every resource again.
+pub struct ResourceSums<'a> {
+    leaves: Vec<Leaf<'a>>,
+}
+
+impl<'a> ResourceSums<'a> {
+    pub fn new(rsrcs: &'a [Resource]) -> Self {
+        let mut index = HashMap::new();
+        let mut groups: Vec<Vec<&Resource>> = vec![];
+        for r in rsrcs {
+            let l = &r.location;
+            // labels & annotations are shared (by `Arc`) by the resources of a node or a pod
+            let key = (
+                r.kind.as_str(),
+                &l.cluster,
+                &l.node_name,
+                &l.namespace,
+                &l.pod_name,
+                &l.container_name,
+                l.qos_class,
+                l.priority,
+                l.node_state,
+                Arc::as_ptr(&l.node_labels),
+                Arc::as_ptr(&l.pod_annotations),
+            );
+            let i = *index.entry(key).or_insert_with(|| {
+                groups.push(vec![]);
+                groups.len() - 1
+            });
+            groups[i].push(r);
+        }
+        let leaves = groups
+            .into_iter()
+            .filter_map(|group| sum_by_qualifier(&group).map(|qtys| (group[0], qtys)))
+            .collect();
+        ResourceSums { leaves }
+    }
+
+    /// same as `make_qualifiers` (on the resources of the sums)
+    pub fn make_qualifiers(
+        &self,
+        group_by: &[GroupBy],
+        resource_names: &[String],
+    ) -> Vec<(Vec<String>, Option<QtyByQualifier>)> {
+        let mut out = make_group_x_qualifier(
+            &(self
+                .leaves
+                .iter()
+                .filter(|(r, _)| accept_resource(&r.kind, resource_names))
+                .collect::<Vec<_>>()),
+            &[],
+            group_by,
+            0,
+        );
+        out.sort_by_key(|i| i.0.clone());
+        out
+    }
+}
+
+/// like `sum_by_qualifier` but from leaves
+fn sum_leaves(leaves: &[&Leaf]) -> Option<QtyByQualifier> {
+    let (first, _) = leaves.first()?;
+    if !leaves.iter().all(|(r, _)| r.kind == first.kind) {
+        return None;
+    }
+    let nodes = leaves
+        .iter()
+        .filter(|(_, qtys)| qtys.allocatable.is_some())
+        .filter_map(|(r, _)| r.location.node_name.as_ref())
+        .unique()
+        .count();
+    let pods = leaves
+        .iter()
+        .filter(|(r, _)| r.location.pod_name.is_some())
+        .map(|(r, _)| (&r.location.namespace, &r.location.pod_name))
+        .unique()
+        .count();
+    let sum = leaves
+        .iter()
+        .fold(QtyByQualifier::default(), |acc, (_, qtys)| acc.merge(qtys));
+    Some(QtyByQualifier { nodes, pods, ..sum })
 }
 
 fn make_group_x_qualifier(
-    rsrcs: &[&Resource],
+    leaves: &[&Leaf],
     prefix: &[String],
-    group_by_fct: &[fn(&Resource) -> Option<String>],
+    group_by: &[GroupBy],
     group_by_depth: usize,
 ) -> Vec<(Vec<String>, Option<QtyByQualifier>)> {
     // Note: The `&` is significant here, `GroupBy` is iterable
     // only by reference. You can also call `.into_iter()` explicitly.
     let mut out = vec![];
-    if let Some(group_by) = group_by_fct.get(group_by_depth) {
-        for (key, group) in rsrcs
+    if let Some(g) = group_by.get(group_by_depth) {
+        for (key, group) in leaves
             .iter()
-            .filter_map(|e| group_by(e).map(|k| (k, *e)))
+            .filter_map(|e| g.extract(e.0).map(|k| (k, *e)))
             .into_group_map()
+            .into_iter()
+            .sorted_by(|a, b| a.0.cmp(&b.0))
         {
             let mut key_full = prefix.to_vec();
             key_full.push(key);
-            let children =
-                make_group_x_qualifier(&group, &key_full, group_by_fct, group_by_depth + 1);
-            out.push((key_full, sum_by_qualifier(&group)));
+            let children = make_group_x_qualifier(&group, &key_full, group_by, group_by_depth + 1);
+            out.push((key_full, sum_leaves(&group)));
             out.extend(children);
         }
     }
@@ -188,683 +623,6697 @@ fn make_group_x_qualifier(
     out
 }
 
-fn accept_resource(name: &str, resource_filter: &[String]) -> bool {
-    resource_filter.is_empty() || resource_filter.iter().any(|x| name.contains(x))
+/// The resources (top-level rows from `make_qualifiers`) with a total requested greater than
+/// the total allocatable, with the percentage requested (pending pods are likely).
+pub fn find_oversubscribed(data: &[(Vec<String>, Option<QtyByQualifier>)]) -> Vec<(String, f64)> {
+    data.iter()
+        .filter(|(k, _)| k.len() == 1)
+        .filter_map(|(k, oqtys)| {
+            let qtys = oqtys.as_ref()?;
+            let requested = qtys.requested.as_ref()?;
+            let allocatable = qtys.allocatable.as_ref()?;
+            // no headroom left
+            allocatable
+                .checked_sub(requested)
+                .is_none()
+                .then(|| (k[0].clone(), requested.calc_percentage(allocatable)))
+        })
+        .collect()
 }
 
-#[instrument(skip(client, resources))]
-pub async fn collect_from_nodes(
-    client: kube::Client,
-    resources: &mut Vec<Resource>,
-) -> Result<(), Error> {
-    let api_nodes: Api<Node> = Api::all(client);
-    let nodes = api_nodes
-        .list(&ListParams::default())
-        .await
-        .map_err(|source| Error::KubeError {
-            context: "list nodes".to_string(),
-            source,
-        })?;
-    extract_allocatable_from_nodes(nodes, resources).await?;
-    Ok(())
+/// A check of `--validate` (see `--skip-check`)
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Check {
+    /// kinds requested (or limited) by pods but allocatable on no node: the pods can't be scheduled
+    unavailable_kinds,
 }
 
-#[instrument(skip(node_list, resources))]
-pub async fn extract_allocatable_from_nodes(
-    node_list: ObjectList<Node>,
-    resources: &mut Vec<Resource>,
-) -> Result<(), Error> {
-    for node in node_list.items {
-        let location = Location {
-            node_name: node.metadata.name,
-            ..Location::default()
-        };
-        if let Some(als) = node.status.and_then(|v| v.allocatable) {
-            // add_resource(resources, &location, ResourceUsage::Allocatable, &als)?
-            for (kind, value) in als.iter() {
-                let quantity =
-                    Qty::from_str(&(value).0).map_err(|source| Error::ResourceQtyParseError {
-                        location: location.clone(),
-                        qualifier: ResourceQualifier::Allocatable,
-                        kind: kind.to_string(),
-                        input: value.0.to_string(),
-                        source,
-                    })?;
-                resources.push(Resource {
-                    kind: kind.clone(),
-                    qualifier: ResourceQualifier::Allocatable,
-                    quantity,
-                    location: location.clone(),
-                });
+/// A kind requested (or limited) by pods but allocatable on no node, with the pods (`namespace/name`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnavailableKind {
+    pub kind: String,
+    pub pods: Vec<String>,
+}
+
+/// The kinds with a non-zero requested or limit and a zero allocatable cluster-wide (eg a typo in the name
+/// of an extended resource, a missing device plugin). The devices of the resource claims are ignored
+/// (allocated by their drivers, not allocatable by the nodes).
+pub fn find_unavailable_kinds(resources: &[Resource]) -> Vec<UnavailableKind> {
+    let mut allocatable: BTreeMap<&str, i128> = BTreeMap::new();
+    let mut pods: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for r in resources {
+        match r.qualifier {
+            ResourceQualifier::Allocatable => {
+                *allocatable.entry(&r.kind).or_default() += r.quantity.value;
+            }
+            ResourceQualifier::Requested | ResourceQualifier::Limit
+                if !r.quantity.is_zero()
+                    && r.location.container_name.as_deref() != Some(CLAIMS_CONTAINER) =>
+            {
+                let l = &r.location;
+                pods.entry(&r.kind).or_default().insert(format!(
+                    "{}/{}",
+                    l.namespace.as_deref().unwrap_or_default(),
+                    l.pod_name.as_deref().unwrap_or_default()
+                ));
             }
+            _ => {}
         }
     }
-    Ok(())
+    pods.into_iter()
+        .filter(|(kind, _)| allocatable.get(kind).copied().unwrap_or_default() <= 0)
+        .map(|(kind, pods)| UnavailableKind {
+            kind: kind.to_string(),
+            pods: pods.into_iter().collect(),
+        })
+        .collect()
 }
 
-/*
-The phase of a Pod is a simple, high-level summary of where the Pod is in its lifecycle. The conditions array, the reason and message fields, and the individual container status arrays contain more detail about the pod's status.
-
-There are five possible phase values:
-Pending: The pod has been accepted by the Kubernetes system, but one or more of the container images has not been created. This includes time before being scheduled as well as time spent downloading images over the network, which could take a while.
-Running: The pod has been bound to a node, and all of the containers have been created. At least one container is still running, or is in the process of starting or restarting.
-Succeeded: All containers in the pod have terminated in success, and will not be restarted.
-Failed: All containers in the pod have terminated, and at least one container has terminated in failure. The container either exited with non-zero status or was terminated by the system.
-Unknown: For some reason the state of the pod could not be obtained, typically due to an error in communicating with the host of the pod.
-
-More info: https://kubernetes.io/docs/concepts/workloads/pods/pod-lifecycle#pod-phase
-*/
+/// A node with pods requesting an extended resource (eg `nvidia.com/gpu`) not allocatable by the node (zero or
+/// missing): likely the device plugin is not (yet) registered, eg at the startup of the node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginNotReady {
+    pub node: String,
+    pub kind: String,
+    pub requested: Qty,
+}
 
-pub fn is_scheduled(pod: &Pod) -> bool {
-    pod.status
-        .as_ref()
-        .and_then(|ps| {
-            ps.phase.as_ref().and_then(|phase| {
-                match &phase[..] {
-                    "Succeeded" | "Failed" => Some(false),
-                    "Running" => Some(true),
-                    "Unknown" => None, // this is the case when a node is down (kubelet is not responding)
-                    "Pending" => ps.conditions.as_ref().map(|o| {
-                        o.iter()
-                            .any(|c| c.type_ == "PodScheduled" && c.status == "True")
-                    }),
-                    &_ => None, // should not happen
-                }
-            })
+/// The nodes with pods requesting an extended resource (`<domain>/<name>`, the ones of device plugins) without any
+/// allocatable of it. The devices of the resource claims are ignored (allocated by their drivers).
+pub fn find_plugins_not_ready(resources: &[Resource]) -> Vec<PluginNotReady> {
+    let mut allocatable: BTreeMap<(&str, &str), i128> = BTreeMap::new();
+    let mut requested: BTreeMap<(&str, &str), Qty> = BTreeMap::new();
+    for r in resources.iter().filter(|r| r.kind.contains('/')) {
+        let node = match r.location.node_name.as_deref() {
+            Some(node) => node,
+            None => continue,
+        };
+        match r.qualifier {
+            ResourceQualifier::Allocatable => {
+                *allocatable.entry((node, &r.kind)).or_default() += r.quantity.value;
+            }
+            ResourceQualifier::Requested
+                if r.location.container_name.as_deref() != Some(CLAIMS_CONTAINER) =>
+            {
+                let sum = requested.entry((node, &r.kind)).or_default();
+                *sum = &*sum + &r.quantity;
+            }
+            _ => {}
+        }
+    }
+    requested
+        .into_iter()
+        .filter(|(key, qty)| {
+            !qty.is_zero() && allocatable.get(key).copied().unwrap_or_default() <= 0
         })
-        .unwrap_or(false)
+        .map(|((node, kind), requested)| PluginNotReady {
+            node: node.to_string(),
+            kind: kind.to_string(),
+            requested,
+        })
+        .collect()
 }
 
-fn push_resources(
-    resources: &mut Vec<Resource>,
-    location: &Location,
-    qualifier: ResourceQualifier,
-    resource_list: &BTreeMap<String, Qty>,
-) -> Result<(), Error> {
-    for (key, quantity) in resource_list.iter() {
-        resources.push(Resource {
-            kind: key.clone(),
-            qualifier: qualifier.clone(),
-            quantity: quantity.clone(),
-            location: location.clone(),
-        });
+/// the lines of the notes of `--validate`, the first one is the summary
+pub fn validation_summary(unavailable_kinds: &[UnavailableKind]) -> Vec<String> {
+    if unavailable_kinds.is_empty() {
+        return vec!["Validation: no issue".to_string()];
     }
-    // add a "pods" resource as well
-    resources.push(Resource {
-        kind: "pods".to_string(),
-        qualifier,
-        quantity: Qty::from_str("1")?,
-        location: location.clone(),
-    });
-    Ok(())
+    let mut lines = vec![format!("Validation: {} issue(s)", unavailable_kinds.len())];
+    for u in unavailable_kinds {
+        lines.push(format!(
+            "  {}: requested by {} pods, but allocatable on no node (the pods can't be scheduled): {}",
+            u.kind,
+            u.pods.len(),
+            u.pods.join(", ")
+        ));
+    }
+    lines
 }
 
-fn process_resources<F>(
-    effective_resources: &mut BTreeMap<String, Qty>,
-    resource_list: &BTreeMap<String, k8s_openapi::apimachinery::pkg::api::resource::Quantity>,
-    op: F,
-) -> Result<(), Error>
-where
-    F: Fn(Qty, Qty) -> Qty,
-{
-    for (key, value) in resource_list.iter() {
-        let quantity = Qty::from_str(&(value).0)?;
-        if let Some(current_quantity) = effective_resources.get_mut(key) {
-            *current_quantity = op(current_quantity.clone(), quantity).clone();
-        } else {
-            effective_resources.insert(key.clone(), quantity.clone());
-        }
+/// The threshold (by kind) crossed by the free quantity of a node row (free < threshold)
+pub fn free_below_threshold<'a>(
+    k: &[String],
+    qtys: &QtyByQualifier,
+    group_by: &[GroupBy],
+    thresholds: &'a [(String, Qty)],
+) -> Option<&'a Qty> {
+    if k.len().checked_sub(1).and_then(|depth| group_by.get(depth)) != Some(&GroupBy::node) {
+        return None;
     }
-    Ok(())
+    let kind = group_by
+        .iter()
+        .position(|g| g == &GroupBy::resource)
+        .and_then(|i| k.get(i))?;
+    let threshold = thresholds
+        .iter()
+        .find(|(name, _)| name == kind)
+        .map(|(_, qty)| qty)?;
+    qtys.calc_free()
+        .filter(|free| free < threshold)
+        .map(|_| threshold)
 }
 
-#[instrument(skip(client, resources))]
-pub async fn collect_from_pods(
-    client: kube::Client,
-    resources: &mut Vec<Resource>,
-    namespace: &Option<String>,
-) -> Result<(), Error> {
-    let api_pods: Api<Pod> = if let Some(ns) = namespace {
-        Api::namespaced(client, ns)
-    } else {
-        Api::all(client)
-    };
-    let pods = api_pods
-        .list(&ListParams::default())
-        .await
-        .map_err(|source| Error::KubeError {
-            context: "list pods".to_string(),
-            source,
-        })?;
-    extract_allocatable_from_pods(pods, resources).await?;
-    Ok(())
+/// The nodes with a reserved quantity (capacity - allocatable) different of the expected one (by kind),
+/// as `(node, kind, reserved)`; the kinds without capacity on the node are ignored.
+pub fn reserved_mismatches(
+    sums: &ResourceSums,
+    expected: &[(String, Qty)],
+) -> Vec<(String, String, Qty)> {
+    if expected.is_empty() {
+        return vec![];
+    }
+    let kinds = expected
+        .iter()
+        .map(|(kind, _)| kind.clone())
+        .collect::<Vec<_>>();
+    sums.make_qualifiers(&[GroupBy::resource, GroupBy::node], &kinds)
+        .into_iter()
+        .filter(|(k, _)| k.len() == 2)
+        .filter_map(|(k, oqtys)| {
+            let reserved = oqtys?.calc_reserved()?;
+            let (_, qty) = expected.iter().find(|(kind, _)| kind == &k[0])?;
+            (reserved.value != qty.value).then(|| (k[1].clone(), k[0].clone(), reserved))
+        })
+        .collect()
 }
 
-#[instrument(skip(pod_list, resources))]
-pub async fn extract_allocatable_from_pods(
-    pod_list: ObjectList<Pod>,
-    resources: &mut Vec<Resource>,
-) -> Result<(), Error> {
-    for pod in pod_list.items.into_iter().filter(is_scheduled) {
-        let spec = pod.spec.as_ref();
-        let node_name = spec.and_then(|s| s.node_name.clone());
-        let metadata = &pod.metadata;
-        let location = Location {
-            node_name: node_name.clone(),
-            namespace: metadata.namespace.clone(),
-            pod_name: metadata.name.clone(),
-        };
-        // compute the effective resource qualifier
-        // see https://kubernetes.io/docs/concepts/workloads/pods/init-containers/#resources
-        let mut resource_requests: BTreeMap<String, Qty> = BTreeMap::new();
-        let mut resource_limits: BTreeMap<String, Qty> = BTreeMap::new();
-        // handle regular containers
-        let containers = spec.map(|s| s.containers.clone()).unwrap_or_default();
-        for container in containers.into_iter() {
-            if let Some(requirements) = container.resources {
-                if let Some(r) = requirements.requests {
-                    process_resources(&mut resource_requests, &r, std::ops::Add::add)?
-                }
-                if let Some(r) = requirements.limits {
-                    process_resources(&mut resource_limits, &r, std::ops::Add::add)?;
-                }
-            }
+/// The function combining the requested% of cpu & memory of a node into a score (see `--merge-cpu-memory-into-score`)
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum ScoreFunction {
+    /// the highest (weighted) percentage, the resource that blocks the scheduling first
+    max,
+    /// the (weighted) arithmetic mean
+    mean,
+    /// the (weighted) harmonic mean, low when one of the resources is mostly free
+    harmonic_mean,
+}
+
+impl ScoreFunction {
+    /// the score of the percentages `(pct, weight)`, the weights are relative (eg `cpu=2,memory=1`)
+    pub fn score(&self, pcts: &[(f64, f64)]) -> Option<f64> {
+        let total_weight: f64 = pcts.iter().map(|(_, w)| w).sum();
+        if pcts.is_empty() || total_weight <= 0.0 {
+            return None;
         }
-        // handle initContainers
-        let init_containers = spec
-            .and_then(|s| s.init_containers.clone())
-            .unwrap_or_default();
-        for container in init_containers.into_iter() {
-            if let Some(requirements) = container.resources {
-                if let Some(r) = requirements.requests {
-                    process_resources(&mut resource_requests, &r, std::cmp::max)?;
-                }
-                if let Some(r) = requirements.limits {
-                    process_resources(&mut resource_limits, &r, std::cmp::max)?;
+        let score = match self {
+            ScoreFunction::max => {
+                let max_weight = pcts.iter().map(|(_, w)| *w).fold(0.0, f64::max);
+                pcts.iter()
+                    .map(|(pct, w)| pct * w / max_weight)
+                    .fold(0.0, f64::max)
+            }
+            ScoreFunction::mean => pcts.iter().map(|(pct, w)| pct * w).sum::<f64>() / total_weight,
+            ScoreFunction::harmonic_mean => {
+                if pcts.iter().any(|(pct, w)| *pct <= 0.0 && *w > 0.0) {
+                    0.0
+                } else {
+                    total_weight / pcts.iter().map(|(pct, w)| w / pct).sum::<f64>()
                 }
             }
-        }
-        // handler overhead (add to both requests and limits)
-        if let Some(ref overhead) = spec.and_then(|s| s.overhead.clone()) {
-            process_resources(&mut resource_requests, overhead, std::ops::Add::add)?;
-            process_resources(&mut resource_limits, overhead, std::ops::Add::add)?;
-        }
-        // push these onto resources
-        push_resources(
-            resources,
-            &location,
-            ResourceQualifier::Requested,
-            &resource_requests,
-        )?;
-        push_resources(
-            resources,
-            &location,
-            ResourceQualifier::Limit,
-            &resource_limits,
-        )?;
+        };
+        Some(score)
     }
-    Ok(())
 }
 
-pub fn extract_locations(
-    resources: &[Resource],
-) -> std::collections::HashMap<(String, String), Location> {
-    resources
-        .iter()
-        .filter_map(|resource| {
-            let loc = &resource.location;
-            loc.pod_name.as_ref().map(|n| {
-                (
-                    (loc.namespace.clone().unwrap_or_default(), n.to_owned()),
-                    loc.clone(),
-                )
+/// The fullness of a node, a score of its requested% of cpu & memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeScore {
+    pub node: String,
+    pub cpu_pct: Option<f64>,
+    pub memory_pct: Option<f64>,
+    pub score: f64,
+}
+
+/// The score of every node (with allocatable cpu or memory), from the fullest one,
+/// the weights are by kind (1 if not set)
+pub fn compute_node_scores(
+    sums: &ResourceSums,
+    function: ScoreFunction,
+    weights: &[(String, f64)],
+) -> Vec<NodeScore> {
+    let kinds = ["cpu".to_string(), "memory".to_string()];
+    let mut by_node: BTreeMap<String, BTreeMap<String, f64>> = BTreeMap::new();
+    for (k, oqtys) in sums.make_qualifiers(&[GroupBy::resource, GroupBy::node], &kinds) {
+        let (kind, node, qtys) = match (k.as_slice(), oqtys) {
+            ([kind, node], Some(qtys)) => (kind.clone(), node.clone(), qtys),
+            _ => continue,
+        };
+        let allocatable = match qtys.allocatable.filter(|a| a.value > 0) {
+            Some(allocatable) => allocatable,
+            None => continue,
+        };
+        let pct = qtys
+            .requested
+            .unwrap_or_default()
+            .calc_percentage(&allocatable);
+        by_node.entry(node).or_default().insert(kind, pct);
+    }
+    let weight_of = |kind: &str| {
+        weights
+            .iter()
+            .find(|(k, _)| k == kind)
+            .map(|(_, w)| *w)
+            .unwrap_or(1.0)
+    };
+    let mut scores = by_node
+        .into_iter()
+        .filter_map(|(node, pcts)| {
+            let weighted = pcts
+                .iter()
+                .map(|(kind, pct)| (*pct, weight_of(kind)))
+                .collect::<Vec<_>>();
+            Some(NodeScore {
+                score: function.score(&weighted)?,
+                cpu_pct: pcts.get("cpu").copied(),
+                memory_pct: pcts.get("memory").copied(),
+                node,
             })
         })
-        .collect()
+        .collect::<Vec<_>>();
+    // stable, the nodes of the same score by name
+    scores.sort_by(|a, b| b.score.total_cmp(&a.score));
+    scores
 }
 
-//TODO need location of pods (aka node because its not part of metrics)
-#[instrument(skip(client, resources))]
-pub async fn collect_from_metrics(
-    client: kube::Client,
-    resources: &mut Vec<Resource>,
-) -> Result<(), Error> {
-    let api_pod_metrics: Api<metrics::PodMetrics> = Api::all(client);
-    let pod_metrics = api_pod_metrics
-        .list(&ListParams::default())
-        .await
-        .map_err(|source| Error::KubeError {
-            context: "list podmetrics, maybe Metrics API not available".to_string(),
-            source,
-        })?;
+/// parse a weight `KIND=NUMBER`, eg `memory=2`
+pub fn parse_weight(s: &str) -> Result<(String, f64), String> {
+    let (kind, v) = parse_key_value(s)?;
+    let weight = v
+        .parse::<f64>()
+        .ok()
+        .filter(|w| *w >= 0.0)
+        .ok_or_else(|| format!("invalid weight `{}` (a positive number)", v))?;
+    Ok((kind, weight))
+}
 
-    extract_utilizations_from_pod_metrics(pod_metrics, resources).await?;
-    Ok(())
+/// A node with a reserved fraction (reserved / capacity) far from the median of the nodes (see `--detect-reservation-drift`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReservationDrift {
+    pub node: String,
+    pub kind: String,
+    pub reserved: Qty,
+    /// reserved / capacity of the node, in %
+    pub reserved_pct: f64,
+    /// median of `reserved_pct` of the nodes
+    pub median_pct: f64,
 }
 
-#[instrument(skip(pod_metrics, resources))]
-pub async fn extract_utilizations_from_pod_metrics(
-    pod_metrics: ObjectList<metrics::PodMetrics>,
-    resources: &mut Vec<Resource>,
-) -> Result<(), Error> {
-    let cpu_kind = "cpu";
-    let memory_kind = "memory";
-    let locations = extract_locations(resources);
-    for pod_metric in pod_metrics.items {
-        let metadata = &pod_metric.metadata;
-        let key = (
-            metadata.namespace.clone().unwrap_or_default(),
-            metadata.name.clone().unwrap_or_default(),
-        );
-        let location = locations.get(&key).cloned().unwrap_or_else(|| Location {
-            // node_name: node_name.clone(),
-            namespace: metadata.namespace.clone(),
-            pod_name: metadata.name.clone(),
-            ..Location::default()
-        });
-        let mut cpu_utilization = Qty::default();
-        let mut memory_utilization = Qty::default();
-        for container in pod_metric.containers.into_iter() {
-            cpu_utilization += &Qty::from_str(&container.usage.cpu)
-                .map_err(|source| Error::ResourceQtyParseError {
-                    location: location.clone(),
-                    qualifier: ResourceQualifier::Utilization,
-                    kind: cpu_kind.to_string(),
-                    input: container.usage.cpu.clone(),
-                    source,
-                })?
-                .max(Qty::lowest_positive());
-            memory_utilization += &Qty::from_str(&container.usage.memory)
-                .map_err(|source| Error::ResourceQtyParseError {
-                    location: location.clone(),
-                    qualifier: ResourceQualifier::Utilization,
-                    kind: memory_kind.to_string(),
-                    input: container.usage.memory.clone(),
-                    source,
-                })?
-                .max(Qty::lowest_positive());
-        }
-        resources.push(Resource {
-            kind: cpu_kind.to_string(),
-            qualifier: ResourceQualifier::Utilization,
-            quantity: cpu_utilization,
-            location: location.clone(),
-        });
-        resources.push(Resource {
-            kind: memory_kind.to_string(),
-            qualifier: ResourceQualifier::Utilization,
-            quantity: memory_utilization,
-            location: location.clone(),
-        });
+fn median(values: &mut [f64]) -> Option<f64> {
+    values.sort_by(f64::total_cmp);
+    let n = values.len();
+    match n {
+        0 => None,
+        _ if n % 2 == 1 => Some(values[n / 2]),
+        _ => Some((values[n / 2 - 1] + values[n / 2]) / 2.0),
     }
-    Ok(())
 }
 
-#[derive(Debug, Eq, PartialEq, ValueEnum, Clone)]
-#[allow(non_camel_case_types)]
-pub enum GroupBy {
-    resource,
-    node,
-    pod,
-    namespace,
+/// The nodes with a reserved fraction (capacity - allocatable, over capacity) deviating from the median of the nodes
+/// by more than `threshold_pct` points (by kind), eg a node bootstrapped with other flags of the kubelet.
+/// The kinds with less than 3 nodes are ignored (no meaningful median).
+pub fn reservation_drifts(sums: &ResourceSums, threshold_pct: f64) -> Vec<ReservationDrift> {
+    let mut by_kind: BTreeMap<String, Vec<(String, Qty, f64)>> = BTreeMap::new();
+    for (k, oqtys) in sums.make_qualifiers(&[GroupBy::resource, GroupBy::node], &[]) {
+        let (kind, node, qtys) = match (k.as_slice(), oqtys) {
+            ([kind, node], Some(qtys)) => (kind.clone(), node.clone(), qtys),
+            _ => continue,
+        };
+        let (reserved, capacity) = match (qtys.calc_reserved(), qtys.capacity) {
+            (Some(reserved), Some(capacity)) if capacity.value > 0 => (reserved, capacity),
+            _ => continue,
+        };
+        let reserved_pct = reserved.calc_percentage(&capacity);
+        by_kind
+            .entry(kind)
+            .or_default()
+            .push((node, reserved, reserved_pct));
+    }
+    let mut drifts = vec![];
+    for (kind, nodes) in by_kind.into_iter().filter(|(_, nodes)| nodes.len() >= 3) {
+        let mut pcts = nodes.iter().map(|(_, _, pct)| *pct).collect::<Vec<_>>();
+        let median_pct = match median(&mut pcts) {
+            Some(median_pct) => median_pct,
+            None => continue,
+        };
+        drifts.extend(
+            nodes
+                .into_iter()
+                .filter(|(_, _, pct)| (pct - median_pct).abs() > threshold_pct)
+                .map(|(node, reserved, reserved_pct)| ReservationDrift {
+                    node,
+                    kind: kind.clone(),
+                    reserved,
+                    reserved_pct,
+                    median_pct,
+                }),
+        );
+    }
+    drifts
 }
 
-impl GroupBy {
-    pub fn to_fct(&self) -> fn(&Resource) -> Option<String> {
-        match self {
-            Self::resource => Self::extract_kind,
-            Self::node => Self::extract_node_name,
-            Self::pod => Self::extract_pod_name,
-            Self::namespace => Self::extract_namespace,
-        }
-    }
+/// The cost of the idle capacity of a node (see `--node-cost`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeCost {
+    pub node: String,
+    /// the key of the rate in the file: the name of the node, or its instance type
+    pub rated_by: String,
+    /// cost of the node per hour
+    pub hourly: f64,
+    /// free / allocatable of cpu & memory (weighted equally), in %
+    pub idle_pct: f64,
+    /// `hourly` * `idle_pct`
+    pub idle_hourly: f64,
+}
 
-    fn extract_kind(e: &Resource) -> Option<String> {
-        Some(e.kind.clone())
-    }
+/// The nodes of a pool to reach a target utilization (requested / allocatable) of its most used kind
+/// (see `--target-utilization`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolPlan {
+    /// the value of the label of the pool (`(none)` for the nodes without the label)
+    pub pool: String,
+    pub nodes: usize,
+    /// the requested / allocatable of the kinds of the pool, in %
+    pub utilizations: Vec<(String, f64)>,
+    /// the nodes (of the average allocatable of the pool) for the target, at least 1
+    pub needed: usize,
+    /// the kind needing the most nodes
+    pub bound_by: String,
+}
 
-    fn extract_node_name(e: &Resource) -> Option<String> {
-        e.location.node_name.clone()
+impl PoolPlan {
+    /// the nodes to add (positive) or to remove (negative)
+    pub fn delta(&self) -> i64 {
+        self.needed as i64 - self.nodes as i64
     }
+}
 
-    fn extract_pod_name(e: &Resource) -> Option<String> {
-        // We do not need to display "pods" resource types when grouping by pods
-        if e.kind == "pods" {
-            return None;
+/// The plan of every pool (the nodes grouped by `pool_label`) to reach `target_pct` of utilization:
+/// by kind, the nodes needed are `ceil(requested / (target * allocatable / nodes))`
+pub fn plan_pools(sums: &ResourceSums, pool_label: &str, target_pct: f64) -> Vec<PoolPlan> {
+    let data = sums.make_qualifiers(
+        &[
+            GroupBy::node_label(pool_label.to_string()),
+            GroupBy::resource,
+        ],
+        &[],
+    );
+    let mut plans: BTreeMap<String, PoolPlan> = BTreeMap::new();
+    for (k, oqtys) in data {
+        let (pool, kind, qtys) = match (k.as_slice(), oqtys) {
+            ([pool, kind], Some(qtys)) => (pool, kind, qtys),
+            _ => continue,
+        };
+        let allocatable = match qtys.allocatable.as_ref().filter(|a| !a.is_zero()) {
+            Some(allocatable) if qtys.nodes > 0 => allocatable,
+            _ => continue,
+        };
+        let ratio = qtys
+            .requested
+            .as_ref()
+            .and_then(|requested| requested.ratio(allocatable))
+            .unwrap_or_default();
+        let needed = (ratio * qtys.nodes as f64 * 100.0 / target_pct)
+            .ceil()
+            .max(1.0) as usize;
+        let plan = plans.entry(pool.clone()).or_insert_with(|| PoolPlan {
+            pool: pool.clone(),
+            nodes: qtys.nodes,
+            utilizations: vec![],
+            needed: 0,
+            bound_by: String::new(),
+        });
+        plan.utilizations.push((kind.clone(), ratio * 100.0));
+        if needed > plan.needed {
+            plan.needed = needed;
+            plan.bound_by = kind.clone();
         }
-        e.location.pod_name.clone()
     }
+    plans.into_values().collect()
+}
 
-    fn extract_namespace(e: &Resource) -> Option<String> {
-        e.location.namespace.clone()
-    }
-}
-
-impl std::fmt::Display for GroupBy {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = match self {
-            Self::resource => "resource",
-            Self::node => "node",
-            Self::pod => "pod",
-            Self::namespace => "namespace",
+/// the lines of the notes of the plans of the pools (see `plan_pools`), the first one is the summary
+pub fn pool_plans_summary(plans: &[PoolPlan], pool_label: &str, target_pct: f64) -> Vec<String> {
+    let delta = plans.iter().map(|p| p.delta()).sum::<i64>();
+    let mut lines = vec![format!(
+        "Nodes for a target utilization of {}% (requested / allocatable, by {}): {:+}",
+        target_pct, pool_label, delta
+    )];
+    for p in plans {
+        let action = match p.delta() {
+            0 => "keep".to_string(),
+            d if d > 0 => format!("add {}", d),
+            d => format!("remove {}", -d),
         };
-        f.write_str(s)
+        lines.push(format!(
+            "  {}: {} nodes ({}) -> {} nodes, {} (by {})",
+            p.pool,
+            p.nodes,
+            p.utilizations
+                .iter()
+                .map(|(kind, pct)| format!("{} {:.0}%", kind, pct))
+                .join(", "),
+            p.needed,
+            action,
+            p.bound_by
+        ));
     }
+    lines
 }
 
-#[derive(Debug, Eq, PartialEq, ValueEnum, Clone)]
-#[allow(non_camel_case_types)]
-pub enum Output {
-    table,
-    csv,
+/// The trend of the requested of a kind, from snapshots to now (see `--since-snapshot`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Growth {
+    pub kind: String,
+    /// the requested now
+    pub requested: Qty,
+    pub allocatable: Option<Qty>,
+    /// the growth of the requested by day (the slope of the linear trend), in milli-units (like `Qty.value`),
+    /// none without 2 points at different times
+    pub per_day: Option<f64>,
+    /// the points of the trend: the snapshots with the kind, and now
+    pub points: usize,
 }
 
-#[derive(Parser, Debug)]
-#[command(
-    version, about,
-    after_help(env!("CARGO_PKG_HOMEPAGE")),
-    propagate_version = true
-)]
-pub struct CliOpts {
-    /// The name of the kubeconfig context to use
-    #[arg(long, value_parser)]
-    pub context: Option<String>,
-
-    /// Show only pods from this namespace
-    #[arg(short, long, value_parser)]
-    pub namespace: Option<String>,
-
-    /// Force to retrieve utilization (for cpu and memory), require to have metrics-server https://github.com/kubernetes-sigs/metrics-server
-    #[arg(short = 'u', long, value_parser)]
-    pub utilization: bool,
-
-    /// Show lines with zero requested and zero limit and zero allocatable
-    #[arg(short = 'z', long, value_parser)]
-    pub show_zero: bool,
+impl Growth {
+    /// the growth by day as a quantity (eg "+500.0m" of cpu)
+    pub fn per_day_qty(&self) -> Option<Qty> {
+        self.per_day.map(|per_day| {
+            Qty {
+                value: per_day.round() as i128,
+                micro: 0,
+                scale: self.requested.scale.clone(),
+            }
+            .adjust_scale()
+        })
+    }
 
-    /// Filter resources shown by name(s), by default all resources are listed
-    #[arg(short, long, value_parser)]
-    pub resource_name: Vec<String>,
+    /// the days until the requested reaches the allocatable at the current rate, `Some(0.0)` if already reached,
+    /// none if not growing (or without allocatable)
+    pub fn days_to_full(&self) -> Option<f64> {
+        let allocatable = self.allocatable.as_ref().filter(|a| !a.is_zero())?;
+        match allocatable.checked_sub(&self.requested) {
+            None => Some(0.0),
+            Some(free) => self
+                .per_day
+                .filter(|per_day| *per_day > 0.0)
+                .map(|per_day| free.value as f64 / per_day),
+        }
+    }
+}
 
-    /// Group information hierarchically (default: -g resource -g node -g pod)
-    #[arg(short, long, value_enum, ignore_case = true, value_parser)]
-    pub group_by: Vec<GroupBy>,
+/// the slope of the linear regression (least squares) of the `points` (x, y), none if every x is the same
+pub fn linear_slope(points: &[(f64, f64)]) -> Option<f64> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let var_x = points
+        .iter()
+        .map(|(x, _)| (x - mean_x).powi(2))
+        .sum::<f64>();
+    let cov = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum::<f64>();
+    (var_x > 0.0).then(|| cov / var_x)
+}
 
-    /// Output format
-    #[arg(
-        short,
-        long,
-        value_enum,
-        ignore_case = true,
-        default_value = "table",
-        value_parser
-    )]
-    pub output: Output,
+/// the growth of the requested of every kind (by the rows of the kinds of `sums`), from the `snapshots` to `now`;
+/// the snapshots with a date not rfc3339 are ignored
+pub fn estimate_growth(
+    sums: &ResourceSums,
+    resource_names: &[String],
+    snapshots: &[Baseline],
+    now: DateTime<Utc>,
+) -> Vec<Growth> {
+    let dated = snapshots
+        .iter()
+        .filter_map(
+            |snapshot| match DateTime::parse_from_rfc3339(&snapshot.date) {
+                Ok(date) => Some((date.with_timezone(&Utc), snapshot)),
+                Err(err) => {
+                    warn!(date = snapshot.date, %err, "snapshot ignored, its date is not rfc3339");
+                    None
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+    let days_ago = |date: DateTime<Utc>| -((now - date).num_seconds() as f64) / 86400.0;
+    sums.make_qualifiers(&[GroupBy::resource], resource_names)
+        .into_iter()
+        .filter_map(|(k, oqtys)| {
+            let qtys = oqtys?;
+            let kind = k.first()?.clone();
+            let family = UnitFamily::from_kind(&kind);
+            let requested = qtys.requested.clone().unwrap_or_default();
+            let points = dated
+                .iter()
+                .filter_map(|(date, snapshot)| {
+                    let base = snapshot.kind_row(&kind)?.requested_base.unwrap_or_default();
+                    Some((
+                        days_ago(*date),
+                        Qty::from_base_value(base, family).value as f64,
+                    ))
+                })
+                .chain(std::iter::once((0.0, requested.value as f64)))
+                .collect::<Vec<_>>();
+            Some(Growth {
+                kind,
+                requested,
+                allocatable: qtys.allocatable.clone(),
+                per_day: linear_slope(&points),
+                points: points.len(),
+            })
+        })
+        .collect()
 }
 
-pub async fn refresh_kube_config(cli_opts: &CliOpts) -> Result<(), Error> {
-    //HACK force refresh token by calling "kubectl cluster-info before loading configuration"
-    use std::process::Command;
-    let mut cmd = Command::new("kubectl");
-    cmd.arg("cluster-info");
-    if let Some(ref context) = cli_opts.context {
-        cmd.arg("--context").arg(context);
+/// the lines of the notes of the growths (see `estimate_growth`) as a table, the first one is the summary
+pub fn growth_summary(growths: &[Growth], snapshots: usize, now: DateTime<Utc>) -> Vec<String> {
+    if growths.iter().all(|g| g.per_day.is_none()) {
+        return vec![format!(
+            "Growth of requested: not enough history ({} snapshot(s), a snapshot of the kinds older than now is required)",
+            snapshots
+        )];
     }
-    let output = cmd.output().map_err(|source| Error::CmdError {
-        cmd: "kubectl cluster-info".to_owned(),
-        output: None,
-        source: Some(source),
-    })?;
-    if !output.status.success() {
-        return Err(Error::CmdError {
-            cmd: "kubectl cluster-info".to_owned(),
-            output: Some(output),
-            source: None,
-        });
+    let mut rows = vec![[
+        "Resource".to_string(),
+        "Requested".to_string(),
+        "Allocatable".to_string(),
+        "Growth/day".to_string(),
+        "Full in".to_string(),
+    ]];
+    for g in growths {
+        let full_in = match (g.per_day, g.days_to_full()) {
+            (None, _) => "not enough history".to_string(),
+            (_, Some(days)) if days <= 0.0 => "full".to_string(),
+            (_, Some(days)) if days > 3650.0 => "> 10 years".to_string(),
+            (_, Some(days)) => {
+                let duration = chrono::Duration::seconds((days * 86400.0) as i64);
+                format!(
+                    "{} ({})",
+                    format_age(duration),
+                    (now + duration).format("%Y-%m-%d")
+                )
+            }
+            (_, None) if g.allocatable.is_none() => "__".to_string(),
+            (_, None) => "never (not growing)".to_string(),
+        };
+        rows.push([
+            g.kind.clone(),
+            format!("{}", g.requested.adjust_scale()),
+            g.allocatable
+                .as_ref()
+                .map(|a| format!("{}", a.adjust_scale()))
+                .unwrap_or_else(|| "__".to_string()),
+            g.per_day_qty()
+                .map(|q| format!("{:+}", q))
+                .unwrap_or_else(|| "__".to_string()),
+            full_in,
+        ]);
     }
-    Ok(())
+    let widths = (0..5)
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    std::iter::once(format!(
+        "Growth of requested (linear trend of {} snapshot(s) to now):",
+        snapshots
+    ))
+    .chain(rows.iter().map(|row| {
+        format!(
+            "  {:<w0$}  {:>w1$}  {:>w2$}  {:>w3$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3]
+        )
+    }))
+    .collect()
 }
 
-pub async fn new_client(cli_opts: &CliOpts) -> Result<kube::Client, Error> {
-    refresh_kube_config(cli_opts).await?;
-    let client_config = match cli_opts.context {
-        Some(ref context) => kube::Config::from_kubeconfig(&kube::config::KubeConfigOptions {
-            context: Some(context.clone()),
-            ..Default::default()
-        })
-        .await
-        .map_err(|source| Error::KubeConfigError {
-            context: "create the kube client config".to_string(),
-            source,
-        })?,
-        None => kube::Config::infer()
-            .await
-            .map_err(|source| Error::KubeInferConfigError {
-                context: "create the kube client config".to_string(),
-                source,
-            })?,
-    };
-    info!(cluster_url = client_config.cluster_url.to_string().as_str());
-    kube::Client::try_from(client_config).map_err(|source| Error::KubeError {
-        context: "create the kube client".to_string(),
+/// read the hourly costs of the nodes, by node's name or instance type (label `node.kubernetes.io/instance-type`),
+/// from a yaml (or json) file, eg `{"m5.xlarge": 0.192, "node-1": 0.5}`
+pub fn read_node_costs(path: &std::path::Path) -> Result<BTreeMap<String, f64>, Error> {
+    let content = std::fs::read(path).map_err(|source| Error::ReadFileError {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    serde_yaml::from_slice(&content).map_err(|source| Error::YamlConfigError {
+        path: path.to_path_buf(),
         source,
     })
 }
 
-#[instrument]
-pub async fn do_main(cli_opts: &CliOpts) -> Result<(), Error> {
-    let client = new_client(cli_opts).await?;
-    let mut resources: Vec<Resource> = vec![];
-    collect_from_nodes(client.clone(), &mut resources).await?;
-    collect_from_pods(client.clone(), &mut resources, &cli_opts.namespace).await?;
-
-    let show_utilization = if cli_opts.utilization {
-        match collect_from_metrics(client.clone(), &mut resources).await {
-            Ok(_) => true,
-            Err(err) => {
-                warn!(?err);
-                false
+/// The cost of the idle (free) capacity of the nodes with a rate in `costs` (by name, else by instance type):
+/// the node's rate is split equally between cpu & memory (a simple model, no price by resource)
+pub fn node_costs(
+    resources: &[Resource],
+    sums: &ResourceSums,
+    costs: &BTreeMap<String, f64>,
+    used_basis: UsedBasis,
+    headroom_pct: f64,
+) -> Vec<NodeCost> {
+    let instance_types = resources
+        .iter()
+        .filter(|r| matches!(r.qualifier, ResourceQualifier::Allocatable))
+        .filter_map(|r| {
+            r.location
+                .node_name
+                .as_ref()
+                .map(|node| (node, r.location.node_labels.get(INSTANCE_TYPE_LABEL)))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut data = sums.make_qualifiers(
+        &[GroupBy::node, GroupBy::resource],
+        &["cpu".to_string(), "memory".to_string()],
+    );
+    apply_used_basis(&mut data, used_basis);
+    apply_free_headroom(&mut data, headroom_pct);
+    let mut free_pcts: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for (k, oqtys) in data {
+        if let ([node, _], Some(mut qtys)) = (k.as_slice(), oqtys) {
+            // a node without pod is fully idle
+            if qtys.requested.is_none() && qtys.limit.is_none() {
+                qtys.requested = Some(Qty::default());
+            }
+            if let (Some(free), Some(allocatable)) = (qtys.calc_free(), qtys.calc_percent_base()) {
+                free_pcts
+                    .entry(node.clone())
+                    .or_default()
+                    .push(free.calc_percentage(&allocatable));
             }
         }
-    } else {
-        false
-    };
-
-    let res = make_qualifiers(&resources, &cli_opts.group_by, &cli_opts.resource_name);
-    match &cli_opts.output {
-        Output::table => display_with_prettytable(&res, !&cli_opts.show_zero, show_utilization),
-        Output::csv => display_as_csv(&res, &cli_opts.group_by, show_utilization),
     }
-    Ok(())
+    free_pcts
+        .into_iter()
+        .filter_map(|(node, pcts)| {
+            let rated_by = if costs.contains_key(&node) {
+                node.clone()
+            } else {
+                instance_types
+                    .get(&node)
+                    .copied()
+                    .flatten()
+                    .filter(|t| costs.contains_key(*t))?
+                    .clone()
+            };
+            let hourly = costs[&rated_by];
+            let idle_pct = pcts.iter().sum::<f64>() / pcts.len() as f64;
+            Some(NodeCost {
+                node,
+                rated_by,
+                hourly,
+                idle_pct,
+                idle_hourly: hourly * idle_pct / 100.0,
+            })
+        })
+        .collect()
 }
 
-pub fn display_as_csv(
-    data: &[(Vec<String>, Option<QtyByQualifier>)],
-    group_by: &[GroupBy],
-    show_utilization: bool,
-) {
-    // print header
-    println!(
-        "Date,Kind,{}{},Requested,%Requested,Limit,%Limit,Allocatable,Free",
-        group_by.iter().map(|x| x.to_string()).join(","),
-        if show_utilization {
-            ",Utilization,%Utilization"
+/// the lines of the notes of `--node-cost`, the first one is the total
+pub fn node_costs_summary(costs: &[NodeCost], unrated: usize) -> Vec<String> {
+    let hourly = costs.iter().map(|c| c.hourly).sum::<f64>();
+    let idle_hourly = costs.iter().map(|c| c.idle_hourly).sum::<f64>();
+    let mut lines = vec![format!(
+        "Idle cost (per hour, free cpu & memory weighted equally): {:.3} of {:.3} ({:.1}%)",
+        idle_hourly,
+        hourly,
+        if hourly > 0.0 {
+            idle_hourly * 100.0 / hourly
         } else {
-            ""
+            0.0
         }
-    );
+    )];
+    for c in costs {
+        let rated_by = if c.rated_by == c.node {
+            String::new()
+        } else {
+            format!(" ({})", c.rated_by)
+        };
+        lines.push(format!(
+            "  {}{}: {:.3}/h, idle {:.1}%, {:.3}/h",
+            c.node, rated_by, c.hourly, c.idle_pct, c.idle_hourly
+        ));
+    }
+    if unrated > 0 {
+        lines.push(format!(
+            "  {} nodes without cost (not in the file)",
+            unrated
+        ));
+    }
+    lines
+}
 
-    // print data
-    let empty = "".to_string();
-    let datetime = Utc::now().to_rfc3339();
-    for (k, oqtys) in data {
-        if let Some(qtys) = oqtys {
-            let mut row = vec![
-                datetime.clone(),
-                group_by
-                    .get(k.len() - 1)
-                    .map(|x| x.to_string())
-                    .unwrap_or_else(|| empty.clone()),
-            ];
-            for i in 0..group_by.len() {
-                row.push(k.get(i).cloned().unwrap_or_else(|| empty.clone()));
-            }
-
-            if show_utilization {
-                add_cells_for_cvs(&mut row, &qtys.utilization, &qtys.allocatable);
-            }
-            add_cells_for_cvs(&mut row, &qtys.requested, &qtys.allocatable);
-            add_cells_for_cvs(&mut row, &qtys.limit, &qtys.allocatable);
-
-            row.push(
-                qtys.allocatable
-                    .as_ref()
-                    .map(|qty| format!("{:.2}", f64::from(qty)))
-                    .unwrap_or_else(|| empty.clone()),
-            );
-            row.push(
-                qtys.calc_free()
-                    .as_ref()
-                    .map(|qty| format!("{:.2}", f64::from(qty)))
-                    .unwrap_or_else(|| empty.clone()),
-            );
-            println!("{}", &row.join(","));
-        }
+/// parse `NODE[:KIND]` (see `--explain`), the kind is `cpu` by default
+pub fn parse_explain(s: &str) -> Result<(String, String), String> {
+    let (node, kind) = s.split_once(':').unwrap_or((s, "cpu"));
+    if node.is_empty() || kind.is_empty() {
+        return Err(format!(
+            "invalid `{}` (expected NODE[:KIND], eg `node-1:memory`)",
+            s
+        ));
     }
+    Ok((node.to_string(), kind.to_string()))
 }
 
-fn add_cells_for_cvs(row: &mut Vec<String>, oqty: &Option<Qty>, o100: &Option<Qty>) {
-    match oqty {
-        None => {
-            row.push("".to_string());
-            row.push("".to_string());
+/// the lines of the notes tracing how the numbers of a kind on a node are computed (see `--explain`):
+/// the containers contributing to requested, limit & utilization, the source of allocatable, and the formula of free
+pub fn explain_node(
+    resources: &[Resource],
+    node: &str,
+    kind: &str,
+    headroom_pct: f64,
+    used_basis: UsedBasis,
+) -> Vec<String> {
+    let rsrcs = resources
+        .iter()
+        .filter(|r| r.kind == kind && r.location.node_name.as_deref() == Some(node))
+        .collect::<Vec<_>>();
+    let mut qtys = match sum_by_qualifier(&rsrcs) {
+        Some(qtys) => qtys,
+        None => return vec![format!("No {} on node {}", kind, node)],
+    };
+    let fmt = |oqty: &Option<Qty>| {
+        oqty.as_ref()
+            .map(|qty| qty.adjust_scale().to_string())
+            .unwrap_or_else(|| "__".to_string())
+    };
+    let mut lines = vec![format!("Explanation of {} on node {}:", kind, node)];
+    let mut allocatable = format!(
+        "  allocatable: {} (status.allocatable of the node)",
+        fmt(&qtys.allocatable)
+    );
+    if qtys.capacity.is_some() {
+        allocatable.push_str(&format!(
+            ", capacity: {}, reserved (capacity - allocatable): {}",
+            fmt(&qtys.capacity),
+            fmt(&qtys.calc_reserved())
+        ));
+    }
+    lines.push(allocatable);
+    let qualifiers = [
+        ("requested", ResourceQualifier::Requested, &qtys.requested),
+        ("limit", ResourceQualifier::Limit, &qtys.limit),
+        (
+            "utilization",
+            ResourceQualifier::Utilization,
+            &qtys.utilization,
+        ),
+    ];
+    for (name, qualifier, total) in qualifiers {
+        let contributions = rsrcs
+            .iter()
+            .filter(|r| std::mem::discriminant(&r.qualifier) == std::mem::discriminant(&qualifier))
+            .collect::<Vec<_>>();
+        if contributions.is_empty() {
+            continue;
         }
-        Some(ref qty) => {
-            row.push(format!("{:.2}", f64::from(qty)));
-            row.push(match o100 {
-                None => "".to_string(),
-                Some(q100) => format!("{:.0}%", qty.calc_percentage(q100)),
-            });
+        lines.push(format!(
+            "  {}: {} = sum of {} containers",
+            name,
+            fmt(total),
+            contributions.len()
+        ));
+        for r in contributions {
+            let location = &r.location;
+            lines.push(format!(
+                "    {}/{}/{}: {}",
+                location.namespace.as_deref().unwrap_or("__"),
+                location.pod_name.as_deref().unwrap_or("__"),
+                location.container_name.as_deref().unwrap_or("__"),
+                r.quantity.adjust_scale()
+            ));
         }
+    }
+    if qtys.no_limit {
+        lines.push("  limit is unbounded (a container requests without limit)".to_string());
+    }
+    qtys.used_basis = used_basis;
+    let mut formula = "allocatable".to_string();
+    let mut values = fmt(&qtys.allocatable);
+    if headroom_pct > 0.0 {
+        let mut data = vec![(vec![], Some(qtys))];
+        apply_free_headroom(&mut data, headroom_pct);
+        qtys = data.pop().and_then(|(_, qtys)| qtys).unwrap_or_default();
+        formula.push_str(&format!(" - headroom ({}%)", headroom_pct));
+        values.push_str(&format!(" - {}", fmt(&qtys.headroom)));
+    }
+    let used = match used_basis {
+        UsedBasis::requested => qtys.requested.clone(),
+        UsedBasis::limit => qtys.limit.clone(),
+        UsedBasis::max => std::cmp::max(qtys.limit.clone(), qtys.requested.clone()),
     };
+    lines.push(format!(
+        "  free = {} - {} = {} - {} = {} (0 if negative)",
+        formula,
+        used_basis,
+        values,
+        fmt(&used),
+        fmt(&qtys.calc_free())
+    ));
+    lines
 }
 
-#[cfg(not(feature = "prettytable"))]
-pub fn display_with_prettytable(
-    _data: &[(Vec<String>, Option<QtyByQualifier>)],
-    _filter_full_zero: bool,
-    _show_utilization: bool,
-) {
-    warn!("feature 'prettytable' not enabled");
+/// the lines of the notes about the drifts of the reservations
+pub fn reservation_drifts_summary(drifts: &[ReservationDrift], threshold_pct: f64) -> Vec<String> {
+    if drifts.is_empty() {
+        return vec![format!(
+            "No reservation drift (reserved / capacity within {}% of the median of the nodes)",
+            threshold_pct
+        )];
+    }
+    std::iter::once(format!(
+        "Reservation drift (reserved / capacity more than {}% from the median of the nodes):",
+        threshold_pct
+    ))
+    .chain(drifts.iter().map(|d| {
+        format!(
+            "  {} {}: reserved {} ({:.1}%), median {:.1}%",
+            d.node,
+            d.kind,
+            d.reserved.adjust_scale(),
+            d.reserved_pct,
+            d.median_pct
+        )
+    }))
+    .collect()
 }
 
-#[cfg(feature = "prettytable")]
-pub fn display_with_prettytable(
-    data: &[(Vec<String>, Option<QtyByQualifier>)],
-    filter_full_zero: bool,
-    show_utilization: bool,
-) {
-    // Create the table
-    let mut table = Table::new();
-    let format = format::FormatBuilder::new()
-        // .column_separator('|')
-        // .borders('|')
-        // .separators(&[format::LinePosition::Top,
-        //               format::LinePosition::Bottom],
-        //             format::LineSeparator::new('-', '+', '+', '+'))
-        .separators(&[], format::LineSeparator::new('-', '+', '+', '+'))
-        .padding(1, 1)
-        .build();
-    table.set_format(format);
-    let mut row_titles = row![bl->"Resource", br->"Utilization", br->"Requested", br->"Limit",  br->"Allocatable", br->"Free"];
-    if !show_utilization {
-        row_titles.remove_cell(1);
-    }
-    table.set_titles(row_titles);
-    let data2 = data
+/// The resources of a count kind (eg `pods`, `nvidia.com/gpu`) with a quantity not integer
+pub fn non_integer_quantities<'a>(
+    resources: &'a [Resource],
+    integer_kinds: &[String],
+) -> Vec<&'a Resource> {
+    resources
         .iter()
-        .filter(|d| {
-            !filter_full_zero
-                || !d
-                    .1
-                    .as_ref()
-                    .map(|x| {
-                        x.utilization.is_none()
-                            && is_empty(&x.requested)
-                            && is_empty(&x.limit)
-                            && is_empty(&x.allocatable)
-                    })
-                    .unwrap_or(false)
+        .filter(|r| integer_kinds.contains(&r.kind))
+        .filter(|r| r.quantity.value % 1000 != 0)
+        .collect()
+}
+
+/// the buckets of requested% (of the allocatable) of `--histogram`, the last one is for the overcommitted nodes
+pub const HISTOGRAM_BUCKETS: [&str; 5] = ["0-25%", "25-50%", "50-75%", "75-100%", ">100%"];
+
+/// The nodes binned by requested% of a kind (see `--histogram`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub kind: String,
+    /// nodes by bucket of `HISTOGRAM_BUCKETS`
+    pub counts: [usize; 5],
+}
+
+fn histogram_bucket(percentage: f64) -> usize {
+    match percentage {
+        p if p < 25.0 => 0,
+        p if p < 50.0 => 1,
+        p if p < 75.0 => 2,
+        p if p <= 100.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Bin the nodes by requested% for every kind, the nodes without allocatable (of the kind) are ignored
+pub fn compute_histograms(sums: &ResourceSums, resource_names: &[String]) -> Vec<Histogram> {
+    let mut histograms: BTreeMap<String, [usize; 5]> = BTreeMap::new();
+    for (k, oqtys) in sums.make_qualifiers(&[GroupBy::resource, GroupBy::node], resource_names) {
+        let (kind, qtys) = match (k.as_slice(), oqtys) {
+            ([kind, _node], Some(qtys)) => (kind.clone(), qtys),
+            _ => continue,
+        };
+        let allocatable = match qtys.allocatable.filter(|a| a.value > 0) {
+            Some(allocatable) => allocatable,
+            None => continue,
+        };
+        let percentage = qtys
+            .requested
+            .unwrap_or_default()
+            .calc_percentage(&allocatable);
+        histograms.entry(kind).or_default()[histogram_bucket(percentage)] += 1;
+    }
+    histograms
+        .into_iter()
+        .map(|(kind, counts)| Histogram { kind, counts })
+        .collect()
+}
+
+impl Histogram {
+    pub fn summary(&self) -> Vec<String> {
+        let total: usize = self.counts.iter().sum();
+        std::iter::once(format!(
+            "Nodes by requested {} (of {} nodes):",
+            self.kind, total
+        ))
+        .chain(
+            HISTOGRAM_BUCKETS
+                .iter()
+                .zip(self.counts.iter())
+                .map(|(bucket, count)| {
+                    format!(
+                        "  {:>7} {} {}",
+                        bucket,
+                        make_bar(*count as f64 * 100.0 / total.max(1) as f64, 20),
+                        count
+                    )
+                }),
+        )
+        .collect()
+    }
+}
+
+/// How many pods of a size (see `--fits`) can be scheduled on the free of the nodes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fits {
+    /// pods fitting by node
+    pub by_node: Vec<(String, i64)>,
+    /// pods fitting on the nodes (sum of `by_node`)
+    pub on_nodes: i64,
+    /// pods fitting into the total free, as if the nodes were a single one
+    pub in_total: i64,
+}
+
+/// the number of `pod` fitting into the `free` quantities (by kind), the kinds requested to zero are ignored
+fn count_fits(free: &BTreeMap<String, Qty>, pod: &[(String, Qty)]) -> i64 {
+    pod.iter()
+        .filter(|(_, request)| request.value > 0)
+        .map(|(kind, request)| {
+            free.get(kind)
+                .map(|free| i64::try_from(free.value.max(0) / request.value).unwrap_or(i64::MAX))
+                .unwrap_or(0)
         })
-        .collect::<Vec<_>>();
-    let prefixes = tree::provide_prefix(&data2, |parent, item| parent.0.len() + 1 == item.0.len());
-
-    for ((k, oqtys), prefix) in data2.iter().zip(prefixes.iter()) {
-        let column0 = format!(
-            "{} {}",
-            prefix,
-            k.last().map(|x| x.as_str()).unwrap_or("???")
-        );
-        if let Some(qtys) = oqtys {
-            let style = if qtys.requested > qtys.limit
-                || qtys.utilization > qtys.limit
-                || is_empty(&qtys.requested)
-                || is_empty(&qtys.limit)
-            {
-                "rFy"
-            } else {
-                "rFg"
-            };
-            let mut row = Row::new(vec![
-                Cell::new(&column0),
-                make_cell_for_prettytable(&qtys.utilization, &qtys.allocatable).style_spec(style),
-                make_cell_for_prettytable(&qtys.requested, &qtys.allocatable).style_spec(style),
-                make_cell_for_prettytable(&qtys.limit, &qtys.allocatable).style_spec(style),
-                make_cell_for_prettytable(&qtys.allocatable, &None).style_spec(style),
-                make_cell_for_prettytable(&qtys.calc_free(), &None).style_spec(style),
-            ]);
-            if !show_utilization {
-                row.remove_cell(1);
+        .min()
+        .unwrap_or(0)
+}
+
+/// The min & the max of the free of the nodes, by kind (with `used_basis` & `headroom_pct` applied, like the free),
+/// a node without pods is fully free (see `--free-spread`)
+pub fn free_spread_by_kind(
+    sums: &ResourceSums,
+    used_basis: UsedBasis,
+    headroom_pct: f64,
+) -> BTreeMap<String, (Qty, Qty)> {
+    let mut rows = sums.make_qualifiers(&[GroupBy::resource, GroupBy::node], &[]);
+    apply_used_basis(&mut rows, used_basis);
+    apply_free_headroom(&mut rows, headroom_pct);
+    let mut spread: BTreeMap<String, (Qty, Qty)> = BTreeMap::new();
+    for (k, oqtys) in rows {
+        if let ([kind, _], Some(mut qtys)) = (k.as_slice(), oqtys) {
+            if qtys.requested.is_none() && qtys.limit.is_none() {
+                qtys.requested = Some(Qty::default());
+            }
+            if let Some(free) = qtys.calc_free() {
+                spread
+                    .entry(kind.clone())
+                    .and_modify(|(min, max)| {
+                        if free < *min {
+                            *min = free.clone();
+                        }
+                        if free > *max {
+                            *max = free.clone();
+                        }
+                    })
+                    .or_insert_with(|| (free.clone(), free));
             }
-            table.add_row(row);
         }
     }
+    spread
+}
 
-    // Print the table to stdout
-    table.printstd();
+/// Count the pods of the size `pod` (requests by kind) fitting on the free of every node
+/// (with the `--free-headroom` percentage kept), and into the total free.
+/// The difference shows the free fragmented across the nodes (ignoring the affinities,
+/// taints, topology spread constraints,... that can only reduce it).
+pub fn compute_fits(sums: &ResourceSums, pod: &[(String, Qty)], headroom_pct: f64) -> Fits {
+    let kinds = pod.iter().map(|(kind, _)| kind.clone()).collect::<Vec<_>>();
+    let mut rows = sums.make_qualifiers(&[GroupBy::resource, GroupBy::node], &kinds);
+    apply_free_headroom(&mut rows, headroom_pct);
+    let mut total: BTreeMap<String, Qty> = BTreeMap::new();
+    let mut by_node: BTreeMap<String, BTreeMap<String, Qty>> = BTreeMap::new();
+    for (k, oqtys) in rows {
+        // nothing requested, everything is free
+        let free = match oqtys.and_then(|qtys| {
+            QtyByQualifier {
+                requested: qtys.requested.clone().or_else(|| Some(Qty::default())),
+                ..qtys
+            }
+            .calc_free()
+        }) {
+            Some(free) => free,
+            None => continue,
+        };
+        match k.as_slice() {
+            [kind] => {
+                total.insert(kind.clone(), free);
+            }
+            [kind, node] => {
+                by_node
+                    .entry(node.clone())
+                    .or_default()
+                    .insert(kind.clone(), free);
+            }
+            _ => {}
+        }
+    }
+    let by_node = by_node
+        .iter()
+        .map(|(node, free)| (node.clone(), count_fits(free, pod)))
+        .collect::<Vec<_>>();
+    Fits {
+        on_nodes: by_node.iter().map(|(_, count)| count).sum(),
+        in_total: count_fits(&total, pod),
+        by_node,
+    }
 }
 
-#[cfg(feature = "prettytable")]
-fn is_empty(oqty: &Option<Qty>) -> bool {
-    match oqty {
-        Some(qty) => qty.is_zero(),
-        None => true,
+impl Fits {
+    pub fn summary(&self, pod: &[(String, Qty)]) -> Vec<String> {
+        let size = pod
+            .iter()
+            .map(|(kind, qty)| format!("{}={}", kind, qty.adjust_scale()))
+            .join(", ");
+        let mut lines = vec![format!(
+            "Fits {} pods ({}) on {} of {} nodes",
+            self.on_nodes,
+            size,
+            self.by_node.iter().filter(|(_, count)| *count > 0).count(),
+            self.by_node.len()
+        )];
+        if let Some((node, count)) = self
+            .by_node
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            // the first one (by name) on ties
+            .rev()
+            .max_by_key(|(_, count)| *count)
+        {
+            lines.push(format!("  the most on {} ({})", node, count));
+        }
+        if self.on_nodes < self.in_total {
+            lines.push(format!(
+                "  the total free would fit {} pods, but it is fragmented across the nodes \
+                 (affinities & topology spread constraints can reduce it more)",
+                self.in_total
+            ));
+        }
+        lines
     }
 }
 
-#[cfg(feature = "prettytable")]
-fn make_cell_for_prettytable(oqty: &Option<Qty>, o100: &Option<Qty>) -> Cell {
-    let txt = match oqty {
-        None => "__".to_string(),
-        Some(ref qty) => match o100 {
-            None => format!("{}", qty.adjust_scale()),
-            Some(q100) => format!("({:.0}%) {}", qty.calc_percentage(q100), qty.adjust_scale()),
-        },
+/// the kinds sized by the average pod (see `average_pod`)
+const AVERAGE_POD_KINDS: [&str; 2] = ["cpu", "memory"];
+
+/// the average requests of the pods (the totals of cpu & memory requested by the pods counted by the kind `pods`),
+/// the kinds not requested are ignored
+pub fn average_pod(sums: &ResourceSums) -> Vec<(String, Qty)> {
+    let rows = sums.make_qualifiers(&[GroupBy::resource], &[]);
+    let requested_of = |kind: &str| {
+        rows.iter()
+            .find(|(k, _)| k.len() == 1 && k[0] == kind)
+            .and_then(|(_, oqtys)| oqtys.as_ref())
+            .and_then(|qtys| qtys.requested.clone())
+            .filter(|qty| !qty.is_zero())
     };
-    Cell::new(&txt)
+    let pods = match requested_of("pods") {
+        Some(pods) => pods.to_base_value(UnitFamily::Count),
+        None => return vec![],
+    };
+    AVERAGE_POD_KINDS
+        .iter()
+        .filter_map(|kind| {
+            let total = requested_of(kind)?;
+            Some((
+                kind.to_string(),
+                Qty {
+                    value: total.value / pods,
+                    micro: 0,
+                    scale: total.scale,
+                },
+            ))
+        })
+        .filter(|(_, qty)| !qty.is_zero())
+        .collect()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// The density of the pods of a node: the pods it holds vs the most it could hold by kind (see `--pod-density`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodDensity {
+    pub node: String,
+    /// the pods on the node
+    pub pods: i128,
+    /// the most pods by kind: `pods` (the cap of the allocatable), then the pods of the size fitting the allocatable
+    /// of every other kind
+    pub max_by_kind: Vec<(String, i128)>,
+}
 
-    #[test]
-    fn test_accept_resource() {
-        assert!(accept_resource("cpu", &[]));
-        assert!(accept_resource("cpu", &["c".to_string()]));
-        assert!(accept_resource("cpu", &["cpu".to_string()]));
-        assert!(!accept_resource("cpu", &["cpu3".to_string()]));
-        assert!(accept_resource("gpu", &["gpu".to_string()]));
-        assert!(accept_resource("nvidia.com/gpu", &["gpu".to_string()]));
+impl PodDensity {
+    /// the kind binding the density (the smallest max, the first one on ties) and its max
+    pub fn binding(&self) -> Option<&(String, i128)> {
+        self.max_by_kind.iter().min_by_key(|(_, max)| *max)
+    }
+}
+
+/// the densities of the pods of the nodes (with an allocatable), for pods of the size `pod` (requests by kind)
+pub fn compute_pod_densities(sums: &ResourceSums, pod: &[(String, Qty)]) -> Vec<PodDensity> {
+    let kinds = std::iter::once("pods".to_string())
+        .chain(pod.iter().map(|(kind, _)| kind.clone()))
+        .collect::<Vec<_>>();
+    let mut by_node: BTreeMap<String, BTreeMap<String, QtyByQualifier>> = BTreeMap::new();
+    for (k, oqtys) in sums.make_qualifiers(&[GroupBy::node, GroupBy::resource], &kinds) {
+        if let ([node, kind], Some(qtys)) = (k.as_slice(), oqtys) {
+            by_node
+                .entry(node.clone())
+                .or_default()
+                .insert(kind.clone(), qtys);
+        }
+    }
+    by_node
+        .into_iter()
+        .filter_map(|(node, qtys)| {
+            let allocatable_of = |kind: &str| qtys.get(kind).and_then(|q| q.allocatable.clone());
+            let mut max_by_kind = vec![];
+            if let Some(cap) = allocatable_of("pods") {
+                max_by_kind.push(("pods".to_string(), cap.to_base_value(UnitFamily::Count)));
+            }
+            for (kind, size) in pod
+                .iter()
+                .filter(|(kind, qty)| kind != "pods" && !qty.is_zero())
+            {
+                // a kind not provided by the node fits no pod
+                let allocatable = allocatable_of(kind).unwrap_or_default();
+                max_by_kind.push((kind.clone(), allocatable.value / size.value.max(1)));
+            }
+            if max_by_kind.is_empty() {
+                return None;
+            }
+            let pods = qtys
+                .get("pods")
+                .and_then(|q| q.requested.as_ref())
+                .map(|q| q.to_base_value(UnitFamily::Count))
+                .unwrap_or_default();
+            Some(PodDensity {
+                node,
+                pods,
+                max_by_kind,
+            })
+        })
+        .collect()
+}
+
+/// the lines of the notes of the densities (see `compute_pod_densities`), the first one is the summary
+pub fn pod_densities_summary(densities: &[PodDensity], pod: &[(String, Qty)]) -> Vec<String> {
+    let size = pod
+        .iter()
+        .map(|(kind, qty)| format!("{}={}", kind, qty.adjust_scale()))
+        .join(", ");
+    let mut lines = vec![format!(
+        "Pod density (the most pods by node, for pods of {}):",
+        if size.is_empty() {
+            "no size, no pod requesting cpu or memory".to_string()
+        } else {
+            size
+        }
+    )];
+    for density in densities {
+        if let Some((kind, max)) = density.binding() {
+            lines.push(format!(
+                "  {}: {} of max {} pods, bound by {} ({})",
+                density.node,
+                density.pods,
+                max,
+                kind,
+                density
+                    .max_by_kind
+                    .iter()
+                    .map(|(kind, max)| format!("{} {}", kind, max))
+                    .join(", ")
+            ));
+        }
+    }
+    lines
+}
+
+/// Sort the rows (from `make_qualifiers`) by the `sort_by` criteria in the `order` direction,
+/// only siblings are reordered, so every row stays after its parent (required by `tree::provide_prefix`).
+/// Siblings with the same quantity (or without) are sorted by name alphabetically.
+/// Top-level rows (resource kinds) are sorted by name (ascending when sorted by quantity),
+/// their quantities are not comparable.
+pub fn sort_qualifiers(
+    data: &mut [(Vec<String>, Option<QtyByQualifier>)],
+    sort_by: &SortBy,
+    order: &SortOrder,
+) {
+    let metrics: std::collections::HashMap<Vec<String>, Option<Qty>> = data
+        .iter()
+        .map(|(k, oqtys)| (k.clone(), oqtys.as_ref().and_then(|q| sort_by.extract(q))))
+        .collect();
+    data.sort_by(|a, b| {
+        let common =
+            a.0.iter()
+                .zip(b.0.iter())
+                .take_while(|(x, y)| x == y)
+                .count();
+        if common == a.0.len() || common == b.0.len() {
+            // one is the ancestor of the other, whatever the order
+            return a.0.len().cmp(&b.0.len());
+        }
+        // the kinds with numbers (eg the MIG profiles `nvidia.com/mig-1g.5gb`, `nvidia.com/mig-1g.10gb`) by size
+        let by_name = if common == 0 {
+            natural_cmp(&a.0[0], &b.0[0])
+        } else {
+            a.0[common].cmp(&b.0[common])
+        };
+        if sort_by == &SortBy::name {
+            return order.apply(by_name);
+        }
+        if common == 0 {
+            return by_name;
+        }
+        let ma = metrics.get(&a.0[..=common]).cloned().flatten();
+        let mb = metrics.get(&b.0[..=common]).cloned().flatten();
+        order.apply(ma.cmp(&mb)).then(by_name)
+    });
+}
+
+/// compare the strings with their runs of digits compared as numbers, eg `mig-2g.10gb` after `mig-2g.5gb`
+pub fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let number = |it: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = it.next_if(|c| c.is_ascii_digit()) {
+                        digits.push(c);
+                    }
+                    digits.trim_start_matches('0').to_string()
+                };
+                let (x, y) = (number(&mut a), number(&mut b));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Keep only the `top` first children of every top-level row (eg the nodes of each resource),
+/// remaining children (and their descendants) are aggregated into a "(others)" row.
+/// The input should already be sorted (see `sort_qualifiers`).
+pub fn keep_top_qualifiers(
+    data: Vec<(Vec<String>, Option<QtyByQualifier>)>,
+    top: usize,
+) -> Vec<(Vec<String>, Option<QtyByQualifier>)> {
+    let mut out = vec![];
+    let mut others: Option<(Vec<String>, Option<QtyByQualifier>)> = None;
+    let mut children_count = 0;
+    for (k, oqtys) in data {
+        match k.len() {
+            1 => {
+                out.extend(others.take());
+                children_count = 0;
+                out.push((k, oqtys));
+            }
+            2 => {
+                children_count += 1;
+                if children_count <= top {
+                    out.push((k, oqtys));
+                } else {
+                    let (_, others_qtys) = others
+                        .get_or_insert_with(|| (vec![k[0].clone(), "(others)".to_string()], None));
+                    if let Some(qtys) = oqtys {
+                        *others_qtys =
+                            Some(others_qtys.as_ref().map(|o| o.merge(&qtys)).unwrap_or(qtys));
+                    }
+                }
+            }
+            _ => {
+                if children_count <= top {
+                    out.push((k, oqtys));
+                }
+            }
+        }
+    }
+    out.extend(others);
+    out
+}
+
+fn accept_resource(name: &str, resource_filter: &[String]) -> bool {
+    resource_filter.is_empty() || resource_filter.iter().any(|x| name.contains(x))
+}
+
+/// the kind is excluded by a part of its name (like `accept_resource`), see `--exclude-resource`
+fn exclude_resource(name: &str, excluded: &[String]) -> bool {
+    excluded.iter().any(|x| name.contains(x))
+}
+
+fn accept_namespace(namespace: &Option<String>, excluded_namespaces: &[String]) -> bool {
+    namespace
+        .as_ref()
+        .map(|ns| !excluded_namespaces.contains(ns))
+        .unwrap_or(true)
+}
+
+/// The regex of `--namespace-regex`, compiled once, it should match the whole name of the namespace
+#[derive(Debug, Clone)]
+pub struct NamespaceRegex {
+    pattern: String,
+    regex: regex_automata::meta::Regex,
+}
+
+impl NamespaceRegex {
+    /// the resources without namespace (the allocatable of nodes) are accepted
+    pub fn accept(&self, namespace: &Option<String>) -> bool {
+        namespace
+            .as_ref()
+            .map(|ns| self.regex.is_match(ns))
+            .unwrap_or(true)
+    }
+}
+
+impl std::fmt::Display for NamespaceRegex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+pub fn parse_namespace_regex(s: &str) -> Result<NamespaceRegex, String> {
+    let regex = regex_automata::meta::Regex::new(&format!("^(?:{})$", s))
+        .map_err(|e| format!("invalid regex '{}': {}", s, e))?;
+    Ok(NamespaceRegex {
+        pattern: s.to_string(),
+        regex,
+    })
+}
+
+/// The rules of `--hook`
+#[cfg(feature = "hook")]
+pub type HookProgram = hook::Program;
+/// The rules of `--hook`, rejected when used (see `apply_hook`)
+#[cfg(not(feature = "hook"))]
+pub type HookProgram = String;
+
+#[cfg(feature = "hook")]
+pub fn parse_hook(s: &str) -> Result<HookProgram, String> {
+    s.parse()
+}
+
+#[cfg(not(feature = "hook"))]
+pub fn parse_hook(s: &str) -> Result<HookProgram, String> {
+    Ok(s.to_string())
+}
+
+/// Keep only the resources of the pod `pod` (`name` or `namespace/name`)
+pub fn retain_pod(resources: &mut Vec<Resource>, pod: &str) -> Result<(), Error> {
+    let (namespace, name) = match pod.split_once('/') {
+        Some((ns, name)) => (Some(ns), name),
+        None => (None, pod),
+    };
+    resources.retain(|r| {
+        r.location.pod_name.as_deref() == Some(name)
+            && (namespace.is_none() || r.location.namespace.as_deref() == namespace)
+    });
+    if resources.is_empty() {
+        return Err(Error::PodNotFound {
+            pod: pod.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Keep only the resources of nodes and of pods with a priority >= `min_priority`
+/// (pods with a lower priority can be preempted, so they don't reduce the free for higher priority).
+/// Pods without priority have the default priority 0.
+pub fn retain_for_priority(resources: &mut Vec<Resource>, min_priority: i32) {
+    resources.retain(|r| {
+        r.location.pod_name.is_none() || r.location.priority.unwrap_or(0) >= min_priority
+    });
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[allow(non_camel_case_types)]
+pub enum GroupBy {
+    resource,
+    node,
+    pod,
+    namespace,
+    qos,
+    container,
+    /// the value of a label of the node (`node-label=<KEY>`)
+    node_label(String),
+    /// the value of an annotation of the pod (`annotation=<KEY>`)
+    annotation(String),
+    /// the lifecycle of the node (`spot` or `on-demand`), from the well-known labels
+    /// or from a label (`lifecycle=<KEY>`)
+    lifecycle(Option<String>),
+    /// the group of the node, from the mapping of `--node-group-file` (`node-group`)
+    node_group(Arc<BTreeMap<String, String>>),
+    /// the state of the node: ready, cordoned or not-ready (`node-state`)
+    node_state,
+    /// the roles of the node, from the `node-role.kubernetes.io/<ROLE>` labels (`node-role`)
+    node_role,
+    /// the top-level owner of the pod, eg `Deployment/web` (`workload`)
+    workload,
+    /// the kubeconfig context of the cluster (`cluster`, see `--batch`)
+    cluster,
+}
+
+/// move the group by resource just after the first group by node (`-g node`, or the instance type of
+/// `--merge-similar-nodes`), see `--node-first`; `false` without group by node (unchanged)
+pub fn move_resource_after_node(group_by: &mut Vec<GroupBy>) -> bool {
+    let is_node = |g: &GroupBy| match g {
+        GroupBy::node => true,
+        GroupBy::node_label(label) => label == INSTANCE_TYPE_LABEL,
+        _ => false,
+    };
+    match (
+        group_by.iter().position(|g| g == &GroupBy::resource),
+        group_by.iter().position(is_node),
+    ) {
+        (Some(resource), Some(node)) => {
+            let g = group_by.remove(resource);
+            let node = if resource < node { node - 1 } else { node };
+            group_by.insert(node + 1, g);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// read the groups of nodes (by node's name) from a yaml (or json) file,
+/// eg `{"node-1": "rack-a", "node-2": "rack-b"}`
+pub fn read_node_groups(path: &std::path::Path) -> Result<BTreeMap<String, String>, Error> {
+    let content = std::fs::read(path).map_err(|source| Error::ReadFileError {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    serde_yaml::from_slice(&content).map_err(|source| Error::YamlConfigError {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// labels of the nodes giving their lifecycle (by cloud provider & autoscaler)
+const LIFECYCLE_LABELS: [&str; 6] = [
+    "node.kubernetes.io/lifecycle",
+    "karpenter.sh/capacity-type",
+    "eks.amazonaws.com/capacityType",
+    "cloud.google.com/gke-spot",
+    "cloud.google.com/gke-preemptible",
+    "kubernetes.azure.com/scalesetpriority",
+];
+
+/// prefix of the labels of the roles of a node (`node-role.kubernetes.io/<ROLE>`, the value is ignored)
+const NODE_ROLE_LABEL_PREFIX: &str = "node-role.kubernetes.io/";
+/// the legacy label of the role of a node (the value is the role)
+const LEGACY_NODE_ROLE_LABEL: &str = "kubernetes.io/role";
+
+/// the roles of a node from its labels (sorted), `worker` for a node without role (like the nodes of the workloads
+/// usually are)
+pub fn roles_of(labels: &BTreeMap<String, String>) -> Vec<String> {
+    let mut roles = labels
+        .iter()
+        .filter_map(|(key, value)| {
+            if key == LEGACY_NODE_ROLE_LABEL {
+                Some(value.as_str())
+            } else {
+                key.strip_prefix(NODE_ROLE_LABEL_PREFIX)
+            }
+        })
+        .filter(|role| !role.is_empty())
+        .map(|role| role.to_string())
+        .collect::<Vec<_>>();
+    roles.sort();
+    roles.dedup();
+    if roles.is_empty() {
+        roles.push("worker".to_string());
+    }
+    roles
+}
+
+/// the lifecycle of a node from its labels: `spot` or `on-demand` (the default),
+/// the unknown values of the label are kept (in lowercase)
+pub fn lifecycle_of(labels: &BTreeMap<String, String>, key: Option<&str>) -> String {
+    let value = match key {
+        Some(key) => labels.get(key),
+        None => LIFECYCLE_LABELS.iter().find_map(|key| labels.get(*key)),
+    };
+    let value = value.map(|v| v.to_lowercase()).unwrap_or_default();
+    match value.as_str() {
+        "spot" | "true" | "preemptible" => "spot".to_string(),
+        "" | "on-demand" | "on_demand" | "ondemand" | "normal" | "regular" | "false" => {
+            "on-demand".to_string()
+        }
+        _ => value,
+    }
+}
+
+impl GroupBy {
+    pub fn extract(&self, e: &Resource) -> Option<String> {
+        match self {
+            Self::resource => Self::extract_kind(e),
+            Self::node => Self::extract_node_name(e),
+            Self::pod => Self::extract_pod_name(e),
+            Self::namespace => Self::extract_namespace(e),
+            Self::qos => Self::extract_qos_class(e),
+            Self::container => Self::extract_container_name(e),
+            Self::node_label(key) => Self::extract_node_label(e, key),
+            Self::annotation(key) => Self::extract_annotation(e, key),
+            Self::lifecycle(key) => Self::extract_lifecycle(e, key.as_deref()),
+            Self::node_group(groups) => Self::extract_node_group(e, groups),
+            Self::node_state => Self::extract_node_state(e),
+            Self::node_role => Self::extract_node_role(e),
+            Self::workload => Self::extract_workload(e),
+            Self::cluster => e.location.cluster.clone(),
+        }
+    }
+
+    fn extract_kind(e: &Resource) -> Option<String> {
+        Some(e.kind.clone())
+    }
+
+    fn extract_node_name(e: &Resource) -> Option<String> {
+        e.location.node_name.clone()
+    }
+
+    fn extract_pod_name(e: &Resource) -> Option<String> {
+        // We do not need to display "pods" resource types when grouping by pods
+        if e.kind == "pods" {
+            return None;
+        }
+        e.location.pod_name.clone()
+    }
+
+    fn extract_namespace(e: &Resource) -> Option<String> {
+        e.location.namespace.clone()
+    }
+
+    fn extract_node_label(e: &Resource, key: &str) -> Option<String> {
+        e.location.node_name.as_ref()?;
+        Some(
+            e.location
+                .node_labels
+                .get(key)
+                .cloned()
+                .unwrap_or_else(|| "(none)".to_string()),
+        )
+    }
+
+    fn extract_lifecycle(e: &Resource, key: Option<&str>) -> Option<String> {
+        e.location.node_name.as_ref()?;
+        Some(lifecycle_of(&e.location.node_labels, key))
+    }
+
+    fn extract_node_group(e: &Resource, groups: &BTreeMap<String, String>) -> Option<String> {
+        let node_name = e.location.node_name.as_ref()?;
+        Some(
+            groups
+                .get(node_name)
+                .cloned()
+                .unwrap_or_else(|| "(ungrouped)".to_string()),
+        )
+    }
+
+    fn extract_node_state(e: &Resource) -> Option<String> {
+        e.location.node_name.as_ref()?;
+        Some(
+            e.location
+                .node_state
+                .map(|state| state.to_string())
+                .unwrap_or_else(|| "(unknown)".to_string()),
+        )
+    }
+
+    fn extract_node_role(e: &Resource) -> Option<String> {
+        e.location.node_name.as_ref()?;
+        Some(roles_of(&e.location.node_labels).join(","))
+    }
+
+    fn extract_workload(e: &Resource) -> Option<String> {
+        e.location.pod_name.as_ref()?;
+        Some(
+            e.location
+                .workload
+                .clone()
+                .unwrap_or_else(|| "(none)".to_string()),
+        )
+    }
+
+    fn extract_annotation(e: &Resource, key: &str) -> Option<String> {
+        e.location.pod_name.as_ref()?;
+        Some(
+            e.location
+                .pod_annotations
+                .get(key)
+                .cloned()
+                .unwrap_or_else(|| "(none)".to_string()),
+        )
+    }
+
+    fn extract_container_name(e: &Resource) -> Option<String> {
+        // "pods" resource is not related to a container
+        if e.kind == "pods" {
+            return None;
+        }
+        e.location.container_name.clone()
+    }
+
+    fn extract_qos_class(e: &Resource) -> Option<String> {
+        e.location.qos_class.map(|q| q.to_string())
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((kind, key)) = s.split_once('=') {
+            match kind.to_lowercase().as_str() {
+                "node-label" | "node_label" => Ok(Self::node_label(key.to_string())),
+                "annotation" => Ok(Self::annotation(key.to_string())),
+                "lifecycle" => Ok(Self::lifecycle(Some(key.to_string()))),
+                _ => Err(format!("unknown group `{}`", kind)),
+            }
+        } else {
+            match s.to_lowercase().as_str() {
+                "resource" => Ok(Self::resource),
+                "node" => Ok(Self::node),
+                "pod" => Ok(Self::pod),
+                "namespace" => Ok(Self::namespace),
+                "qos" => Ok(Self::qos),
+                "container" => Ok(Self::container),
+                "lifecycle" => Ok(Self::lifecycle(None)),
+                // the mapping is set from `--node-group-file`
+                "node-group" | "node_group" => Ok(Self::node_group(Arc::default())),
+                "node-state" | "node_state" => Ok(Self::node_state),
+                "node-role" | "node_role" => Ok(Self::node_role),
+                "workload" => Ok(Self::workload),
+                "cluster" => Ok(Self::cluster),
+                _ => Err(format!("unknown group `{}`", s)),
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::resource => "resource",
+            Self::node => "node",
+            Self::pod => "pod",
+            Self::namespace => "namespace",
+            Self::qos => "qos",
+            Self::container => "container",
+            Self::lifecycle(_) => "lifecycle",
+            Self::node_group(_) => "node-group",
+            Self::node_state => "node-state",
+            Self::node_role => "node-role",
+            Self::workload => "workload",
+            Self::cluster => "cluster",
+            Self::node_label(key) | Self::annotation(key) => key,
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone)]
+#[allow(non_camel_case_types)]
+pub enum SortBy {
+    name,
+    utilization,
+    requested,
+    limit,
+    allocatable,
+    free,
+    /// requested - utilization, the (estimated) savings of a rightsizing
+    waste,
+}
+
+impl SortBy {
+    /// the order when `--sort-order` is not set: descending for quantities, ascending for names
+    pub fn default_order(&self) -> SortOrder {
+        match self {
+            Self::name => SortOrder::asc,
+            _ => SortOrder::desc,
+        }
+    }
+
+    fn extract(&self, qtys: &QtyByQualifier) -> Option<Qty> {
+        match self {
+            Self::name => None,
+            Self::utilization => qtys.utilization.clone(),
+            Self::requested => qtys.requested.clone(),
+            Self::limit => qtys.limit.clone(),
+            Self::allocatable => qtys.allocatable.clone(),
+            Self::free => qtys.calc_free(),
+            Self::waste => calc_waste(qtys.requested.as_ref()?, qtys.utilization.as_ref()?),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum SortOrder {
+    asc,
+    desc,
+}
+
+impl SortOrder {
+    fn apply(&self, ordering: std::cmp::Ordering) -> std::cmp::Ordering {
+        match self {
+            Self::asc => ordering,
+            Self::desc => ordering.reverse(),
+        }
+    }
+}
+
+/// requested - utilization, none when the utilization is over the requested
+fn calc_waste(requested: &Qty, utilization: &Qty) -> Option<Qty> {
+    requested.checked_sub(utilization)
+}
+
+/// under this percentage of utilization of the requested, a container is over-provisioned (see `--efficiency`)
+pub const OVER_PROVISIONED_PCT: f64 = 50.0;
+
+/// The requested & the utilization of a resource of a container (see `--efficiency`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EfficiencyRow {
+    pub namespace: String,
+    pub pod: String,
+    pub container: String,
+    pub kind: String,
+    pub requested: Qty,
+    pub utilization: Qty,
+}
+
+impl EfficiencyRow {
+    /// utilization / requested in %, none without requested
+    pub fn efficiency_pct(&self) -> Option<f64> {
+        (self.requested.value > 0).then(|| self.utilization.calc_percentage(&self.requested))
+    }
+
+    pub fn waste(&self) -> Qty {
+        calc_waste(&self.requested, &self.utilization).unwrap_or_default()
+    }
+
+    /// "over" when the utilization is < `OVER_PROVISIONED_PCT` of the requested,
+    /// "under" when the utilization is over the requested (or without requested), else ""
+    pub fn provisioning(&self) -> &'static str {
+        match self.efficiency_pct() {
+            Some(pct) if pct < OVER_PROVISIONED_PCT => "over",
+            Some(pct) if pct <= 100.0 => "",
+            _ if self.utilization.is_zero() => "",
+            _ => "under",
+        }
+    }
+}
+
+/// Join the requested and the utilization of the containers (for the kinds with utilization: cpu & memory),
+/// sorted by name or by waste (in the `order` direction)
+pub fn make_efficiency_rows(
+    resources: &[Resource],
+    sort_by: &SortBy,
+    order: &SortOrder,
+) -> Vec<EfficiencyRow> {
+    let mut qtys: BTreeMap<(String, String, String, String), (Qty, Qty)> = BTreeMap::new();
+    for r in resources {
+        let location = &r.location;
+        let key = match (
+            &location.namespace,
+            &location.pod_name,
+            &location.container_name,
+        ) {
+            (Some(ns), Some(pod), Some(container)) => {
+                (ns.clone(), pod.clone(), container.clone(), r.kind.clone())
+            }
+            _ => continue,
+        };
+        match r.qualifier {
+            ResourceQualifier::Requested => qtys.entry(key).or_default().0 += &r.quantity,
+            ResourceQualifier::Utilization => qtys.entry(key).or_default().1 += &r.quantity,
+            _ => {}
+        }
+    }
+    let mut rows = qtys
+        .into_iter()
+        .map(
+            |((namespace, pod, container, kind), (requested, utilization))| EfficiencyRow {
+                namespace,
+                pod,
+                container,
+                kind,
+                requested,
+                utilization,
+            },
+        )
+        .collect::<Vec<_>>();
+    // only the kinds with utilization (the pseudo containers "(init)",... have none)
+    let with_utilization = rows
+        .iter()
+        .filter(|row| !row.utilization.is_zero())
+        .map(|row| row.kind.clone())
+        .collect::<std::collections::HashSet<_>>();
+    rows.retain(|row| with_utilization.contains(&row.kind) && !row.container.starts_with('('));
+    if sort_by == &SortBy::waste {
+        // stable, the rows of the same waste by name
+        rows.sort_by(|a, b| order.apply(a.waste().cmp(&b.waste())));
+    } else if order == &SortOrder::desc {
+        rows.reverse();
+    }
+    rows
+}
+
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone)]
+#[allow(non_camel_case_types)]
+pub enum Output {
+    table,
+    /// columns separated by a space, without borders, separators nor styles (eg for logs)
+    plain,
+    csv,
+    /// line protocol of InfluxDB (eg for telegraf), measurement `kube_allocations`
+    influx,
+    /// text format of prometheus, gauges `kube_allocations_<field>` (eg for a Pushgateway or a textfile collector)
+    prometheus,
+    json,
+    /// table on stderr (for the operator) and json on stdout (for the piped consumer)
+    console_json,
+    /// nodes (allocatable) & pods (requests, node), as input of scheduling simulators
+    sim_json,
+    /// only the totals of the cluster by resource, as a single json object (eg for a capacity gauge)
+    totals_json,
+    /// the resources (kind, qualifier, quantity, location) not grouped, eg to aggregate them differently
+    resources_json,
+    /// only the free of every node by resource (`<kind>_free` in the base unit), eg as input of an autoscaler
+    node_free_json,
+}
+
+impl Output {
+    /// the rendering of the quantities when not set by `--quantities`: numbers for the csv, else scaled
+    pub fn default_qty_style(&self) -> QtyStyle {
+        match self {
+            Self::csv => QtyStyle::base,
+            _ => QtyStyle::scaled,
+        }
+    }
+
+    /// extension of the files written in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::table | Self::plain => "txt",
+            Self::csv => "csv",
+            Self::influx => "lp",
+            Self::prometheus => "prom",
+            Self::json
+            | Self::console_json
+            | Self::sim_json
+            | Self::totals_json
+            | Self::resources_json
+            | Self::node_free_json => "json",
+        }
+    }
+}
+
+/// The rendering of the quantities of an output (see `--quantities`)
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+#[allow(non_camel_case_types)]
+pub enum QtyStyle {
+    /// with the prefix keeping the magnitude >= 1, for humans (eg `1.5Gi`)
+    scaled,
+    /// the number of base units (eg `1610612736.00`), for machines
+    base,
+}
+
+/// A usage of the resources, displayed as column(s) of the table & csv (see `--usage`)
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Usage {
+    utilization,
+    requested,
+    limit,
+    allocatable,
+    free,
+}
+
+/// The denominator of the percentages (see `--percent-base`)
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub enum PercentBase {
+    /// schedulable to pods (capacity - reserved for kube & system)
+    #[default]
+    allocatable,
+    /// allocatable minus the headroom (see `--free-headroom`), the base of the free
+    effective,
+    /// the hardware of the nodes
+    capacity,
+}
+
+/// The glyphs of the tree of the table (see `--tree-style`)
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum TreeStyle {
+    /// `|-`, `` `- ``
+    ascii,
+    /// `├─`, `└─`
+    unicode,
+}
+
+impl TreeStyle {
+    /// unicode if the locale (`LC_ALL`, `LC_CTYPE` or `LANG`, the first set) is UTF-8, else ascii
+    pub fn detect() -> TreeStyle {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|v| !v.is_empty())
+            .unwrap_or_default();
+        TreeStyle::of_locale(&locale)
+    }
+
+    fn of_locale(locale: &str) -> TreeStyle {
+        let locale = locale.to_lowercase();
+        if locale.contains("utf-8") || locale.contains("utf8") {
+            TreeStyle::unicode
+        } else {
+            TreeStyle::ascii
+        }
+    }
+
+    pub fn glyphs(&self) -> tree::Glyphs {
+        match self {
+            TreeStyle::ascii => tree::Glyphs::ASCII,
+            TreeStyle::unicode => tree::Glyphs::UNICODE,
+        }
+    }
+}
+
+/// The quantity subtracted from allocatable to compute the free (see `--used-basis`)
+#[derive(Debug, Eq, PartialEq, ValueEnum, Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub enum UsedBasis {
+    /// like the scheduler
+    #[default]
+    requested,
+    limit,
+    /// the max of requested and limit
+    max,
+}
+
+impl std::fmt::Display for UsedBasis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::requested => "requested",
+            Self::limit => "limit",
+            Self::max => "max(requested, limit)",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    version, about,
+    after_help(concat!(
+        "Examples:\n",
+        "  kubectl-view-allocations -g namespace         the requests by namespace\n",
+        "  kubectl-view-allocations -r gpu -u            the gpus, with their utilization\n",
+        "  kubectl-view-allocations -o csv > alloc.csv   the full detail for a spreadsheet\n",
+        "  source <(kubectl-view-allocations --completions bash)\n",
+        "\n",
+        env!("CARGO_PKG_HOMEPAGE")
+    )),
+    propagate_version = true
+)]
+pub struct CliOpts {
+    /// The name of the kubeconfig context to use
+    #[arg(long, value_parser)]
+    pub context: Option<String>,
+
+    /// Path to the kubeconfig file to use (instead of `$KUBECONFIG` or `~/.kube/config`)
+    #[arg(long, value_parser)]
+    pub kubeconfig: Option<std::path::PathBuf>,
+
+    /// The name of the kubeconfig cluster to use
+    #[arg(long, value_parser)]
+    pub cluster: Option<String>,
+
+    /// The name of the kubeconfig user to use
+    #[arg(long, value_parser)]
+    pub user: Option<String>,
+
+    /// Username to impersonate for the requests (like kubectl), eg `system:serviceaccount:ns:sa`
+    #[arg(long = "as", value_parser, value_name = "USER")]
+    pub as_user: Option<String>,
+
+    /// Group to impersonate for the requests (can be repeated)
+    #[arg(long = "as-group", value_parser, value_name = "GROUP")]
+    pub as_group: Vec<String>,
+
+    /// Show only pods from this namespace
+    #[arg(short, long, value_parser)]
+    pub namespace: Option<String>,
+
+    /// Show only this pod (`name` or `namespace/name`), by default detailed by container
+    #[arg(long, value_parser)]
+    pub pod: Option<String>,
+
+    /// Show the allocations seen by a pod of this priority class (name or value): pods with
+    /// a lower priority are ignored (they are preemptible), so free is the free for this priority
+    #[arg(long, value_parser, value_name = "CLASS")]
+    pub for_priority: Option<String>,
+
+    /// Hide pods from this namespace (can be repeated), allocatable of nodes is not affected
+    #[arg(long, value_parser)]
+    pub exclude_namespace: Vec<String>,
+
+    /// Hide the static pods (run by the kubelet from files, like the api-server or etcd of kubeadm), they are
+    /// part of the baseline of the nodes, not of the workloads
+    #[arg(long, value_parser)]
+    pub exclude_static_pods: bool,
+
+    /// Hide the nodes with this role (and their pods), eg `control-plane` (can be repeated or comma separated),
+    /// see `-g node-role`
+    #[arg(long, value_parser, value_delimiter = ',', value_name = "ROLE")]
+    pub exclude_role: Vec<String>,
+
+    /// Show only pods from the namespaces matching this regex (the whole name, eg `team-a-.*`),
+    /// allocatable of nodes is not affected, --exclude-namespace still applies
+    #[arg(long, value_parser = parse_namespace_regex, value_name = "REGEX")]
+    pub namespace_regex: Option<NamespaceRegex>,
+
+    /// Force to retrieve utilization (for cpu and memory), require to have metrics-server https://github.com/kubernetes-sigs/metrics-server
+    #[arg(short = 'u', long, value_parser)]
+    pub utilization: bool,
+
+    /// Show only pods of this QoS class (can be repeated)
+    #[arg(long, value_enum, ignore_case = true, value_parser)]
+    pub qos: Vec<QosClass>,
+
+    /// Show lines with zero requested and zero limit and zero allocatable
+    #[arg(short = 'z', long, value_parser)]
+    pub show_zero: bool,
+
+    /// Filter resources shown by name(s), by default all resources are listed
+    #[arg(short, long, value_parser)]
+    pub resource_name: Vec<String>,
+
+    /// Hide resources by name(s), eg `pods,ephemeral-storage` (wins over `--resource-name`)
+    #[arg(long, value_parser, value_delimiter = ',')]
+    pub exclude_resource: Vec<String>,
+
+    /// Rename a resource in the output, eg `nvidia.com/gpu=gpu` (can be repeated or comma separated)
+    #[arg(long, value_parser = parse_key_value, value_delimiter = ',')]
+    pub kind_alias: Vec<(String, String)>,
+
+    /// Read a field of custom resources as the allocatable of a kind (can be repeated),
+    /// `KIND=API_VERSION/PLURAL:PATH[@NODE_PATH]` with `PATH` & `NODE_PATH` the fields separated by `.`,
+    /// eg `example.com/licenses=licensing.example.com/v1/licensepools:.status.capacity@.spec.nodeName`
+    #[arg(long, value_parser = parse_extra_allocatable, value_name = "KIND=API_VERSION/PLURAL:PATH[@NODE_PATH]")]
+    pub extra_allocatable_from: Vec<ExtraAllocatable>,
+
+    /// Merge a variant of a kind into its canonical kind before the grouping, eg `Nvidia.com/GPU=nvidia.com/gpu`
+    /// (can be repeated or comma separated, the variants are matched ignoring the case)
+    #[arg(long, value_parser = parse_key_value, value_delimiter = ',', value_name = "VARIANT=CANONICAL")]
+    pub normalize_kind: Vec<(String, String)>,
+
+    /// Add a kind summing other kinds (can be repeated), eg `accelerators=nvidia.com/gpu,amd.com/gpu`,
+    /// a kind ending with `*` is a prefix, eg `gpu=nvidia.com/gpu,nvidia.com/mig-*` for the MIG profiles
+    #[arg(long, value_parser = parse_sum_kinds, value_name = "NAME=KIND,...")]
+    pub sum_kinds: Vec<(String, Vec<String>)>,
+
+    /// Group information hierarchically (default: -g resource -g node -g pod)
+    /// [possible values: resource, node, pod, namespace, qos, container, node-label=<KEY>, annotation=<KEY>,
+    /// lifecycle (spot or on-demand, from well-known labels), lifecycle=<KEY>,
+    /// node-group (from --node-group-file), node-state (ready, cordoned or not-ready),
+    /// node-role (from the node-role.kubernetes.io/<ROLE> labels, worker if none),
+    /// workload (the owner of the pods, eg Deployment/web), cluster (with --batch)]
+    #[arg(short, long, value_parser = GroupBy::from_str)]
+    pub group_by: Vec<GroupBy>,
+
+    /// Separator of the groups when the path of a row is written on one line (eg in the warnings),
+    /// to avoid the ambiguity with the names containing `/` (like `nvidia.com/gpu`)
+    #[arg(long, default_value = " / ", value_name = "SEP")]
+    pub group_separator: String,
+
+    /// Group the nodes by a mapping of a yaml (or json) file, eg `{"node-1": "rack-a", "node-2": "rack-b"}`,
+    /// the nodes not in the file are "(ungrouped)" (appended to --group-by, unless `-g node-group` is set)
+    #[arg(long, value_parser)]
+    pub node_group_file: Option<std::path::PathBuf>,
+
+    /// Display, by pool of nodes (see `--pool-label`), the nodes to add or to remove to reach a target utilization
+    /// (requested / allocatable) of PCT % of the most used kind, with nodes of the average allocatable of the pool
+    #[arg(long, value_parser = parse_percentage, value_name = "PCT")]
+    pub target_utilization: Option<f64>,
+
+    /// The label of the nodes defining their pools (see `--target-utilization`)
+    #[arg(long, default_value = INSTANCE_TYPE_LABEL, value_name = "LABEL")]
+    pub pool_label: String,
+
+    /// Display the cost of the idle capacity (free cpu & memory) of the nodes, from the hourly costs of a yaml
+    /// (or json) file by node's name or instance type, eg `{"m5.xlarge": 0.192, "node-1": 0.5}`
+    #[arg(long, value_parser, value_name = "FILE")]
+    pub node_cost: Option<std::path::PathBuf>,
+
+    /// Group by the value of an annotation of pods, appended to --group-by (same as `-g annotation=<KEY>`)
+    #[arg(long, value_parser)]
+    pub group_by_annotation: Vec<String>,
+
+    /// Show limit as ∞ when at least one container requests the resource without limit
+    #[arg(long, value_parser)]
+    pub unbounded_limit: bool,
+
+    /// Show the part of the capacity of nodes reserved for kube & system (capacity - allocatable)
+    #[arg(long, value_parser)]
+    pub show_reserved: bool,
+
+    /// Display only these usages (columns of table & csv, with their percentage), eg `requested,allocatable,free`,
+    /// all by default (utilization requires `--utilization`)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub usage: Vec<Usage>,
+
+    /// Percentage of allocatable kept as safety buffer, not counted as free
+    #[arg(long, default_value = "0", value_parser = parse_percentage, value_name = "PCT")]
+    pub free_headroom: f64,
+
+    /// Quantity reserved on every node (for bursts, system processes,...) beyond the reservations of the kubelet,
+    /// subtracted from the allocatable of the nodes, eg `cpu=200m,memory=512Mi`
+    #[arg(long, value_parser = parse_threshold, value_delimiter = ',', value_name = "KIND=QTY")]
+    pub reserve_for_system: Vec<(String, Qty)>,
+
+    /// The quantity subtracted from allocatable to compute the free of the table, csv, json,...
+    /// (`--fits` and `--compare-to-quota` always subtract the requested, like the scheduler)
+    #[arg(long, value_enum, default_value_t = UsedBasis::requested, value_name = "BASIS")]
+    pub used_basis: UsedBasis,
+
+    /// The denominator of the percentages: allocatable, effective (allocatable minus `--free-headroom`,
+    /// so requested 100% when free is 0) or capacity (eg the 8 cores of a node with 7.5 allocatable).
+    /// The allocatable is always after `--reserve-for-system`. Not shown when zero
+    #[arg(long, value_enum, default_value_t = PercentBase::allocatable, value_name = "BASE")]
+    pub percent_base: PercentBase,
+
+    /// Show the age of nodes (on the rows of nodes)
+    #[arg(long, value_parser)]
+    pub show_age: bool,
+
+    /// Show a bar of the percentage requested (of allocatable) in the table, eg `████░░░░░░`
+    /// (ignored when the output is not a terminal)
+    #[arg(long, value_parser)]
+    pub bars: bool,
+
+    /// Show quantities rounded to whole units, without decimal (eg `1Gi` instead of `1.04Gi`)
+    #[arg(long, value_parser)]
+    pub compact: bool,
+
+    /// Show the quantities of a column of the table with the same prefix (by resource), the largest keeping
+    /// the smallest value >= 1, eg `512.0Mi` & `2048.0Mi` instead of `512.0Mi` & `2.0Gi`
+    #[arg(long, value_parser)]
+    pub align_units: bool,
+
+    /// The glyphs of the tree of the table, unicode by default on UTF-8 locales (see `LANG`), else ascii
+    #[arg(long, value_enum, value_name = "STYLE")]
+    pub tree_style: Option<TreeStyle>,
+
+    /// Draw a rule between the top-level groups of the table (eg between cpu, memory,...)
+    #[arg(long, value_parser)]
+    pub separators: bool,
+
+    /// Collapse the rows of the table with exactly one child into the child, with the keys joined by
+    /// `--group-separator` (eg `cpu / node-1` on a single node cluster), when both have the same sums
+    /// (eg not a node with one pod, the allocatable is only on the node)
+    #[arg(long, value_parser)]
+    pub flatten_single_child: bool,
+
+    /// Display the min & the max of the free of the nodes on the rows of the kinds (is the free spread evenly or
+    /// concentrated on a few nodes?)
+    #[arg(long, value_parser)]
+    pub free_spread: bool,
+
+    /// Print one line summarizing the cluster by kind, instead of the output, eg
+    /// `cpu: 62% requested (38.0 free of 100.0), memory: 71% (29.0Gi free of 100.0Gi)` (eg for a status page)
+    #[arg(long, value_parser)]
+    pub banner: bool,
+
+    /// Display the requested & the limit in one column of the table, as `requested / limit` (eg `1.5 / 2.0`,
+    /// `∞` for an unbounded limit)
+    #[arg(long, value_parser)]
+    pub combined_req_limit: bool,
+
+    /// Show the percentage of allocatable next to the free in the table, eg `3.2Gi (40%)`
+    #[arg(long, value_parser)]
+    pub free_with_pct: bool,
+
+    /// Show the ratio requested / limit (a low ratio flags over-generous limits)
+    #[arg(long, value_parser)]
+    pub show_ratio: bool,
+
+    /// Split the requested into the part of the pods of QoS Guaranteed (firm) and the part of the other pods
+    /// (Burstable & BestEffort, elastic: more eviction & overcommit risk)
+    #[arg(long, value_parser)]
+    pub qos_split: bool,
+
+    /// Number of decimals of percentages
+    #[arg(long, default_value = "1", value_parser)]
+    pub percent_decimals: usize,
+
+    /// The rendering of the quantities of an output (can be repeated or comma separated), `scaled` (eg `1.5Gi`)
+    /// or `base` (the number of base units, eg `1610612736.00`), eg `json=base,csv=scaled`;
+    /// by default `base` for the csv, `scaled` for table, plain & json (their `*_base` fields are numbers)
+    #[arg(long, value_parser = parse_quantities, value_delimiter = ',', value_name = "FORMAT=STYLE")]
+    pub quantities: Vec<(Output, QtyStyle)>,
+
+    /// Number of decimals of the quantities of a kind (can be repeated or comma separated, at most 6),
+    /// eg `cpu=2,memory=1,nvidia.com/gpu=0`, the other kinds with the default (1, none with `--compact`)
+    #[arg(long, value_parser = parse_precision, value_delimiter = ',', value_name = "KIND=DECIMALS")]
+    pub precision: Vec<(String, usize)>,
+
+    /// Delimiter of the fields of the csv output (eg ';' for spreadsheets of european locales)
+    #[arg(long, default_value = ",", value_parser)]
+    pub csv_delimiter: char,
+
+    /// Always quote the columns of the resource path in the csv output
+    #[arg(long, value_parser)]
+    pub csv_quote_keys: bool,
+
+    /// Display a table of the requested vs the utilization of every container (implies `--utilization`),
+    /// highlighting the over-provisioned (utilization < 50% of requested) & under-provisioned (over requested), see `--sort-by waste`
+    #[arg(long, value_parser)]
+    pub efficiency: bool,
+
+    /// Show only the totals by resource (the top-level rows), without detail
+    #[arg(long, value_parser)]
+    pub summary_only: bool,
+
+    /// Sort rows (of the same parent) by name or by quantity (see --sort-order)
+    #[arg(
+        long,
+        value_enum,
+        ignore_case = true,
+        default_value = "name",
+        value_parser
+    )]
+    pub sort_by: SortBy,
+
+    /// Direction of --sort-by [default: desc for quantities, asc for name]
+    #[arg(long, value_enum, ignore_case = true, value_parser)]
+    pub sort_order: Option<SortOrder>,
+
+    /// Show only the first N children of each resource (see --sort-by), others are aggregated
+    #[arg(long, value_parser)]
+    pub top: Option<usize>,
+
+    /// Merge nodes of the same instance type (label `node.kubernetes.io/instance-type`)
+    /// into one row (`-g node` is replaced), with the count of nodes
+    #[arg(long, value_parser)]
+    pub merge_similar_nodes: bool,
+
+    /// Group by node then by kind (`-g resource` moved after `-g node`), so each node shows its cpu, memory,...
+    /// together
+    #[arg(long, value_parser)]
+    pub node_first: bool,
+
+    /// Warn (and highlight in the table) the nodes with a free quantity below a threshold,
+    /// eg `cpu=500m,memory=1Gi`
+    #[arg(long, value_parser = parse_threshold, value_delimiter = ',')]
+    pub warn_free_below: Vec<(String, Qty)>,
+
+    /// Warn about the nodes with a reserved quantity (capacity - allocatable, ie kube-reserved,
+    /// system-reserved & eviction threshold of the kubelet) different of the expected one, eg `cpu=1,memory=2Gi`
+    #[arg(long, value_parser = parse_threshold, value_delimiter = ',')]
+    pub reserved: Vec<(String, Qty)>,
+
+    /// Replace the allocatable of a node, to model a node with a different capacity (what-if),
+    /// eg `node-1:cpu=8,memory=32Gi` (the reserved of these kinds is no longer displayed)
+    #[arg(long, value_parser = parse_node_allocatable_override, value_name = "NODE:KIND=QTY,...")]
+    pub node_allocatable_override: Vec<NodeAllocatableOverride>,
+
+    /// Collapse the namespaces requesting less than PCT % of the requested of their resource (1 if no value)
+    /// into one `(other)` namespace (with `-g namespace`), to focus on the heavy hitters of many small namespaces
+    #[arg(long, value_parser = parse_percentage, value_name = "PCT", num_args = 0..=1, default_missing_value = "1")]
+    pub collapse_namespaces_below: Option<f64>,
+
+    /// Display the nodes with a reserved fraction (reserved / capacity) deviating from the median of the nodes
+    /// by more than PCT points (5 if no value), eg bootstrapped with wrong reservations of the kubelet
+    #[arg(long, value_parser = parse_percentage, value_name = "PCT", num_args = 0..=1, default_missing_value = "5")]
+    pub detect_reservation_drift: Option<f64>,
+
+    /// Print how the numbers of a kind on a node are computed (the containers contributing to requested,
+    /// the source of allocatable, the formula of free), eg `node-1:memory` (`cpu` if no kind)
+    #[arg(long, value_parser = parse_explain, value_name = "NODE[:KIND]")]
+    pub explain: Option<(String, String)>,
+
+    /// Kinds of resource that are counts, warn when a quantity of them is not an integer (bad data)
+    #[arg(
+        long,
+        value_parser,
+        value_delimiter = ',',
+        default_value = "pods,nvidia.com/gpu,amd.com/gpu,gpu.intel.com/i915",
+        value_name = "KIND,..."
+    )]
+    pub integer_kinds: Vec<String>,
+
+    /// List the containers without requests for these kinds (scheduling hazards: BestEffort, poor bin-packing)
+    #[arg(long, value_name = "KIND", num_args = 0.., value_delimiter = ',', default_missing_values = ["cpu", "memory"])]
+    pub no_requests: Option<Vec<String>>,
+
+    /// Check the consistency of the resources (see `--skip-check`), display the issues & exit with an error if any
+    #[arg(long, value_parser)]
+    pub validate: bool,
+
+    /// Skip a check of `--validate` (can be repeated)
+    #[arg(long, value_enum, value_name = "CHECK")]
+    pub skip_check: Vec<Check>,
+
+    /// Exit with an error when no resources matched the filters (by default, a message is displayed and it exits with 0)
+    #[arg(long, value_parser)]
+    pub fail_on_empty: bool,
+
+    /// Display, for every kind, the number of nodes by bucket of requested% (0-25%, 25-50%,...),
+    /// to see the quality of the bin-packing (eg many half-empty nodes)
+    #[arg(long, value_parser)]
+    pub histogram: bool,
+
+    /// Display, by node, the pods it holds vs the most pods it could hold, and the kind binding the density:
+    /// the cap `pods` of the allocatable, or the cpu or the memory allocatable for pods of the size of `--fits`
+    /// (default: the average requests of the pods)
+    #[arg(long, value_parser)]
+    pub pod_density: bool,
+
+    /// Display a table of the nodes (from the fullest) with a score combining their requested% of cpu & memory
+    /// (max if no value), eg to choose the nodes to cordon/drain (table only)
+    #[arg(long, value_enum, value_name = "FUNCTION", num_args = 0..=1, default_missing_value = "max")]
+    pub merge_cpu_memory_into_score: Option<ScoreFunction>,
+
+    /// Weights of the kinds in the score of `--merge-cpu-memory-into-score`, eg `cpu=2,memory=1` (1 by default)
+    #[arg(long, value_parser = parse_weight, value_delimiter = ',', value_name = "KIND=WEIGHT,...")]
+    pub score_weights: Vec<(String, f64)>,
+
+    /// Count how many pods of this size (requests) fit on the free of the nodes, eg `cpu=500m,memory=1Gi`,
+    /// and warn when the free is fragmented (enough in total, not on the nodes)
+    #[arg(long, value_parser = parse_threshold, value_delimiter = ',', value_name = "KIND=QTY,...")]
+    pub fits: Vec<(String, Qty)>,
+
+    /// Color the percentages of utilization & requested (of allocatable) in the table with the
+    /// thresholds of a json file, by kind, eg `{"default": {"warn": 80, "crit": 95}, "kinds": {"nvidia.com/gpu": {"warn": 101, "crit": 101}}}`
+    #[arg(long, value_parser)]
+    pub thresholds_file: Option<std::path::PathBuf>,
+
+    /// Display the deltas from a snapshot made with `-o json` (and the same `--group-by`),
+    /// eg with `--watch` to follow how a rollout diverges from the state before
+    #[arg(long, value_parser)]
+    pub baseline: Option<std::path::PathBuf>,
+
+    /// Estimate the growth by day of the requested of every kind (linear trend), from snapshots made with `-o json`
+    /// (repeatable, eg one by week) to now, and when the allocatable would be exhausted at this rate
+    #[arg(long, value_name = "FILE")]
+    pub since_snapshot: Vec<std::path::PathBuf>,
+
+    /// Show the number of nodes (total, ready, schedulable) and of pods above the table (and in the json),
+    /// eg to catch a selector matching no node
+    #[arg(long, value_parser)]
+    pub node_count: bool,
+
+    /// Add the devices allocated to the pods by ResourceClaims (dynamic resource allocation, kubernetes >= 1.31),
+    /// as requested & limit by driver (eg `gpu.nvidia.com`), with the version of `resource.k8s.io` preferred by the cluster
+    #[arg(long, value_parser)]
+    pub resource_claims: bool,
+
+    /// The group/version of the Metrics API (for `--utilization`), eg `metrics.k8s.io/v1beta1`,
+    /// by default the preferred version served by the cluster (nodes & pods are always of the stable core `v1`)
+    #[arg(long, value_name = "GROUP/VERSION")]
+    pub metrics_api_version: Option<String>,
+
+    /// Use the resources allocated to the containers (from the status of the pods, with the in-place resize of kubernetes >= 1.27)
+    /// instead of the requests & limits of their spec, that can be the desired state of a pending resize
+    /// (the pods with a resize in progress are listed after the output)
+    #[arg(long, value_parser)]
+    pub allocated_resources: bool,
+
+    /// Estimate the requests of the pods not yet created by jobs & cronjobs (parallelism x pod template),
+    /// shown in a separate "pending burst" section
+    #[arg(long, value_parser)]
+    pub include_jobs: bool,
+
+    /// Compare the requested of every namespace to its ResourceQuotas (hard) and to the free of the cluster,
+    /// to tell if a namespace is limited by its quota or by the capacity (table only)
+    #[arg(long, value_parser)]
+    pub compare_to_quota: bool,
+
+    /// Explain the requests set by the defaults of LimitRanges (on containers without explicit requests),
+    /// after the output
+    #[arg(long, value_parser)]
+    pub limit_ranges: bool,
+
+    /// Do not warn when the requested of a resource exceeds its allocatable cluster-wide
+    #[arg(long, value_parser)]
+    pub no_warnings: bool,
+
+    /// Only the data: no logs (except the errors), warnings, header, legend nor notes, eg to pipe the output
+    #[arg(short, long, value_parser, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// More logs on stderr (can be repeated): `-v` info, `-vv` debug, `-vvv` trace (else `RUST_LOG` or warn)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Maximum number of requests per second sent to the API server (0 to disable throttling)
+    #[arg(long, default_value = "5", value_parser = parse_qps)]
+    pub qps: f32,
+
+    /// Maximum burst of requests sent to the API server
+    #[arg(long, default_value = "10", value_parser)]
+    pub burst: u32,
+
+    /// Return large lists in chunks rather than all at once (0 to disable)
+    #[arg(long, default_value = "500", value_parser)]
+    pub chunk_size: u32,
+
+    /// Print the requests that would be sent to the cluster (and the filters), then exit without contacting it
+    #[arg(long, value_parser)]
+    pub dry_run: bool,
+
+    /// Write the rows of every node into its own file `<DIR>/<node>.<ext>` (in the output format)
+    #[arg(long, value_parser, value_name = "DIR")]
+    pub split_by_node: Option<std::path::PathBuf>,
+
+    /// Also write the rows into a file in another format (can be repeated), from the same collect,
+    /// eg `--also json:report.json --also csv:report.csv`
+    #[arg(long, value_parser = parse_also, value_name = "FORMAT:PATH")]
+    pub also: Vec<(Output, std::path::PathBuf)>,
+
+    /// With --watch, rebuild the client when the kubeconfig file changes (eg rotation of credentials),
+    /// so long sessions survive the expiration of tokens
+    #[arg(long, value_parser, requires = "watch")]
+    pub refresh_on_change: bool,
+
+    /// List the pods with a container in CrashLoopBackOff, with the requests they hold (reserved on their nodes
+    /// without providing any service), and their total by kind
+    #[arg(long, value_parser)]
+    pub crashloop_waste: bool,
+
+    /// Show the number of pending pods blocked by a lack of the kind (`Insufficient <kind>` reported by the scheduler
+    /// in the conditions of the pods), on the rows of the kinds
+    #[arg(long, value_parser)]
+    pub pending_pods: bool,
+
+    /// Collect only the nodes (allocatable & capacity), not the pods (nor utilization, jobs, resource claims)
+    #[arg(long, value_parser, conflicts_with = "pods_only")]
+    pub nodes_only: bool,
+
+    /// Collect only the pods (requests & limits), not the nodes (nor extra allocatable), so without allocatable
+    #[arg(long, value_parser)]
+    pub pods_only: bool,
+
+    /// Keep the display up to date, with the changes of nodes & pods (watch API)
+    #[arg(short, long, value_parser)]
+    pub watch: bool,
+
+    /// Collect from several kubeconfig contexts (concurrently) and display them together,
+    /// grouped by cluster (`-g cluster` is added after `-g resource`).
+    /// A failed context is reported, it doesn't abort the others
+    #[arg(long, value_delimiter = ',', value_name = "CONTEXT,...", conflicts_with_all = ["watch", "context"])]
+    pub batch: Vec<String>,
+
+    /// Abort with an error when the run takes longer, eg `30s`, `2m` (with --watch: stop watching)
+    #[arg(long, value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Output format
+    #[arg(
+        short,
+        long,
+        value_enum,
+        ignore_case = true,
+        default_value = "table",
+        value_parser
+    )]
+    pub output: Output,
+
+    /// Print the completion script for the shell (to source), then exit
+    #[arg(long, value_enum)]
+    pub completions: Option<completions::Shell>,
+
+    /// Push the rows as metrics (like `-o prometheus`) to the Pushgateway at this url (eg `http://pushgateway:9091`),
+    /// in addition to the output, for the runs that can't be scraped (eg a CronJob), retried 3 times
+    #[arg(long, value_name = "URL", conflicts_with = "watch")]
+    pub push_gateway: Option<String>,
+
+    /// The label `job` of the metrics pushed (see `--push-gateway`)
+    #[arg(long, default_value = "kubectl-view-allocations", value_name = "JOB")]
+    pub push_job: String,
+
+    /// The label `instance` of the metrics pushed (see `--push-gateway`), default: the context of the cluster
+    #[arg(long, value_name = "INSTANCE")]
+    pub push_instance: Option<String>,
+
+    /// Drop or relabel the collected resources before the grouping, with rules evaluated over their fields
+    /// (no program is run, no file or network is reached), separated by `;` and applied in order:
+    /// `drop [if CONDITIONS]` or `set FIELD=VALUE... [if CONDITIONS]`, the conditions separated by `and`, each
+    /// `FIELD=VALUE`, `FIELD!=VALUE`, `FIELD~REGEX` or `FIELD!~REGEX`, on the fields `kind`, `namespace`, `node_name`,
+    /// `pod_name`, `container_name`, `workload`, eg `drop if namespace=kube-system; set namespace=team-a if namespace~team-a-.*`
+    #[arg(long, value_parser = parse_hook, value_name = "RULES")]
+    pub hook: Option<HookProgram>,
+
+    /// Write the capacity report (eg `cpu 80% requested, memory 60% requested`) as the annotation
+    /// `kubectl-view-allocations/capacity-report` of `configmap/<NAMESPACE>/<NAME>` (existing, the report of
+    /// the cluster) or of `nodes` (every node, its own report), for the controllers to react;
+    /// requires the permission `patch` on the target
+    #[arg(long, value_parser = parse_annotate_target, value_name = "TARGET", conflicts_with_all = ["watch", "batch"])]
+    pub annotate: Option<AnnotateTarget>,
+
+    /// Copy the output (in the format of `--output`, without colors) to the clipboard of the desktop, in addition to
+    /// stdout (requires the feature `clipboard` of the build, and one of pbcopy, clip, wl-copy, xclip or xsel)
+    #[arg(long, value_parser, conflicts_with = "watch")]
+    pub clipboard: bool,
+}
+
+impl CliOpts {
+    /// the nodes (allocatable & capacity) are collected, unless `--pods-only`
+    pub fn collects_nodes(&self) -> bool {
+        !self.pods_only
+    }
+
+    /// the pods (requests & limits, utilization, jobs,...) are collected, unless `--nodes-only`
+    pub fn collects_pods(&self) -> bool {
+        !self.nodes_only
+    }
+
+    /// the namespace passes `--exclude-namespace` & `--namespace-regex`
+    pub fn accepts_namespace(&self, namespace: &Option<String>) -> bool {
+        accept_namespace(namespace, &self.exclude_namespace)
+            && self
+                .namespace_regex
+                .as_ref()
+                .map(|regex| regex.accept(namespace))
+                .unwrap_or(true)
+    }
+
+    /// the direction of `--sort-by`, `--sort-order` or the default of the criteria
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort_order
+            .unwrap_or_else(|| self.sort_by.default_order())
+    }
+}
+
+/// format a duration like kubectl, with the 2 biggest units: `45s`, `12m30s`, `5h12m`, `3d4h`
+pub fn format_age(age: chrono::Duration) -> String {
+    let secs = age.num_seconds().max(0);
+    let (days, hours, minutes, seconds) = (
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    );
+    let units = [(days, "d"), (hours, "h"), (minutes, "m"), (seconds, "s")];
+    let first = units.iter().position(|(v, _)| *v > 0).unwrap_or(3);
+    units[first..]
+        .iter()
+        .take(2)
+        .filter(|(v, _)| *v > 0 || first == 3)
+        .map(|(v, unit)| format!("{}{}", v, unit))
+        .collect()
+}
+
+/// a bar of `width` blocks filled proportionally to the percentage (capped to 100%),
+/// eg `████░░░░░░` for 40%
+pub fn make_bar(percentage: f64, width: usize) -> String {
+    let filled =
+        ((percentage.clamp(0.0, 100.0) * width as f64 / 100.0).round() as usize).min(width);
+    "█".repeat(filled) + &"░".repeat(width - filled)
+}
+
+/// parse a percentage between 0 and 100 (`%` is optional)
+pub fn parse_percentage(s: &str) -> Result<f64, String> {
+    let value = s
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| format!("invalid percentage `{}`", s))?;
+    if (0.0..=100.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("invalid percentage `{}`: not between 0 and 100", s))
+    }
+}
+
+/// the requests per second of `--qps`, finite and not negative (`0` disables the throttling)
+pub fn parse_qps(s: &str) -> Result<f32, String> {
+    let value = s
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| format!("invalid qps `{}`", s))?;
+    if value.is_finite() && value >= 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("invalid qps `{}`: not a number >= 0", s))
+    }
+}
+
+/// the max of decimals of the quantities (see `--precision`), more is noise (and beyond the milli-units)
+pub const MAX_PRECISION: usize = 6;
+
+/// parse a precision `KIND=DECIMALS`, eg `cpu=2`, the decimals above `MAX_PRECISION` are clamped to it
+pub fn parse_precision(s: &str) -> Result<(String, usize), String> {
+    let (kind, decimals) = parse_key_value(s)?;
+    if kind.chars().any(|c| c.is_whitespace() || c == '=') {
+        return Err(format!("invalid kind `{}` in `{}`", kind, s));
+    }
+    let decimals = decimals
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| format!("invalid number of decimals `{}` in `{}`", decimals, s))?;
+    Ok((kind, decimals.min(MAX_PRECISION)))
+}
+
+/// parse a duration like `30s`, `500ms`, `2m`, `1h` (seconds without unit)
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration `{}`: a number is expected", s))?;
+    let secs = |factor: u64| {
+        value
+            .checked_mul(factor)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("invalid duration `{}`: too long", s))
+    };
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(value)),
+        "" | "s" => Ok(Duration::from_secs(value)),
+        "m" => secs(60),
+        "h" => secs(3600),
+        unit => Err(format!(
+            "invalid duration `{}`: unknown unit `{}` (ms, s, m, h)",
+            s, unit
+        )),
+    }
+}
+
+/// parse a cli argument like `key=value`
+pub fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .ok_or_else(|| format!("invalid KEY=VALUE: no `=` found in `{}`", s))
+}
+
+/// parse an additional output `FORMAT:PATH`, eg `json:report.json`
+pub fn parse_also(s: &str) -> Result<(Output, std::path::PathBuf), String> {
+    let (format, path) = s
+        .split_once(':')
+        .filter(|(_, path)| !path.is_empty())
+        .ok_or_else(|| format!("invalid FORMAT:PATH: no `:` found in `{}`", s))?;
+    let output = Output::from_str(format, true)?;
+    if output == Output::console_json {
+        return Err("console-json can not be written into a file, use json".to_string());
+    }
+    Ok((output, std::path::PathBuf::from(path)))
+}
+
+/// the outputs with quantities rendered by `DisplayOpts::format_qty` (see `--quantities`)
+const QTY_STYLED_OUTPUTS: [Output; 4] = [Output::table, Output::plain, Output::csv, Output::json];
+
+/// parse the rendering of the quantities of an output `FORMAT=STYLE`, eg `csv=scaled`
+pub fn parse_quantities(s: &str) -> Result<(Output, QtyStyle), String> {
+    let (format, style) = parse_key_value(s)?;
+    let output = Output::from_str(&format, true)?;
+    if !QTY_STYLED_OUTPUTS.contains(&output) {
+        return Err(format!(
+            "the quantities of `{}` are not formatted, only the ones of table, plain, csv & json",
+            format
+        ));
+    }
+    Ok((output, QtyStyle::from_str(&style, true)?))
+}
+
+/// parse a threshold `KIND=QTY`, eg `memory=1Gi`
+pub fn parse_threshold(s: &str) -> Result<(String, Qty), String> {
+    let (kind, v) = parse_key_value(s)?;
+    let qty = Qty::from_str(&v).map_err(|e| format!("invalid quantity `{}`: {}", v, e))?;
+    Ok((kind, qty))
+}
+
+/// parse a synthetic kind `NAME=KIND1,KIND2,...`, eg `accelerators=nvidia.com/gpu,amd.com/gpu`,
+/// a kind ending with `*` is a prefix, eg `gpu=nvidia.com/gpu,nvidia.com/mig-*` (see `sum_kind_matches`)
+pub fn parse_sum_kinds(s: &str) -> Result<(String, Vec<String>), String> {
+    let (name, kinds) = parse_key_value(s)?;
+    let kinds = kinds
+        .split(',')
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+        .collect::<Vec<_>>();
+    if kinds.is_empty() {
+        return Err(format!("no kind to sum in `{}`", s));
+    }
+    Ok((name, kinds))
+}
+
+/// the annotation of the mirror pods (of the api-server) of the static pods run by the kubelet
+pub const MIRROR_POD_ANNOTATION: &str = "kubernetes.io/config.mirror";
+
+/// the location is the one of a static pod (by its mirror annotation, see `--exclude-static-pods`)
+pub fn is_static_pod(location: &Location) -> bool {
+    location.pod_annotations.contains_key(MIRROR_POD_ANNOTATION)
+}
+
+/// Remove the resources of the nodes (allocatable & pods) with one of the `roles` (see `roles_of`),
+/// the resources without node (eg the pending pods) are kept
+pub fn retain_without_roles(resources: &mut Vec<Resource>, roles: &[String]) {
+    resources.retain(|r| {
+        r.location.node_name.is_none()
+            || !roles_of(&r.location.node_labels)
+                .iter()
+                .any(|role| roles.contains(role))
+    });
+}
+
+/// Rename the kinds matching a variant (ignoring the case) to their canonical kind (variant, canonical),
+/// so a resource reported under several kinds is displayed as one row
+pub fn normalize_kinds(resources: &mut [Resource], variants: &[(String, String)]) {
+    if variants.is_empty() {
+        return;
+    }
+    for r in resources.iter_mut() {
+        if let Some((_, canonical)) = variants
+            .iter()
+            .find(|(variant, _)| variant.eq_ignore_ascii_case(&r.kind))
+        {
+            r.kind = canonical.clone();
+        }
+    }
+}
+
+/// the namespace of the namespaces collapsed (see `--collapse-namespaces-below`)
+pub const OTHER_NAMESPACE: &str = "(other)";
+
+/// Move the resources of the namespaces requesting less than `threshold_pct` % of the requested of their kind
+/// (by the pods of every namespace) into the namespace `(other)`, so they are summed as one row;
+/// return the number of namespaces collapsed (by kind)
+pub fn collapse_small_namespaces(resources: &mut [Resource], threshold_pct: f64) -> usize {
+    let mut requested: BTreeMap<(&str, &str), i128> = BTreeMap::new();
+    let mut totals: BTreeMap<&str, i128> = BTreeMap::new();
+    for r in resources.iter() {
+        if let (ResourceQualifier::Requested, Some(ns)) = (&r.qualifier, &r.location.namespace) {
+            *requested.entry((&r.kind, ns)).or_default() += r.quantity.value;
+            *totals.entry(&r.kind).or_default() += r.quantity.value;
+        }
+    }
+    let is_small = |kind: &str, ns: &str| match totals.get(kind) {
+        Some(total) => {
+            let value = requested.get(&(kind, ns)).copied().unwrap_or_default();
+            (value as f64) * 100.0 < threshold_pct * (*total as f64)
+        }
+        None => false,
+    };
+    let mut collapsed = BTreeSet::new();
+    let smalls = resources
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| {
+            r.location
+                .namespace
+                .as_deref()
+                .filter(|ns| *ns != OTHER_NAMESPACE && is_small(&r.kind, ns))
+                .map(|ns| (i, (r.kind.clone(), ns.to_string())))
+        })
+        .collect::<Vec<_>>();
+    for (i, key) in smalls {
+        resources[i].location.namespace = Some(OTHER_NAMESPACE.to_string());
+        collapsed.insert(key);
+    }
+    collapsed.len()
+}
+
+/// the `kind` is summed by the `pattern` of a synthetic kind: the same kind, or a kind starting with the prefix
+/// of a pattern ending with `*` (eg `nvidia.com/mig-*` for every MIG profile)
+pub fn sum_kind_matches(pattern: &str, kind: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => kind.starts_with(prefix),
+        None => pattern == kind,
+    }
+}
+
+/// Add the resources of the synthetic kinds (name, kinds summed), a copy of the resources of every kind summed
+pub fn add_sum_kinds(resources: &mut Vec<Resource>, sum_kinds: &[(String, Vec<String>)]) {
+    let mut synthetics = vec![];
+    for (name, kinds) in sum_kinds {
+        synthetics.extend(
+            resources
+                .iter()
+                .filter(|r| &r.kind != name && kinds.iter().any(|k| sum_kind_matches(k, &r.kind)))
+                .map(|r| Resource {
+                    kind: name.clone(),
+                    ..r.clone()
+                }),
+        );
+    }
+    resources.extend(synthetics);
+}
+
+pub async fn refresh_kube_config(cli_opts: &CliOpts) -> Result<(), Error> {
+    //HACK force refresh token by calling "kubectl cluster-info before loading configuration"
+    use tokio::process::Command;
+    let mut cmd = Command::new("kubectl");
+    // killed when the run times out (see `--timeout`)
+    cmd.arg("cluster-info")
+        .args(kubectl_args(cli_opts))
+        .kill_on_drop(true);
+    let output = cmd.output().await.map_err(|source| Error::CmdError {
+        cmd: "kubectl cluster-info".to_owned(),
+        output: None,
+        source: Some(source),
+    })?;
+    if !output.status.success() {
+        return Err(Error::CmdError {
+            cmd: "kubectl cluster-info".to_owned(),
+            output: Some(output),
+            source: None,
+        });
+    }
+    Ok(())
+}
+
+/// the kubectl global flags (of the kubeconfig) to forward to kubectl
+pub fn kubectl_args(cli_opts: &CliOpts) -> Vec<std::ffi::OsString> {
+    let mut args: Vec<std::ffi::OsString> = vec![];
+    if let Some(ref kubeconfig) = cli_opts.kubeconfig {
+        args.push("--kubeconfig".into());
+        args.push(kubeconfig.into());
+    }
+    for (flag, value) in [
+        ("--context", &cli_opts.context),
+        ("--cluster", &cli_opts.cluster),
+        ("--user", &cli_opts.user),
+    ] {
+        if let Some(value) = value {
+            args.push(flag.into());
+            args.push(value.into());
+        }
+    }
+    args
+}
+
+/// set the impersonation headers (`Impersonate-User`, `Impersonate-Group`) of `--as` & `--as-group`
+pub fn apply_impersonation(client_config: &mut kube::Config, cli_opts: &CliOpts) {
+    if let Some(ref user) = cli_opts.as_user {
+        client_config.auth_info.impersonate = Some(user.clone());
+    }
+    if !cli_opts.as_group.is_empty() {
+        client_config.auth_info.impersonate_groups = Some(cli_opts.as_group.clone());
+    }
+}
+
+pub async fn new_client(cli_opts: &CliOpts) -> Result<kube::Client, Error> {
+    let (client, _) = new_client_with_info(cli_opts).await?;
+    Ok(client)
+}
+
+/// The cluster targeted by a run (for auditability)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClusterInfo {
+    /// name of the kubeconfig context (none when in-cluster)
+    pub context: Option<String>,
+    /// url of the API server
+    pub server: String,
+}
+
+impl ClusterInfo {
+    pub fn new(context: Option<String>, client_config: &kube::Config) -> ClusterInfo {
+        ClusterInfo {
+            context,
+            server: client_config.cluster_url.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ClusterInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Context: {}, Server: {}",
+            self.context.as_deref().unwrap_or("(in-cluster)"),
+            self.server
+        )
+    }
+}
+
+pub async fn new_client_with_info(
+    cli_opts: &CliOpts,
+) -> Result<(kube::Client, ClusterInfo), Error> {
+    refresh_kube_config(cli_opts).await?;
+    let options = kube::config::KubeConfigOptions {
+        context: cli_opts.context.clone(),
+        cluster: cli_opts.cluster.clone(),
+        user: cli_opts.user.clone(),
+    };
+    let kubeconfig = match cli_opts.kubeconfig {
+        Some(ref path) => Some(kube::config::Kubeconfig::read_from(path).map_err(|source| {
+            Error::KubeConfigError {
+                context: format!("read the kubeconfig {}", path.display()),
+                source,
+            }
+        })?),
+        None => None,
+    };
+    let config_error = |source| Error::KubeConfigError {
+        context: "create the kube client config".to_string(),
+        source,
+    };
+    let mut client_config = match kubeconfig.clone() {
+        Some(kubeconfig) => kube::Config::from_custom_kubeconfig(kubeconfig, &options)
+            .await
+            .map_err(config_error)?,
+        None if options.context.is_some()
+            || options.cluster.is_some()
+            || options.user.is_some() =>
+        {
+            kube::Config::from_kubeconfig(&options)
+                .await
+                .map_err(config_error)?
+        }
+        None => kube::Config::infer()
+            .await
+            .map_err(|source| Error::KubeInferConfigError {
+                context: "create the kube client config".to_string(),
+                source,
+            })?,
+    };
+    apply_impersonation(&mut client_config, cli_opts);
+    info!(cluster_url = client_config.cluster_url.to_string().as_str());
+    // the context used by `infer` is the current one of the kubeconfig (if any)
+    let context = cli_opts.context.clone().or_else(|| {
+        kubeconfig
+            .or_else(|| kube::config::Kubeconfig::read().ok())
+            .and_then(|k| k.current_context)
+    });
+    let cluster = ClusterInfo::new(context, &client_config);
+    Ok((make_client(client_config)?, cluster))
+}
+
+/// Create the client, credentials of the user are resolved here: token, client certificate,
+/// auth-provider or exec credential plugins (eg `aws eks get-token`, `gke-gcloud-auth-plugin`).
+pub fn make_client(client_config: kube::Config) -> Result<kube::Client, Error> {
+    kube::Client::try_from(client_config).map_err(|source| Error::KubeError {
+        context: "create the kube client".to_string(),
+        source,
+    })
+}
+
+/// Describe the requests that would be sent to the API server (and the filters applied locally)
+pub fn describe_query_plan(cli_opts: &CliOpts) -> Vec<String> {
+    let params = if cli_opts.chunk_size > 0 {
+        format!("?limit={} (paginated)", cli_opts.chunk_size)
+    } else {
+        "".to_string()
+    };
+    let mut plan = vec![if cli_opts.batch.is_empty() {
+        format!(
+            "context: {}",
+            cli_opts.context.as_deref().unwrap_or("(current)")
+        )
+    } else {
+        format!(
+            "contexts: {} (concurrently, the requests below by context)",
+            cli_opts.batch.join(", ")
+        )
+    }];
+    if let Some(ref kubeconfig) = cli_opts.kubeconfig {
+        plan.push(format!("kubeconfig: {}", kubeconfig.display()));
+    }
+    if cli_opts.as_user.is_some() || !cli_opts.as_group.is_empty() {
+        plan.push(format!(
+            "impersonate: user {}, groups [{}]",
+            cli_opts.as_user.as_deref().unwrap_or("(none)"),
+            cli_opts.as_group.join(", ")
+        ));
+    }
+    plan.push("run: kubectl cluster-info (to refresh the token)".to_string());
+    if cli_opts.allocated_resources {
+        plan.push("get version: GET /version (--allocated-resources requires >= 1.27)".to_string());
+    }
+    if cli_opts.collects_nodes() {
+        plan.push(format!("list nodes: GET /api/v1/nodes{}", params));
+        for extra in &cli_opts.extra_allocatable_from {
+            plan.push(format!(
+                "list {} (allocatable of {}): GET {}{}",
+                extra.plural,
+                extra.kind,
+                extra.url_path(),
+                params
+            ));
+        }
+    }
+    if cli_opts.collects_pods() {
+        plan.push(match &cli_opts.namespace {
+            Some(ns) => format!("list pods: GET /api/v1/namespaces/{}/pods{}", ns, params),
+            None => format!("list pods: GET /api/v1/pods{}", params),
+        });
+    }
+    if cli_opts.utilization && cli_opts.collects_pods() {
+        plan.push(match &cli_opts.metrics_api_version {
+            Some(api_version) => format!("list pod metrics: GET /apis/{}/pods{}", api_version, params),
+            None => format!(
+                "list pod metrics: GET /apis/<preferred version of metrics.k8s.io, else {}>/pods{} (after a GET /apis)",
+                DEFAULT_METRICS_API_VERSION, params
+            ),
+        });
+    }
+    if cli_opts.resource_claims && cli_opts.collects_pods() {
+        plan.push(match &cli_opts.namespace {
+            Some(ns) => format!(
+                "list resource claims: GET /apis/<preferred version of {}>/namespaces/{}/resourceclaims{} (after a GET /apis)",
+                RESOURCE_CLAIM_GROUP, ns, params
+            ),
+            None => format!(
+                "list resource claims: GET /apis/<preferred version of {}>/resourceclaims{} (after a GET /apis)",
+                RESOURCE_CLAIM_GROUP, params
+            ),
+        });
+    }
+    if cli_opts.compare_to_quota {
+        plan.push(match &cli_opts.namespace {
+            Some(ns) => format!(
+                "list resourcequotas: GET /api/v1/namespaces/{}/resourcequotas{}",
+                ns, params
+            ),
+            None => format!("list resourcequotas: GET /api/v1/resourcequotas{}", params),
+        });
+    }
+    if cli_opts.limit_ranges {
+        plan.push(match &cli_opts.namespace {
+            Some(ns) => format!(
+                "list limitranges: GET /api/v1/namespaces/{}/limitranges{}",
+                ns, params
+            ),
+            None => format!("list limitranges: GET /api/v1/limitranges{}", params),
+        });
+    }
+    if cli_opts.include_jobs {
+        for (kind, group) in [("jobs", "batch/v1"), ("cronjobs", "batch/v1")] {
+            plan.push(match &cli_opts.namespace {
+                Some(ns) => format!(
+                    "list {}: GET /apis/{}/namespaces/{}/{}{}",
+                    kind, group, ns, kind, params
+                ),
+                None => format!("list {}: GET /apis/{}/{}{}", kind, group, kind, params),
+            });
+        }
+    }
+    match &cli_opts.annotate {
+        Some(AnnotateTarget::ConfigMap { namespace, name }) => plan.push(format!(
+            "annotate: PATCH /api/v1/namespaces/{}/configmaps/{} (after the display)",
+            namespace, name
+        )),
+        Some(AnnotateTarget::Nodes) => plan.push(
+            "annotate: PATCH /api/v1/nodes/<node> for every node (after the display)".to_string(),
+        ),
+        None => {}
+    }
+    plan.push(if cli_opts.qps > 0.0 {
+        format!(
+            "throttling: {} requests/s, burst {}",
+            cli_opts.qps, cli_opts.burst
+        )
+    } else {
+        "throttling: disabled".to_string()
+    });
+    if !cli_opts.exclude_namespace.is_empty() {
+        plan.push(format!(
+            "filter: exclude namespaces {}",
+            cli_opts.exclude_namespace.join(", ")
+        ));
+    }
+    if let Some(regex) = &cli_opts.namespace_regex {
+        plan.push(format!("filter: namespaces matching {}", regex));
+    }
+    if let Some(pod) = &cli_opts.pod {
+        plan.push(format!("filter: pod {}", pod));
+    }
+    if let Some(class) = &cli_opts.for_priority {
+        if class.parse::<i32>().is_err() {
+            plan.push(format!(
+                "get priority class: GET /apis/scheduling.k8s.io/v1/priorityclasses/{}",
+                class
+            ));
+        }
+        plan.push(format!("filter: pods with priority >= {}", class));
+    }
+    if !cli_opts.qos.is_empty() {
+        plan.push(format!(
+            "filter: qos class {}",
+            cli_opts.qos.iter().map(|q| q.to_string()).join(", ")
+        ));
+    }
+    plan
+}
+
+#[instrument]
+pub async fn do_main(cli_opts: &CliOpts) -> Result<(), Error> {
+    let result = match cli_opts.timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, run(cli_opts)).await {
+            Ok(result) => result,
+            // a bounded watch
+            Err(_) if cli_opts.watch => Ok(()),
+            Err(_) => Err(Error::Timeout { timeout }),
+        },
+        None => run(cli_opts).await,
+    };
+    result.map_err(|err| explain_impersonation_error(err, cli_opts))
+}
+
+/// replace the error of a request denied because of the impersonation (`--as`, `--as-group`)
+/// by a clearer one, other errors are unchanged
+pub fn explain_impersonation_error(err: Error, cli_opts: &CliOpts) -> Error {
+    if cli_opts.as_user.is_none() && cli_opts.as_group.is_empty() {
+        return err;
+    }
+    match err {
+        Error::KubeError {
+            source: kube::Error::Api(response),
+            ..
+        } if response.code == 403 && response.message.contains("impersonate") => {
+            Error::ImpersonationForbidden {
+                target: format!(
+                    "user {}, groups [{}]",
+                    cli_opts.as_user.as_deref().unwrap_or("(none)"),
+                    cli_opts.as_group.join(", ")
+                ),
+                source: kube::Error::Api(response),
+            }
+        }
+        err => err,
+    }
+}
+
+impl CliOpts {
+    /// the filter of the logs (like `RUST_LOG`) set by `--quiet` or `--verbose`, none if neither
+    pub fn log_filter(&self) -> Option<&'static str> {
+        if self.quiet {
+            return Some("error,kube_client=off");
+        }
+        match self.verbose {
+            0 => None,
+            1 => Some("info,kube_client=warn"),
+            2 => Some("debug,kube_client=info"),
+            _ => Some("trace"),
+        }
+    }
+}
+
+async fn run(cli_opts: &CliOpts) -> Result<(), Error> {
+    if cli_opts.dry_run {
+        for line in describe_query_plan(cli_opts) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    if !cli_opts.batch.is_empty() {
+        return run_batch(cli_opts).await;
+    }
+    let (client, cluster) = new_client_with_info(cli_opts).await?;
+    let mut lister = Lister {
+        chunk_size: cli_opts.chunk_size,
+        limiter: RateLimiter::new(cli_opts.qps, cli_opts.burst),
+    };
+    let min_priority = match &cli_opts.for_priority {
+        Some(class) => Some(resolve_priority(client.clone(), class).await?),
+        None => None,
+    };
+    if cli_opts.watch {
+        return watch_and_display(client, &mut lister, cli_opts, &cluster, min_priority).await;
+    }
+    let collected = collect(client.clone(), &mut lister, cli_opts).await?;
+    // before the display (that consumes the resources), on every resource (whatever the filters of the display)
+    let reports = cli_opts.annotate.as_ref().map(|target| {
+        (
+            target,
+            capacity_reports(&collected.resources, &cli_opts.resource_name, target),
+        )
+    });
+    let displayed = display_to_stdout(cli_opts, &cluster, min_priority, collected);
+    if let Some((target, reports)) = reports {
+        annotate(client, &mut lister.limiter, target, &reports, Utc::now()).await?;
+    }
+    displayed
+}
+
+/// The object annotated with the capacity report (see `--annotate`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnotateTarget {
+    /// the (existing) ConfigMap, with the report of the cluster
+    ConfigMap { namespace: String, name: String },
+    /// every node, with its own report
+    Nodes,
+}
+
+impl std::fmt::Display for AnnotateTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ConfigMap { namespace, name } => write!(f, "configmap/{}/{}", namespace, name),
+            Self::Nodes => f.write_str("nodes"),
+        }
+    }
+}
+
+/// parse the target of `--annotate`: `configmap/<NAMESPACE>/<NAME>` or `nodes`
+pub fn parse_annotate_target(s: &str) -> Result<AnnotateTarget, String> {
+    let parts = s.split('/').collect::<Vec<_>>();
+    match parts.as_slice() {
+        ["nodes"] => Ok(AnnotateTarget::Nodes),
+        ["configmap" | "cm", namespace, name] if !namespace.is_empty() && !name.is_empty() => {
+            Ok(AnnotateTarget::ConfigMap {
+                namespace: namespace.to_string(),
+                name: name.to_string(),
+            })
+        }
+        _ => Err(format!(
+            "'{}': expected `configmap/<NAMESPACE>/<NAME>` or `nodes`",
+            s
+        )),
+    }
+}
+
+/// the annotation of the capacity report (see `--annotate`)
+pub const CAPACITY_REPORT_ANNOTATION: &str = "kubectl-view-allocations/capacity-report";
+/// the annotation of the time of the capacity report (rfc3339), to detect a stale report
+pub const CAPACITY_REPORT_AT_ANNOTATION: &str = "kubectl-view-allocations/capacity-report-at";
+
+/// the capacity reports of the `target` (eg `cpu 80% requested, memory 60% requested`), by object to annotate:
+/// the one of the cluster (an empty name) or the ones of the nodes (by name); the kinds without allocatable are ignored
+pub fn capacity_reports(
+    resources: &[Resource],
+    resource_names: &[String],
+    target: &AnnotateTarget,
+) -> BTreeMap<String, String> {
+    let group_by = match target {
+        AnnotateTarget::ConfigMap { .. } => vec![GroupBy::resource],
+        AnnotateTarget::Nodes => vec![GroupBy::node, GroupBy::resource],
+    };
+    let opts = DisplayOpts::default();
+    let mut reports: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (k, oqtys) in make_qualifiers(resources, &group_by, resource_names) {
+        if k.len() != group_by.len() {
+            continue;
+        }
+        let qtys = match oqtys {
+            Some(qtys) => qtys,
+            None => continue,
+        };
+        let allocatable = match &qtys.allocatable {
+            Some(allocatable) if !allocatable.is_zero() => allocatable,
+            _ => continue,
+        };
+        let requested = qtys.requested.clone().unwrap_or_default();
+        let name = if k.len() > 1 {
+            k[0].clone()
+        } else {
+            String::new()
+        };
+        reports.entry(name).or_default().push(format!(
+            "{} {}% requested",
+            k[k.len() - 1],
+            opts.format_percentage(requested.calc_percentage(allocatable))
+        ));
+    }
+    reports
+        .into_iter()
+        .map(|(name, kinds)| (name, kinds.join(", ")))
+        .collect()
+}
+
+/// the explanation of a failed write of the annotation on `plural` (eg a denial by the RBAC)
+pub fn explain_write_error(err: &kube::Error, plural: &str) -> String {
+    match err {
+        kube::Error::Api(response) if response.code == 403 => format!(
+            "forbidden (the current user requires the permission `patch` on {})",
+            plural
+        ),
+        kube::Error::Api(response) if response.code == 404 => "not found".to_string(),
+        err => err.to_string(),
+    }
+}
+
+/// write the `reports` (see `capacity_reports`) as annotations of the `target`, every write is throttled by the `limiter`;
+/// a node failing doesn't stop the others, but a denial (the same for every node) does
+pub async fn annotate(
+    client: kube::Client,
+    limiter: &mut RateLimiter,
+    target: &AnnotateTarget,
+    reports: &BTreeMap<String, String>,
+    now: DateTime<Utc>,
+) -> Result<(), Error> {
+    let patch_of = |report: &str| {
+        serde_json::json!({
+            "metadata": {
+                "annotations": {
+                    CAPACITY_REPORT_ANNOTATION: report,
+                    CAPACITY_REPORT_AT_ANNOTATION: now.to_rfc3339(),
+                }
+            }
+        })
+    };
+    let params = PatchParams::default();
+    let mut failures = vec![];
+    match target {
+        AnnotateTarget::ConfigMap { namespace, name } => {
+            let api: Api<ConfigMap> = Api::namespaced(client, namespace);
+            let report = reports.get("").map(|r| r.as_str()).unwrap_or_default();
+            limiter.acquire().await;
+            if let Err(err) = api
+                .patch(name, &params, &Patch::Merge(patch_of(report)))
+                .await
+            {
+                failures.push(explain_write_error(&err, "configmaps"));
+            }
+        }
+        AnnotateTarget::Nodes => {
+            let api: Api<Node> = Api::all(client);
+            for (node, report) in reports {
+                limiter.acquire().await;
+                if let Err(err) = api
+                    .patch(node, &params, &Patch::Merge(patch_of(report)))
+                    .await
+                {
+                    let denied = matches!(&err, kube::Error::Api(response) if response.code == 403);
+                    failures.push(format!("{}: {}", node, explain_write_error(&err, "nodes")));
+                    if denied {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    if failures.is_empty() {
+        info!(%target, objects = reports.len(), "capacity report annotated");
+        Ok(())
+    } else {
+        Err(Error::AnnotateError {
+            target: target.to_string(),
+            message: failures.join("; "),
+        })
+    }
+}
+
+/// Collect from every context of `--batch` concurrently, and display the merged resources.
+/// The failure of a context is recorded into the report (the run fails only when every context failed).
+async fn run_batch(cli_opts: &CliOpts) -> Result<(), Error> {
+    let results = futures::future::join_all(
+        cli_opts
+            .batch
+            .iter()
+            .map(|context| collect_context(cli_opts, context)),
+    )
+    .await;
+    let mut merged = Collected::default();
+    let mut servers = vec![];
+    let mut last_err = None;
+    for (context, result) in cli_opts.batch.iter().zip(results) {
+        match result {
+            Ok((cluster, collected)) => {
+                servers.push(cluster.server);
+                merged.merge(collected);
+            }
+            Err(err) => {
+                merged
+                    .report
+                    .record_error(&format!("context {}", context), &err);
+                last_err = Some(err);
+            }
+        }
+    }
+    if let (true, Some(err)) = (servers.is_empty(), last_err) {
+        return Err(err);
+    }
+    let cluster = ClusterInfo {
+        context: Some(cli_opts.batch.join(",")),
+        server: servers.join(","),
+    };
+    display_to_stdout(cli_opts, &cluster, None, merged)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_resource() {
+        assert!(accept_resource("cpu", &[]));
+        assert!(accept_resource("cpu", &["c".to_string()]));
+        assert!(accept_resource("cpu", &["cpu".to_string()]));
+        assert!(!accept_resource("cpu", &["cpu3".to_string()]));
+        assert!(accept_resource("gpu", &["gpu".to_string()]));
+        assert!(accept_resource("nvidia.com/gpu", &["gpu".to_string()]));
+    }
+
+    #[test]
+    fn test_used_basis() {
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap());
+        let qtys = QtyByQualifier {
+            requested: qty("1"),
+            limit: qty("3"),
+            allocatable: qty("4"),
+            ..QtyByQualifier::default()
+        };
+        let free_of = |qtys: &QtyByQualifier, basis: UsedBasis| {
+            let mut data = vec![(vec![], Some(qtys.clone()))];
+            apply_used_basis(&mut data, basis);
+            data[0].1.as_ref().unwrap().calc_free().map(|q| q.value)
+        };
+        assert_eq!(free_of(&qtys, UsedBasis::requested), Some(3000));
+        assert_eq!(free_of(&qtys, UsedBasis::limit), Some(1000));
+        assert_eq!(free_of(&qtys, UsedBasis::max), Some(1000));
+        // requested over limit (eg limit of a part of the containers)
+        let over = QtyByQualifier {
+            requested: qty("2"),
+            limit: qty("1500m"),
+            ..qtys.clone()
+        };
+        assert_eq!(free_of(&over, UsedBasis::limit), Some(2500));
+        assert_eq!(free_of(&over, UsedBasis::max), Some(2000));
+        // only requested (no limit): nothing used by the limits
+        let no_limit = QtyByQualifier {
+            limit: None,
+            ..qtys.clone()
+        };
+        assert_eq!(free_of(&no_limit, UsedBasis::limit), Some(4000));
+        assert_eq!(free_of(&no_limit, UsedBasis::requested), Some(3000));
+        // neither requested nor limited
+        let unused = QtyByQualifier {
+            requested: None,
+            limit: None,
+            ..qtys
+        };
+        assert_eq!(free_of(&unused, UsedBasis::max), None);
+        assert_eq!(
+            CliOpts::parse_from(["test"]).used_basis,
+            UsedBasis::requested
+        );
+    }
+
+    #[test]
+    fn test_percent_base() {
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap());
+        let qtys = QtyByQualifier {
+            requested: qty("4"),
+            allocatable: qty("7500m"),
+            capacity: qty("8"),
+            ..QtyByQualifier::default()
+        };
+        let mut data = vec![(vec!["cpu".to_string()], Some(qtys.clone()))];
+        assert_eq!(
+            data[0].1.as_ref().unwrap().calc_percent_base(),
+            qty("7500m")
+        );
+        apply_percent_base(&mut data, PercentBase::capacity);
+        let qtys = data[0].1.as_ref().unwrap();
+        assert_eq!(qtys.calc_percent_base(), qty("8"));
+        let opts = DisplayOpts::default();
+        assert_eq!(opts.format_free_with_pct(qtys), "3.5 (44%)");
+        // the zero is not a denominator
+        let zero = QtyByQualifier {
+            capacity: qty("0"),
+            ..qtys.clone()
+        };
+        assert_eq!(zero.calc_percent_base(), None);
+        assert_eq!(opts.format_free_with_pct(&zero), "3.5");
+        assert_eq!(
+            CliOpts::parse_from(["test"]).percent_base,
+            PercentBase::allocatable
+        );
+    }
+
+    #[tokio::test]
+    async fn test_percent_base_effective() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "4" }),
+        )]);
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("cpu", "1500m")], &[])],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let cli_opts = CliOpts::parse_from([
+            "test",
+            "--reserve-for-system",
+            "cpu=1",
+            "--free-headroom",
+            "50",
+            "--percent-base",
+            "effective",
+        ]);
+        apply_reserve_for_system(&mut resources, &cli_opts.reserve_for_system);
+        let mut data = make_qualifiers(&resources, &[GroupBy::resource], &[]);
+        apply_percent_base(&mut data, cli_opts.percent_base);
+        apply_free_headroom(&mut data, cli_opts.free_headroom);
+        let qtys = data[0].1.as_ref().unwrap();
+        // (4 - 1) * 50%
+        assert_eq!(
+            qtys.calc_percent_base().map(|q| q.to_canonical_string()),
+            Some("1500m".to_string())
+        );
+        assert_eq!(qtys.calc_free().map(|q| q.value), Some(0));
+        let requested = qtys.requested.as_ref().unwrap();
+        assert_eq!(
+            requested.calc_percentage(&qtys.calc_percent_base().unwrap()),
+            100.0
+        );
+        // against the allocatable (after the reserve), requested 50% with 0 free
+        let mut data = make_qualifiers(&resources, &[GroupBy::resource], &[]);
+        apply_free_headroom(&mut data, cli_opts.free_headroom);
+        let qtys = data[0].1.as_ref().unwrap();
+        assert_eq!(
+            requested.calc_percentage(&qtys.calc_percent_base().unwrap()),
+            50.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_unavailable_kinds() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "4", "pods": "110", "example.com/bar": "0" }),
+        )]);
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(
+                    &[("cpu", "1"), ("example.com/foo", "1")],
+                    &[],
+                )],
+            ),
+            make_pod(
+                "p2",
+                "n1",
+                vec![make_container(&[], &[("example.com/foo", "2")])],
+            ),
+            make_pod(
+                "p3",
+                "n1",
+                vec![make_container(&[("example.com/bar", "1")], &[])],
+            ),
+            make_pod(
+                "p4",
+                "n1",
+                vec![make_container(&[("example.com/baz", "0")], &[])],
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let unavailable_kinds = find_unavailable_kinds(&resources);
+        assert_eq!(
+            unavailable_kinds,
+            vec![
+                UnavailableKind {
+                    kind: "example.com/bar".to_string(),
+                    pods: vec!["default/p3".to_string()],
+                },
+                UnavailableKind {
+                    kind: "example.com/foo".to_string(),
+                    pods: vec!["default/p1".to_string(), "default/p2".to_string()],
+                },
+            ]
+        );
+        assert_eq!(
+            validation_summary(&unavailable_kinds)[0],
+            "Validation: 2 issue(s)"
+        );
+        assert_eq!(validation_summary(&[]), vec!["Validation: no issue"]);
+        assert!(matches!(
+            validated(2),
+            Err(Error::ValidationFailed { count: 2 })
+        ));
+        let cli_opts =
+            CliOpts::parse_from(["test", "--validate", "--skip-check", "unavailable-kinds"]);
+        assert_eq!(cli_opts.skip_check, vec![Check::unavailable_kinds]);
+    }
+
+    #[test]
+    fn test_exclude_resource() {
+        let excluded =
+            CliOpts::parse_from(["test", "--exclude-resource", "pods,ephemeral-storage"])
+                .exclude_resource;
+        assert!(exclude_resource("pods", &excluded));
+        assert!(exclude_resource("ephemeral-storage", &excluded));
+        assert!(!exclude_resource("cpu", &excluded));
+        assert!(!exclude_resource("cpu", &[]));
+    }
+
+    #[test]
+    fn test_calc_request_limit_ratio() {
+        let qtys = QtyByQualifier {
+            requested: Some(Qty::from_str("100m").unwrap()),
+            limit: Some(Qty::from_str("4").unwrap()),
+            ..QtyByQualifier::default()
+        };
+        assert_eq!(qtys.calc_request_limit_ratio(), Some(0.025));
+        let no_limit = QtyByQualifier {
+            no_limit: true,
+            ..qtys.clone()
+        };
+        assert_eq!(no_limit.calc_request_limit_ratio(), None);
+        let zero_limit = QtyByQualifier {
+            limit: Some(Qty::default()),
+            ..qtys
+        };
+        assert_eq!(zero_limit.calc_request_limit_ratio(), None);
+    }
+
+    pub(crate) fn qtys_requested(requested: &str) -> Option<QtyByQualifier> {
+        Some(QtyByQualifier {
+            requested: Some(Qty::from_str(requested).unwrap()),
+            ..QtyByQualifier::default()
+        })
+    }
+
+    pub(crate) fn keys(data: &[(Vec<String>, Option<QtyByQualifier>)]) -> Vec<String> {
+        data.iter().map(|(k, _)| k.join("/")).collect()
+    }
+
+    #[test]
+    fn test_sort_and_keep_top_qualifiers() {
+        let mut data = vec![
+            (vec!["cpu".to_string()], qtys_requested("6")),
+            (
+                vec!["cpu".to_string(), "n1".to_string()],
+                qtys_requested("1"),
+            ),
+            (
+                vec!["cpu".to_string(), "n1".to_string(), "p1".to_string()],
+                qtys_requested("1"),
+            ),
+            (
+                vec!["cpu".to_string(), "n2".to_string()],
+                qtys_requested("3"),
+            ),
+            (
+                vec!["cpu".to_string(), "n3".to_string()],
+                qtys_requested("2"),
+            ),
+            (vec!["memory".to_string()], qtys_requested("1Gi")),
+            (
+                vec!["memory".to_string(), "n1".to_string()],
+                qtys_requested("1Gi"),
+            ),
+        ];
+        let mut ascending = data.clone();
+        sort_qualifiers(&mut ascending, &SortBy::requested, &SortOrder::asc);
+        assert_eq!(
+            keys(&ascending),
+            vec![
+                "cpu",
+                "cpu/n1",
+                "cpu/n1/p1",
+                "cpu/n3",
+                "cpu/n2",
+                "memory",
+                "memory/n1"
+            ]
+        );
+        let mut by_name = data.clone();
+        sort_qualifiers(&mut by_name, &SortBy::name, &SortOrder::desc);
+        assert_eq!(
+            keys(&by_name),
+            vec![
+                "memory",
+                "memory/n1",
+                "cpu",
+                "cpu/n3",
+                "cpu/n2",
+                "cpu/n1",
+                "cpu/n1/p1"
+            ]
+        );
+        sort_qualifiers(&mut data, &SortBy::requested, &SortOrder::desc);
+        assert_eq!(
+            keys(&data),
+            vec![
+                "cpu",
+                "cpu/n2",
+                "cpu/n3",
+                "cpu/n1",
+                "cpu/n1/p1",
+                "memory",
+                "memory/n1"
+            ]
+        );
+        let top = keep_top_qualifiers(data, 1);
+        assert_eq!(
+            keys(&top),
+            vec!["cpu", "cpu/n2", "cpu/(others)", "memory", "memory/n1"]
+        );
+        assert_eq!(
+            top[2].1.as_ref().and_then(|q| q.requested.clone()),
+            Some(Qty::from_str("3").unwrap())
+        );
+    }
+
+    pub(crate) fn make_container(
+        requests: &[(&str, &str)],
+        limits: &[(&str, &str)],
+    ) -> serde_json::Value {
+        make_named_container("c", requests, limits)
+    }
+
+    pub(crate) fn make_named_container(
+        name: &str,
+        requests: &[(&str, &str)],
+        limits: &[(&str, &str)],
+    ) -> serde_json::Value {
+        let to_map = |l: &[(&str, &str)]| {
+            l.iter()
+                .map(|(k, v)| (k.to_string(), serde_json::Value::from(*v)))
+                .collect::<serde_json::Map<_, _>>()
+        };
+        serde_json::json!({
+            "name": name,
+            "resources": { "requests": to_map(requests), "limits": to_map(limits) }
+        })
+    }
+
+    pub(crate) fn make_pod_spec(containers: Vec<serde_json::Value>) -> PodSpec {
+        serde_json::from_value(serde_json::json!({ "containers": containers })).unwrap()
+    }
+
+    pub(crate) fn make_pod(name: &str, node_name: &str, containers: Vec<serde_json::Value>) -> Pod {
+        serde_json::from_value(serde_json::json!({
+            "metadata": { "name": name, "namespace": "default" },
+            "spec": { "nodeName": node_name, "containers": containers },
+            "status": { "phase": "Running" },
+        }))
+        .unwrap()
+    }
+
+    pub(crate) fn make_pod_list(pods: Vec<Pod>) -> ObjectList<Pod> {
+        ObjectList {
+            metadata: Default::default(),
+            items: pods,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_limit_is_tracked_per_group() {
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("cpu", "1")], &[("cpu", "2")])],
+            ),
+            make_pod("p2", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::node, GroupBy::pod],
+            &["cpu".to_string()],
+        );
+        let no_limit_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.as_ref())
+                .map(|q| q.no_limit)
+        };
+        assert_eq!(no_limit_of("cpu/n1"), Some(true));
+        assert_eq!(no_limit_of("cpu/n1/p1"), Some(false));
+        assert_eq!(no_limit_of("cpu/n1/p2"), Some(true));
+    }
+
+    pub(crate) fn requested_of(
+        res: &[(Vec<String>, Option<QtyByQualifier>)],
+        key: &str,
+    ) -> Option<Qty> {
+        res.iter()
+            .find(|(k, _)| k.join("/") == key)
+            .and_then(|(_, q)| q.as_ref())
+            .and_then(|q| q.requested.clone())
+    }
+
+    #[tokio::test]
+    async fn test_sum_of_tiny_requests() {
+        let pods = make_pod_list(
+            (0..1000)
+                .map(|i| {
+                    make_pod(
+                        &format!("p{}", i),
+                        "n1",
+                        vec![make_container(&[("cpu", "1m")], &[])],
+                    )
+                })
+                .collect(),
+        );
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(&resources, &[GroupBy::resource], &["cpu".to_string()]);
+        assert_eq!(
+            requested_of(&res, "cpu").map(|q| q.to_canonical_string()),
+            Some("1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_container_resources_counted_once() {
+        // `container.resources` is one `Option<ResourceRequirements>` (not a list of blocks)
+        let pod = make_pod(
+            "p1",
+            "n1",
+            vec![
+                make_named_container("app", &[("cpu", "1")], &[("cpu", "2")]),
+                serde_json::json!({ "name": "no-resources" }),
+                serde_json::json!({ "name": "empty-resources", "resources": {} }),
+            ],
+        );
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(vec![pod]), &mut resources)
+            .await
+            .unwrap();
+        assert!(!resources.iter().any(|r| matches!(
+            r.location.container_name.as_deref(),
+            Some("no-resources") | Some("empty-resources")
+        )));
+        let res = make_qualifiers(&resources, &[GroupBy::resource, GroupBy::pod], &[]);
+        assert_eq!(
+            requested_of(&res, "cpu/p1"),
+            Some(Qty::from_str("1").unwrap())
+        );
+        let limit = res
+            .iter()
+            .find(|(k, _)| k.join("/") == "cpu/p1")
+            .and_then(|(_, q)| q.as_ref())
+            .and_then(|q| q.limit.clone());
+        assert_eq!(limit, Some(Qty::from_str("2").unwrap()));
+    }
+
+    /// the grouping without `ResourceSums`: every group sums its resources
+    fn naive_make_qualifiers(
+        rsrcs: &[&Resource],
+        prefix: &[String],
+        group_by: &[GroupBy],
+    ) -> Vec<(Vec<String>, Option<QtyByQualifier>)> {
+        let mut out = vec![];
+        if let Some(g) = group_by.get(prefix.len()) {
+            for (key, group) in rsrcs
+                .iter()
+                .filter_map(|e| g.extract(e).map(|k| (k, *e)))
+                .into_group_map()
+            {
+                let mut key_full = prefix.to_vec();
+                key_full.push(key);
+                out.extend(naive_make_qualifiers(&group, &key_full, group_by));
+                out.push((key_full, sum_by_qualifier(&group)));
+            }
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn test_resource_sums_same_as_naive() {
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(
+            make_node_list(vec![
+                make_node(
+                    "n1",
+                    serde_json::json!({}),
+                    serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+                ),
+                make_node(
+                    "n2",
+                    serde_json::json!({}),
+                    serde_json::json!({ "cpu": "2", "pods": "110" }),
+                ),
+            ]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        let mut pods = vec![];
+        for (i, n) in ["n1", "n2", "n1"].iter().enumerate() {
+            pods.push(make_pod(
+                &format!("p{}", i),
+                n,
+                vec![
+                    make_named_container(
+                        "app",
+                        &[("cpu", "1"), ("memory", "1Gi")],
+                        &[("cpu", "2")],
+                    ),
+                    make_named_container("sidecar", &[("cpu", "100m")], &[("memory", "128Mi")]),
+                ],
+            ));
+        }
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let values = |data: Vec<(Vec<String>, Option<QtyByQualifier>)>| {
+            data.into_iter()
+                .map(|(k, oqtys)| {
+                    let v = |q: &Option<Qty>| q.as_ref().map(|q| q.value);
+                    let qtys = oqtys.map(|q| {
+                        (
+                            v(&q.limit),
+                            v(&q.requested),
+                            v(&q.allocatable),
+                            v(&q.utilization),
+                            q.no_limit,
+                            q.nodes,
+                        )
+                    });
+                    (k, qtys)
+                })
+                .sorted_by(|a, b| a.0.cmp(&b.0))
+                .collect::<Vec<_>>()
+        };
+        for group_by in [
+            vec![GroupBy::resource],
+            vec![GroupBy::resource, GroupBy::node, GroupBy::pod],
+            vec![GroupBy::resource, GroupBy::namespace, GroupBy::container],
+            vec![GroupBy::resource, GroupBy::qos, GroupBy::node],
+            // kinds are mixed in the groups by node
+            vec![GroupBy::node, GroupBy::resource],
+        ] {
+            let all = resources.iter().collect::<Vec<_>>();
+            let expected = values(naive_make_qualifiers(&all, &[], &group_by));
+            assert_eq!(values(sums.make_qualifiers(&group_by, &[])), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_make_qualifiers_is_deterministic() {
+        let containers = || vec![make_container(&[("cpu", "100m")], &[])];
+        let mut pods = vec![];
+        for n in ["n3", "n1", "n2"] {
+            for p in ["p2", "p3", "p1"] {
+                pods.push(make_pod(&format!("{}-{}", n, p), n, containers()));
+            }
+        }
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(pods.clone()), &mut resources)
+            .await
+            .unwrap();
+        pods.reverse();
+        let mut resources_reversed = vec![];
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources_reversed)
+            .await
+            .unwrap();
+        let group_by = [GroupBy::resource, GroupBy::node, GroupBy::pod];
+        let expected = keys(&make_qualifiers(&resources, &group_by, &[]));
+        assert_eq!(expected.first(), Some(&"cpu".to_string()));
+        assert_eq!(expected.get(1), Some(&"cpu/n1".to_string()));
+        assert_eq!(expected.get(2), Some(&"cpu/n1/n1-p1".to_string()));
+        for _ in 0..10 {
+            assert_eq!(keys(&make_qualifiers(&resources, &group_by, &[])), expected);
+            assert_eq!(
+                keys(&make_qualifiers(&resources_reversed, &group_by, &[])),
+                expected
+            );
+        }
+    }
+
+    pub(crate) fn make_node(
+        name: &str,
+        labels: serde_json::Value,
+        allocatable: serde_json::Value,
+    ) -> Node {
+        serde_json::from_value(serde_json::json!({
+            "metadata": { "name": name, "labels": labels },
+            "status": { "allocatable": allocatable },
+        }))
+        .unwrap()
+    }
+
+    pub(crate) fn make_node_list(nodes: Vec<Node>) -> ObjectList<Node> {
+        ObjectList {
+            metadata: Default::default(),
+            items: nodes,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reserved_is_capacity_minus_allocatable() {
+        let node: Node = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "n1" },
+            "status": {
+                "allocatable": { "cpu": "3860m", "memory": "12880712Ki", "pods": "110" },
+                "capacity": { "cpu": "4", "memory": "16393520Ki", "pods": "110" },
+            },
+        }))
+        .unwrap();
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(make_node_list(vec![node]), &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(&resources, &[GroupBy::resource, GroupBy::node], &[]);
+        let reserved_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.as_ref())
+                .and_then(|q| q.calc_reserved())
+                .map(|q| q.value)
+        };
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        assert_eq!(reserved_of("cpu/n1"), qty("140m"));
+        assert_eq!(reserved_of("memory"), qty("3512808Ki"));
+        assert_eq!(reserved_of("pods/n1"), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_group_by_annotation() {
+        let mut pods = vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n1", vec![make_container(&[("cpu", "2")], &[])]),
+            make_pod("p3", "n1", vec![make_container(&[("cpu", "4")], &[])]),
+        ];
+        for (pod, cost_center) in pods.iter_mut().zip(["team-a", "team-a"]) {
+            pod.metadata.annotations = Some(BTreeMap::from([(
+                "example.com/cost-center".to_string(),
+                cost_center.to_string(),
+            )]));
+        }
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[
+                GroupBy::resource,
+                GroupBy::annotation("example.com/cost-center".to_string()),
+            ],
+            &[],
+        );
+        assert_eq!(
+            keys(&res),
+            vec![
+                "cpu",
+                "cpu/(none)",
+                "cpu/team-a",
+                "pods",
+                "pods/(none)",
+                "pods/team-a"
+            ]
+        );
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        assert_eq!(requested_of(&res, "cpu/team-a").map(|q| q.value), qty("3"));
+        assert_eq!(requested_of(&res, "cpu/(none)").map(|q| q.value), qty("4"));
+    }
+
+    #[tokio::test]
+    async fn test_merge_similar_nodes() {
+        let allocatable = || serde_json::json!({ "cpu": "4", "memory": "16Gi" });
+        let nodes = make_node_list(
+            (1..=40)
+                .map(|i| {
+                    make_node(
+                        &format!("m5-{}", i),
+                        serde_json::json!({ INSTANCE_TYPE_LABEL: "m5.xlarge" }),
+                        allocatable(),
+                    )
+                })
+                .chain(std::iter::once(make_node(
+                    "c5-1",
+                    serde_json::json!({ INSTANCE_TYPE_LABEL: "c5.large" }),
+                    allocatable(),
+                )))
+                .collect(),
+        );
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let pods = make_pod_list(vec![
+            make_pod("p1", "m5-1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "m5-2", vec![make_container(&[("cpu", "2")], &[])]),
+        ]);
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[
+                GroupBy::resource,
+                GroupBy::node_label(INSTANCE_TYPE_LABEL.to_string()),
+            ],
+            &["cpu".to_string()],
+        );
+        assert_eq!(keys(&res), vec!["cpu", "cpu/c5.large", "cpu/m5.xlarge"]);
+        let m5 = res[2].1.as_ref().unwrap();
+        assert_eq!(m5.nodes, 40);
+        assert_eq!(m5.allocatable.as_ref().map(|q| q.value), Some(160_000));
+        assert_eq!(m5.requested.as_ref().map(|q| q.value), Some(3_000));
+        assert_eq!(res[0].1.as_ref().map(|q| q.nodes), Some(41));
+    }
+
+    #[tokio::test]
+    async fn test_hugepages() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "hugepages-1Gi": "2Gi", "hugepages-2Mi": "1Gi", "memory": "16Gi" }),
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(
+                &[("hugepages-2Mi", "512Mi"), ("memory", "1Gi")],
+                &[("hugepages-2Mi", "512Mi"), ("memory", "1Gi")],
+            )],
+        )]);
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(&resources, &[GroupBy::resource, GroupBy::node], &[]);
+        let qtys_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.clone())
+                .unwrap()
+        };
+        let fmt = |q: &Option<Qty>| q.as_ref().map(|q| format!("{}", q.adjust_scale()));
+        let small = qtys_of("hugepages-2Mi/n1");
+        assert_eq!(fmt(&small.requested), Some("512.0Mi".to_string()));
+        assert_eq!(fmt(&small.allocatable), Some("1.0Gi".to_string()));
+        assert_eq!(fmt(&small.calc_free()), Some("512.0Mi".to_string()));
+        let large = qtys_of("hugepages-1Gi");
+        assert_eq!(fmt(&large.allocatable), Some("2.0Gi".to_string()));
+        assert_eq!(large.requested, None);
+        let output = make_json_output(
+            &res,
+            &DisplayOpts {
+                group_by: vec![GroupBy::resource, GroupBy::node],
+                ..DisplayOpts::default()
+            },
+        );
+        let row = output
+            .items
+            .iter()
+            .find(|r| r.keys.get("resource") == Some(&"hugepages-2Mi".to_string()))
+            .unwrap();
+        assert_eq!(row.requested_base, Some(512 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_parse_group_by() {
+        assert_eq!(GroupBy::from_str("Node"), Ok(GroupBy::node));
+        assert_eq!(
+            GroupBy::from_str("node-label=kubernetes.io/os"),
+            Ok(GroupBy::node_label("kubernetes.io/os".to_string()))
+        );
+        assert_eq!(
+            GroupBy::from_str("annotation=example.com/cost-center"),
+            Ok(GroupBy::annotation("example.com/cost-center".to_string()))
+        );
+        assert_eq!(GroupBy::from_str("lifecycle"), Ok(GroupBy::lifecycle(None)));
+        assert_eq!(
+            GroupBy::from_str("lifecycle=example.com/pool-type"),
+            Ok(GroupBy::lifecycle(Some(
+                "example.com/pool-type".to_string()
+            )))
+        );
+        assert_eq!(
+            GroupBy::from_str("node-group"),
+            Ok(GroupBy::node_group(Arc::default()))
+        );
+        assert!(GroupBy::from_str("foo").is_err());
+        assert!(GroupBy::from_str("foo=bar").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_group_by_lifecycle() {
+        let allocatable = || serde_json::json!({ "cpu": "4" });
+        let nodes = make_node_list(vec![
+            make_node(
+                "spot-1",
+                serde_json::json!({ "karpenter.sh/capacity-type": "spot" }),
+                allocatable(),
+            ),
+            make_node(
+                "spot-2",
+                serde_json::json!({ "eks.amazonaws.com/capacityType": "SPOT" }),
+                allocatable(),
+            ),
+            make_node(
+                "on-demand-1",
+                serde_json::json!({ "eks.amazonaws.com/capacityType": "ON_DEMAND" }),
+                allocatable(),
+            ),
+            make_node("on-demand-2", serde_json::json!({}), allocatable()),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(
+            make_pod_list(vec![make_pod(
+                "p1",
+                "spot-1",
+                vec![make_container(&[("cpu", "1")], &[])],
+            )]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::lifecycle(None)],
+            &["cpu".to_string()],
+        );
+        assert_eq!(keys(&res), vec!["cpu", "cpu/on-demand", "cpu/spot"]);
+        let spot = res[2].1.as_ref().unwrap();
+        assert_eq!(spot.nodes, 2);
+        assert_eq!(spot.calc_free().map(|q| q.value), Some(7000));
+        let labels = BTreeMap::from([("example.com/pool".to_string(), "Batch".to_string())]);
+        assert_eq!(lifecycle_of(&labels, Some("example.com/pool")), "batch");
+        assert_eq!(
+            lifecycle_of(&labels, Some("example.com/other")),
+            "on-demand"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pods_by_namespace_and_limit_ranges() {
+        let mut pods = vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod(
+                "p2",
+                "n1",
+                vec![
+                    make_named_container("c1", &[("cpu", "1")], &[]),
+                    make_named_container("c2", &[("cpu", "100m")], &[]),
+                ],
+            ),
+            make_pod("p3", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+        ];
+        pods[1].metadata.annotations = Some(BTreeMap::from([(
+            LIMIT_RANGER_ANNOTATION.to_string(),
+            "LimitRanger plugin set: cpu request for container c2".to_string(),
+        )]));
+        pods[2].metadata.namespace = Some("other".to_string());
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::namespace],
+            &["cpu".to_string()],
+        );
+        let pods_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.as_ref())
+                .map(|q| q.pods)
+        };
+        assert_eq!(pods_of("cpu"), Some(3));
+        assert_eq!(pods_of("cpu/default"), Some(2));
+        assert_eq!(pods_of("cpu/other"), Some(1));
+
+        let limit_range: LimitRange = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "defaults", "namespace": "default" },
+            "spec": { "limits": [
+                { "type": "Container", "defaultRequest": { "cpu": "100m" }, "default": { "cpu": "1" } },
+                { "type": "Pod", "max": { "cpu": "4" } },
+            ]},
+        }))
+        .unwrap();
+        assert_eq!(
+            explain_limit_ranges(&[limit_range], &resources),
+            vec!["default/defaults: default requests cpu=100m (set on 1 pods)".to_string()]
+        );
+        assert_eq!(
+            explain_limit_ranges(&[], &resources),
+            vec!["default: requests set by a LimitRange on 1 pods".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_utilization_with_partial_metrics() {
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4" }),
+            ),
+        ]);
+        let pods = make_pod_list(vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p3", "n2", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p4", "n2", vec![make_container(&[("cpu", "1")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        // only p1 & p3 have metrics (eg p2 & p4 just started)
+        let pod_metrics = ObjectList {
+            metadata: Default::default(),
+            items: ["p1", "p3"]
+                .iter()
+                .map(|name| {
+                    serde_json::from_value(serde_json::json!({
+                        "metadata": { "name": name, "namespace": "default" },
+                        "containers": [{ "name": "c", "usage": { "cpu": "250m", "memory": "10Mi" } }],
+                        "timestamp": "2026-10-14T00:00:00Z",
+                        "window": "30s",
+                    }))
+                    .unwrap()
+                })
+                .collect(),
+        };
+        extract_utilizations_from_pod_metrics(pod_metrics, &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::node, GroupBy::pod],
+            &["cpu".to_string()],
+        );
+        let qtys_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.clone())
+                .unwrap()
+        };
+        let v = |q: &Option<Qty>| q.as_ref().map(|q| q.value);
+        // the pods without metrics keep their requests, without utilization (not 0)
+        for pod in ["cpu/n1/p2", "cpu/n2/p4"] {
+            assert_eq!(v(&qtys_of(pod).requested), Some(1000));
+            assert_eq!(v(&qtys_of(pod).utilization), None);
+        }
+        assert_eq!(v(&qtys_of("cpu/n1/p1").utilization), Some(250));
+        assert_eq!(v(&qtys_of("cpu/n1").requested), Some(2000));
+        assert_eq!(v(&qtys_of("cpu/n1").utilization), Some(250));
+        assert_eq!(v(&qtys_of("cpu").requested), Some(4000));
+        assert_eq!(v(&qtys_of("cpu").utilization), Some(500));
+        // blank, not zero
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node, GroupBy::pod],
+            show_utilization: true,
+            ..DisplayOpts::default()
+        };
+        let row = make_json_output(&res, &opts)
+            .items
+            .into_iter()
+            .find(|row| row.keys.get("pod").map(|p| p.as_str()) == Some("p2"))
+            .unwrap();
+        assert_eq!(row.utilization, None);
+        assert_eq!(row.requested_base, Some(1000));
+    }
+
+    #[tokio::test]
+    async fn test_group_by_node_group() {
+        let allocatable = || serde_json::json!({ "cpu": "4" });
+        let nodes = make_node_list(vec![
+            make_node("n1", serde_json::json!({}), allocatable()),
+            make_node("n2", serde_json::json!({}), allocatable()),
+            make_node("n3", serde_json::json!({}), allocatable()),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let groups: BTreeMap<String, String> =
+            serde_yaml::from_str("n1: rack-a\nn2: rack-a\n").unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::node_group(Arc::new(groups))],
+            &["cpu".to_string()],
+        );
+        assert_eq!(keys(&res), vec!["cpu", "cpu/(ungrouped)", "cpu/rack-a"]);
+        assert_eq!(res[2].1.as_ref().unwrap().nodes, 2);
+        // json is yaml too
+        let groups: BTreeMap<String, String> = serde_yaml::from_str(r#"{"n1": "rack-a"}"#).unwrap();
+        assert_eq!(groups.get("n1").map(|g| g.as_str()), Some("rack-a"));
+    }
+
+    #[tokio::test]
+    async fn test_group_by_node_label_with_windows_node() {
+        let nodes = make_node_list(vec![
+            make_node(
+                "linux-1",
+                serde_json::json!({ "kubernetes.io/os": "linux" }),
+                serde_json::json!({ "cpu": "3860m", "memory": "12880712Ki", "pods": "110" }),
+            ),
+            // values as reported by a windows node on AKS
+            make_node(
+                "windows-1",
+                serde_json::json!({ "kubernetes.io/os": "windows" }),
+                serde_json::json!({
+                    "cpu": "3860m",
+                    "ephemeral-storage": "133003395207",
+                    "memory": "13365452Ki",
+                    "pods": "30",
+                }),
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "windows-1",
+            vec![make_container(&[("memory", "1Gi")], &[])],
+        )]);
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let res = make_qualifiers(
+            &resources,
+            &[
+                GroupBy::resource,
+                GroupBy::node_label("kubernetes.io/os".to_string()),
+            ],
+            &[],
+        );
+        let allocatable_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.as_ref())
+                .and_then(|q| q.allocatable.as_ref())
+                .map(|q| format!("{}", q.adjust_scale()))
+        };
+        assert_eq!(allocatable_of("memory/windows"), Some("12.7Gi".to_string()));
+        assert_eq!(allocatable_of("cpu/windows"), Some("3.9".to_string()));
+        assert_eq!(allocatable_of("pods/windows"), Some("30.0".to_string()));
+        assert_eq!(
+            requested_of(&res, "memory/windows").map(|q| q.value),
+            Some(Qty::from_str("1Gi").unwrap().value)
+        );
+        assert_eq!(requested_of(&res, "memory/linux"), None);
+    }
+
+    async fn make_config_with_exec(command: &str, args: &[&str]) -> kube::Config {
+        let kubeconfig = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Config",
+            "clusters": [{ "name": "c1", "cluster": { "server": "https://127.0.0.1:6443" } }],
+            "users": [{
+                "name": "u1",
+                "user": {
+                    "exec": {
+                        "apiVersion": "client.authentication.k8s.io/v1beta1",
+                        "command": command,
+                        "args": args,
+                    },
+                },
+            }],
+            "contexts": [{ "name": "ctx1", "context": { "cluster": "c1", "user": "u1" } }],
+            "current-context": "ctx1",
+        });
+        let kubeconfig = kube::config::Kubeconfig::from_yaml(&kubeconfig.to_string()).unwrap();
+        kube::Config::from_custom_kubeconfig(kubeconfig, &Default::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_make_client_runs_exec_credential_plugin() {
+        let credential = r#"{"apiVersion":"client.authentication.k8s.io/v1beta1","kind":"ExecCredential","status":{"token":"t0ken"}}"#;
+        let config = make_config_with_exec("sh", &["-c", &format!("echo '{}'", credential)]).await;
+        assert!(make_client(config).is_ok());
+
+        let config = make_config_with_exec("false", &[]).await;
+        assert!(matches!(make_client(config), Err(Error::KubeError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_kubectl_global_flags() {
+        let cli_opts = CliOpts::parse_from([
+            "kubectl-view-allocations",
+            "--kubeconfig",
+            "/tmp/kubeconfig",
+            "--context=ctx1",
+            "--user",
+            "u1",
+            "--as",
+            "system:serviceaccount:ns1:sa1",
+            "--as-group",
+            "g1",
+            "--as-group",
+            "g2",
+            "--namespace=ns1",
+        ]);
+        assert_eq!(
+            kubectl_args(&cli_opts),
+            [
+                "--kubeconfig",
+                "/tmp/kubeconfig",
+                "--context",
+                "ctx1",
+                "--user",
+                "u1"
+            ]
+            .map(std::ffi::OsString::from)
+        );
+        assert_eq!(cli_opts.namespace.as_deref(), Some("ns1"));
+        let mut config = make_config_with_exec("true", &[]).await;
+        apply_impersonation(&mut config, &cli_opts);
+        assert_eq!(
+            config.auth_info.impersonate.as_deref(),
+            Some("system:serviceaccount:ns1:sa1")
+        );
+        assert_eq!(
+            config.auth_info.impersonate_groups,
+            Some(vec!["g1".to_string(), "g2".to_string()])
+        );
+        assert!(describe_query_plan(&cli_opts).contains(
+            &"impersonate: user system:serviceaccount:ns1:sa1, groups [g1, g2]".to_string()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_estimate_growth() {
+        let resources_with = |cpu: &'static str| async move {
+            let nodes = make_node_list(vec![make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "16", "memory": "64Gi" }),
+            )]);
+            let pods = make_pod_list(vec![make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("cpu", cpu), ("memory", "8Gi")], &[])],
+            )]);
+            let mut resources = vec![];
+            extract_allocatable_from_nodes(nodes, &mut resources)
+                .await
+                .unwrap();
+            extract_allocatable_from_pods(pods, &mut resources)
+                .await
+                .unwrap();
+            resources
+        };
+        let now = Utc.with_ymd_and_hms(2023, 1, 9, 0, 0, 0).unwrap();
+        let mut snapshots = vec![];
+        for (days_ago, cpu) in [(8, "2"), (4, "4")] {
+            let opts = DisplayOpts {
+                group_by: vec![GroupBy::resource],
+                now: Some(now - chrono::Duration::days(days_ago)),
+                ..DisplayOpts::default()
+            };
+            let data = make_qualifiers(&resources_with(cpu).await, &opts.group_by, &[]);
+            snapshots.push(Baseline::from(make_json_output(&data, &opts)));
+        }
+        let resources = resources_with("6").await;
+        let sums = ResourceSums::new(&resources);
+        let kinds = ["cpu".to_string(), "memory".to_string()];
+        let growths = estimate_growth(&sums, &kinds, &snapshots, now);
+        let cpu = &growths[0];
+        assert_eq!((cpu.kind.as_str(), cpu.points), ("cpu", 3));
+        // +0.5 cpu by day, (16 - 6) / 0.5
+        assert_eq!(
+            cpu.per_day_qty().map(|q| q.to_string()),
+            Some("500.0m".to_string())
+        );
+        assert_eq!(cpu.days_to_full(), Some(20.0));
+        assert_eq!(growths[1].days_to_full(), None);
+        let lines = growth_summary(&growths, snapshots.len(), now);
+        assert_eq!(
+            lines
+                .iter()
+                .map(|l| l.split_whitespace().join(" "))
+                .collect::<Vec<_>>(),
+            vec![
+                "Growth of requested (linear trend of 2 snapshot(s) to now):",
+                "Resource Requested Allocatable Growth/day Full in",
+                "cpu 6.0 16.0 +500.0m 20d (2023-01-29)",
+                "memory 8.0Gi 64.0Gi +0.0Gi never (not growing)",
+            ]
+        );
+
+        // a single point (no snapshot, or one of now)
+        let growths = estimate_growth(&sums, &kinds, &[], now);
+        assert_eq!(growths[0].per_day, None);
+        assert!(growth_summary(&growths, 0, now)[0].contains("not enough history"));
+        assert_eq!(linear_slope(&[(0.0, 1.0), (0.0, 2.0)]), None);
+    }
+
+    #[tokio::test]
+    async fn test_capacity_reports() {
+        assert_eq!(
+            parse_annotate_target("configmap/ops/capacity"),
+            Ok(AnnotateTarget::ConfigMap {
+                namespace: "ops".to_string(),
+                name: "capacity".to_string(),
+            })
+        );
+        assert_eq!(parse_annotate_target("nodes"), Ok(AnnotateTarget::Nodes));
+        for invalid in ["configmap/ops", "configmap//capacity", "node/n1", ""] {
+            assert!(parse_annotate_target(invalid).is_err(), "{}", invalid);
+        }
+        let cli_opts =
+            CliOpts::parse_from(["kubectl-view-allocations", "--annotate", "cm/ops/capacity"]);
+        assert_eq!(
+            cli_opts.annotate.map(|t| t.to_string()),
+            Some("configmap/ops/capacity".to_string())
+        );
+        assert!(
+            CliOpts::try_parse_from(["kubectl-view-allocations", "--annotate", "nodes", "-w"])
+                .is_err()
+        );
+
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+            ),
+        ]);
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("cpu", "3200m"), ("memory", "2Gi")], &[])],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let kinds = ["cpu".to_string(), "memory".to_string()];
+        let target = AnnotateTarget::ConfigMap {
+            namespace: "ops".to_string(),
+            name: "capacity".to_string(),
+        };
+        assert_eq!(
+            capacity_reports(&resources, &kinds, &target),
+            BTreeMap::from([(
+                "".to_string(),
+                "cpu 40% requested, memory 13% requested".to_string()
+            )])
+        );
+        assert_eq!(
+            capacity_reports(&resources, &kinds, &AnnotateTarget::Nodes),
+            BTreeMap::from([
+                (
+                    "n1".to_string(),
+                    "cpu 80% requested, memory 25% requested".to_string()
+                ),
+                (
+                    "n2".to_string(),
+                    "cpu 0% requested, memory 0% requested".to_string()
+                ),
+            ])
+        );
+
+        let api_error = |code: u16| {
+            kube::Error::Api(kube::core::ErrorResponse {
+                status: "Failure".to_string(),
+                message: "denied".to_string(),
+                reason: "Forbidden".to_string(),
+                code,
+            })
+        };
+        assert_eq!(
+            explain_write_error(&api_error(403), "configmaps"),
+            "forbidden (the current user requires the permission `patch` on configmaps)"
+        );
+        assert_eq!(
+            explain_write_error(&api_error(404), "configmaps"),
+            "not found"
+        );
+        let plan = describe_query_plan(&CliOpts::parse_from([
+            "kubectl-view-allocations",
+            "--annotate",
+            "nodes",
+        ]));
+        assert!(plan
+            .iter()
+            .any(|line| line.starts_with("annotate: PATCH /api/v1/nodes/<node>")));
+    }
+
+    #[test]
+    fn test_explain_impersonation_error() {
+        let forbidden = |message: &str| Error::KubeError {
+            context: "list nodes".to_string(),
+            source: kube::Error::Api(kube::core::ErrorResponse {
+                status: "Failure".to_string(),
+                message: message.to_string(),
+                reason: "Forbidden".to_string(),
+                code: 403,
+            }),
+        };
+        let impersonation_denied = r#"users "sa1" is forbidden: User "u1" cannot impersonate resource "users" in API group "" at the cluster scope"#;
+        let cli_opts = CliOpts::parse_from(["kubectl-view-allocations", "--as", "sa1"]);
+        let err = explain_impersonation_error(forbidden(impersonation_denied), &cli_opts);
+        assert!(matches!(err, Error::ImpersonationForbidden { .. }));
+        assert_eq!(
+            err.to_string(),
+            "Impersonation as user sa1, groups [] is forbidden (the current user requires the `impersonate` permission)"
+        );
+        // the denials of the impersonated user are kept
+        let err = explain_impersonation_error(
+            forbidden(r#"nodes is forbidden: User "sa1" cannot list resource "nodes""#),
+            &cli_opts,
+        );
+        assert!(matches!(err, Error::KubeError { .. }));
+        let cli_opts = CliOpts::parse_from(["kubectl-view-allocations"]);
+        let err = explain_impersonation_error(forbidden(impersonation_denied), &cli_opts);
+        assert!(matches!(err, Error::KubeError { .. }));
+    }
+
+    #[test]
+    fn test_tree_style() {
+        assert_eq!(TreeStyle::of_locale("en_US.UTF-8"), TreeStyle::unicode);
+        assert_eq!(TreeStyle::of_locale("C.utf8"), TreeStyle::unicode);
+        assert_eq!(TreeStyle::of_locale("C"), TreeStyle::ascii);
+        assert_eq!(TreeStyle::of_locale(""), TreeStyle::ascii);
+        let cli_opts = CliOpts::parse_from(["test", "--tree-style", "ascii"]);
+        assert_eq!(cli_opts.tree_style, Some(TreeStyle::ascii));
+    }
+
+    #[test]
+    fn test_describe_query_plan() {
+        let cli_opts = CliOpts::parse_from([
+            "kubectl-view-allocations",
+            "-n",
+            "team-a",
+            "-u",
+            "--chunk-size",
+            "100",
+        ]);
+        let plan = describe_query_plan(&cli_opts);
+        assert!(plan.contains(&"list nodes: GET /api/v1/nodes?limit=100 (paginated)".to_string()));
+        assert!(plan.contains(
+            &"list pods: GET /api/v1/namespaces/team-a/pods?limit=100 (paginated)".to_string()
+        ));
+        assert!(plan.iter().any(|l| l.starts_with("list pod metrics:")));
+        assert!(plan.contains(&"throttling: 5 requests/s, burst 10".to_string()));
+
+        let cli_opts = CliOpts::parse_from(["kubectl-view-allocations", "--qps", "0"]);
+        let plan = describe_query_plan(&cli_opts);
+        assert!(plan.contains(&"list pods: GET /api/v1/pods?limit=500 (paginated)".to_string()));
+        assert!(!plan.iter().any(|l| l.starts_with("list pod metrics:")));
+        assert!(plan.contains(&"throttling: disabled".to_string()));
+
+        let cli_opts = CliOpts::parse_from(["kubectl-view-allocations", "--nodes-only", "-u"]);
+        let plan = describe_query_plan(&cli_opts);
+        assert!(plan.iter().any(|l| l.starts_with("list nodes:")));
+        assert!(!plan.iter().any(|l| l.starts_with("list pods:")));
+        assert!(!plan.iter().any(|l| l.starts_with("list pod metrics:")));
+
+        let cli_opts = CliOpts::parse_from(["kubectl-view-allocations", "--pods-only"]);
+        let plan = describe_query_plan(&cli_opts);
+        assert!(!plan.iter().any(|l| l.starts_with("list nodes:")));
+        assert!(plan.iter().any(|l| l.starts_with("list pods:")));
+
+        assert!(CliOpts::try_parse_from([
+            "kubectl-view-allocations",
+            "--nodes-only",
+            "--pods-only"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let cli_opts = CliOpts::parse_from(["kubectl-view-allocations", "-o", "console-json"]);
+        assert_eq!(cli_opts.output, Output::console_json);
+        assert_eq!(cli_opts.output.extension(), "json");
+    }
+
+    #[test]
+    fn test_free_headroom() {
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap());
+        let mut data = vec![
+            (
+                vec!["cpu".to_string()],
+                Some(QtyByQualifier {
+                    requested: qty("5"),
+                    allocatable: qty("10"),
+                    ..QtyByQualifier::default()
+                }),
+            ),
+            (
+                vec!["memory".to_string()],
+                Some(QtyByQualifier {
+                    requested: qty("9Gi"),
+                    allocatable: qty("10Gi"),
+                    ..QtyByQualifier::default()
+                }),
+            ),
+        ];
+        let free = |data: &[(Vec<String>, Option<QtyByQualifier>)], i: usize| {
+            data[i].1.as_ref().unwrap().calc_free().map(|q| q.value)
+        };
+        apply_free_headroom(&mut data, 0.0);
+        assert_eq!(free(&data, 0), Some(5000));
+        apply_free_headroom(&mut data, 20.0);
+        assert_eq!(free(&data, 0), Some(3000));
+        // the headroom is not available, even if requested
+        assert_eq!(free(&data, 1), Some(0));
+        assert_eq!(
+            data[1]
+                .1
+                .as_ref()
+                .unwrap()
+                .allocatable
+                .as_ref()
+                .map(|q| q.value),
+            Some(Qty::from_str("10Gi").unwrap().value)
+        );
+        assert_eq!(parse_percentage("20%"), Ok(20.0));
+        assert!(parse_percentage("120").is_err());
+    }
+
+    #[test]
+    fn test_parse_qps() {
+        assert_eq!(parse_qps("2.5"), Ok(2.5));
+        assert_eq!(parse_qps("0"), Ok(0.0));
+        for invalid in ["NaN", "inf", "-1", "fast"] {
+            assert!(parse_qps(invalid).is_err(), "{}", invalid);
+        }
+        assert!(CliOpts::try_parse_from(["test", "--qps", "NaN"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_qos_split() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "8", "memory": "16Gi" }),
+        )]);
+        let pods = make_pod_list(vec![
+            make_pod(
+                "guaranteed",
+                "n1",
+                vec![make_container(
+                    &[("cpu", "2"), ("memory", "4Gi")],
+                    &[("cpu", "2"), ("memory", "4Gi")],
+                )],
+            ),
+            make_pod(
+                "burstable",
+                "n1",
+                vec![make_container(
+                    &[("cpu", "1"), ("memory", "2Gi")],
+                    &[("cpu", "2")],
+                )],
+            ),
+            make_pod("best-effort", "n1", vec![make_container(&[], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let group_by = vec![GroupBy::resource, GroupBy::node];
+        let res = make_qualifiers(&resources, &group_by, &[]);
+        let split_of = |key: &str| {
+            let qtys = res
+                .iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.clone())
+                .unwrap();
+            (
+                qtys.calc_requested_guaranteed().map(|q| q.value),
+                qtys.calc_requested_burstable().map(|q| q.value),
+            )
+        };
+        assert_eq!(split_of("cpu/n1"), (Some(2000), Some(1000)));
+        let gi = Qty::from_str("1Gi").unwrap().value;
+        assert_eq!(split_of("memory"), (Some(4 * gi), Some(2 * gi)));
+        // the pods counted by QoS, the best-effort one is elastic
+        assert_eq!(split_of("pods/n1"), (Some(1000), Some(2000)));
+
+        let opts = DisplayOpts {
+            qos_split: true,
+            usages: vec![Usage::requested, Usage::free],
+            ..DisplayOpts::default()
+        };
+        let out = render(&resources, &group_by, Output::plain, &opts);
+        let lines = out
+            .lines()
+            .map(|l| l.split_whitespace().join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(lines[0], "Resource Requested Guaranteed Burstable Free");
+        assert_eq!(lines[1], "cpu (38%) 3.0 (25%) 2.0 (13%) 1.0 5.0");
+        let csv = render(&resources, &group_by, Output::csv, &opts);
+        assert!(csv
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with("Requested,%Requested,Guaranteed,%Guaranteed,Burstable,%Burstable,Free"));
+    }
+
+    #[tokio::test]
+    async fn test_quantities() {
+        let cli_opts = CliOpts::parse_from(["test", "--quantities", "json=base,csv=scaled"]);
+        assert_eq!(
+            cli_opts.quantities,
+            vec![
+                (Output::json, QtyStyle::base),
+                (Output::csv, QtyStyle::scaled)
+            ]
+        );
+        assert!(parse_quantities("influx=base").is_err());
+        assert!(parse_quantities("table=bytes").is_err());
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "memory": "4Gi" }),
+        )]);
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("memory", "1536Mi")], &[])],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let group_by = vec![GroupBy::resource];
+        let with = |quantities: Vec<(Output, QtyStyle)>| DisplayOpts {
+            usages: vec![Usage::requested, Usage::free],
+            quantities,
+            ..DisplayOpts::default()
+        };
+        let first_row = |output: Output, opts: &DisplayOpts| {
+            render(&resources, &group_by, output, opts)
+                .lines()
+                .nth(1)
+                .map(|l| l.split_whitespace().join(" "))
+                .unwrap()
+        };
+        // by default: scaled for the humans, numbers for the csv
+        let opts = with(vec![]);
+        assert_eq!(first_row(Output::plain, &opts), "memory (38%) 1.5Gi 2.5Gi");
+        assert!(first_row(Output::csv, &opts).ends_with(",1610612736.00,38%,2684354560.00"));
+        let json: JsonOutput =
+            serde_json::from_str(&render(&resources, &group_by, Output::json, &opts)).unwrap();
+        assert_eq!(json.items[0].requested.as_deref(), Some("1.5Gi"));
+        // every format independently
+        let opts = with(vec![
+            (Output::plain, QtyStyle::base),
+            (Output::csv, QtyStyle::scaled),
+            (Output::json, QtyStyle::base),
+        ]);
+        assert_eq!(
+            first_row(Output::plain, &opts),
+            "memory (38%) 1610612736.00 2684354560.00"
+        );
+        assert!(first_row(Output::csv, &opts).ends_with(",1.5Gi,38%,2.5Gi"));
+        assert!(first_row(Output::table, &opts).contains("1.5Gi"));
+        let json: JsonOutput =
+            serde_json::from_str(&render(&resources, &group_by, Output::json, &opts)).unwrap();
+        assert_eq!(json.items[0].requested.as_deref(), Some("1610612736.00"));
+        assert_eq!(json.items[0].requested_base, Some(1610612736));
+    }
+
+    #[test]
+    fn test_precision() {
+        let cli_opts =
+            CliOpts::parse_from(["test", "--precision", "cpu=2,memory=1,nvidia.com/gpu=0"]);
+        assert_eq!(
+            cli_opts.precision,
+            vec![
+                ("cpu".to_string(), 2),
+                ("memory".to_string(), 1),
+                ("nvidia.com/gpu".to_string(), 0),
+            ]
+        );
+        assert_eq!(
+            parse_precision("cpu=12"),
+            Ok(("cpu".to_string(), MAX_PRECISION))
+        );
+        assert!(parse_precision("cpu=-1").is_err());
+        assert!(parse_precision("cpu=two").is_err());
+        assert!(parse_precision("=2").is_err());
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node],
+            precisions: cli_opts.precision,
+            ..DisplayOpts::default()
+        };
+        let fmt = |k: &[&str], qty: &str| {
+            let k = k.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            opts.for_kind(&k).format_qty(&Qty::from_str(qty).unwrap())
+        };
+        assert_eq!(fmt(&["cpu", "n1"], "1250m"), "1.25");
+        assert_eq!(fmt(&["memory"], "1536Mi"), "1.5Gi");
+        assert_eq!(fmt(&["nvidia.com/gpu"], "2"), "2");
+        // the default
+        assert_eq!(fmt(&["ephemeral-storage"], "1250m"), "1.2");
+        let compact = DisplayOpts {
+            compact: true,
+            ..opts.clone()
+        };
+        let k = vec!["cpu".to_string()];
+        assert_eq!(
+            compact
+                .for_kind(&k)
+                .format_qty(&Qty::from_str("1250m").unwrap()),
+            "1.25"
+        );
+        let mut out = vec![];
+        let data = vec![(
+            k,
+            Some(QtyByQualifier {
+                requested: Some(Qty::from_str("1250m").unwrap()),
+                allocatable: Some(Qty::from_str("3").unwrap()),
+                ..QtyByQualifier::default()
+            }),
+        )];
+        write_as_csv(&data, &opts, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let mut lines = csv.lines().map(|line| line.split(',').collect::<Vec<_>>());
+        let header = lines.next().unwrap();
+        let row = lines.next().unwrap();
+        let column = |name: &str| row[header.iter().position(|x| *x == name).unwrap()];
+        assert_eq!(column("Requested"), "1.25");
+        assert_eq!(column("Allocatable"), "3.00");
+        assert_eq!(column("Free"), "1.75");
+    }
+
+    #[test]
+    fn test_make_bar() {
+        assert_eq!(make_bar(0.0, 10), "░░░░░░░░░░");
+        assert_eq!(make_bar(40.0, 10), "████░░░░░░");
+        assert_eq!(make_bar(44.9, 10), "████░░░░░░");
+        assert_eq!(make_bar(45.0, 10), "█████░░░░░");
+        assert_eq!(make_bar(100.0, 10), "██████████");
+        assert_eq!(make_bar(250.0, 10), "██████████");
+        assert_eq!(make_bar(50.0, 4), "██░░");
+    }
+
+    #[test]
+    fn test_format_age() {
+        let age = |secs: i64| format_age(chrono::Duration::seconds(secs));
+        assert_eq!(age(0), "0s");
+        assert_eq!(age(45), "45s");
+        assert_eq!(age(750), "12m30s");
+        assert_eq!(age(3600), "1h");
+        assert_eq!(age(5 * 3600 + 12 * 60 + 5), "5h12m");
+        assert_eq!(age(3 * 86400 + 4 * 3600 + 60), "3d4h");
+        assert_eq!(age(-5), "0s");
+    }
+
+    #[tokio::test]
+    async fn test_node_created_on_rows_of_nodes() {
+        let node = |name: &str, created: &str| -> Node {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": name, "creationTimestamp": created },
+                "status": { "allocatable": { "cpu": "4" } },
+            }))
+            .unwrap()
+        };
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(
+            make_node_list(vec![
+                node("n1", "2024-01-01T00:00:00Z"),
+                node("n2", "2024-03-01T00:00:00Z"),
+            ]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node],
+            show_age: true,
+            ..DisplayOpts::default()
+        };
+        let res = make_qualifiers(&resources, &opts.group_by, &[]);
+        let items = make_json_output(&res, &opts).items;
+        assert_eq!(items[0].created, None);
+        assert_eq!(
+            items[1].created.as_deref(),
+            Some("2024-01-01T00:00:00+00:00")
+        );
+        assert_eq!(
+            items[2].created.as_deref(),
+            Some("2024-03-01T00:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("3d").is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX / 60 + 1)).is_err());
+        assert!(parse_duration(&format!("{}h", u64::MAX)).is_err());
+        assert_eq!(
+            parse_duration(&format!("{}m", u64::MAX / 60)),
+            Ok(Duration::from_secs(u64::MAX / 60 * 60))
+        );
+    }
+
+    #[test]
+    fn test_parse_also() {
+        assert_eq!(
+            parse_also("json:report.json"),
+            Ok((Output::json, std::path::PathBuf::from("report.json")))
+        );
+        assert_eq!(
+            parse_also("sim-json:/tmp/sim:1.json"),
+            Ok((
+                Output::sim_json,
+                std::path::PathBuf::from("/tmp/sim:1.json")
+            ))
+        );
+        assert!(parse_also("json").is_err());
+        assert!(parse_also("json:").is_err());
+        assert!(parse_also("yaml:report.yaml").is_err());
+        assert!(parse_also("console-json:report.json").is_err());
+    }
+
+    #[test]
+    fn test_parse_key_value() {
+        assert_eq!(
+            parse_key_value("nvidia.com/gpu=gpu"),
+            Ok(("nvidia.com/gpu".to_string(), "gpu".to_string()))
+        );
+        assert_eq!(
+            parse_key_value("cpu= 500m"),
+            Ok(("cpu".to_string(), "500m".to_string()))
+        );
+        assert!(parse_key_value("cpu").is_err());
+        assert!(parse_key_value("=1").is_err());
+    }
+
+    #[test]
+    fn test_delta_from_baseline() {
+        let with = |requested: &str, allocatable: &str| QtyByQualifier {
+            requested: Some(Qty::from_str(requested).unwrap()),
+            allocatable: Some(Qty::from_str(allocatable).unwrap()),
+            ..QtyByQualifier::default()
+        };
+        let key = |k: &[&str]| k.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node],
+            ..DisplayOpts::default()
+        };
+        let before = vec![
+            (key(&["memory"]), Some(with("1Gi", "4Gi"))),
+            (key(&["memory", "n1"]), Some(with("1Gi", "4Gi"))),
+        ];
+        let json = serde_json::to_string(&make_json_output(&before, &opts)).unwrap();
+        let baseline = Baseline::from(serde_json::from_str::<JsonOutput>(&json).unwrap());
+        let after = vec![
+            (key(&["memory"]), Some(with("1536Mi", "6Gi"))),
+            (key(&["memory", "n1"]), Some(with("1Gi", "4Gi"))),
+            (key(&["memory", "n2"]), Some(with("512Mi", "2Gi"))),
+        ];
+        let table = make_delta_prettytable(&after, &opts, &baseline);
+        let cells = |i: usize| {
+            table
+                .get_row(i)
+                .unwrap()
+                .iter()
+                .skip(1)
+                .map(|c| c.get_content())
+                .collect::<Vec<_>>()
+        };
+        // requested, limit, allocatable, free
+        assert_eq!(cells(0), vec!["+512.0Mi", "__", "+2.0Gi", "+1.5Gi"]);
+        assert_eq!(cells(1), vec!["=", "__", "=", "="]);
+        // not in the baseline
+        assert_eq!(cells(2), vec!["+512.0Mi", "__", "+2.0Gi", "+1.5Gi"]);
+    }
+
+    #[tokio::test]
+    async fn test_normalize_kinds() {
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "nvidia.com/gpu": "2" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "Nvidia.com/GPU": "4", "gpu.example.com/nvidia": "1" }),
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let variants = vec![
+            ("nvidia.com/gpu".to_string(), "nvidia.com/gpu".to_string()),
+            (
+                "gpu.example.com/nvidia".to_string(),
+                "nvidia.com/gpu".to_string(),
+            ),
+        ];
+        normalize_kinds(&mut resources, &variants);
+        let res = make_qualifiers(&resources, &[GroupBy::resource], &[]);
+        assert_eq!(
+            res.iter().map(|(k, _)| k.join("/")).collect::<Vec<_>>(),
+            vec!["nvidia.com/gpu"]
+        );
+        assert_eq!(
+            res[0]
+                .1
+                .as_ref()
+                .and_then(|q| q.allocatable.as_ref().map(|q| q.value)),
+            Some(7000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collapse_small_namespaces() {
+        let mut pods = vec![];
+        for (ns, cpu) in [
+            ("big", "8"),
+            ("medium", "1900m"),
+            ("tiny-1", "50m"),
+            ("tiny-2", "50m"),
+        ] {
+            let mut pod = make_pod(
+                &format!("{}-pod", ns),
+                "n1",
+                vec![make_container(&[("cpu", cpu), ("memory", "1Gi")], &[])],
+            );
+            pod.metadata.namespace = Some(ns.to_string());
+            pods.push(pod);
+        }
+        let mut resources = vec![];
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources)
+            .await
+            .unwrap();
+        // 50m of 10 cpu < 1%, but every namespace requests 25% of the memory
+        assert_eq!(collapse_small_namespaces(&mut resources, 1.0), 2);
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::namespace],
+            &["cpu".to_string(), "memory".to_string()],
+        );
+        assert_eq!(
+            keys(&res),
+            vec![
+                "cpu",
+                "cpu/(other)",
+                "cpu/big",
+                "cpu/medium",
+                "memory",
+                "memory/big",
+                "memory/medium",
+                "memory/tiny-1",
+                "memory/tiny-2",
+            ]
+        );
+        assert_eq!(
+            requested_of(&res, "cpu/(other)").map(|q| q.value),
+            Some(100)
+        );
+        // nothing requested, nothing collapsed
+        assert_eq!(collapse_small_namespaces(&mut [], 1.0), 0);
+    }
+
+    #[tokio::test]
+    async fn test_add_sum_kinds() {
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("nvidia.com/gpu", "2")], &[])],
+            ),
+            make_pod(
+                "p2",
+                "n2",
+                vec![make_container(&[("amd.com/gpu", "1")], &[])],
+            ),
+            make_pod("p3", "n2", vec![make_container(&[("cpu", "1")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sum_kinds = vec![parse_sum_kinds("accelerators=nvidia.com/gpu, amd.com/gpu").unwrap()];
+        assert_eq!(
+            sum_kinds[0].1,
+            vec!["nvidia.com/gpu".to_string(), "amd.com/gpu".to_string()]
+        );
+        add_sum_kinds(&mut resources, &sum_kinds);
+        let res = make_qualifiers(&resources, &[GroupBy::resource, GroupBy::node], &[]);
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        assert_eq!(
+            requested_of(&res, "accelerators").map(|q| q.value),
+            qty("3")
+        );
+        assert_eq!(
+            requested_of(&res, "accelerators/n2").map(|q| q.value),
+            qty("1")
+        );
+        assert_eq!(
+            requested_of(&res, "nvidia.com/gpu").map(|q| q.value),
+            qty("2")
+        );
+        assert!(parse_sum_kinds("accelerators=").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mig_profiles() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({
+                "nvidia.com/mig-1g.10gb": "2",
+                "nvidia.com/mig-1g.5gb": "4",
+                "nvidia.com/mig-3g.20gb": "1",
+            }),
+        )]);
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("nvidia.com/mig-1g.5gb", "3")], &[])],
+            ),
+            make_pod(
+                "p2",
+                "n1",
+                vec![make_container(&[("nvidia.com/mig-3g.20gb", "1")], &[])],
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sum_kinds = vec![parse_sum_kinds("gpu=nvidia.com/gpu,nvidia.com/mig-*").unwrap()];
+        add_sum_kinds(&mut resources, &sum_kinds);
+        let mut res = make_qualifiers(&resources, &[GroupBy::resource], &[]);
+        sort_qualifiers(&mut res, &SortBy::name, &SortOrder::asc);
+        assert_eq!(
+            keys(&res),
+            vec![
+                "gpu",
+                "nvidia.com/mig-1g.5gb",
+                "nvidia.com/mig-1g.10gb",
+                "nvidia.com/mig-3g.20gb",
+                "pods",
+            ]
+        );
+        let qtys = res[0].1.as_ref().unwrap();
+        assert_eq!(qtys.requested.as_ref().map(|q| q.value), Some(4000));
+        assert_eq!(qtys.allocatable.as_ref().map(|q| q.value), Some(7000));
+        assert_eq!(
+            requested_of(&res, "nvidia.com/mig-1g.5gb").map(|q| q.value),
+            Some(3000)
+        );
+        assert!(sum_kind_matches(
+            "nvidia.com/mig-*",
+            "nvidia.com/mig-7g.80gb"
+        ));
+        assert!(!sum_kind_matches("nvidia.com/mig-*", "nvidia.com/gpu"));
+        assert_eq!(
+            natural_cmp("mig-2g.10gb", "mig-2g.5gb"),
+            std::cmp::Ordering::Greater
+        );
+        assert_eq!(natural_cmp("n01", "n1"), std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn test_quiet() {
+        let cli_opts = CliOpts::parse_from(["test", "-q"]);
+        assert!(cli_opts.quiet);
+        assert_eq!(cli_opts.log_filter(), Some("error,kube_client=off"));
+        assert_eq!(CliOpts::parse_from(["test"]).log_filter(), None);
+        assert_eq!(
+            CliOpts::parse_from(["test", "-vv"]).log_filter(),
+            Some("debug,kube_client=info")
+        );
+        assert!(CliOpts::try_parse_from(["test", "--quiet", "--verbose"]).is_err());
+        let data = vec![(vec!["cpu".to_string()], qtys_requested("1"))];
+        let table = |quiet| {
+            let opts = DisplayOpts {
+                group_by: vec![GroupBy::resource],
+                cluster: Some(ClusterInfo {
+                    context: Some("prod".to_string()),
+                    server: "https://k8s".to_string(),
+                }),
+                quiet,
+                ..DisplayOpts::default()
+            };
+            let mut out = vec![];
+            display_with_prettytable(&data, &opts, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(table(false).starts_with("Context: prod, Server: https://k8s\n"));
+        // only the table
+        assert!(!table(true).contains("Context"));
+        assert!(table(true).contains("cpu"));
+    }
+
+    #[test]
+    fn test_find_oversubscribed() {
+        let with = |requested: &str, allocatable: &str| {
+            Some(QtyByQualifier {
+                requested: Some(Qty::from_str(requested).unwrap()),
+                allocatable: Some(Qty::from_str(allocatable).unwrap()),
+                ..QtyByQualifier::default()
+            })
+        };
+        let data = vec![
+            (vec!["cpu".to_string()], with("12", "10")),
+            (vec!["cpu".to_string(), "n1".to_string()], with("12", "10")),
+            (vec!["memory".to_string()], with("1Gi", "2Gi")),
+            (vec!["nvidia.com/gpu".to_string()], qtys_requested("1")),
+        ];
+        let found = find_oversubscribed(&data);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "cpu");
+        assert_eq!(format!("{:.0}", found[0].1), "120");
+    }
+
+    #[tokio::test]
+    async fn test_retain_pod() {
+        let mut pod_other_ns = make_pod("p1", "n2", vec![make_container(&[("cpu", "2")], &[])]);
+        pod_other_ns.metadata.namespace = Some("other".to_string());
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![
+                    make_named_container("c1", &[("cpu", "1")], &[]),
+                    make_named_container("c2", &[("cpu", "3")], &[]),
+                ],
+            ),
+            make_pod("p2", "n1", vec![make_container(&[("cpu", "4")], &[])]),
+            pod_other_ns,
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let group_by = [GroupBy::resource, GroupBy::pod, GroupBy::container];
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+
+        // without namespace, pods of every namespace match
+        let mut by_name = resources.clone();
+        retain_pod(&mut by_name, "p1").unwrap();
+        let res = make_qualifiers(&by_name, &group_by, &["cpu".to_string()]);
+        assert_eq!(requested_of(&res, "cpu/p1").map(|q| q.value), qty("6"));
+
+        let mut by_namespace = resources.clone();
+        retain_pod(&mut by_namespace, "default/p1").unwrap();
+        let res = make_qualifiers(&by_namespace, &group_by, &["cpu".to_string()]);
+        assert_eq!(keys(&res), vec!["cpu", "cpu/p1", "cpu/p1/c1", "cpu/p1/c2"]);
+        assert_eq!(requested_of(&res, "cpu/p1").map(|q| q.value), qty("4"));
+
+        assert!(matches!(
+            retain_pod(&mut resources, "default/p3"),
+            Err(Error::PodNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_free_below_threshold() {
+        let with = |requested: &str, allocatable: &str| QtyByQualifier {
+            requested: Some(Qty::from_str(requested).unwrap()),
+            allocatable: Some(Qty::from_str(allocatable).unwrap()),
+            ..QtyByQualifier::default()
+        };
+        let thresholds = vec![
+            parse_threshold("cpu=500m").unwrap(),
+            parse_threshold("memory=1Gi").unwrap(),
+        ];
+        let group_by = [GroupBy::resource, GroupBy::node, GroupBy::pod];
+        let key = |k: &[&str]| k.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let check = |k: &[&str], qtys: &QtyByQualifier| {
+            free_below_threshold(&key(k), qtys, &group_by, &thresholds).map(|q| q.value)
+        };
+        assert_eq!(check(&["cpu", "n1"], &with("3.6", "4")), Some(500));
+        assert_eq!(check(&["cpu", "n1"], &with("3.5", "4")), None);
+        assert_eq!(check(&["memory", "n1"], &with("1Gi", "2Gi")), None);
+        // only rows of nodes are checked
+        assert_eq!(check(&["cpu"], &with("3.6", "4")), None);
+        assert_eq!(check(&["cpu", "n1", "p1"], &with("3.6", "4")), None);
+        // no threshold
+        assert_eq!(check(&["pods", "n1"], &with("110", "110")), None);
+        assert!(parse_threshold("cpu=abc").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_nodes_are_collapsed() {
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(
+            make_node_list(vec![
+                make_node(
+                    "n1",
+                    serde_json::json!({}),
+                    serde_json::json!({ "cpu": "4" }),
+                ),
+                make_node(
+                    "n2",
+                    serde_json::json!({}),
+                    serde_json::json!({ "cpu": "2" }),
+                ),
+                make_node(
+                    "n1",
+                    serde_json::json!({}),
+                    serde_json::json!({ "cpu": "8" }),
+                ),
+            ]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        assert_eq!(resources.len(), 2);
+        let res = make_qualifiers(&resources, &[GroupBy::resource, GroupBy::node], &[]);
+        let allocatable_of = |key: &str| {
+            res.iter()
+                .find(|(k, _)| k.join("/") == key)
+                .and_then(|(_, q)| q.as_ref())
+                .and_then(|q| q.allocatable.as_ref())
+                .map(|q| q.value)
+        };
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        // the last one is kept
+        assert_eq!(allocatable_of("cpu/n1"), qty("8"));
+        assert_eq!(allocatable_of("cpu"), qty("10"));
+    }
+
+    #[tokio::test]
+    async fn test_reserved_mismatches() {
+        let node = |name: &str, allocatable_cpu: &str| -> Node {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": name },
+                "status": {
+                    "allocatable": { "cpu": allocatable_cpu, "memory": "14Gi" },
+                    "capacity": { "cpu": "4", "memory": "16Gi" },
+                },
+            }))
+            .unwrap()
+        };
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(
+            make_node_list(vec![node("n1", "3"), node("n2", "3500m")]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        let expected = vec![
+            parse_threshold("cpu=1").unwrap(),
+            parse_threshold("memory=2Gi").unwrap(),
+        ];
+        let sums = ResourceSums::new(&resources);
+        let mismatches = reserved_mismatches(&sums, &expected)
+            .into_iter()
+            .map(|(node, kind, qty)| (node, kind, qty.value))
+            .collect::<Vec<_>>();
+        assert_eq!(mismatches, vec![("n2".to_string(), "cpu".to_string(), 500)]);
+        assert!(reserved_mismatches(&sums, &[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_non_integer_quantities() {
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(
+            make_node_list(vec![make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "1500m", "nvidia.com/gpu": "4", "pods": "110" }),
+            )]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        extract_allocatable_from_pods(
+            make_pod_list(vec![make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("nvidia.com/gpu", "1.5")], &[])],
+            )]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        let cli_opts = CliOpts::parse_from(["kubectl-view-allocations"]);
+        let found = non_integer_quantities(&resources, &cli_opts.integer_kinds)
+            .into_iter()
+            .map(|r| {
+                (
+                    r.kind.as_str(),
+                    r.location.pod_name.as_deref(),
+                    r.quantity.value,
+                )
+            })
+            .collect::<Vec<_>>();
+        // cpu is not a count
+        assert_eq!(found, vec![("nvidia.com/gpu", Some("p1"), 1500)]);
+        assert!(non_integer_quantities(&resources, &[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compute_fits() {
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "2", "memory": "4Gi" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "2", "memory": "4Gi" }),
+            ),
+        ]);
+        let pods = make_pod_list(vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1200m")], &[])]),
+            make_pod("p2", "n2", vec![make_container(&[("cpu", "1200m")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let pod = vec![
+            parse_threshold("cpu=1").unwrap(),
+            parse_threshold("memory=1Gi").unwrap(),
+        ];
+        // 800m free on every node, 1600m in total
+        let fits = compute_fits(&sums, &pod, 0.0);
+        assert_eq!(
+            fits,
+            Fits {
+                by_node: vec![("n1".to_string(), 0), ("n2".to_string(), 0)],
+                on_nodes: 0,
+                in_total: 1,
+            }
+        );
+        assert_eq!(fits.summary(&pod).len(), 2);
+        let pod = vec![parse_threshold("cpu=400m").unwrap()];
+        let fits = compute_fits(&sums, &pod, 0.0);
+        assert_eq!((fits.on_nodes, fits.in_total), (4, 4));
+        assert_eq!(
+            fits.summary(&pod),
+            vec![
+                "Fits 4 pods (cpu=400.0m) on 2 of 2 nodes".to_string(),
+                "  the most on n1 (2)".to_string()
+            ]
+        );
+        // the headroom is not free
+        let fits = compute_fits(&sums, &pod, 10.0);
+        assert_eq!((fits.on_nodes, fits.in_total), (2, 3));
+        // a kind not provided by the nodes
+        let pod = vec![parse_threshold("nvidia.com/gpu=1").unwrap()];
+        assert_eq!(compute_fits(&sums, &pod, 0.0).on_nodes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_pod_density() {
+        let nodes = make_node_list(vec![
+            // bound by the cap of pods
+            make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "64", "memory": "256Gi", "pods": "10" }),
+            ),
+            // bound by the cpu
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "2", "memory": "64Gi", "pods": "110" }),
+            ),
+            // bound by the memory
+            make_node(
+                "n3",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "16", "memory": "4Gi", "pods": "110" }),
+            ),
+        ]);
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("cpu", "100m"), ("memory", "512Mi")], &[])],
+            ),
+            make_pod(
+                "p2",
+                "n2",
+                vec![make_container(
+                    &[("cpu", "900m"), ("memory", "1536Mi")],
+                    &[],
+                )],
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let pod = average_pod(&sums);
+        assert_eq!(
+            pod.iter()
+                .map(|(kind, qty)| format!("{}={}", kind, qty.adjust_scale()))
+                .collect::<Vec<_>>(),
+            vec!["cpu=500.0m", "memory=1.0Gi"]
+        );
+        let densities = compute_pod_densities(&sums, &pod);
+        assert_eq!(
+            densities
+                .iter()
+                .map(|d| (d.node.as_str(), d.pods, d.binding().cloned()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("n1", 1, Some(("pods".to_string(), 10))),
+                ("n2", 1, Some(("cpu".to_string(), 4))),
+                ("n3", 0, Some(("memory".to_string(), 4))),
+            ]
+        );
+        assert_eq!(
+            pod_densities_summary(&densities, &pod),
+            vec![
+                "Pod density (the most pods by node, for pods of cpu=500.0m, memory=1.0Gi):",
+                "  n1: 1 of max 10 pods, bound by pods (pods 10, cpu 128, memory 256)",
+                "  n2: 1 of max 4 pods, bound by cpu (pods 110, cpu 4, memory 64)",
+                "  n3: 0 of max 4 pods, bound by memory (pods 110, cpu 32, memory 4)",
+            ]
+        );
+        // the size of `--fits`, a kind not provided by the nodes fits no pod
+        let pod = vec![parse_threshold("nvidia.com/gpu=1").unwrap()];
+        assert_eq!(
+            compute_pod_densities(&sums, &pod)[0].binding(),
+            Some(&("nvidia.com/gpu".to_string(), 0))
+        );
+        assert_eq!(average_pod(&ResourceSums::new(&[])), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_retain_for_priority() {
+        let mut pods = vec![
+            make_pod("low", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("high", "n1", vec![make_container(&[("cpu", "2")], &[])]),
+            make_pod("critical", "n1", vec![make_container(&[("cpu", "4")], &[])]),
+        ];
+        pods[1].spec.as_mut().unwrap().priority = Some(1000);
+        pods[2].spec.as_mut().unwrap().priority = Some(2000000000);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(
+            make_node_list(vec![make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "8" }),
+            )]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources)
+            .await
+            .unwrap();
+        retain_for_priority(&mut resources, 1000);
+        let res = make_qualifiers(&resources, &[GroupBy::resource, GroupBy::node], &[]);
+        let cpu = res
+            .iter()
+            .find(|(k, _)| k.join("/") == "cpu/n1")
+            .and_then(|(_, q)| q.clone())
+            .unwrap();
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap().value);
+        assert_eq!(cpu.requested.as_ref().map(|q| q.value), qty("6"));
+        assert_eq!(cpu.calc_free().map(|q| q.value), qty("2"));
+    }
+
+    #[test]
+    fn test_accept_namespace() {
+        let excluded = vec!["kube-system".to_string(), "kube-public".to_string()];
+        assert!(accept_namespace(&None, &excluded));
+        assert!(accept_namespace(&Some("default".to_string()), &[]));
+        assert!(accept_namespace(&Some("default".to_string()), &excluded));
+        assert!(!accept_namespace(
+            &Some("kube-system".to_string()),
+            &excluded
+        ));
+        assert!(!accept_namespace(
+            &Some("kube-public".to_string()),
+            &excluded
+        ));
+    }
+
+    #[test]
+    fn test_namespace_regex() {
+        let regex = parse_namespace_regex("team-a-.*").unwrap();
+        assert_eq!(regex.to_string(), "team-a-.*");
+        assert!(regex.accept(&None));
+        assert!(regex.accept(&Some("team-a-prod".to_string())));
+        assert!(regex.accept(&Some("team-a-staging".to_string())));
+        // the whole name should match
+        assert!(!regex.accept(&Some("team-b-prod".to_string())));
+        assert!(!regex.accept(&Some("old-team-a-prod".to_string())));
+        assert!(parse_namespace_regex("team-(a").is_err());
+        assert!(CliOpts::try_parse_from(["test", "--namespace-regex", "team-(a"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compute_histograms() {
+        let nodes = make_node_list(
+            ["n1", "n2", "n3", "n4"]
+                .iter()
+                .map(|name| {
+                    make_node(
+                        name,
+                        serde_json::json!({}),
+                        serde_json::json!({ "cpu": "4", "memory": "4Gi" }),
+                    )
+                })
+                .collect(),
+        );
+        let pods = make_pod_list(vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n2", vec![make_container(&[("cpu", "3")], &[])]),
+            make_pod("p3", "n3", vec![make_container(&[("cpu", "4")], &[])]),
+            make_pod("p4", "n3", vec![make_container(&[("memory", "5Gi")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let histograms = compute_histograms(&sums, &[]);
+        assert_eq!(
+            histograms,
+            vec![
+                Histogram {
+                    kind: "cpu".to_string(),
+                    counts: [1, 1, 0, 2, 0],
+                },
+                Histogram {
+                    kind: "memory".to_string(),
+                    counts: [3, 0, 0, 0, 1],
+                },
+            ]
+        );
+        assert_eq!(
+            histograms[0].summary(),
+            vec![
+                "Nodes by requested cpu (of 4 nodes):".to_string(),
+                format!("    0-25% {} 1", make_bar(25.0, 20)),
+                format!("   25-50% {} 1", make_bar(25.0, 20)),
+                format!("   50-75% {} 0", make_bar(0.0, 20)),
+                format!("  75-100% {} 2", make_bar(50.0, 20)),
+                format!("    >100% {} 0", make_bar(0.0, 20)),
+            ]
+        );
+        let cpu_only = compute_histograms(&sums, &["cpu".to_string()]);
+        assert_eq!(cpu_only.len(), 1);
+    }
+
+    pub(crate) fn render(
+        resources: &[Resource],
+        group_by: &[GroupBy],
+        output: Output,
+        opts: &DisplayOpts,
+    ) -> String {
+        let opts = DisplayOpts {
+            group_by: group_by.to_vec(),
+            ..opts.clone()
+        };
+        let data = make_qualifiers(resources, group_by, &[]);
+        let mut out = vec![];
+        write_output(resources, &data, &opts, &output, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_free_spread() {
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4", "memory": "16Gi" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "8", "memory": "16Gi" }),
+            ),
+        ]);
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("cpu", "3500m"), ("memory", "4Gi")], &[])],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let value = |qty: &Qty| qty.value;
+        let spread = free_spread_by_kind(&sums, UsedBasis::requested, 0.0);
+        // n2 has no pods, fully free
+        let (min, max) = &spread["cpu"];
+        assert_eq!((value(min), value(max)), (500, 8000));
+        let (min, max) = &spread["memory"];
+        assert_eq!(
+            (value(min), value(max)),
+            (
+                Qty::from_str("12Gi").unwrap().value,
+                Qty::from_str("16Gi").unwrap().value
+            )
+        );
+        let spread = free_spread_by_kind(&sums, UsedBasis::requested, 50.0);
+        assert_eq!(value(&spread["cpu"].1), 4000);
+
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node],
+            free_spread: Some(free_spread_by_kind(&sums, UsedBasis::requested, 0.0)),
+            ..DisplayOpts::default()
+        };
+        assert!(opts.free_spread_of(&["cpu".to_string()]).is_some());
+        // only on the rows of the kinds
+        assert!(opts
+            .free_spread_of(&["cpu".to_string(), "n1".to_string()])
+            .is_none());
+        let rows = make_json_output(
+            &sums.make_qualifiers(&opts.group_by, &["cpu".to_string()]),
+            &opts,
+        )
+        .items;
+        assert_eq!(rows[0].node_free_min_base, Some(500));
+        assert_eq!(rows[0].node_free_max_base, Some(8000));
+        assert_eq!(rows[1].node_free_min, None);
+    }
+
+    #[tokio::test]
+    async fn test_plan_pools() {
+        let node = |name: &str, pool: &str| {
+            make_node(
+                name,
+                serde_json::json!({ "pool": pool }),
+                serde_json::json!({ "cpu": "4", "memory": "16Gi" }),
+            )
+        };
+        let nodes = make_node_list(vec![
+            node("a1", "a"),
+            node("a2", "a"),
+            node("a3", "a"),
+            node("b1", "b"),
+        ]);
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "a1",
+                vec![make_container(&[("cpu", "3"), ("memory", "4Gi")], &[])],
+            ),
+            make_pod("p2", "a2", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod(
+                "p3",
+                "b1",
+                vec![make_container(&[("cpu", "3600m"), ("memory", "1Gi")], &[])],
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let plans = plan_pools(&sums, "pool", 70.0);
+        // a: 4 cpu of 12 (4 by node) fit on 2 nodes at 70%, b: 3.6 cpu need 2 nodes
+        assert_eq!(
+            plans
+                .iter()
+                .map(|p| (
+                    p.pool.as_str(),
+                    p.nodes,
+                    p.needed,
+                    p.delta(),
+                    p.bound_by.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![("a", 3, 2, -1, "cpu"), ("b", 1, 2, 1, "cpu")]
+        );
+        let summary = pool_plans_summary(&plans, "pool", 70.0);
+        assert_eq!(
+            summary[0],
+            "Nodes for a target utilization of 70% (requested / allocatable, by pool): +0"
+        );
+        assert_eq!(
+            summary[2],
+            "  b: 1 nodes (cpu 90%, memory 6%) -> 2 nodes, add 1 (by cpu)"
+        );
+        // nothing requested, at least 1 node
+        let empty = vec![node("c1", "c"), node("c2", "c")];
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(make_node_list(empty), &mut resources)
+            .await
+            .unwrap();
+        let plans = plan_pools(&ResourceSums::new(&resources), "pool", 70.0);
+        assert_eq!(plans[0].needed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_node_costs() {
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({ INSTANCE_TYPE_LABEL: "m5.xlarge" }),
+                serde_json::json!({ "cpu": "4", "memory": "16Gi" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({ INSTANCE_TYPE_LABEL: "m5.xlarge" }),
+                serde_json::json!({ "cpu": "4", "memory": "16Gi" }),
+            ),
+            make_node(
+                "n3",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4", "memory": "16Gi" }),
+            ),
+        ]);
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("cpu", "2"), ("memory", "12Gi")], &[])],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let costs: BTreeMap<String, f64> =
+            serde_yaml::from_str("{m5.xlarge: 0.2, n2: 0.4}").unwrap();
+        let node_costs = node_costs(&resources, &sums, &costs, UsedBasis::requested, 0.0);
+        assert_eq!(node_costs.len(), 2);
+        // (50% + 25%) / 2
+        assert_eq!(node_costs[0].rated_by, "m5.xlarge");
+        assert_eq!(node_costs[0].idle_pct, 37.5);
+        assert!((node_costs[0].idle_hourly - 0.075).abs() < 1e-9);
+        // the name of the node is preferred to its instance type
+        assert_eq!(node_costs[1].rated_by, "n2");
+        assert_eq!(node_costs[1].idle_pct, 100.0);
+        assert_eq!(
+            node_costs_summary(&node_costs, 1),
+            vec![
+                "Idle cost (per hour, free cpu & memory weighted equally): 0.475 of 0.600 (79.2%)",
+                "  n1 (m5.xlarge): 0.200/h, idle 37.5%, 0.075/h",
+                "  n2: 0.400/h, idle 100.0%, 0.400/h",
+                "  1 nodes without cost (not in the file)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reservation_drifts() {
+        let node = |name: &str, capacity: &str, allocatable: &str| -> Node {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": name },
+                "status": {
+                    "capacity": { "cpu": "4", "memory": capacity },
+                    "allocatable": { "cpu": "3800m", "memory": allocatable },
+                },
+            }))
+            .unwrap()
+        };
+        let nodes = make_node_list(vec![
+            node("n1", "16Gi", "15Gi"),
+            node("n2", "16Gi", "15Gi"),
+            node("n3", "16Gi", "15Gi"),
+            // bootstrapped without the memory reservation
+            node("n4", "16Gi", "16Gi"),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        // 6.25% reserved on 3 nodes
+        let drifts = reservation_drifts(&sums, 5.0);
+        assert_eq!(drifts.len(), 1);
+        assert_eq!(
+            (drifts[0].node.as_str(), drifts[0].kind.as_str()),
+            ("n4", "memory")
+        );
+        assert_eq!(drifts[0].reserved_pct, 0.0);
+        assert_eq!(drifts[0].median_pct, 6.25);
+        assert_eq!(
+            reservation_drifts_summary(&drifts, 5.0),
+            vec![
+                "Reservation drift (reserved / capacity more than 5% from the median of the nodes):"
+                    .to_string(),
+                "  n4 memory: reserved 0.0 (0.0%), median 6.2%".to_string(),
+            ]
+        );
+        assert!(reservation_drifts(&sums, 10.0).is_empty());
+        assert_eq!(median(&mut [3.0, 1.0, 2.0, 4.0]), Some(2.5));
+    }
+
+    #[tokio::test]
+    async fn test_make_efficiency_rows() {
+        let pods = make_pod_list(vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n1", vec![make_container(&[("cpu", "100m")], &[])]),
+            make_pod("p3", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let pod_metrics = ObjectList {
+            metadata: Default::default(),
+            items: [("p1", "250m"), ("p2", "200m"), ("p3", "800m")]
+                .iter()
+                .map(|(name, cpu)| {
+                    serde_json::from_value(serde_json::json!({
+                        "metadata": { "name": name, "namespace": "default" },
+                        "containers": [{ "name": "c", "usage": { "cpu": cpu, "memory": "10Mi" } }],
+                        "timestamp": "2026-10-14T00:00:00Z",
+                        "window": "30s",
+                    }))
+                    .unwrap()
+                })
+                .collect(),
+        };
+        extract_utilizations_from_pod_metrics(pod_metrics, &mut resources)
+            .await
+            .unwrap();
+        let summary = |sort_by: &SortBy| {
+            make_efficiency_rows(&resources, sort_by, &sort_by.default_order())
+                .iter()
+                .filter(|row| row.kind == "cpu")
+                .map(|row| {
+                    format!(
+                        "{}:{:.0}%:{}:{}",
+                        row.pod,
+                        row.efficiency_pct().unwrap_or_default(),
+                        row.waste().to_canonical_string(),
+                        row.provisioning()
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            summary(&SortBy::name),
+            vec!["p1:25%:750m:over", "p2:200%:0:under", "p3:80%:200m:"]
+        );
+        assert_eq!(
+            summary(&SortBy::waste),
+            vec!["p1:25%:750m:over", "p3:80%:200m:", "p2:200%:0:under"]
+        );
+        assert_eq!(
+            make_efficiency_rows(&resources, &SortBy::waste, &SortOrder::asc)
+                .iter()
+                .filter(|row| row.kind == "cpu")
+                .map(|row| row.pod.as_str())
+                .collect::<Vec<_>>(),
+            vec!["p2", "p3", "p1"]
+        );
+        // memory is used without request
+        assert!(
+            make_efficiency_rows(&resources, &SortBy::name, &SortOrder::asc)
+                .iter()
+                .filter(|row| row.kind == "memory")
+                .all(|row| row.efficiency_pct().is_none() && row.provisioning() == "under")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_explain_node() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "4" }),
+        )]);
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![
+                make_named_container("app", &[("cpu", "1")], &[("cpu", "2")]),
+                make_named_container("sidecar", &[("cpu", "500m")], &[("cpu", "500m")]),
+            ],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        assert_eq!(
+            explain_node(&resources, "n1", "cpu", 10.0, UsedBasis::max),
+            vec![
+                "Explanation of cpu on node n1:",
+                "  allocatable: 4.0 (status.allocatable of the node)",
+                "  requested: 1.5 = sum of 2 containers",
+                "    default/p1/app: 1.0",
+                "    default/p1/sidecar: 500.0m",
+                "  limit: 2.5 = sum of 2 containers",
+                "    default/p1/app: 2.0",
+                "    default/p1/sidecar: 500.0m",
+                "  free = allocatable - headroom (10%) - max(requested, limit) = 4.0 - 400.0m - 2.5 = 1.1 (0 if negative)",
+            ]
+        );
+        assert_eq!(
+            explain_node(&resources, "n2", "cpu", 0.0, UsedBasis::requested),
+            vec!["No cpu on node n2"]
+        );
+        assert_eq!(
+            parse_explain("n1"),
+            Ok(("n1".to_string(), "cpu".to_string()))
+        );
+        assert_eq!(
+            parse_explain("n1:nvidia.com/gpu"),
+            Ok(("n1".to_string(), "nvidia.com/gpu".to_string()))
+        );
+        assert!(parse_explain(":memory").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_node_allocatable_overrides() {
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({ "pool": "a" }),
+                serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let o = parse_node_allocatable_override("n1:cpu=8,nvidia.com/gpu=1").unwrap();
+        assert_eq!(o.node, "n1");
+        assert_eq!(o.allocatable.len(), 2);
+        apply_node_allocatable_overrides(&mut resources, &[o]).unwrap();
+        let allocatable_of = |node: &str, kind: &str| {
+            resources
+                .iter()
+                .filter(|r| {
+                    r.kind == kind
+                        && r.location.node_name.as_deref() == Some(node)
+                        && matches!(r.qualifier, ResourceQualifier::Allocatable)
+                })
+                .map(|r| r.quantity.to_canonical_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(allocatable_of("n1", "cpu"), vec!["8"]);
+        assert_eq!(allocatable_of("n1", "memory"), vec!["8589934592"]);
+        assert_eq!(allocatable_of("n1", "nvidia.com/gpu"), vec!["1"]);
+        assert_eq!(allocatable_of("n2", "cpu"), vec!["4"]);
+        // the location of the node is kept (eg for the grouping by label)
+        assert!(resources
+            .iter()
+            .filter(|r| r.location.node_name.as_deref() == Some("n1"))
+            .all(|r| r.location.node_labels.get("pool") == Some(&"a".to_string())));
+
+        let unknown = parse_node_allocatable_override("n3:cpu=8").unwrap();
+        assert!(matches!(
+            apply_node_allocatable_overrides(&mut resources, &[unknown]),
+            Err(Error::NodeNotFound { .. })
+        ));
+        assert!(parse_node_allocatable_override("n1").is_err());
+        assert!(parse_node_allocatable_override("n1:cpu").is_err());
+        assert!(parse_node_allocatable_override("n1:cpu=lots").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reserve_for_system() {
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "100m", "memory": "8Gi" }),
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let cli_opts =
+            CliOpts::parse_from(["test", "--reserve-for-system", "cpu=200m,memory=512Mi"]);
+        apply_reserve_for_system(&mut resources, &cli_opts.reserve_for_system);
+        let res = make_qualifiers(&resources, &[GroupBy::resource, GroupBy::node], &[]);
+        let allocatable_of = |key: &[&str]| {
+            res.iter()
+                .find(|(k, _)| k == key)
+                .and_then(|(_, q)| q.as_ref())
+                .and_then(|q| q.allocatable.as_ref())
+                .map(|q| q.to_canonical_string())
+        };
+        assert_eq!(allocatable_of(&["cpu", "n1"]), Some("3800m".to_string()));
+        // not negative
+        assert_eq!(allocatable_of(&["cpu", "n2"]), Some("0".to_string()));
+        assert_eq!(
+            allocatable_of(&["memory"]),
+            // 2 * (8Gi - 512Mi)
+            Some((30 * 512 * 1024 * 1024_i64).to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_collected_by_cluster() {
+        let collected_of = |cluster: &'static str, cpu: &'static str| async move {
+            let mut resources = vec![];
+            let nodes = make_node_list(vec![make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4" }),
+            )]);
+            extract_allocatable_from_nodes(nodes, &mut resources)
+                .await
+                .unwrap();
+            let pods = make_pod_list(vec![make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("cpu", cpu)], &[])],
+            )]);
+            extract_allocatable_from_pods(pods, &mut resources)
+                .await
+                .unwrap();
+            let mut collected = Collected {
+                resources,
+                fleet: FleetSummary {
+                    nodes: 1,
+                    ..FleetSummary::default()
+                },
+                ..Collected::default()
+            };
+            collected.report.failed.push("limitranges".to_string());
+            collected.tag_cluster(cluster);
+            collected
+        };
+        let mut merged = Collected::default();
+        merged.merge(collected_of("prod", "1").await);
+        merged.merge(collected_of("staging", "3").await);
+        assert_eq!(merged.fleet.nodes, 2);
+        assert_eq!(
+            merged.report.failed,
+            vec!["prod: limitranges", "staging: limitranges"]
+        );
+        let res = make_qualifiers(
+            &merged.resources,
+            &[GroupBy::resource, GroupBy::cluster, GroupBy::node],
+            &["cpu".to_string()],
+        );
+        assert_eq!(
+            keys(&res),
+            vec![
+                "cpu",
+                "cpu/prod",
+                "cpu/prod/n1",
+                "cpu/staging",
+                "cpu/staging/n1"
+            ]
+        );
+        assert_eq!(requested_of(&res, "cpu"), Some(Qty::from_str("4").unwrap()));
+        assert_eq!(
+            requested_of(&res, "cpu/staging/n1"),
+            Some(Qty::from_str("3").unwrap())
+        );
+        let cli_opts = CliOpts::parse_from(["test", "--batch", "prod,staging"]);
+        assert_eq!(cli_opts.batch, vec!["prod", "staging"]);
+        assert!(CliOpts::try_parse_from(["test", "--batch", "prod", "--watch"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_group_by_node_state() {
+        let node = |name: &str, ready: &str, unschedulable: bool| -> Node {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": name },
+                "spec": { "unschedulable": unschedulable },
+                "status": {
+                    "allocatable": { "cpu": "4" },
+                    "conditions": [{ "type": "Ready", "status": ready }],
+                },
+            }))
+            .unwrap()
+        };
+        let nodes = make_node_list(vec![
+            node("n1", "True", false),
+            node("n2", "True", false),
+            node("n3", "True", true),
+            node("n4", "False", true),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n3",
+            vec![make_container(&[("cpu", "1")], &[])],
+        )]);
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        assert_eq!(GroupBy::from_str("node-state"), Ok(GroupBy::node_state));
+        let res = make_qualifiers(
+            &resources,
+            &[GroupBy::resource, GroupBy::node_state],
+            &["cpu".to_string()],
+        );
+        assert_eq!(
+            keys(&res),
+            vec!["cpu", "cpu/cordoned", "cpu/not-ready", "cpu/ready"]
+        );
+        let allocatable_of = |i: usize| res[i].1.as_ref().and_then(|q| q.allocatable.clone());
+        assert_eq!(allocatable_of(0), Some(Qty::from_str("16").unwrap()));
+        assert_eq!(allocatable_of(1), Some(Qty::from_str("4").unwrap()));
+        assert_eq!(allocatable_of(2), Some(Qty::from_str("4").unwrap()));
+        assert_eq!(allocatable_of(3), Some(Qty::from_str("8").unwrap()));
+        // the pods are with the state of their node
+        assert_eq!(
+            requested_of(&res, "cpu/cordoned"),
+            Some(Qty::from_str("1").unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_static_pods() {
+        let mut static_pod = make_pod(
+            "kube-apiserver-n1",
+            "n1",
+            vec![make_container(&[("cpu", "250m")], &[])],
+        );
+        static_pod.metadata.annotations = Some(
+            [(MIRROR_POD_ANNOTATION.to_string(), "a1b2c3".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let pods = make_pod_list(vec![
+            static_pod,
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        resources.retain(|r| !is_static_pod(&r.location));
+        let res = make_qualifiers(&resources, &[GroupBy::resource, GroupBy::pod], &[]);
+        assert_eq!(keys(&res), vec!["cpu", "cpu/p1", "pods"]);
+        assert_eq!(requested_of(&res, "pods").map(|q| q.value), Some(1000));
+    }
+
+    #[tokio::test]
+    async fn test_node_role() {
+        let labels = |json| serde_json::from_value::<BTreeMap<String, String>>(json).unwrap();
+        assert_eq!(roles_of(&labels(serde_json::json!({}))), vec!["worker"]);
+        assert_eq!(
+            roles_of(&labels(serde_json::json!({
+                "node-role.kubernetes.io/master": "",
+                "node-role.kubernetes.io/control-plane": "",
+                "kubernetes.io/role": "master",
+            }))),
+            vec!["control-plane", "master"]
+        );
+        let nodes = make_node_list(vec![
+            make_node(
+                "n1",
+                serde_json::json!({ "node-role.kubernetes.io/control-plane": "" }),
+                serde_json::json!({ "cpu": "4" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "8" }),
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let pods = make_pod_list(vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n2", vec![make_container(&[("cpu", "2")], &[])]),
+        ]);
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        assert_eq!(GroupBy::from_str("node-role"), Ok(GroupBy::node_role));
+        let by_role = |resources: &[Resource]| {
+            make_qualifiers(
+                resources,
+                &[GroupBy::resource, GroupBy::node_role],
+                &["cpu".to_string()],
+            )
+        };
+        let res = by_role(&resources);
+        assert_eq!(keys(&res), vec!["cpu", "cpu/control-plane", "cpu/worker"]);
+        assert_eq!(
+            requested_of(&res, "cpu/control-plane"),
+            Some(Qty::from_str("1").unwrap())
+        );
+
+        retain_without_roles(&mut resources, &["control-plane".to_string()]);
+        let res = by_role(&resources);
+        assert_eq!(keys(&res), vec!["cpu", "cpu/worker"]);
+        assert_eq!(
+            res[0]
+                .1
+                .as_ref()
+                .and_then(|q| q.allocatable.as_ref().map(|q| q.value)),
+            Some(8000)
+        );
+        assert_eq!(requested_of(&res, "cpu").map(|q| q.value), Some(2000));
+    }
+
+    #[tokio::test]
+    async fn test_compute_node_scores() {
+        let nodes = make_node_list(
+            ["n1", "n2", "n3"]
+                .iter()
+                .map(|name| {
+                    make_node(
+                        name,
+                        serde_json::json!({}),
+                        serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+                    )
+                })
+                .collect(),
+        );
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("cpu", "1"), ("memory", "6Gi")], &[])],
+            ),
+            make_pod(
+                "p2",
+                "n2",
+                vec![make_container(&[("cpu", "2"), ("memory", "4Gi")], &[])],
+            ),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let sums = ResourceSums::new(&resources);
+        let ranking = |function: ScoreFunction, weights: &[(String, f64)]| {
+            compute_node_scores(&sums, function, weights)
+                .iter()
+                .map(|s| format!("{}={:.1}", s.node, s.score))
+                .collect::<Vec<_>>()
+        };
+        // n1: cpu 25%, memory 75%; n2: cpu 50%, memory 50%; n3 empty
+        assert_eq!(
+            ranking(ScoreFunction::max, &[]),
+            vec!["n1=75.0", "n2=50.0", "n3=0.0"]
+        );
+        assert_eq!(
+            ranking(ScoreFunction::mean, &[]),
+            vec!["n1=50.0", "n2=50.0", "n3=0.0"]
+        );
+        assert_eq!(
+            ranking(ScoreFunction::harmonic_mean, &[]),
+            vec!["n2=50.0", "n1=37.5", "n3=0.0"]
+        );
+        let cpu_first = parse_weight("cpu=3").unwrap();
+        assert_eq!(
+            ranking(ScoreFunction::mean, &[cpu_first]),
+            vec!["n2=50.0", "n1=37.5", "n3=0.0"]
+        );
+        assert!(parse_weight("cpu=-1").is_err());
+        assert_eq!(ScoreFunction::max.score(&[]), None);
     }
 }
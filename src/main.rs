@@ -1,8 +1,13 @@
-use clap::Parser;
-use color_eyre::eyre::Result;
-use kubectl_view_allocations::{do_main, CliOpts, GroupBy};
+use clap::{CommandFactory, Parser};
+use color_eyre::eyre::{bail, Result};
+use kubectl_view_allocations::{
+    completions, do_main, move_resource_after_node, read_node_groups, CliOpts, GroupBy, SortBy,
+    INSTANCE_TYPE_LABEL,
+};
+use std::sync::Arc;
 
-fn init_tracing() {
+/// `filter` (of `--quiet` & `--verbose`) overrides `RUST_LOG`
+fn init_tracing(filter: Option<&str>) {
     // std::env::set_var("RUST_LOG", "info,kube=trace");
     use tracing_error::ErrorLayer;
     use tracing_subscriber::prelude::*;
@@ -10,7 +15,12 @@ fn init_tracing() {
 
     std::env::set_var(
         "RUST_LOG",
-        std::env::var("RUST_LOG").unwrap_or_else(|_| "warn,kube_client=error".to_string()),
+        match filter {
+            Some(filter) => filter.to_string(),
+            None => {
+                std::env::var("RUST_LOG").unwrap_or_else(|_| "warn,kube_client=error".to_string())
+            }
+        },
     );
 
     let fmt_layer = fmt::layer().with_target(false);
@@ -27,20 +37,72 @@ fn init_tracing() {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_tracing();
+    let mut cli_opts = CliOpts::parse();
+    init_tracing(cli_opts.log_filter());
     color_eyre::config::HookBuilder::default()
         .panic_section("consider reporting the bug on github")
         .install()?;
-    let mut cli_opts = CliOpts::parse();
+    if let Some(shell) = cli_opts.completions {
+        completions::generate(shell, CliOpts::command(), &mut std::io::stdout())?;
+        return Ok(());
+    }
+    for key in &cli_opts.group_by_annotation {
+        cli_opts.group_by.push(GroupBy::annotation(key.clone()));
+    }
+    if let Some(path) = &cli_opts.node_group_file {
+        let groups = Arc::new(read_node_groups(path)?);
+        if !cli_opts
+            .group_by
+            .iter()
+            .any(|g| matches!(g, GroupBy::node_group(_)))
+        {
+            cli_opts.group_by.push(GroupBy::node_group(Arc::default()));
+        }
+        for g in cli_opts.group_by.iter_mut() {
+            if let GroupBy::node_group(m) = g {
+                *m = groups.clone();
+            }
+        }
+    } else if cli_opts
+        .group_by
+        .iter()
+        .any(|g| matches!(g, GroupBy::node_group(_)))
+    {
+        bail!("`--group-by node-group` requires `--node-group-file`");
+    }
     //HACK because I didn't find how to default a multiple opts
     if cli_opts.group_by.is_empty() {
         cli_opts.group_by.push(GroupBy::resource);
         cli_opts.group_by.push(GroupBy::node);
         cli_opts.group_by.push(GroupBy::pod);
+        if cli_opts.pod.is_some() {
+            cli_opts.group_by.push(GroupBy::container);
+        }
+    }
+    if cli_opts.efficiency || cli_opts.sort_by == SortBy::waste {
+        cli_opts.utilization = true;
+    }
+    if cli_opts.merge_similar_nodes {
+        for g in cli_opts.group_by.iter_mut() {
+            if g == &GroupBy::node {
+                *g = GroupBy::node_label(INSTANCE_TYPE_LABEL.to_string());
+            }
+        }
     }
     if !cli_opts.group_by.contains(&GroupBy::resource) {
         cli_opts.group_by.insert(0, GroupBy::resource)
     }
+    if !cli_opts.batch.is_empty() && !cli_opts.group_by.contains(&GroupBy::cluster) {
+        let i = cli_opts
+            .group_by
+            .iter()
+            .position(|g| g == &GroupBy::resource)
+            .unwrap_or_default();
+        cli_opts.group_by.insert(i + 1, GroupBy::cluster)
+    }
+    if cli_opts.node_first && !move_resource_after_node(&mut cli_opts.group_by) {
+        tracing::warn!("--node-first has no effect without `-g node`");
+    }
     cli_opts.group_by.dedup();
     // dbg!(&cli_opts);
 
@@ -0,0 +1,281 @@
+//! `--output csv`
+
+use crate::*;
+
+pub fn write_as_csv(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let opts: &DisplayOpts = &opts.for_output(&Output::csv);
+    let group_by = &opts.group_by;
+    let show_utilization = opts.shows(Usage::utilization);
+    // the columns of the resource path
+    let keys = 2..(2 + group_by.len());
+    // print header
+    let mut header = vec!["Date".to_string(), "Kind".to_string()];
+    header.extend(group_by.iter().map(|x| x.to_string()));
+    if opts.show_nodes {
+        header.push("Nodes".to_string());
+    }
+    if opts.show_pods {
+        header.push("Pods".to_string());
+    }
+    if show_utilization {
+        header.push("Utilization".to_string());
+        header.push("%Utilization".to_string());
+    }
+    if opts.shows(Usage::requested) {
+        header.push("Requested".to_string());
+        header.push("%Requested".to_string());
+    }
+    if opts.qos_split && opts.shows(Usage::requested) {
+        header.push("Guaranteed".to_string());
+        header.push("%Guaranteed".to_string());
+        header.push("Burstable".to_string());
+        header.push("%Burstable".to_string());
+    }
+    if opts.shows(Usage::limit) {
+        header.push("Limit".to_string());
+        header.push("%Limit".to_string());
+    }
+    if opts.show_ratio {
+        header.push("Ratio".to_string());
+    }
+    if opts.shows(Usage::allocatable) {
+        header.push("Allocatable".to_string());
+    }
+    if opts.show_reserved {
+        header.push("Reserved".to_string());
+    }
+    if opts.shows(Usage::free) {
+        header.push("Free".to_string());
+    }
+    if opts.pending.is_some() {
+        header.push("Pending".to_string());
+    }
+    if opts.free_spread.is_some() {
+        header.push("Min Free/Node".to_string());
+        header.push("Max Free/Node".to_string());
+    }
+    opts.csv.write_record(out, &header, &keys)?;
+
+    // print data
+    let empty = "".to_string();
+    let datetime = opts.now().to_rfc3339();
+    for (k, oqtys) in data {
+        let opts: &DisplayOpts = &opts.for_kind(k);
+        if let Some(qtys) = oqtys {
+            let mut row = vec![
+                datetime.clone(),
+                group_by
+                    .get(k.len() - 1)
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| empty.clone()),
+            ];
+            for i in 0..group_by.len() {
+                row.push(opts.display_key(k, i).to_string());
+            }
+            if opts.show_nodes {
+                row.push(qtys.nodes.to_string());
+            }
+            if opts.show_pods {
+                row.push(qtys.pods.to_string());
+            }
+
+            let base = qtys.calc_percent_base();
+            if show_utilization {
+                add_cells_for_cvs(&mut row, &qtys.utilization, &base, opts);
+            }
+            if opts.shows(Usage::requested) {
+                add_cells_for_cvs(&mut row, &qtys.requested, &base, opts);
+            }
+            if opts.qos_split && opts.shows(Usage::requested) {
+                add_cells_for_cvs(&mut row, &qtys.calc_requested_guaranteed(), &base, opts);
+                add_cells_for_cvs(&mut row, &qtys.calc_requested_burstable(), &base, opts);
+            }
+            if opts.shows(Usage::limit) {
+                if opts.unbounded_limit && qtys.no_limit {
+                    row.push("inf".to_string());
+                    row.push("".to_string());
+                } else {
+                    add_cells_for_cvs(&mut row, &qtys.limit, &base, opts);
+                }
+            }
+            if opts.show_ratio {
+                row.push(
+                    qtys.calc_request_limit_ratio()
+                        .map(|r| format!("{:.2}", r))
+                        .unwrap_or_else(|| empty.clone()),
+                );
+            }
+
+            if opts.shows(Usage::allocatable) {
+                row.push(
+                    qtys.allocatable
+                        .as_ref()
+                        .map(|qty| opts.format_qty(qty))
+                        .unwrap_or_else(|| empty.clone()),
+                );
+            }
+            if opts.show_reserved {
+                row.push(
+                    qtys.calc_reserved()
+                        .as_ref()
+                        .map(|qty| opts.format_qty(qty))
+                        .unwrap_or_else(|| empty.clone()),
+                );
+            }
+            if opts.shows(Usage::free) {
+                row.push(
+                    qtys.calc_free()
+                        .as_ref()
+                        .map(|qty| opts.format_qty(qty))
+                        .unwrap_or_else(|| empty.clone()),
+                );
+            }
+            if opts.pending.is_some() {
+                row.push(
+                    opts.pending_of(k)
+                        .map(|n| n.to_string())
+                        .unwrap_or_else(|| empty.clone()),
+                );
+            }
+            if opts.free_spread.is_some() {
+                let spread = opts.free_spread_of(k);
+                for qty in [spread.map(|(min, _)| min), spread.map(|(_, max)| max)] {
+                    row.push(
+                        qty.map(|qty| opts.format_qty(qty))
+                            .unwrap_or_else(|| empty.clone()),
+                    );
+                }
+            }
+            opts.csv.write_record(out, &row, &keys)?;
+        }
+    }
+    Ok(())
+}
+
+/// Options of the csv output, the quoting is the one of RFC 4180: a field is quoted when it contains the delimiter,
+/// a quote or a line break (or always for the keys with `quote_keys`), its quotes doubled.
+#[derive(Debug, Clone)]
+pub struct CsvOpts {
+    /// separator of the fields
+    pub delimiter: char,
+    /// always quote the fields of the resource path (else only when required)
+    pub quote_keys: bool,
+}
+
+impl Default for CsvOpts {
+    fn default() -> Self {
+        CsvOpts {
+            delimiter: ',',
+            quote_keys: false,
+        }
+    }
+}
+
+impl CsvOpts {
+    /// the field, quoted (RFC 4180) when forced or when it contains the delimiter, a quote or a line break
+    pub fn escape<'a>(&self, field: &'a str, force_quote: bool) -> std::borrow::Cow<'a, str> {
+        let required = field
+            .chars()
+            .any(|c| c == self.delimiter || c == '"' || c == '\n' || c == '\r');
+        if force_quote || required {
+            format!("\"{}\"", field.replace('"', "\"\"")).into()
+        } else {
+            field.into()
+        }
+    }
+
+    /// write a line of fields, the fields in `keys` are the ones of the resource path
+    fn write_record(
+        &self,
+        out: &mut dyn Write,
+        fields: &[String],
+        keys: &std::ops::Range<usize>,
+    ) -> std::io::Result<()> {
+        let line = fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| self.escape(f, self.quote_keys && keys.contains(&i)))
+            .join(&self.delimiter.to_string());
+        writeln!(out, "{}", line)
+    }
+}
+
+fn add_cells_for_cvs(
+    row: &mut Vec<String>,
+    oqty: &Option<Qty>,
+    o100: &Option<Qty>,
+    opts: &DisplayOpts,
+) {
+    match oqty {
+        None => {
+            row.push("".to_string());
+            row.push("".to_string());
+        }
+        Some(ref qty) => {
+            row.push(opts.format_qty(qty));
+            row.push(match o100 {
+                None => "".to_string(),
+                Some(q100) => format!("{}%", opts.format_percentage(qty.calc_percentage(q100))),
+            });
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_as_csv() {
+        let qtys = QtyByQualifier {
+            requested: Some(Qty::from_str("1500m").unwrap()),
+            allocatable: Some(Qty::from_str("2").unwrap()),
+            ..QtyByQualifier::default()
+        };
+        let data = vec![
+            (vec!["cpu".to_string()], Some(qtys.clone())),
+            (
+                vec!["cpu".to_string(), "node;\"1\"".to_string()],
+                Some(qtys),
+            ),
+        ];
+        let write = |csv: CsvOpts| {
+            let opts = DisplayOpts {
+                group_by: vec![GroupBy::resource, GroupBy::node],
+                percent_decimals: 0,
+                csv,
+                ..DisplayOpts::default()
+            };
+            let mut out = vec![];
+            write_as_csv(&data, &opts, &mut out).unwrap();
+            String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(|l| l.split_once([',', ';']).unwrap().1.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            write(CsvOpts::default()),
+            vec![
+                "Kind,resource,node,Requested,%Requested,Limit,%Limit,Allocatable,Free",
+                "resource,cpu,,1.50,75%,,,2.00,0.50",
+                "node,cpu,\"node;\"\"1\"\"\",1.50,75%,,,2.00,0.50",
+            ]
+        );
+        assert_eq!(
+            write(CsvOpts {
+                delimiter: ';',
+                quote_keys: true,
+            }),
+            vec![
+                "Kind;\"resource\";\"node\";Requested;%Requested;Limit;%Limit;Allocatable;Free",
+                "resource;\"cpu\";\"\";1.50;75%;;;2.00;0.50",
+                "node;\"cpu\";\"node;\"\"1\"\"\";1.50;75%;;;2.00;0.50",
+            ]
+        );
+    }
+}
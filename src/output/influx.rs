@@ -0,0 +1,69 @@
+//! `--output influx` (line protocol)
+
+use crate::*;
+
+/// the measurement of the lines of `--output influx`
+pub const INFLUX_MEASUREMENT: &str = "kube_allocations";
+
+/// escape the commas, equal signs & spaces of a tag (key or value) of the line protocol
+fn escape_influx_tag(s: &str) -> String {
+    s.replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// write the rows as line protocol of InfluxDB, eg
+/// `kube_allocations,resource=cpu,node=n1 requested=1.5,limit=2,allocatable=4,free=2.5 1672628645000000000`:
+/// the keys of the row as tags, the quantities in the unit of the resource (cores, bytes,...) as fields,
+/// and the date of the collect as timestamp (in nanoseconds)
+pub fn write_as_influx(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let timestamp = opts.now().timestamp_nanos_opt().unwrap_or_default();
+    for (k, oqtys) in data {
+        let qtys = match oqtys {
+            Some(qtys) => qtys,
+            None => continue,
+        };
+        let fields = metric_fields(qtys, opts)
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>();
+        // a line without field is invalid
+        if fields.is_empty() {
+            continue;
+        }
+        let tags = opts
+            .group_by
+            .iter()
+            .enumerate()
+            .take(k.len())
+            .map(|(i, g)| (g.to_string(), opts.display_key(k, i)))
+            // a tag without value is invalid
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(g, v)| format!(",{}={}", escape_influx_tag(&g), escape_influx_tag(v)))
+            .collect::<String>();
+        writeln!(
+            out,
+            "{}{} {} {}",
+            INFLUX_MEASUREMENT,
+            tags,
+            fields.join(","),
+            timestamp
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_influx_tag() {
+        assert_eq!(escape_influx_tag("nvidia.com/gpu"), "nvidia.com/gpu");
+        assert_eq!(escape_influx_tag("a b,c=d"), "a\\ b\\,c\\=d");
+    }
+}
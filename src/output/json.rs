@@ -0,0 +1,563 @@
+//! the json outputs (`--output json`, totals, free by node, resources, simulation) and the snapshots read back
+
+use crate::*;
+
+/// version of the structure of the json output, bumped when a field is removed or changes of meaning
+/// (adding an optional field is not a breaking change)
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonOutput {
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
+    pub date: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<ClusterInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fleet: Option<FleetSummary>,
+    pub items: Vec<JsonRow>,
+    /// estimated requests of the pods not yet created by jobs & cronjobs (see `--include-jobs`)
+    #[serde(rename = "pendingBurst", skip_serializing_if = "Option::is_none")]
+    pub pending_burst: Option<Vec<JsonRow>>,
+}
+
+/// A row of the json output, every quantity is provided as a formatted string (like in table)
+/// and as a raw value into the base unit of the resource (millicores for cpu, bytes for memory)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JsonRow {
+    pub kind: String,
+    #[serde(flatten)]
+    pub keys: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pods: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utilization: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utilization_base: Option<i128>,
+    pub requested: Option<String>,
+    pub requested_base: Option<i128>,
+    /// the requested by the pods of QoS Guaranteed, see `--qos-split`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_guaranteed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_guaranteed_base: Option<i128>,
+    /// the requested by the other pods (Burstable & BestEffort), see `--qos-split`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_burstable: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_burstable_base: Option<i128>,
+    pub limit: Option<String>,
+    pub limit_base: Option<i128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratio: Option<f64>,
+    pub allocatable: Option<String>,
+    pub allocatable_base: Option<i128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reserved: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reserved_base: Option<i128>,
+    pub free: Option<String>,
+    pub free_base: Option<i128>,
+    /// the number of pending pods blocked by a lack of the kind (rows of kinds, see `--pending-pods`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending: Option<usize>,
+    /// the min & the max of the free of the nodes (rows of kinds, see `--free-spread`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_free_min: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_free_min_base: Option<i128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_free_max: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_free_max_base: Option<i128>,
+    /// creation time of the node (rows of nodes, see `--show-age`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+}
+
+fn json_qty(
+    oqty: &Option<Qty>,
+    family: UnitFamily,
+    opts: &DisplayOpts,
+) -> (Option<String>, Option<i128>) {
+    (
+        oqty.as_ref().map(|qty| opts.format_qty(qty)),
+        oqty.as_ref().map(|qty| qty.to_base_value(family)),
+    )
+}
+
+pub fn make_json_output(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+) -> JsonOutput {
+    let opts: &DisplayOpts = &opts.for_output(&Output::json);
+    let group_by = &opts.group_by;
+    let resource_idx = group_by.iter().position(|g| g == &GroupBy::resource);
+    let items = data
+        .iter()
+        .filter_map(|(k, oqtys)| oqtys.as_ref().map(|qtys| (k, qtys)))
+        .map(|(k, qtys)| {
+            let opts: &DisplayOpts = &opts.for_kind(k);
+            let family = UnitFamily::from_kind(
+                resource_idx
+                    .and_then(|i| k.get(i))
+                    .map(|x| x.as_str())
+                    .unwrap_or_default(),
+            );
+            let mut row = JsonRow {
+                kind: group_by
+                    .get(k.len() - 1)
+                    .map(|x| x.to_string())
+                    .unwrap_or_default(),
+                keys: group_by
+                    .iter()
+                    .enumerate()
+                    .take(k.len())
+                    .map(|(i, g)| (g.to_string(), opts.display_key(k, i).to_string()))
+                    .collect(),
+                ..JsonRow::default()
+            };
+            if opts.show_nodes {
+                row.nodes = Some(qtys.nodes);
+            }
+            if opts.show_pods {
+                row.pods = Some(qtys.pods);
+            }
+            if opts.show_utilization {
+                (row.utilization, row.utilization_base) = json_qty(&qtys.utilization, family, opts);
+            }
+            (row.requested, row.requested_base) = json_qty(&qtys.requested, family, opts);
+            if opts.qos_split {
+                (row.requested_guaranteed, row.requested_guaranteed_base) =
+                    json_qty(&qtys.calc_requested_guaranteed(), family, opts);
+                (row.requested_burstable, row.requested_burstable_base) =
+                    json_qty(&qtys.calc_requested_burstable(), family, opts);
+            }
+            (row.limit, row.limit_base) = json_qty(&qtys.limit, family, opts);
+            if opts.show_ratio {
+                row.ratio = qtys.calc_request_limit_ratio();
+            }
+            (row.allocatable, row.allocatable_base) = json_qty(&qtys.allocatable, family, opts);
+            if opts.show_reserved {
+                (row.reserved, row.reserved_base) = json_qty(&qtys.calc_reserved(), family, opts);
+            }
+            (row.free, row.free_base) = json_qty(&qtys.calc_free(), family, opts);
+            row.pending = opts.pending_of(k);
+            let spread = opts.free_spread_of(k);
+            (row.node_free_min, row.node_free_min_base) =
+                json_qty(&spread.map(|(min, _)| min.clone()), family, opts);
+            (row.node_free_max, row.node_free_max_base) =
+                json_qty(&spread.map(|(_, max)| max.clone()), family, opts);
+            if opts.show_age && opts.is_node_row(k) {
+                row.created = qtys.node_created.map(|t| t.to_rfc3339());
+            }
+            row
+        })
+        .collect();
+    JsonOutput {
+        schema_version: JSON_SCHEMA_VERSION,
+        date: opts.now().to_rfc3339(),
+        cluster: opts.cluster.clone(),
+        fleet: opts.fleet.clone(),
+        pending_burst: None,
+        items,
+    }
+}
+
+/// The totals of a resource for the whole cluster (`-o totals-json`), with the fields of `JsonRow`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JsonTotals {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utilization: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utilization_base: Option<i128>,
+    pub requested: Option<String>,
+    pub requested_base: Option<i128>,
+    pub limit: Option<String>,
+    pub limit_base: Option<i128>,
+    pub allocatable: Option<String>,
+    pub allocatable_base: Option<i128>,
+    pub free: Option<String>,
+    pub free_base: Option<i128>,
+}
+
+impl From<JsonRow> for JsonTotals {
+    fn from(row: JsonRow) -> Self {
+        JsonTotals {
+            utilization: row.utilization,
+            utilization_base: row.utilization_base,
+            requested: row.requested,
+            requested_base: row.requested_base,
+            limit: row.limit,
+            limit_base: row.limit_base,
+            allocatable: row.allocatable,
+            allocatable_base: row.allocatable_base,
+            free: row.free,
+            free_base: row.free_base,
+        }
+    }
+}
+
+/// the totals by resource (whatever the `--group-by`), keyed by the (displayed) name of the resource
+pub fn make_totals_json(
+    resources: &[Resource],
+    resource_names: &[String],
+    opts: &DisplayOpts,
+) -> BTreeMap<String, JsonTotals> {
+    let group_by = vec![GroupBy::resource];
+    let data = make_qualifiers(resources, &group_by, resource_names);
+    let opts = DisplayOpts {
+        group_by,
+        ..opts.clone()
+    };
+    make_json_output(&data, &opts)
+        .items
+        .into_iter()
+        .map(|mut row| {
+            let kind = row.keys.remove("resource").unwrap_or_default();
+            (kind, JsonTotals::from(row))
+        })
+        .collect()
+}
+
+/// The free of a node (`-o node-free-json`), by resource as `<kind>_free` (eg `cpu_free`), in the base unit
+/// of the resource (like `JsonRow::free_base`), the resources without free (eg no allocatable) are omitted
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeFree {
+    pub node: String,
+    #[serde(flatten)]
+    pub free: BTreeMap<String, i128>,
+}
+
+/// the free of every node (whatever the `--group-by`), with the used basis & the headroom of `opts`
+pub fn make_node_free_json(
+    resources: &[Resource],
+    resource_names: &[String],
+    opts: &DisplayOpts,
+) -> Vec<NodeFree> {
+    let group_by = vec![GroupBy::node, GroupBy::resource];
+    let mut data = make_qualifiers(resources, &group_by, resource_names);
+    apply_used_basis(&mut data, opts.used_basis);
+    apply_free_headroom(&mut data, opts.free_headroom);
+    let opts = DisplayOpts {
+        group_by,
+        ..opts.clone()
+    };
+    let mut nodes: Vec<NodeFree> = vec![];
+    for mut row in make_json_output(&data, &opts).items {
+        let node = row.keys.remove("node").unwrap_or_default();
+        // the rows of the pods not scheduled have no node
+        if node.is_empty() {
+            continue;
+        }
+        if nodes.last().map(|n| n.node != node).unwrap_or(true) {
+            nodes.push(NodeFree {
+                node,
+                ..NodeFree::default()
+            });
+        }
+        if let (Some(kind), Some(free), Some(last)) =
+            (row.keys.remove("resource"), row.free_base, nodes.last_mut())
+        {
+            last.free.insert(format!("{}_free", kind), free);
+        }
+    }
+    nodes
+}
+
+/// version of the structure of the resources-json output (see `JSON_SCHEMA_VERSION`)
+pub const RESOURCES_SCHEMA_VERSION: u32 = 1;
+
+/// The resources not grouped (`-o resources-json`), as collected (after the filters)
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourcesOutput<'a> {
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
+    pub resources: Vec<&'a Resource>,
+}
+
+pub fn make_resources_output<'a>(
+    resources: &'a [Resource],
+    resource_names: &[String],
+) -> ResourcesOutput<'a> {
+    ResourcesOutput {
+        schema_version: RESOURCES_SCHEMA_VERSION,
+        resources: resources
+            .iter()
+            .filter(|r| accept_resource(&r.kind, resource_names))
+            .collect(),
+    }
+}
+
+/// version of the structure of the sim-json output (see `JSON_SCHEMA_VERSION`)
+pub const SIM_SCHEMA_VERSION: u32 = 1;
+
+/// The state of the cluster as input of scheduling simulators (`-o sim-json`),
+/// quantities are formatted like by kubernetes (`1500m` for cpu, bytes for memory, counts for the others)
+#[derive(Debug, Clone, Serialize)]
+pub struct SimOutput {
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
+    pub nodes: Vec<SimNode>,
+    pub pods: Vec<SimPod>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SimNode {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub allocatable: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimPod {
+    pub namespace: String,
+    pub name: String,
+    /// the node the pod is bound to (none when pending)
+    pub node_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// the requests of the pod (sum of its containers, "pods" excluded)
+    pub requests: BTreeMap<String, String>,
+}
+
+fn sim_quantity(kind: &str, qty: &Qty) -> String {
+    match UnitFamily::from_kind(kind) {
+        UnitFamily::Millicores => format!("{}m", qty.value),
+        // lossless, even for the fractions of unit
+        _ => qty.to_canonical_string(),
+    }
+}
+
+pub fn make_sim_output(resources: &[Resource], resource_names: &[String]) -> SimOutput {
+    let mut nodes: BTreeMap<String, (SimNode, BTreeMap<String, Qty>)> = BTreeMap::new();
+    let mut pods: BTreeMap<(String, String), (SimPod, BTreeMap<String, Qty>)> = BTreeMap::new();
+    for r in resources
+        .iter()
+        .filter(|r| accept_resource(&r.kind, resource_names))
+    {
+        let l = &r.location;
+        match (&r.qualifier, &l.node_name, &l.pod_name) {
+            (ResourceQualifier::Allocatable, Some(node_name), None) => {
+                let (_, qtys) = nodes.entry(node_name.clone()).or_insert_with(|| {
+                    let node = SimNode {
+                        name: node_name.clone(),
+                        labels: (*l.node_labels).clone(),
+                        ..SimNode::default()
+                    };
+                    (node, BTreeMap::new())
+                });
+                let qty = qtys.remove(&r.kind);
+                qtys.insert(r.kind.clone(), add(qty, &r.quantity).unwrap_or_default());
+            }
+            (ResourceQualifier::Requested, _, Some(pod_name)) => {
+                let namespace = l.namespace.clone().unwrap_or_default();
+                let (_, qtys) = pods
+                    .entry((namespace.clone(), pod_name.clone()))
+                    .or_insert_with(|| {
+                        let pod = SimPod {
+                            namespace,
+                            name: pod_name.clone(),
+                            node_name: l.node_name.clone(),
+                            priority: l.priority,
+                            ..SimPod::default()
+                        };
+                        (pod, BTreeMap::new())
+                    });
+                // every pod requests 1 "pods" (so pods without requests are listed too)
+                if r.kind != "pods" {
+                    let qty = qtys.remove(&r.kind);
+                    qtys.insert(r.kind.clone(), add(qty, &r.quantity).unwrap_or_default());
+                }
+            }
+            _ => {}
+        }
+    }
+    let format = |qtys: BTreeMap<String, Qty>| {
+        qtys.iter()
+            .map(|(kind, qty)| (kind.clone(), sim_quantity(kind, qty)))
+            .collect()
+    };
+    SimOutput {
+        schema_version: SIM_SCHEMA_VERSION,
+        nodes: nodes
+            .into_values()
+            .map(|(node, qtys)| SimNode {
+                allocatable: format(qtys),
+                ..node
+            })
+            .collect(),
+        pods: pods
+            .into_values()
+            .map(|(pod, qtys)| SimPod {
+                requests: format(qtys),
+                ..pod
+            })
+            .collect(),
+    }
+}
+
+/// A snapshot (a json output, see `-o json`) to compare with, the rows are matched by their keys
+/// (so the snapshot should be made with the same `--group-by`)
+#[derive(Debug, Clone)]
+pub struct Baseline {
+    pub date: String,
+    rows: BTreeMap<(String, BTreeMap<String, String>), JsonRow>,
+}
+
+impl Baseline {
+    pub fn read(path: &std::path::Path) -> Result<Self, Error> {
+        let content = std::fs::read(path).map_err(|source| Error::ReadFileError {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let output: JsonOutput =
+            serde_json::from_slice(&content).map_err(|source| Error::ConfigError {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        if output.schema_version != JSON_SCHEMA_VERSION {
+            warn!(
+                schema_version = output.schema_version,
+                "the baseline was made by an other version"
+            );
+        }
+        Ok(Baseline::from(output))
+    }
+
+    pub fn get(&self, row: &JsonRow) -> Option<&JsonRow> {
+        self.rows.get(&(row.kind.clone(), row.keys.clone()))
+    }
+
+    /// the row of the total of the `kind` (the snapshot made with `-g resource` first)
+    pub fn kind_row(&self, kind: &str) -> Option<&JsonRow> {
+        let resource = GroupBy::resource.to_string();
+        self.rows.get(&(
+            resource.clone(),
+            BTreeMap::from([(resource, kind.to_string())]),
+        ))
+    }
+}
+
+impl From<JsonOutput> for Baseline {
+    fn from(output: JsonOutput) -> Self {
+        Baseline {
+            date: output.date,
+            rows: output
+                .items
+                .into_iter()
+                .map(|row| ((row.kind.clone(), row.keys.clone()), row))
+                .collect(),
+        }
+    }
+}
+
+pub fn write_as_json(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    serde_json::to_writer_pretty(&mut *out, &make_json_output(data, opts))?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    fn test_make_json_output_with_base_values() {
+        let qtys = QtyByQualifier {
+            requested: Some(Qty::from_str("1500m").unwrap()),
+            allocatable: Some(Qty::from_str("4").unwrap()),
+            ..QtyByQualifier::default()
+        };
+        let data = vec![(vec!["cpu".to_string(), "node-1".to_string()], Some(qtys))];
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node],
+            ..DisplayOpts::default()
+        };
+        let output = make_json_output(&data, &opts);
+        let row = &output.items[0];
+        assert_eq!(row.kind, "node");
+        assert_eq!(row.keys.get("node"), Some(&"node-1".to_string()));
+        assert_eq!(row.requested, Some("1.5".to_string()));
+        assert_eq!(row.requested_base, Some(1500));
+        assert_eq!(row.limit_base, None);
+        assert_eq!(row.allocatable_base, Some(4000));
+        assert_eq!(row.free_base, Some(2500));
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["schemaVersion"], JSON_SCHEMA_VERSION);
+        assert!(json.get("cluster").is_none());
+
+        let opts = DisplayOpts {
+            cluster: Some(ClusterInfo {
+                context: Some("prod".to_string()),
+                server: "https://10.0.0.1:6443/".to_string(),
+            }),
+            ..opts
+        };
+        let json = serde_json::to_value(make_json_output(&data, &opts)).unwrap();
+        assert_eq!(json["cluster"]["context"], "prod");
+        assert_eq!(json["cluster"]["server"], "https://10.0.0.1:6443/");
+    }
+
+    #[tokio::test]
+    async fn test_make_sim_output() {
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(
+            make_node_list(vec![make_node(
+                "n1",
+                serde_json::json!({ "kubernetes.io/os": "linux" }),
+                serde_json::json!({ "cpu": "4", "memory": "1Gi", "pods": "110" }),
+            )]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        extract_allocatable_from_pods(
+            make_pod_list(vec![
+                make_pod(
+                    "p1",
+                    "n1",
+                    vec![
+                        make_named_container("app", &[("cpu", "1"), ("memory", "256Mi")], &[]),
+                        make_named_container("sidecar", &[("cpu", "500m")], &[]),
+                    ],
+                ),
+                make_pod("best-effort", "n1", vec![make_container(&[], &[])]),
+            ]),
+            &mut resources,
+        )
+        .await
+        .unwrap();
+        let output = serde_json::to_value(make_sim_output(&resources, &[])).unwrap();
+        assert_eq!(
+            output,
+            serde_json::json!({
+                "schemaVersion": 1,
+                "nodes": [{
+                    "name": "n1",
+                    "labels": { "kubernetes.io/os": "linux" },
+                    "allocatable": { "cpu": "4000m", "memory": "1073741824", "pods": "110" },
+                }],
+                "pods": [
+                    { "namespace": "default", "name": "best-effort", "nodeName": "n1", "requests": {} },
+                    {
+                        "namespace": "default",
+                        "name": "p1",
+                        "nodeName": "n1",
+                        "requests": { "cpu": "1500m", "memory": "268435456" },
+                    },
+                ],
+            })
+        );
+        let cpu_only = make_sim_output(&resources, &["cpu".to_string()]);
+        assert_eq!(cpu_only.nodes[0].allocatable.len(), 1);
+    }
+}
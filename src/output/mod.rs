@@ -0,0 +1,710 @@
+//! module of the outputs (see `--output`), and of the options of the rendering shared by them
+
+mod csv;
+mod influx;
+mod json;
+mod prometheus;
+mod table;
+
+pub use self::csv::*;
+pub use influx::*;
+pub use json::*;
+pub use prometheus::*;
+pub use table::*;
+
+use crate::*;
+
+/// One line summarizing the rows of the kinds (see `--banner`), eg
+/// `cpu: 62% requested (38.0 free of 100.0), memory: 71% (29.0Gi free of 100.0Gi)`,
+/// the kinds without allocatable are omitted
+pub fn make_banner(data: &[(Vec<String>, Option<QtyByQualifier>)], opts: &DisplayOpts) -> String {
+    data.iter()
+        .filter(|(k, _)| opts.kind_row_of(k).is_some())
+        .filter_map(|(k, oqtys)| {
+            let qtys = oqtys.as_ref()?;
+            let base = qtys.calc_percent_base()?;
+            let requested = qtys.requested.clone().unwrap_or_default();
+            Some((k, qtys, base, requested))
+        })
+        .enumerate()
+        .map(|(i, (k, qtys, base, requested))| {
+            let opts: &DisplayOpts = &opts.for_kind(k);
+            format!(
+                "{}: {}%{} ({} free of {})",
+                opts.display_key(k, 0),
+                opts.format_percentage(requested.calc_percentage(&base)),
+                // the legend, once
+                if i == 0 { " requested" } else { "" },
+                opts.format_qty(&qtys.calc_free().unwrap_or_default()),
+                opts.format_qty(&base)
+            )
+        })
+        .join(", ")
+}
+
+/// Partition the resources by node, and write the rows of every node into `<dir>/<node>.<ext>`
+/// (in the output format), resources without node (eg pending pods) are ignored
+pub fn write_split_by_node(
+    resources: &[Resource],
+    cli_opts: &CliOpts,
+    display_opts: &DisplayOpts,
+    dir: &std::path::Path,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(dir).map_err(|source| Error::FileError {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+    let by_node = resources
+        .iter()
+        .filter_map(|r| r.location.node_name.as_ref().map(|n| (n, r)))
+        .into_group_map();
+    for node_name in by_node.keys().sorted() {
+        let node_resources = by_node[node_name]
+            .iter()
+            .filter(|r| accept_resource(&r.kind, &cli_opts.resource_name))
+            .map(|r| (*r).clone())
+            .collect::<Vec<_>>();
+        let mut res = make_qualifiers(&node_resources, &cli_opts.group_by, &cli_opts.resource_name);
+        apply_used_basis(&mut res, cli_opts.used_basis);
+        apply_percent_base(&mut res, cli_opts.percent_base);
+        apply_free_headroom(&mut res, cli_opts.free_headroom);
+        let res = finalize_rows(res, cli_opts);
+        let path = dir.join(format!("{}.{}", node_name, cli_opts.output.extension()));
+        write_output_file(&node_resources, &res, display_opts, &cli_opts.output, &path)?;
+    }
+    info!(nodes = by_node.len(), dir = ?dir, "files written by node");
+    Ok(())
+}
+
+/// write the rows (of the resources) into the file at `path` in the `output` format
+pub fn write_output_file(
+    resources: &[Resource],
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    output: &Output,
+    path: &std::path::Path,
+) -> Result<(), Error> {
+    let mut file = std::fs::File::create(path)
+        .map(std::io::BufWriter::new)
+        .map_err(|source| Error::FileError {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    write_output(resources, data, opts, output, &mut file)
+        .and_then(|_| file.flush().map_err(Error::from))
+        .map_err(|err| match err {
+            Error::OutputError { source } => Error::FileError {
+                path: path.to_path_buf(),
+                source,
+            },
+            err => err,
+        })
+}
+
+/// write the rows (of the resources) into `out` in the `output` format
+pub fn write_output(
+    resources: &[Resource],
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    output: &Output,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    match output {
+        Output::table => write_with_prettytable(data, opts, out),
+        Output::plain => write_as_plain(data, opts, out),
+        Output::csv => write_as_csv(data, opts, out),
+        Output::influx => write_as_influx(data, opts, out),
+        Output::prometheus => write_as_prometheus(data, opts, out),
+        Output::json | Output::console_json => write_as_json(data, opts, out),
+        Output::sim_json => {
+            // the resources are already filtered by kind
+            serde_json::to_writer_pretty(&mut *out, &make_sim_output(resources, &[]))?;
+            writeln!(out)?;
+            Ok(())
+        }
+        Output::totals_json => {
+            serde_json::to_writer_pretty(&mut *out, &make_totals_json(resources, &[], opts))?;
+            writeln!(out)?;
+            Ok(())
+        }
+        Output::resources_json => {
+            serde_json::to_writer_pretty(&mut *out, &make_resources_output(resources, &[]))?;
+            writeln!(out)?;
+            Ok(())
+        }
+        Output::node_free_json => {
+            serde_json::to_writer_pretty(&mut *out, &make_node_free_json(resources, &[], opts))?;
+            writeln!(out)?;
+            Ok(())
+        }
+    }
+}
+
+/// Options of the rendering, shared by every output format
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOpts {
+    pub group_by: Vec<GroupBy>,
+    /// display name of resource kinds (kind, alias)
+    pub kind_aliases: Vec<(String, String)>,
+    pub show_utilization: bool,
+    /// hide lines with zero requested and zero limit and zero allocatable (table only)
+    pub filter_full_zero: bool,
+    /// show limit as unbounded when a container requests without limit
+    pub unbounded_limit: bool,
+    /// show the reserved part of the capacity (capacity - allocatable)
+    pub show_reserved: bool,
+    /// show the age of the nodes (on the rows of nodes)
+    pub show_age: bool,
+    /// show a bar of the percentage requested (table only)
+    pub show_bars: bool,
+    /// the usages displayed (see `DisplayOpts::shows`), all if empty
+    pub usages: Vec<Usage>,
+    /// show quantities rounded to whole units (see `Qty::to_compact_string`)
+    pub compact: bool,
+    /// the glyphs of the tree (table only, see `--tree-style`)
+    pub tree_glyphs: tree::Glyphs,
+    /// draw a rule between the top-level groups (table only, see `--separators`)
+    pub separators: bool,
+    /// display the rows with one child as the child (table only, see `--flatten-single-child`)
+    pub flatten_single_child: bool,
+    /// display the requested & limit in one column (table only, see `combines_req_limit`)
+    pub combined_req_limit: bool,
+    /// the (kind, node) of the device plugins not ready (see `find_plugins_not_ready`)
+    pub plugins_not_ready: BTreeSet<(String, String)>,
+    /// the min & the max of the free of the nodes by kind (see `free_spread_of`)
+    pub free_spread: Option<BTreeMap<String, (Qty, Qty)>>,
+    /// the separator of the keys displayed on one line (see `--group-separator`)
+    pub group_separator: String,
+    /// show the quantities of a column (by kind) with the same prefix (table only, see `Qty::common_scale`)
+    pub align_units: bool,
+    /// show the percentage of allocatable next to the free (table only)
+    pub free_with_pct: bool,
+    /// show the number of nodes
+    pub show_nodes: bool,
+    /// show the number of pods (when grouped by namespace)
+    pub show_pods: bool,
+    /// show the ratio requested / limit
+    pub show_ratio: bool,
+    /// show the requested of the pods Guaranteed & of the others (see `--qos-split`)
+    pub qos_split: bool,
+    /// the quantity subtracted from allocatable by the free computed from the resources (see `--used-basis`)
+    pub used_basis: UsedBasis,
+    /// the percentage of allocatable not counted as free by the free computed from the resources
+    /// (see `--free-headroom`)
+    pub free_headroom: f64,
+    /// the number of pending pods blocked by a lack of the kind, shown on the rows of the kinds (see `--pending-pods`)
+    pub pending: Option<BTreeMap<String, usize>>,
+    /// highlight the free of nodes below the threshold (by kind)
+    pub free_thresholds: Vec<(String, Qty)>,
+    /// the cluster the data come from (header of table, field of json)
+    pub cluster: Option<ClusterInfo>,
+    /// the size of the fleet (header of table, field of json), see `--node-count`
+    pub fleet: Option<FleetSummary>,
+    /// number of decimals of percentages
+    pub percent_decimals: usize,
+    /// the number of decimals of the quantities by kind (see `--precision` & `for_kind`)
+    pub precisions: Vec<(String, usize)>,
+    /// the number of decimals of the quantities of the row (see `for_kind`), else the default
+    pub precision: Option<usize>,
+    /// the rendering of the quantities by output (see `--quantities` & `for_output`)
+    pub quantities: Vec<(Output, QtyStyle)>,
+    /// render the quantities as numbers of base units (see `QtyStyle::base`)
+    pub base_values: bool,
+    /// delimiter & quoting of the csv output
+    pub csv: CsvOpts,
+    /// color the percentages of the table by thresholds
+    pub color_thresholds: Option<ThresholdsConfig>,
+    /// apply the styles (colors, bold) of the tables, eg when the output is a terminal
+    pub colored: bool,
+    /// only the data: no header (cluster, fleet) nor legend above & under the table (see `--quiet`)
+    pub quiet: bool,
+    /// the date of the output and the reference of the ages (now if none, fixed for reproducible outputs)
+    pub now: Option<DateTime<Utc>>,
+}
+
+impl DisplayOpts {
+    /// the usage is displayed (table & csv), utilization only if collected
+    pub fn shows(&self, usage: Usage) -> bool {
+        (usage != Usage::utilization || self.show_utilization)
+            && (self.usages.is_empty() || self.usages.contains(&usage))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.now.unwrap_or_else(Utc::now)
+    }
+
+    /// format the percentage with `percent_decimals` decimals, rounded half away from zero
+    /// (the same way for every output), except to 100 and to 0 (see below)
+    pub fn format_percentage(&self, percentage: f64) -> String {
+        let decimals = self.percent_decimals;
+        let factor = 10f64.powi(decimals as i32);
+        let step = 1.0 / factor;
+        let rounded = (percentage * factor).round() / factor;
+        // never "100" when not full (eg 99.6), nor "0" when not empty (eg 0.4)
+        let rounded = if percentage < 100.0 && rounded >= 100.0 {
+            100.0 - step
+        } else if percentage > 0.0 && rounded <= 0.0 {
+            step
+        } else {
+            rounded
+        };
+        format!("{:.*}", decimals, rounded)
+    }
+
+    pub fn format_qty(&self, qty: &Qty) -> String {
+        self.format_qty_in(qty, None)
+    }
+
+    /// the requested & limit are displayed in one column (table only, see `--combined-req-limit`)
+    pub fn combines_req_limit(&self) -> bool {
+        self.combined_req_limit && self.shows(Usage::requested) && self.shows(Usage::limit)
+    }
+
+    /// `requested / limit` (eg `1.5 / 2.0`), `∞` for an unbounded limit, with the prefixes `requested_scale`
+    /// & `limit_scale` if some (see `--align-units`)
+    pub fn format_req_limit_in(
+        &self,
+        qtys: &QtyByQualifier,
+        requested_scale: Option<&Scale>,
+        limit_scale: Option<&Scale>,
+    ) -> String {
+        let fmt = |oqty: &Option<Qty>, scale| {
+            oqty.as_ref()
+                .map(|qty| self.format_qty_in(qty, scale))
+                .unwrap_or_else(|| "__".to_string())
+        };
+        let limit = if qtys.no_limit {
+            "∞".to_string()
+        } else {
+            fmt(&qtys.limit, limit_scale)
+        };
+        format!("{} / {}", fmt(&qtys.requested, requested_scale), limit)
+    }
+
+    /// like `format_qty`, with a leading `+` if positive (eg the deltas)
+    pub fn format_signed_qty(&self, qty: &Qty) -> String {
+        let formatted = self.format_qty(qty);
+        if qty.value > 0 {
+            format!("+{}", formatted)
+        } else {
+            formatted
+        }
+    }
+
+    /// the options of the rendering as `output`, with its style of quantities (see `--quantities`)
+    pub fn for_output(&self, output: &Output) -> Cow<'_, DisplayOpts> {
+        let style = self
+            .quantities
+            .iter()
+            .find(|(o, _)| o == output)
+            .map(|(_, style)| *style)
+            .unwrap_or_else(|| output.default_qty_style());
+        let base_values = style == QtyStyle::base;
+        if base_values == self.base_values {
+            Cow::Borrowed(self)
+        } else {
+            Cow::Owned(DisplayOpts {
+                base_values,
+                ..self.clone()
+            })
+        }
+    }
+
+    /// the options of the rows of the kind of `k`, with its precision (see `--precision`) if any
+    pub fn for_kind(&self, k: &[String]) -> Cow<'_, DisplayOpts> {
+        let precision = self.kind_of(k).and_then(|kind| {
+            self.precisions
+                .iter()
+                .find(|(x, _)| x == kind)
+                .map(|(_, decimals)| *decimals)
+        });
+        match precision {
+            Some(_) => Cow::Owned(DisplayOpts {
+                precision,
+                ..self.clone()
+            }),
+            None => Cow::Borrowed(self),
+        }
+    }
+
+    /// like `format_qty`, with the prefix `scale` if some (see `--align-units`)
+    pub fn format_qty_in(&self, qty: &Qty, scale: Option<&Scale>) -> String {
+        if self.base_values {
+            return format!("{:.*}", self.precision.unwrap_or(2), f64::from(qty));
+        }
+        if let Some(decimals) = self.precision {
+            let qty = match scale {
+                Some(scale) => qty.with_scale(scale),
+                None => qty.adjust_scale(),
+            };
+            return format!("{:.*}", decimals, qty);
+        }
+        match (scale, self.compact) {
+            (Some(scale), true) => qty.with_scale(scale).to_compact_string_in_scale(),
+            (Some(scale), false) => format!("{}", qty.with_scale(scale)),
+            (None, true) => qty.to_compact_string(),
+            (None, false) => format!("{}", qty.adjust_scale()),
+        }
+    }
+
+    /// the free with its percentage of allocatable (or capacity, see `--percent-base`), eg `3.2Gi (40%)`
+    pub fn format_free_with_pct(&self, qtys: &QtyByQualifier) -> String {
+        self.format_free_with_pct_in(qtys, None)
+    }
+
+    /// like `format_free_with_pct`, with the prefix `scale` if some (see `--align-units`)
+    pub fn format_free_with_pct_in(&self, qtys: &QtyByQualifier, scale: Option<&Scale>) -> String {
+        match (qtys.calc_free(), qtys.calc_percent_base()) {
+            (None, _) => "__".to_string(),
+            (Some(free), None) => self.format_qty_in(&free, scale),
+            (Some(free), Some(base)) => format!(
+                "{} ({}%)",
+                self.format_qty_in(&free, scale),
+                self.format_percentage(free.calc_percentage(&base))
+            ),
+        }
+    }
+
+    /// the value to display for the `depth`-th part of the key of a row (kind aliases applied)
+    pub fn display_key<'a>(&'a self, k: &'a [String], depth: usize) -> &'a str {
+        let v = k.get(depth).map(|x| x.as_str()).unwrap_or_default();
+        if self.group_by.get(depth) == Some(&GroupBy::resource) {
+            if let Some((_, alias)) = self.kind_aliases.iter().find(|(name, _)| name == v) {
+                return alias;
+            }
+        }
+        v
+    }
+
+    /// the row with the key `k` is the one of a node
+    pub fn is_node_row(&self, k: &[String]) -> bool {
+        k.len()
+            .checked_sub(1)
+            .and_then(|depth| self.group_by.get(depth))
+            == Some(&GroupBy::node)
+    }
+
+    /// the kind of the row with the key `k`, only if it is the row of the kind (not of a group under it)
+    pub fn kind_row_of<'a>(&self, k: &'a [String]) -> Option<&'a String> {
+        k.len()
+            .checked_sub(1)
+            .filter(|depth| self.group_by.get(*depth) == Some(&GroupBy::resource))
+            .and_then(|depth| k.get(depth))
+    }
+
+    /// the number of pending pods blocked by a lack of the kind, only on the rows of the kinds (see `--pending-pods`)
+    pub fn pending_of(&self, k: &[String]) -> Option<usize> {
+        let pending = self.pending.as_ref()?;
+        let kind = self.kind_row_of(k)?;
+        Some(pending.get(kind).copied().unwrap_or_default())
+    }
+
+    /// the row with the key `k` is the one of a node where the device plugin of the kind is not ready
+    pub fn is_plugin_not_ready(&self, k: &[String]) -> bool {
+        match (self.kind_of(k), k.last()) {
+            (Some(kind), Some(node)) if self.is_node_row(k) => self
+                .plugins_not_ready
+                .contains(&(kind.to_string(), node.clone())),
+            _ => false,
+        }
+    }
+
+    /// the min & the max of the free of the nodes, only on the rows of the kinds (see `--free-spread`)
+    pub fn free_spread_of(&self, k: &[String]) -> Option<&(Qty, Qty)> {
+        self.free_spread.as_ref()?.get(self.kind_row_of(k)?)
+    }
+
+    /// the kind of the row with the key `k`
+    pub fn kind_of<'a>(&self, k: &'a [String]) -> Option<&'a str> {
+        self.group_by
+            .iter()
+            .position(|g| g == &GroupBy::resource)
+            .and_then(|i| k.get(i))
+            .map(|x| x.as_str())
+    }
+
+    /// the style of the cell of `oqty` (percentage of `o100`), colored by the thresholds if any
+    pub fn style_by_thresholds<'a>(
+        &self,
+        k: &[String],
+        oqty: &Option<Qty>,
+        o100: &Option<Qty>,
+        style: &'a str,
+    ) -> std::borrow::Cow<'a, str> {
+        let color = self
+            .color_thresholds
+            .as_ref()
+            .zip(self.kind_of(k))
+            .zip(oqty.as_ref().zip(o100.as_ref()))
+            .and_then(|((thresholds, kind), (qty, q100))| {
+                thresholds.color_of(kind, qty.calc_percentage(q100))
+            });
+        match color {
+            Some(color) => format!("r{}", color).into(),
+            None => style.into(),
+        }
+    }
+}
+
+/// Percentages (of allocatable) from which a quantity is colored as warning (yellow) or critical (red)
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Thresholds {
+    pub warn: f64,
+    pub crit: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            warn: 80.0,
+            crit: 95.0,
+        }
+    }
+}
+
+/// The thresholds by kind (eg a gpu used at 100% is the goal, not an alarm),
+/// `default` for the other kinds
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ThresholdsConfig {
+    #[serde(default)]
+    pub default: Thresholds,
+    #[serde(default)]
+    pub kinds: BTreeMap<String, Thresholds>,
+}
+
+impl ThresholdsConfig {
+    pub fn read(path: &std::path::Path) -> Result<Self, Error> {
+        let content = std::fs::read(path).map_err(|source| Error::ReadFileError {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        serde_json::from_slice(&content).map_err(|source| Error::ConfigError {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    pub fn for_kind(&self, kind: &str) -> &Thresholds {
+        self.kinds.get(kind).unwrap_or(&self.default)
+    }
+
+    /// the color (prettytable style) of the percentage, `None` below the warning
+    pub fn color_of(&self, kind: &str, percentage: f64) -> Option<&'static str> {
+        let thresholds = self.for_kind(kind);
+        if percentage >= thresholds.crit {
+            Some("Fr")
+        } else if percentage >= thresholds.warn {
+            Some("Fy")
+        } else {
+            None
+        }
+    }
+
+    /// the legend of the colors, with the thresholds of every kind
+    pub fn legend(&self) -> String {
+        let format = |name: &str, t: &Thresholds| format!("{} {}%/{}%", name, t.warn, t.crit);
+        let kinds = std::iter::once(format("default", &self.default))
+            .chain(self.kinds.iter().map(|(kind, t)| format(kind, t)))
+            .join(", ");
+        format!(
+            "% of allocatable in yellow from the warning, in red from the critical threshold (warn/crit): {}",
+            kinds
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[tokio::test]
+    async fn test_write_split_by_node() {
+        let pods = make_pod_list(vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n2", vec![make_container(&[("cpu", "2")], &[])]),
+            make_pod("p3", "n2", vec![make_container(&[("cpu", "3")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("kva-split-{}", std::process::id()));
+        let cli_opts = CliOpts::parse_from([
+            "kubectl-view-allocations",
+            "-o",
+            "json",
+            "-g",
+            "resource",
+            "-g",
+            "node",
+            "-g",
+            "pod",
+            "-r",
+            "cpu",
+        ]);
+        let display_opts = DisplayOpts {
+            group_by: cli_opts.group_by.clone(),
+            ..DisplayOpts::default()
+        };
+        write_split_by_node(&resources, &cli_opts, &display_opts, &dir).unwrap();
+        let read = |node: &str| -> serde_json::Value {
+            serde_json::from_slice(&std::fs::read(dir.join(format!("{}.json", node))).unwrap())
+                .unwrap()
+        };
+        let n2 = read("n2");
+        let items = n2["items"].as_array().unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0]["requested_base"], 5000);
+        assert!(items
+            .iter()
+            .all(|i| i["node"].is_null() || i["node"] == "n2"));
+        assert_eq!(read("n1")["items"][0]["requested_base"], 1000);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_banner() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "8", "memory": "16Gi" }),
+        )]);
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("cpu", "5"), ("memory", "4Gi")], &[])],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let group_by = vec![GroupBy::resource, GroupBy::node];
+        let opts = DisplayOpts {
+            group_by: group_by.clone(),
+            kind_aliases: vec![("memory".to_string(), "mem".to_string())],
+            ..DisplayOpts::default()
+        };
+        let data = make_qualifiers(
+            &resources,
+            &group_by,
+            &["cpu".to_string(), "memory".to_string()],
+        );
+        assert_eq!(
+            make_banner(&data, &opts),
+            "cpu: 63% requested (3.0 free of 8.0), mem: 25% (12.0Gi free of 16.0Gi)"
+        );
+        // no kind with allocatable
+        assert_eq!(make_banner(&data[1..2], &opts), "");
+    }
+
+    #[test]
+    fn test_format_free_with_pct() {
+        let opts = DisplayOpts::default();
+        let qtys = QtyByQualifier {
+            requested: Some(Qty::from_str("4.8Gi").unwrap()),
+            allocatable: Some(Qty::from_str("8Gi").unwrap()),
+            ..QtyByQualifier::default()
+        };
+        assert_eq!(opts.format_free_with_pct(&qtys), "3.2Gi (40%)");
+        let opts = DisplayOpts {
+            percent_decimals: 1,
+            ..DisplayOpts::default()
+        };
+        assert_eq!(opts.format_free_with_pct(&qtys), "3.2Gi (40.0%)");
+        assert_eq!(opts.format_free_with_pct(&QtyByQualifier::default()), "__");
+    }
+
+    #[test]
+    fn test_format_percentage() {
+        let opts = DisplayOpts {
+            percent_decimals: 1,
+            ..DisplayOpts::default()
+        };
+        assert_eq!(opts.format_percentage(89.6), "89.6");
+        assert_eq!(opts.format_percentage(90.44), "90.4");
+        assert_eq!(opts.format_percentage(90.45), "90.5");
+        assert_eq!(opts.format_percentage(100.0), "100.0");
+        let opts = DisplayOpts {
+            percent_decimals: 0,
+            ..DisplayOpts::default()
+        };
+        assert_eq!(opts.format_percentage(89.6), "90");
+        assert_eq!(opts.format_percentage(0.5), "1");
+        assert_eq!(opts.format_percentage(2.5), "3");
+        // the boundaries
+        assert_eq!(opts.format_percentage(99.4), "99");
+        assert_eq!(opts.format_percentage(99.6), "99");
+        assert_eq!(opts.format_percentage(99.99), "99");
+        assert_eq!(opts.format_percentage(100.0), "100");
+        assert_eq!(opts.format_percentage(100.4), "100");
+        assert_eq!(opts.format_percentage(0.4), "1");
+        assert_eq!(opts.format_percentage(0.0), "0");
+        let opts = DisplayOpts {
+            percent_decimals: 1,
+            ..DisplayOpts::default()
+        };
+        assert_eq!(opts.format_percentage(99.96), "99.9");
+        assert_eq!(opts.format_percentage(0.04), "0.1");
+        assert_eq!(opts.format_percentage(0.0), "0.0");
+    }
+
+    #[test]
+    fn test_group_separator() {
+        let cli_opts = CliOpts::parse_from(["test"]);
+        assert_eq!(cli_opts.group_separator, " / ");
+        let cli_opts = CliOpts::parse_from(["test", "--group-separator", "::"]);
+        let k = ["nvidia.com/gpu".to_string(), "n1".to_string()];
+        assert_eq!(k.join(&cli_opts.group_separator), "nvidia.com/gpu::n1");
+    }
+
+    #[test]
+    fn test_display_key_with_kind_aliases() {
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node],
+            kind_aliases: vec![("nvidia.com/gpu".to_string(), "gpu".to_string())],
+            ..DisplayOpts::default()
+        };
+        let k = vec!["nvidia.com/gpu".to_string(), "nvidia.com/gpu".to_string()];
+        assert_eq!(opts.display_key(&k, 0), "gpu");
+        // only the resource part of the key is renamed
+        assert_eq!(opts.display_key(&k, 1), "nvidia.com/gpu");
+        assert_eq!(opts.display_key(&["cpu".to_string()], 0), "cpu");
+    }
+
+    #[test]
+    fn test_thresholds_config() {
+        let config: ThresholdsConfig = serde_json::from_value(serde_json::json!({
+            "kinds": { "nvidia.com/gpu": { "warn": 101, "crit": 101 } }
+        }))
+        .unwrap();
+        assert_eq!(config.default, Thresholds::default());
+        assert_eq!(config.color_of("cpu", 79.9), None);
+        assert_eq!(config.color_of("cpu", 80.0), Some("Fy"));
+        assert_eq!(config.color_of("cpu", 96.0), Some("Fr"));
+        assert_eq!(config.color_of("nvidia.com/gpu", 100.0), None);
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource, GroupBy::node],
+            color_thresholds: Some(config),
+            ..DisplayOpts::default()
+        };
+        let key = vec!["cpu".to_string(), "n1".to_string()];
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap());
+        assert_eq!(
+            opts.style_by_thresholds(&key, &qty("3.9"), &qty("4"), "rFg"),
+            "rFr"
+        );
+        assert_eq!(
+            opts.style_by_thresholds(&key, &qty("1"), &qty("4"), "rFg"),
+            "rFg"
+        );
+        assert_eq!(
+            opts.style_by_thresholds(&key, &None, &qty("4"), "rFg"),
+            "rFg"
+        );
+        assert!(serde_json::from_str::<ThresholdsConfig>(r#"{"default": {"warn": 1}}"#).is_err());
+    }
+}
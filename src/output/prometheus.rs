@@ -0,0 +1,96 @@
+//! `--output prometheus` (text format), also pushed by `--push-gateway`
+
+use crate::*;
+
+/// the quantities of a row as metrics (influx & prometheus), by name, into the unit of the kind (eg cores, bytes)
+pub(crate) fn metric_fields(qtys: &QtyByQualifier, opts: &DisplayOpts) -> Vec<(&'static str, f64)> {
+    let mut fields = vec![];
+    let mut field = |name: &'static str, oqty: Option<Qty>| {
+        if let Some(qty) = oqty {
+            // divided (not multiplied by 0.001 like `f64::from`) to print `7.1` not `7.1000000000000005`
+            fields.push((name, qty.value as f64 / 1000.0));
+        }
+    };
+    if opts.shows(Usage::utilization) {
+        field("utilization", qtys.utilization.clone());
+    }
+    if opts.shows(Usage::requested) {
+        field("requested", qtys.requested.clone());
+    }
+    if opts.shows(Usage::limit) && !(opts.unbounded_limit && qtys.no_limit) {
+        field("limit", qtys.limit.clone());
+    }
+    if opts.shows(Usage::allocatable) {
+        field("allocatable", qtys.allocatable.clone());
+    }
+    if opts.show_reserved {
+        field("reserved", qtys.calc_reserved());
+    }
+    if opts.shows(Usage::free) {
+        field("free", qtys.calc_free());
+    }
+    fields
+}
+
+/// Write the rows in the text format of prometheus (eg for a Pushgateway, see `--push-gateway`), a gauge
+/// `kube_allocations_<field>` by field (like the fields of influx) with the groups as labels
+pub fn write_as_prometheus(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    // the samples of a metric are contiguous, after its type
+    let mut metrics: Vec<(&str, Vec<String>)> = vec![];
+    for (k, qtys) in data
+        .iter()
+        .filter_map(|(k, oqtys)| oqtys.as_ref().map(|qtys| (k, qtys)))
+    {
+        let labels = opts
+            .group_by
+            .iter()
+            .enumerate()
+            .take(k.len())
+            .map(|(i, g)| {
+                format!(
+                    "{}=\"{}\"",
+                    prometheus_label_name(&g.to_string()),
+                    escape_prometheus_value(opts.display_key(k, i))
+                )
+            })
+            .join(",");
+        for (name, value) in metric_fields(qtys, opts) {
+            let sample = format!("{}_{}{{{}}} {}", INFLUX_MEASUREMENT, name, labels, value);
+            match metrics.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, samples)) => samples.push(sample),
+                None => metrics.push((name, vec![sample])),
+            }
+        }
+    }
+    for (name, samples) in metrics {
+        writeln!(out, "# TYPE {}_{} gauge", INFLUX_MEASUREMENT, name)?;
+        for sample in samples {
+            writeln!(out, "{}", sample)?;
+        }
+    }
+    Ok(())
+}
+
+/// a valid name of label (`[a-zA-Z_][a-zA-Z0-9_]*`), eg `topology_kubernetes_io_zone` for a node label
+fn prometheus_label_name(s: &str) -> String {
+    let name = s
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// escape the backslashes, double quotes & line feeds of the value of a label
+fn escape_prometheus_value(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
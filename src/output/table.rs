@@ -0,0 +1,1435 @@
+//! the tables (`--output table` & `plain`) rendered with prettytable
+
+use crate::*;
+
+/// print the table into `out`, with its styles (colors, bold) if `colored` and the terminal supports them
+#[cfg(feature = "prettytable")]
+fn print_table(table: &Table, out: &mut dyn Write, colored: bool) -> Result<(), Error> {
+    if colored {
+        if let Some(mut terminal) = term::TerminfoTerminal::new(&mut *out) {
+            table.print_term(&mut terminal)?;
+            return Ok(());
+        }
+    }
+    table.print(out)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "prettytable"))]
+pub fn display_with_prettytable(
+    _data: &[(Vec<String>, Option<QtyByQualifier>)],
+    _opts: &DisplayOpts,
+    _out: &mut dyn Write,
+) -> Result<(), Error> {
+    warn!("feature 'prettytable' not enabled");
+    Ok(())
+}
+
+#[cfg(feature = "prettytable")]
+pub fn display_with_prettytable(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let table = make_prettytable(data, &opts.for_output(&Output::table));
+    write_header(opts, out)?;
+    print_table(&table, out, opts.colored)?;
+    if let Some(thresholds) = opts.color_thresholds.as_ref().filter(|_| !opts.quiet) {
+        writeln!(out, "{}", thresholds.legend())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "prettytable"))]
+pub fn write_with_prettytable(
+    _data: &[(Vec<String>, Option<QtyByQualifier>)],
+    _opts: &DisplayOpts,
+    _out: &mut dyn Write,
+) -> Result<(), Error> {
+    warn!("feature 'prettytable' not enabled");
+    Ok(())
+}
+
+/// the lines above the table: the cluster and the size of the fleet (if any)
+#[cfg(feature = "prettytable")]
+fn write_header(opts: &DisplayOpts, out: &mut dyn Write) -> Result<(), Error> {
+    if opts.quiet {
+        return Ok(());
+    }
+    if let Some(cluster) = &opts.cluster {
+        writeln!(out, "{}", cluster)?;
+    }
+    if let Some(fleet) = &opts.fleet {
+        writeln!(out, "{}", fleet)?;
+    }
+    Ok(())
+}
+
+/// like `display_with_prettytable` but without the styles & the legend (eg into a file)
+#[cfg(feature = "prettytable")]
+pub fn write_with_prettytable(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let table = make_prettytable(data, &opts.for_output(&Output::table));
+    write_header(opts, out)?;
+    print_table(&table, out, false)
+}
+
+#[cfg(not(feature = "prettytable"))]
+pub fn write_as_plain(
+    _data: &[(Vec<String>, Option<QtyByQualifier>)],
+    _opts: &DisplayOpts,
+    _out: &mut dyn Write,
+) -> Result<(), Error> {
+    warn!("feature 'prettytable' not enabled");
+    Ok(())
+}
+
+/// the rows of the table, with the columns separated by a space (no padding, border, separator nor style)
+#[cfg(feature = "prettytable")]
+pub fn write_as_plain(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let opts = DisplayOpts {
+        separators: false,
+        ..opts.for_output(&Output::plain).into_owned()
+    };
+    let mut table = make_prettytable(data, &opts);
+    table.set_format(format::FormatBuilder::new().column_separator(' ').build());
+    let mut buffer = vec![];
+    table.print(&mut buffer)?;
+    // the last column is padded too
+    for line in String::from_utf8_lossy(&buffer).lines() {
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "prettytable"))]
+pub fn display_efficiency_with_prettytable(
+    _rows: &[EfficiencyRow],
+    _opts: &DisplayOpts,
+    _out: &mut dyn Write,
+) -> Result<(), Error> {
+    warn!("feature 'prettytable' not enabled");
+    Ok(())
+}
+
+#[cfg(feature = "prettytable")]
+pub fn display_efficiency_with_prettytable(
+    rows: &[EfficiencyRow],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let mut table = new_prettytable();
+    table.set_titles(row![bl->"Namespace", bl->"Pod", bl->"Container", bl->"Resource", br->"Requested", br->"Utilization", br->"Efficiency", br->"Waste", bl->"Provisioned"]);
+    let fmt = |q: &Qty| format!("{}", q.adjust_scale());
+    for row in rows {
+        let style = match row.provisioning() {
+            "over" => "Fy",
+            "under" => "Fr",
+            _ => "",
+        };
+        table.add_row(Row::new(vec![
+            Cell::new(&row.namespace),
+            Cell::new(&row.pod),
+            Cell::new(&row.container),
+            Cell::new(&row.kind),
+            Cell::new(&fmt(&row.requested)).style_spec("r"),
+            Cell::new(&fmt(&row.utilization)).style_spec("r"),
+            Cell::new(
+                &row.efficiency_pct()
+                    .map(|pct| format!("{}%", opts.format_percentage(pct)))
+                    .unwrap_or_else(|| "__".to_string()),
+            )
+            .style_spec(&format!("r{}", style)),
+            Cell::new(&fmt(&row.waste())).style_spec("r"),
+            Cell::new(row.provisioning()).style_spec(style),
+        ]));
+    }
+    print_table(&table, out, opts.colored)
+}
+
+#[cfg(not(feature = "prettytable"))]
+pub fn display_scores_with_prettytable(
+    _scores: &[NodeScore],
+    _opts: &DisplayOpts,
+    _out: &mut dyn Write,
+) -> Result<(), Error> {
+    warn!("feature 'prettytable' not enabled");
+    Ok(())
+}
+
+#[cfg(feature = "prettytable")]
+pub fn display_scores_with_prettytable(
+    scores: &[NodeScore],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let mut table = new_prettytable();
+    table.set_titles(row![bl->"Node", br->"%cpu", br->"%memory", br->"Score"]);
+    let fmt = |pct: Option<f64>| {
+        pct.map(|pct| format!("{}%", opts.format_percentage(pct)))
+            .unwrap_or_else(|| "__".to_string())
+    };
+    for score in scores {
+        table.add_row(Row::new(vec![
+            Cell::new(&score.node),
+            Cell::new(&fmt(score.cpu_pct)).style_spec("r"),
+            Cell::new(&fmt(score.memory_pct)).style_spec("r"),
+            Cell::new(&fmt(Some(score.score))).style_spec("r"),
+        ]));
+    }
+    print_table(&table, out, opts.colored)
+}
+
+#[cfg(not(feature = "prettytable"))]
+pub fn display_quotas_with_prettytable(
+    _rows: &[QuotaRow],
+    _opts: &DisplayOpts,
+    _out: &mut dyn Write,
+) -> Result<(), Error> {
+    warn!("feature 'prettytable' not enabled");
+    Ok(())
+}
+
+#[cfg(feature = "prettytable")]
+pub fn display_quotas_with_prettytable(
+    rows: &[QuotaRow],
+    opts: &DisplayOpts,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let mut table = new_prettytable();
+    table.set_titles(row![bl->"Namespace", bl->"Resource", br->"Requested", br->"Quota", br->"Quota Free", br->"Cluster Free", bl->"Limited By"]);
+    let fmt = |q: &Qty| format!("{}", q.adjust_scale());
+    for row in rows {
+        table.add_row(Row::new(vec![
+            Cell::new(&row.namespace),
+            Cell::new(&row.kind),
+            Cell::new(&fmt(&row.requested)).style_spec("r"),
+            Cell::new(&fmt(&row.hard)).style_spec("r"),
+            Cell::new(&fmt(&row.quota_free())).style_spec("r"),
+            Cell::new(
+                &row.cluster_free
+                    .as_ref()
+                    .map(fmt)
+                    .unwrap_or_else(|| "__".to_string()),
+            )
+            .style_spec("r"),
+            Cell::new(row.limited_by()),
+        ]));
+    }
+    print_table(&table, out, opts.colored)
+}
+
+#[cfg(not(feature = "prettytable"))]
+pub fn display_delta_with_prettytable(
+    _data: &[(Vec<String>, Option<QtyByQualifier>)],
+    _opts: &DisplayOpts,
+    _baseline: &Baseline,
+    _out: &mut dyn Write,
+) -> Result<(), Error> {
+    warn!("feature 'prettytable' not enabled");
+    Ok(())
+}
+
+#[cfg(feature = "prettytable")]
+pub fn display_delta_with_prettytable(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    baseline: &Baseline,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let table = make_delta_prettytable(data, opts, baseline);
+    write_header(opts, out)?;
+    writeln!(out, "Delta since the baseline of {}", baseline.date)?;
+    print_table(&table, out, opts.colored)
+}
+
+/// the table of the deltas of the rows from the baseline (the rows not in the baseline are new, from zero)
+#[cfg(feature = "prettytable")]
+pub fn make_delta_prettytable(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    baseline: &Baseline,
+) -> Table {
+    let mut table = new_prettytable();
+    let mut row_titles = row![bl->"Resource", br->"Utilization", br->"Requested", br->"Limit", br->"Allocatable", br->"Free"];
+    if !opts.show_utilization {
+        row_titles.remove_cell(1);
+    }
+    table.set_titles(row_titles);
+    let data2 = data
+        .iter()
+        .filter(|(_, oqtys)| oqtys.is_some())
+        .collect::<Vec<_>>();
+    let current = make_json_output(data, opts).items;
+    let prefixes = tree::provide_prefix_with(&data2, &opts.tree_glyphs, |parent, item| {
+        parent.0.len() + 1 == item.0.len()
+    });
+    for (((k, _), row), prefix) in data2.iter().zip(current.iter()).zip(prefixes.iter()) {
+        let family = UnitFamily::from_kind(opts.kind_of(k).unwrap_or_default());
+        let base = baseline.get(row).cloned().unwrap_or_default();
+        let cell = |current: Option<i128>, base: Option<i128>| {
+            make_delta_cell_for_prettytable(current, base, family, opts)
+        };
+        let column0 = format!(
+            "{} {}",
+            prefix,
+            k.len()
+                .checked_sub(1)
+                .map(|depth| opts.display_key(k, depth))
+                .unwrap_or("???")
+        );
+        let mut cells = vec![
+            Cell::new(&column0),
+            cell(row.utilization_base, base.utilization_base),
+            cell(row.requested_base, base.requested_base),
+            cell(row.limit_base, base.limit_base),
+            cell(row.allocatable_base, base.allocatable_base),
+            cell(row.free_base, base.free_base),
+        ];
+        if !opts.show_utilization {
+            cells.remove(1);
+        }
+        table.add_row(Row::new(cells));
+    }
+    table
+}
+
+#[cfg(feature = "prettytable")]
+fn make_delta_cell_for_prettytable(
+    current: Option<i128>,
+    base: Option<i128>,
+    family: UnitFamily,
+    opts: &DisplayOpts,
+) -> Cell {
+    if current.is_none() && base.is_none() {
+        return Cell::new("__").style_spec("r");
+    }
+    let delta = current.unwrap_or_default() - base.unwrap_or_default();
+    if delta == 0 {
+        return Cell::new("=").style_spec("r");
+    }
+    let qty = Qty::from_base_value(delta, family);
+    Cell::new(&opts.format_signed_qty(&qty)).style_spec("rFy")
+}
+
+#[cfg(feature = "prettytable")]
+fn new_prettytable() -> Table {
+    let mut table = Table::new();
+    let format = format::FormatBuilder::new()
+        // .column_separator('|')
+        // .borders('|')
+        // .separators(&[format::LinePosition::Top,
+        //               format::LinePosition::Bottom],
+        //             format::LineSeparator::new('-', '+', '+', '+'))
+        .separators(&[], format::LineSeparator::new('-', '+', '+', '+'))
+        .padding(1, 1)
+        .build();
+    table.set_format(format);
+    table
+}
+
+/// the width of the part after the decimal point of the last number of `content` (but a trailing percentage,
+/// eg "1.0Gi (12%)"): its decimals, its suffix & the rest (eg 4 for "1.5Gi", 10 for "1.0Gi (12%)", 2 for "250m",
+/// 0 for "32"), none without number (eg "__")
+pub fn decimal_tail_width(content: &str) -> Option<usize> {
+    let quantity = match content.trim_end().strip_suffix("%)") {
+        Some(rest) => match rest.rfind('(') {
+            Some(open) => content[..open].trim_end(),
+            None => content,
+        },
+        None => content,
+    };
+    let number = quantity.trim_end_matches(|c: char| !c.is_ascii_digit());
+    if number.is_empty() {
+        return None;
+    }
+    let integer = number.trim_end_matches(|c: char| c.is_ascii_digit());
+    let point = match integer.strip_suffix('.') {
+        Some(before) => before.len(),
+        None => number.len(),
+    };
+    Some(content[point..].chars().count())
+}
+
+/// the table of the rows, the quantities of a column (right-justified) are padded on their right to line up
+/// by their decimal point (eg "250.0m", "  1.5 " & " 32.0 "), whatever their suffix & their decimals
+#[cfg(feature = "prettytable")]
+pub fn make_prettytable(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+) -> Table {
+    // first pass: the widest tail (see `decimal_tail_width`) of every column
+    let mut tails = HashMap::new();
+    make_prettytable_with_tails(data, opts, &HashMap::new(), &mut tails);
+    make_prettytable_with_tails(data, opts, &tails, &mut HashMap::new())
+}
+
+/// like `make_prettytable`, the quantities padded to the `tails` by column (before the removal of the hidden),
+/// the tails of the quantities are collected into `seen`
+#[cfg(feature = "prettytable")]
+fn make_prettytable_with_tails(
+    data: &[(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+    tails: &HashMap<usize, usize>,
+    seen: &mut HashMap<usize, usize>,
+) -> Table {
+    let seen = std::cell::RefCell::new(seen);
+    let pad = |column: usize, cell: Cell| {
+        let content = cell.get_content();
+        if content.is_empty() {
+            return cell;
+        }
+        // the cells without number (eg "__", "∞") end where the integer parts end
+        let tail = decimal_tail_width(&content).unwrap_or_default();
+        let mut seen = seen.borrow_mut();
+        let widest = seen.entry(column).or_default();
+        *widest = tail.max(*widest);
+        let width = tails.get(&column).copied().unwrap_or_default();
+        Cell::new(&format!(
+            "{}{}",
+            content,
+            " ".repeat(width.saturating_sub(tail))
+        ))
+    };
+    // Create the table
+    let mut table = new_prettytable();
+    let mut row_titles = row![bl->"Resource", br->"Nodes", br->"Pods", br->"Utilization", br->"Requested", bl->"%Requested", br->"Limit", br->"Ratio", br->"Allocatable", br->"Reserved", br->"Free", br->"Age", br->"Pending", br->"Min Free/Node", br->"Max Free/Node"];
+    if opts.combines_req_limit() {
+        row_titles
+            .set_cell(Cell::new("Requested / Limit").style_spec("br"), 4)
+            .expect("the column of the requested");
+    }
+    remove_hidden_cells(&mut row_titles, opts);
+    insert_qos_split_cells(
+        &mut row_titles,
+        opts,
+        [
+            Cell::new("Guaranteed").style_spec("br"),
+            Cell::new("Burstable").style_spec("br"),
+        ],
+    );
+    table.set_titles(row_titles.clone());
+    let data2 = data
+        .iter()
+        .filter(|d| {
+            !opts.filter_full_zero
+                || !d
+                    .1
+                    .as_ref()
+                    .map(|x| {
+                        x.utilization.is_none()
+                            && is_empty(&x.requested)
+                            && is_empty(&x.limit)
+                            && is_empty(&x.allocatable)
+                    })
+                    .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+    let data2 = if opts.flatten_single_child {
+        flatten_single_child(data2)
+    } else {
+        data2
+    };
+    // the parent of a row is the previous row with a prefix of its key (not always one key shorter,
+    // see `--flatten-single-child`)
+    let prefixes = tree::provide_prefix_with(&data2, &opts.tree_glyphs, |parent, item| {
+        item.0.len() > parent.0.len() && item.0.starts_with(&parent.0)
+    });
+    let starts = first_displayed_depths(&data2);
+    // first pass: the prefix of every column (by kind), before to format the cells
+    let scales = if opts.align_units {
+        aligned_scales(&data2, opts)
+    } else {
+        HashMap::new()
+    };
+
+    // the indexes of the rows starting a top-level group (except the first one), for `--separators`
+    let mut group_starts = vec![];
+    for (((k, oqtys), prefix), start) in data2.iter().zip(prefixes.iter()).zip(starts) {
+        let displayed = oqtys.is_some() || opts.kind_of(k).is_none();
+        if opts.separators && start == 0 && displayed && !table.is_empty() {
+            group_starts.push(table.len());
+        }
+        let opts: &DisplayOpts = &opts.for_kind(k);
+        let scale_of = |column: usize| {
+            opts.kind_of(k)
+                .and_then(|kind| scales.get(&(kind.to_string(), column)))
+        };
+        // blank on the rows under the kinds (like the pending)
+        let spread_cell = |column: usize, oqty: Option<&Qty>| {
+            oqty.map(|qty| {
+                pad(
+                    column,
+                    make_cell_for_prettytable(&Some(qty.clone()), &None, opts, scale_of(10)),
+                )
+            })
+            .unwrap_or_else(|| Cell::new(""))
+            .style_spec("r")
+        };
+        let column0 = format!(
+            "{} {}",
+            prefix,
+            if k.is_empty() {
+                "???".to_string()
+            } else {
+                (start..k.len())
+                    .map(|depth| opts.display_key(k, depth))
+                    .join(&opts.group_separator)
+            }
+        );
+        if let Some(qtys) = oqtys {
+            let style = if qtys.requested > qtys.limit
+                || qtys.utilization > qtys.limit
+                || is_empty(&qtys.requested)
+                || is_empty(&qtys.limit)
+            {
+                "rFy"
+            } else {
+                "rFg"
+            };
+            let base = qtys.calc_percent_base();
+            let mut row = Row::new(vec![
+                Cell::new(&column0),
+                Cell::new(&qtys.nodes.to_string()).style_spec("r"),
+                Cell::new(&qtys.pods.to_string()).style_spec("r"),
+                pad(
+                    3,
+                    make_cell_for_prettytable(&qtys.utilization, &base, opts, scale_of(3)),
+                )
+                .style_spec(&opts.style_by_thresholds(
+                    k,
+                    &qtys.utilization,
+                    &base,
+                    style,
+                )),
+                pad(
+                    4,
+                    if opts.combines_req_limit() {
+                        Cell::new(&opts.format_req_limit_in(qtys, scale_of(4), scale_of(6)))
+                    } else {
+                        make_cell_for_prettytable(&qtys.requested, &base, opts, scale_of(4))
+                    },
+                )
+                .style_spec(&opts.style_by_thresholds(
+                    k,
+                    &qtys.requested,
+                    &base,
+                    style,
+                )),
+                Cell::new(
+                    &qtys
+                        .requested
+                        .as_ref()
+                        .zip(base.as_ref())
+                        .map(|(requested, base)| make_bar(requested.calc_percentage(base), 10))
+                        .unwrap_or_default(),
+                )
+                // the bars have the same width, the alignment doesn't matter
+                .style_spec(&opts.style_by_thresholds(
+                    k,
+                    &qtys.requested,
+                    &base,
+                    style,
+                )),
+                pad(
+                    6,
+                    if opts.unbounded_limit && qtys.no_limit {
+                        Cell::new("∞")
+                    } else {
+                        make_cell_for_prettytable(&qtys.limit, &base, opts, scale_of(6))
+                    },
+                )
+                .style_spec(style),
+                Cell::new(
+                    &qtys
+                        .calc_request_limit_ratio()
+                        .map(|r| format!("{:.2}", r))
+                        .unwrap_or_else(|| "__".to_string()),
+                )
+                .style_spec(style),
+                pad(
+                    8,
+                    make_cell_for_prettytable(&qtys.allocatable, &None, opts, scale_of(8)),
+                )
+                .style_spec(style),
+                pad(
+                    9,
+                    make_cell_for_prettytable(
+                        &qtys.calc_reserved(),
+                        &qtys.capacity,
+                        opts,
+                        scale_of(9),
+                    ),
+                )
+                .style_spec(style),
+                pad(
+                    10,
+                    if opts.is_plugin_not_ready(k) {
+                        // not a lack of free, the allocatable is not yet known
+                        Cell::new("plugin not ready")
+                    } else if opts.free_with_pct {
+                        Cell::new(&opts.format_free_with_pct_in(qtys, scale_of(10)))
+                    } else {
+                        make_cell_for_prettytable(&qtys.calc_free(), &None, opts, scale_of(10))
+                    },
+                )
+                .style_spec(
+                    if free_below_threshold(k, qtys, &opts.group_by, &opts.free_thresholds)
+                        .is_some()
+                    {
+                        "rFr"
+                    } else {
+                        style
+                    },
+                ),
+                Cell::new(
+                    &qtys
+                        .node_created
+                        .filter(|_| opts.is_node_row(k))
+                        .map(|created| format_age(opts.now() - created))
+                        .unwrap_or_default(),
+                )
+                .style_spec("r"),
+                Cell::new(
+                    &opts
+                        .pending_of(k)
+                        .map(|n| n.to_string())
+                        .unwrap_or_default(),
+                )
+                .style_spec(if opts.pending_of(k).unwrap_or_default() > 0 {
+                    "rFr"
+                } else {
+                    "r"
+                }),
+                spread_cell(13, opts.free_spread_of(k).map(|(min, _)| min)),
+                spread_cell(14, opts.free_spread_of(k).map(|(_, max)| max)),
+            ]);
+            remove_hidden_cells(&mut row, opts);
+            insert_qos_split_cells(
+                &mut row,
+                opts,
+                [
+                    pad(
+                        15,
+                        make_cell_for_prettytable(
+                            &qtys.calc_requested_guaranteed(),
+                            &base,
+                            opts,
+                            scale_of(4),
+                        ),
+                    )
+                    .style_spec(style),
+                    pad(
+                        16,
+                        make_cell_for_prettytable(
+                            &qtys.calc_requested_burstable(),
+                            &base,
+                            opts,
+                            scale_of(4),
+                        ),
+                    )
+                    .style_spec(style),
+                ],
+            );
+            table.add_row(row);
+        } else if opts.kind_of(k).is_none() {
+            // the heading of a group above the kinds (eg a node with `--node-first`), its kinds can't be summed
+            let mut row = Row::new(
+                std::iter::once(Cell::new(&column0))
+                    .chain((1..15).map(|_| Cell::new("")))
+                    .collect(),
+            );
+            remove_hidden_cells(&mut row, opts);
+            insert_qos_split_cells(&mut row, opts, [Cell::new(""), Cell::new("")]);
+            table.add_row(row);
+        }
+    }
+    if !group_starts.is_empty() {
+        insert_separators(
+            &mut table,
+            &row_titles,
+            &group_starts,
+            opts.tree_glyphs.rule(),
+        );
+    }
+    table
+}
+
+/// Remove the rows with exactly one child and the same sums (eg not a node with one pod, the allocatable is
+/// only on the node), the child is displayed with the keys of the removed rows (see `--flatten-single-child`)
+#[cfg(feature = "prettytable")]
+fn flatten_single_child(
+    rows: Vec<&(Vec<String>, Option<QtyByQualifier>)>,
+) -> Vec<&(Vec<String>, Option<QtyByQualifier>)> {
+    let single_child = |i: usize| {
+        let k = &rows[i].0;
+        let mut children = rows[i + 1..]
+            .iter()
+            .take_while(|(c, _)| c.len() > k.len() && c.starts_with(k))
+            .filter(|(c, _)| c.len() == k.len() + 1);
+        match (children.next(), children.next()) {
+            (Some(child), None) => Some(child),
+            _ => None,
+        }
+    };
+    (0..rows.len())
+        .filter(|i| {
+            !single_child(*i)
+                .map(|(_, child)| same_sums(&rows[*i].1, child))
+                .unwrap_or_default()
+        })
+        .map(|i| rows[i])
+        .collect()
+}
+
+/// the displayed sums of both are equal (whatever the scales of their quantities)
+#[cfg(feature = "prettytable")]
+fn same_sums(lhs: &Option<QtyByQualifier>, rhs: &Option<QtyByQualifier>) -> bool {
+    let same = |l: &Option<Qty>, r: &Option<Qty>| match (l, r) {
+        (Some(l), Some(r)) => l.cmp(r) == std::cmp::Ordering::Equal,
+        (l, r) => l.is_none() && r.is_none(),
+    };
+    match (lhs, rhs) {
+        (Some(l), Some(r)) => {
+            same(&l.limit, &r.limit)
+                && same(&l.requested, &r.requested)
+                && same(&l.requested_guaranteed, &r.requested_guaranteed)
+                && same(&l.allocatable, &r.allocatable)
+                && same(&l.capacity, &r.capacity)
+                && same(&l.utilization, &r.utilization)
+                && same(&l.headroom, &r.headroom)
+                && l.no_limit == r.no_limit
+                && l.nodes == r.nodes
+                && l.pods == r.pods
+        }
+        (l, r) => l.is_none() && r.is_none(),
+    }
+}
+
+/// the depth of the first key displayed by row: the one after the key of its parent (the previous row
+/// with a prefix of its key), 0 for the roots
+#[cfg(feature = "prettytable")]
+fn first_displayed_depths(rows: &[&(Vec<String>, Option<QtyByQualifier>)]) -> Vec<usize> {
+    let mut parents: Vec<&[String]> = vec![];
+    rows.iter()
+        .map(|(k, _)| {
+            while let Some(parent) = parents.last() {
+                if k.len() > parent.len() && k.starts_with(parent) {
+                    break;
+                }
+                parents.pop();
+            }
+            let start = parents.last().map(|p| p.len()).unwrap_or_default();
+            parents.push(k);
+            start
+        })
+        .collect()
+}
+
+/// insert a (dimmed) rule before the rows at `indexes`, as wide as every column (and its title)
+#[cfg(feature = "prettytable")]
+fn insert_separators(table: &mut Table, titles: &Row, indexes: &[usize], rule: char) {
+    let mut widths: Vec<usize> = vec![];
+    for row in std::iter::once(titles).chain(table.row_iter()) {
+        for (i, cell) in row.iter().enumerate() {
+            let width = cell
+                .get_content()
+                .lines()
+                .map(|l| l.chars().count())
+                .max()
+                .unwrap_or_default();
+            match widths.get_mut(i) {
+                Some(w) => *w = std::cmp::max(*w, width),
+                None => widths.push(width),
+            }
+        }
+    }
+    for index in indexes.iter().rev() {
+        let cells = widths
+            .iter()
+            .map(|w| Cell::new(&rule.to_string().repeat(*w)).with_style(Attr::Dim))
+            .collect();
+        table.insert_row(*index, Row::new(cells));
+    }
+}
+
+/// the common prefix of the quantities by (kind, index of the column in the table), see `--align-units`
+#[cfg(feature = "prettytable")]
+fn aligned_scales(
+    data: &[&(Vec<String>, Option<QtyByQualifier>)],
+    opts: &DisplayOpts,
+) -> HashMap<(String, usize), Scale> {
+    let mut columns: HashMap<(String, usize), Vec<Qty>> = HashMap::new();
+    for (k, oqtys) in data {
+        let (kind, qtys) = match (opts.kind_of(k), oqtys) {
+            (Some(kind), Some(qtys)) => (kind, qtys),
+            _ => continue,
+        };
+        for (column, oqty) in [
+            (3, qtys.utilization.clone()),
+            (4, qtys.requested.clone()),
+            (6, qtys.limit.clone()),
+            (8, qtys.allocatable.clone()),
+            (9, qtys.calc_reserved()),
+            (10, qtys.calc_free()),
+        ] {
+            if let Some(qty) = oqty {
+                columns
+                    .entry((kind.to_string(), column))
+                    .or_default()
+                    .push(qty);
+            }
+        }
+    }
+    columns
+        .into_iter()
+        .filter_map(|(key, qtys)| Qty::common_scale(&qtys).map(|scale| (key, scale)))
+        .collect()
+}
+
+/// remove the cells of optional columns (from the last one, to keep the indexes valid)
+#[cfg(feature = "prettytable")]
+fn remove_hidden_cells(row: &mut Row, opts: &DisplayOpts) {
+    if opts.free_spread.is_none() {
+        row.remove_cell(14);
+        row.remove_cell(13);
+    }
+    if opts.pending.is_none() {
+        row.remove_cell(12);
+    }
+    if !opts.show_age {
+        row.remove_cell(11);
+    }
+    if !opts.shows(Usage::free) {
+        row.remove_cell(10);
+    }
+    if !opts.show_reserved {
+        row.remove_cell(9);
+    }
+    if !opts.shows(Usage::allocatable) {
+        row.remove_cell(8);
+    }
+    if !opts.show_ratio {
+        row.remove_cell(7);
+    }
+    if !opts.shows(Usage::limit) || opts.combines_req_limit() {
+        row.remove_cell(6);
+    }
+    // the bar of the percentage requested
+    if !opts.show_bars || !opts.shows(Usage::requested) {
+        row.remove_cell(5);
+    }
+    if !opts.shows(Usage::requested) {
+        row.remove_cell(4);
+    }
+    if !opts.shows(Usage::utilization) {
+        row.remove_cell(3);
+    }
+    if !opts.show_pods {
+        row.remove_cell(2);
+    }
+    if !opts.show_nodes {
+        row.remove_cell(1);
+    }
+}
+
+/// insert the cells of the requested Guaranteed & Burstable after the (visible) requested (see `--qos-split`)
+#[cfg(feature = "prettytable")]
+fn insert_qos_split_cells(row: &mut Row, opts: &DisplayOpts, cells: [Cell; 2]) {
+    if !opts.qos_split || !opts.shows(Usage::requested) {
+        return;
+    }
+    // the resource & requested, with the visible columns before the requested (see `remove_hidden_cells`)
+    let at = 2 + [
+        opts.show_nodes,
+        opts.show_pods,
+        opts.shows(Usage::utilization),
+        opts.show_bars,
+    ]
+    .iter()
+    .filter(|shown| **shown)
+    .count();
+    for (i, cell) in cells.into_iter().enumerate() {
+        row.insert_cell(at + i, cell);
+    }
+}
+
+#[cfg(feature = "prettytable")]
+fn is_empty(oqty: &Option<Qty>) -> bool {
+    match oqty {
+        Some(qty) => qty.is_zero(),
+        None => true,
+    }
+}
+
+#[cfg(feature = "prettytable")]
+fn make_cell_for_prettytable(
+    oqty: &Option<Qty>,
+    o100: &Option<Qty>,
+    opts: &DisplayOpts,
+    scale: Option<&Scale>,
+) -> Cell {
+    let txt = match oqty {
+        None => "__".to_string(),
+        Some(ref qty) => match o100 {
+            None => opts.format_qty_in(qty, scale),
+            Some(q100) => format!(
+                "({}%) {}",
+                opts.format_percentage(qty.calc_percentage(q100)),
+                opts.format_qty_in(qty, scale)
+            ),
+        },
+    };
+    Cell::new(&txt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[tokio::test]
+    async fn test_decimal_alignment() {
+        assert_eq!(decimal_tail_width("250.0m"), Some(3));
+        assert_eq!(decimal_tail_width("250m"), Some(1));
+        assert_eq!(decimal_tail_width("(40%) 1.5"), Some(2));
+        assert_eq!(decimal_tail_width("32"), Some(0));
+        // aligned on the quantity, not on its percentage
+        assert_eq!(decimal_tail_width("1.0Gi (12%)"), Some(10));
+        assert_eq!(decimal_tail_width("12.5 (8.5%)"), Some(9));
+        assert_eq!(decimal_tail_width("__"), None);
+        assert_eq!(decimal_tail_width("∞"), None);
+        let pods = make_pod_list(
+            [("p1", "250m"), ("p2", "1500m"), ("p3", "32")]
+                .into_iter()
+                .map(|(name, cpu)| make_pod(name, "n1", vec![make_container(&[("cpu", cpu)], &[])]))
+                .collect(),
+        );
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let opts = DisplayOpts {
+            usages: vec![Usage::requested],
+            ..DisplayOpts::default()
+        };
+        let table = render(
+            &resources,
+            &[GroupBy::resource, GroupBy::pod],
+            Output::plain,
+            &opts,
+        );
+        let points = table
+            .lines()
+            .filter(|line| line.contains("─ p"))
+            .map(|line| line.rfind('.').unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(points.len(), 3, "{}", table);
+        assert!(points.iter().all(|p| *p == points[0]), "{}", table);
+    }
+
+    #[tokio::test]
+    async fn test_flatten_single_child() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "4" }),
+        )]);
+        let pods = make_pod_list(vec![
+            make_pod("p1", "n1", vec![make_container(&[("cpu", "1")], &[])]),
+            make_pod("p2", "n1", vec![make_container(&[("cpu", "2")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let opts = DisplayOpts {
+            flatten_single_child: true,
+            group_separator: " / ".to_string(),
+            usages: vec![Usage::requested],
+            ..DisplayOpts::default()
+        };
+        let group_by = [GroupBy::resource, GroupBy::node, GroupBy::pod];
+        let lines = |resources: &[Resource], opts: &DisplayOpts| {
+            render(resources, &group_by, Output::plain, opts)
+                .lines()
+                .map(|l| l.split_whitespace().join(" "))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines(&resources, &opts),
+            vec![
+                "Resource Requested",
+                "cpu / n1 (75%) 3.0",
+                "├─ p1 1.0",
+                "└─ p2 2.0",
+                // no pod for the kind pods
+                "pods / n1 2.0",
+            ]
+        );
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("cpu", "1")], &[])],
+        )]);
+        resources.retain(|r| r.location.pod_name.is_none());
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        // the node keeps its allocatable (not on its pod)
+        assert_eq!(
+            lines(&resources, &opts),
+            vec![
+                "Resource Requested",
+                "cpu / n1 (25%) 1.0",
+                "└─ p1 1.0",
+                "pods / n1 1.0"
+            ]
+        );
+        let lines_of = |usages: Vec<Usage>| {
+            let opts = DisplayOpts {
+                usages,
+                ..opts.clone()
+            };
+            lines(&resources, &opts)
+        };
+        assert_eq!(
+            lines_of(vec![Usage::requested, Usage::allocatable, Usage::free])[1..3],
+            ["cpu / n1 (25%) 1.0 4.0 3.0", "└─ p1 1.0 __ __"]
+        );
+        // the sums (and the rows) are unchanged without the option
+        let opts = DisplayOpts {
+            flatten_single_child: false,
+            ..opts
+        };
+        assert_eq!(lines(&resources, &opts).len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_align_units() {
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "4", "memory": "8Gi" }),
+        )]);
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("memory", "512Mi")], &[])],
+            ),
+            make_pod("p2", "n1", vec![make_container(&[("memory", "2Gi")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let group_by = [GroupBy::resource, GroupBy::node, GroupBy::pod];
+        let requested_of = |opts: &DisplayOpts| {
+            render(&resources, &group_by, Output::plain, opts)
+                .lines()
+                .filter(|line| line.contains("p1") || line.contains("p2"))
+                .map(|line| {
+                    line.split_whitespace()
+                        .last()
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        let opts = DisplayOpts {
+            usages: vec![Usage::requested],
+            ..DisplayOpts::default()
+        };
+        assert_eq!(requested_of(&opts), vec!["512.0Mi", "2.0Gi"]);
+        let aligned = DisplayOpts {
+            align_units: true,
+            ..opts.clone()
+        };
+        assert_eq!(requested_of(&aligned), vec!["512.0Mi", "2048.0Mi"]);
+        // the same prefix for the rows of the nodes (and of the resource) in the column
+        assert!(render(&resources, &group_by, Output::plain, &aligned).contains("(31%) 2560.0Mi"));
+    }
+
+    #[test]
+    fn test_combined_req_limit() {
+        let qty = |s: &str| Some(Qty::from_str(s).unwrap());
+        let qtys = QtyByQualifier {
+            requested: qty("1500m"),
+            limit: qty("2"),
+            ..QtyByQualifier::default()
+        };
+        let opts = DisplayOpts {
+            combined_req_limit: true,
+            ..DisplayOpts::default()
+        };
+        assert!(opts.combines_req_limit());
+        assert_eq!(opts.format_req_limit_in(&qtys, None, None), "1.5 / 2.0");
+        let unbounded = QtyByQualifier {
+            no_limit: true,
+            ..qtys.clone()
+        };
+        assert_eq!(opts.format_req_limit_in(&unbounded, None, None), "1.5 / ∞");
+        let limited = QtyByQualifier {
+            requested: None,
+            ..qtys.clone()
+        };
+        assert_eq!(opts.format_req_limit_in(&limited, None, None), "__ / 2.0");
+        // one of the two is hidden
+        let opts = DisplayOpts {
+            usages: vec![Usage::requested],
+            ..opts
+        };
+        assert!(!opts.combines_req_limit());
+
+        let opts = DisplayOpts {
+            group_by: vec![GroupBy::resource],
+            combined_req_limit: true,
+            usages: vec![Usage::requested, Usage::limit],
+            ..DisplayOpts::default()
+        };
+        let data = vec![(vec!["cpu".to_string()], Some(qtys))];
+        let mut out = vec![];
+        write_as_plain(&data, &opts, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out
+            .lines()
+            .map(|l| l.split_whitespace().join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec!["Resource Requested / Limit", "cpu 1.5 / 2.0"]);
+    }
+
+    /// compare `actual` to the golden file `tests/golden/<name>`,
+    /// run the tests with `UPDATE_GOLDEN=1` to (re)write the golden files after a change of the rendering
+    fn assert_golden(name: &str, actual: &str) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+            panic!(
+                "failed to read {:?} ({}), run with UPDATE_GOLDEN=1 to create it",
+                path, err
+            )
+        });
+        assert!(
+            actual == expected,
+            "the output differs from {:?} (run with UPDATE_GOLDEN=1 to update it)\n--- expected\n{}\n--- actual\n{}",
+            path,
+            expected,
+            actual
+        );
+    }
+
+    /// a fixed cluster: 2 nodes, pods of 2 namespaces (with several containers, without limit, pending)
+    async fn make_golden_resources() -> Vec<Resource> {
+        let nodes = make_node_list(vec![
+            make_node(
+                "node-1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "4", "memory": "16Gi", "pods": "110" }),
+            ),
+            make_node(
+                "node-2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "8", "memory": "32Gi", "pods": "110", "nvidia.com/gpu": "2" }),
+            ),
+        ]);
+        let mut pods = vec![
+            make_pod(
+                "web-1",
+                "node-1",
+                vec![
+                    make_named_container(
+                        "app",
+                        &[("cpu", "1500m"), ("memory", "2Gi")],
+                        &[("cpu", "2"), ("memory", "4Gi")],
+                    ),
+                    make_named_container("sidecar", &[("cpu", "100m"), ("memory", "128Mi")], &[]),
+                ],
+            ),
+            make_pod(
+                "web-2",
+                "node-2",
+                vec![make_named_container(
+                    "app",
+                    &[("cpu", "1500m"), ("memory", "2Gi")],
+                    &[("cpu", "2"), ("memory", "4Gi")],
+                )],
+            ),
+            make_pod(
+                "train",
+                "node-2",
+                vec![make_named_container(
+                    "main",
+                    &[("cpu", "4"), ("memory", "24Gi"), ("nvidia.com/gpu", "2")],
+                    &[("memory", "24Gi"), ("nvidia.com/gpu", "2")],
+                )],
+            ),
+        ];
+        pods[2].metadata.namespace = Some("ml".to_string());
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(make_pod_list(pods), &mut resources)
+            .await
+            .unwrap();
+        resources
+    }
+
+    #[tokio::test]
+    async fn test_golden_outputs() {
+        let resources = make_golden_resources().await;
+        let default_group_by = [GroupBy::resource, GroupBy::node, GroupBy::pod];
+        let by_namespace = [GroupBy::resource, GroupBy::namespace];
+        let opts = DisplayOpts {
+            filter_full_zero: true,
+            now: Some(Utc.with_ymd_and_hms(2023, 1, 2, 3, 4, 5).unwrap()),
+            ..DisplayOpts::default()
+        };
+        let cases = [
+            ("table.txt", &default_group_by[..], Output::table),
+            ("table_by_namespace.txt", &by_namespace[..], Output::table),
+            ("plain.txt", &default_group_by[..], Output::plain),
+            ("csv.csv", &default_group_by[..], Output::csv),
+            ("influx.lp", &default_group_by[..], Output::influx),
+            ("prometheus.prom", &default_group_by[..], Output::prometheus),
+            ("json.json", &default_group_by[..], Output::json),
+            ("sim.json", &default_group_by[..], Output::sim_json),
+            ("totals.json", &default_group_by[..], Output::totals_json),
+            (
+                "node_free.json",
+                &default_group_by[..],
+                Output::node_free_json,
+            ),
+            (
+                "resources.json",
+                &default_group_by[..],
+                Output::resources_json,
+            ),
+        ];
+        for (name, group_by, output) in cases {
+            assert_golden(name, &render(&resources, group_by, output, &opts));
+        }
+        // the kinds of each node together (see `--node-first`)
+        let mut node_first = default_group_by.to_vec();
+        assert!(move_resource_after_node(&mut node_first));
+        assert_eq!(
+            node_first,
+            vec![GroupBy::node, GroupBy::resource, GroupBy::pod]
+        );
+        for (name, output) in [
+            ("table_node_first.txt", Output::table),
+            ("plain_node_first.txt", Output::plain),
+        ] {
+            assert_golden(name, &render(&resources, &node_first, output, &opts));
+        }
+        let mut without_node = by_namespace.to_vec();
+        assert!(!move_resource_after_node(&mut without_node));
+        assert_eq!(without_node, by_namespace.to_vec());
+        let all_columns = DisplayOpts {
+            show_pods: true,
+            show_ratio: true,
+            unbounded_limit: true,
+            free_with_pct: true,
+            ..opts.clone()
+        };
+        assert_golden(
+            "table_all_columns.txt",
+            &render(&resources, &by_namespace, Output::table, &all_columns),
+        );
+        let separators = DisplayOpts {
+            separators: true,
+            ..opts.clone()
+        };
+        assert_golden(
+            "table_separators.txt",
+            &render(&resources, &by_namespace, Output::table, &separators),
+        );
+        let usages = DisplayOpts {
+            usages: vec![Usage::requested, Usage::free],
+            ..opts.clone()
+        };
+        assert_golden(
+            "table_usages.txt",
+            &render(&resources, &by_namespace, Output::table, &usages),
+        );
+        assert_golden(
+            "csv_usages.csv",
+            &render(&resources, &by_namespace, Output::csv, &usages),
+        );
+        let quotas: Vec<ResourceQuota> = serde_json::from_value(serde_json::json!([
+            { "metadata": { "name": "q", "namespace": "default" }, "spec": { "hard": { "requests.cpu": "10" } } },
+            { "metadata": { "name": "q", "namespace": "ml" }, "spec": { "hard": { "requests.memory": "20Gi", "requests.nvidia.com/gpu": "4" } } },
+        ]))
+        .unwrap();
+        let mut out = vec![];
+        display_quotas_with_prettytable(
+            &make_quota_rows(&quotas, &ResourceSums::new(&resources), 0.0),
+            &opts,
+            &mut out,
+        )
+        .unwrap();
+        assert_golden("quotas.txt", &String::from_utf8(out).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_nodes_or_pods_only() {
+        let cli_opts = CliOpts::parse_from(["test", "--pods-only"]);
+        assert!(!cli_opts.collects_nodes() && cli_opts.collects_pods());
+        let cli_opts = CliOpts::parse_from(["test", "--nodes-only"]);
+        assert!(cli_opts.collects_nodes() && !cli_opts.collects_pods());
+        assert!(CliOpts::try_parse_from(["test", "--nodes-only", "--pods-only"]).is_err());
+        let group_by = [GroupBy::resource, GroupBy::node, GroupBy::pod];
+        // the requests, without allocatable: no percentage, no free
+        let pods = make_pod_list(vec![make_pod(
+            "p1",
+            "n1",
+            vec![make_container(&[("cpu", "1")], &[("cpu", "2")])],
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let table = render(
+            &resources,
+            &group_by,
+            Output::plain,
+            &DisplayOpts::default(),
+        );
+        assert!(!table.contains('%'), "{}", table);
+        for line in table.lines().skip(1) {
+            let cells = line.split_whitespace().rev().collect::<Vec<_>>();
+            // Free & Allocatable
+            assert_eq!(&cells[..2], ["__", "__"], "{}", table);
+        }
+        assert!(table.contains("└─ p1       1.0   2.0"), "{}", table);
+        // the allocatable, without requests: no percentage, no free
+        let nodes = make_node_list(vec![make_node(
+            "n1",
+            serde_json::json!({}),
+            serde_json::json!({ "cpu": "4" }),
+        )]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        let table = render(
+            &resources,
+            &group_by,
+            Output::plain,
+            &DisplayOpts::default(),
+        );
+        assert!(!table.contains('%'), "{}", table);
+        assert_eq!(
+            table
+                .lines()
+                .skip(1)
+                .map(|line| line.split_whitespace().rev().take(4).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            vec![vec!["__", "4.0", "__", "__"]; 2],
+            "{}",
+            table
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plugins_not_ready() {
+        let nodes = make_node_list(vec![
+            // registered, but no device yet
+            make_node(
+                "n1",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "8", "nvidia.com/gpu": "0" }),
+            ),
+            make_node(
+                "n2",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "8", "nvidia.com/gpu": "4" }),
+            ),
+            // not registered
+            make_node(
+                "n3",
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": "8" }),
+            ),
+        ]);
+        let pods = make_pod_list(vec![
+            make_pod(
+                "p1",
+                "n1",
+                vec![make_container(&[("nvidia.com/gpu", "2")], &[])],
+            ),
+            make_pod(
+                "p2",
+                "n2",
+                vec![make_container(&[("nvidia.com/gpu", "1")], &[])],
+            ),
+            make_pod(
+                "p3",
+                "n3",
+                vec![make_container(&[("nvidia.com/gpu", "1")], &[])],
+            ),
+            // not an extended resource
+            make_pod("p4", "n3", vec![make_container(&[("cpu", "1")], &[])]),
+        ]);
+        let mut resources = vec![];
+        extract_allocatable_from_nodes(nodes, &mut resources)
+            .await
+            .unwrap();
+        extract_allocatable_from_pods(pods, &mut resources)
+            .await
+            .unwrap();
+        let not_ready = find_plugins_not_ready(&resources);
+        assert_eq!(
+            not_ready
+                .iter()
+                .map(|p| format!("{}:{}={}", p.node, p.kind, p.requested))
+                .collect::<Vec<_>>(),
+            vec!["n1:nvidia.com/gpu=2.0", "n3:nvidia.com/gpu=1.0"]
+        );
+
+        let group_by = vec![GroupBy::resource, GroupBy::node];
+        let opts = DisplayOpts {
+            plugins_not_ready: not_ready
+                .iter()
+                .map(|p| (p.kind.clone(), p.node.clone()))
+                .collect(),
+            ..DisplayOpts::default()
+        };
+        let out = render(&resources, &group_by, Output::plain, &opts);
+        let lines = out
+            .lines()
+            .map(|l| l.split_whitespace().join(" "))
+            .filter(|l| l.contains("n1") || l.contains("n2") || l.contains("n3"))
+            .collect::<Vec<_>>();
+        // no percentage of a zero allocatable, the free is not a lack of free
+        assert_eq!(
+            lines[3..6],
+            [
+                "├─ n1 2.0 __ 0.0 plugin not ready",
+                "├─ n2 (25%) 1.0 __ 4.0 3.0",
+                "└─ n3 1.0 __ __ plugin not ready",
+            ]
+        );
+        // only on the rows of the kinds of the plugins
+        assert_eq!(lines[0], "├─ n1 __ __ 8.0 __");
+    }
+}
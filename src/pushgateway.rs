@@ -0,0 +1,163 @@
+//! module to push the metrics (text format of prometheus) to a Pushgateway (see `--push-gateway`),
+//! for the one-shot runs (eg a CronJob) that can't be scraped
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// the url of the group `job` & `instance` of the Pushgateway at `gateway` (eg `http://pushgateway:9091`)
+pub fn url_of(gateway: &str, job: &str, instance: &str) -> String {
+    format!(
+        "{}/metrics/{}/{}",
+        gateway.trim_end_matches('/'),
+        label_segments("job", job),
+        label_segments("instance", instance)
+    )
+}
+
+/// the segments `<name>/<value>` of a label of the group, a value with `/` (or empty) is encoded in base64
+/// (`<name>@base64/<value>`, like expected by the Pushgateway)
+fn label_segments(name: &str, value: &str) -> String {
+    if value.is_empty() || value.contains('/') {
+        // the empty value is `=` (the padding alone), not an empty segment
+        let encoded = base64::encode_config(value, base64::URL_SAFE);
+        format!(
+            "{}@base64/{}",
+            name,
+            if encoded.is_empty() { "=" } else { &encoded }
+        )
+    } else {
+        format!("{}/{}", name, utf8_percent_encode(value, SEGMENT))
+    }
+}
+
+/// the characters of a segment of path to percent-encode: all but the unreserved ones (RFC 3986)
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// the time to wait for the answer of the Pushgateway, per attempt (so an unresponsive gateway doesn't hang the run)
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POST the `metrics` to the `url` (replacing the metrics of the same names of the group), retried `attempts` times
+/// (with a backoff of 1s, 2s, 4s,...); return the error of the last attempt.
+/// The push runs on its own thread (and runtime), so it can be called from the synchronous display.
+pub fn push(url: &str, metrics: Vec<u8>, attempts: u32) -> Result<(), String> {
+    let url = url.to_string();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| err.to_string())?;
+        runtime.block_on(push_with_retries(&url, metrics, attempts, ATTEMPT_TIMEOUT))
+    })
+    .join()
+    .map_err(|_| "the push panicked".to_string())?
+}
+
+async fn push_with_retries(
+    url: &str,
+    metrics: Vec<u8>,
+    attempts: u32,
+    timeout: Duration,
+) -> Result<(), String> {
+    let connector = hyper_openssl::HttpsConnector::new().map_err(|err| err.to_string())?;
+    let client = hyper::Client::builder().build::<_, hyper::Body>(connector);
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let posted = tokio::time::timeout(timeout, post(&client, url, metrics.clone()))
+            .await
+            .unwrap_or_else(|_| Err(format!("no answer after {:?}", timeout)));
+        match posted {
+            Ok(()) => {
+                debug!(url, attempt, "metrics pushed");
+                return Ok(());
+            }
+            Err(err) if attempt < attempts => {
+                warn!(url, attempt, %err, "push failed, retrying in {:?}", backoff);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+async fn post<C>(client: &hyper::Client<C>, url: &str, metrics: Vec<u8>) -> Result<(), String>
+where
+    C: hyper::client::connect::Connect + Clone + Send + Sync + 'static,
+{
+    let request = hyper::Request::post(url)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(hyper::Body::from(metrics))
+        .map_err(|err| err.to_string())?;
+    let response = client
+        .request(request)
+        .await
+        .map_err(|err| err.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map(|b| String::from_utf8_lossy(&b).trim().to_string())
+        .unwrap_or_default();
+    Err(format!("{} {}", status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_of() {
+        assert_eq!(
+            url_of(
+                "http://pushgateway:9091/",
+                "kubectl-view-allocations",
+                "prod"
+            ),
+            "http://pushgateway:9091/metrics/job/kubectl-view-allocations/instance/prod"
+        );
+        assert_eq!(
+            url_of("http://pg", "job", "user@prod"),
+            "http://pg/metrics/job/job/instance/user%40prod"
+        );
+        // the names of contexts can contain `/` (eg the arn of a cluster)
+        assert_eq!(
+            url_of("http://pg", "job", "cluster/prod"),
+            "http://pg/metrics/job/job/instance@base64/Y2x1c3Rlci9wcm9k"
+        );
+        assert_eq!(
+            url_of("http://pg", "job", ""),
+            "http://pg/metrics/job/job/instance@base64/="
+        );
+        assert_eq!(
+            url_of("http://pg", "job", "a/b?"),
+            "http://pg/metrics/job/job/instance@base64/YS9iPw=="
+        );
+    }
+
+    #[test]
+    fn test_push_failure() {
+        // nothing listens on the port 9 (discard) of localhost
+        let err = push("http://127.0.0.1:9/metrics/job/j", b"m 1\n".to_vec(), 1).unwrap_err();
+        assert!(!err.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_push_timeout() {
+        // a gateway accepting the connection but never answering
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics/job/j", listener.local_addr().unwrap());
+        let err = push_with_retries(&url, b"m 1\n".to_vec(), 1, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(err.starts_with("no answer after"), "{}", err);
+    }
+}
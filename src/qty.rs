@@ -16,6 +16,12 @@ pub enum Error {
         #[source] // optional if field name is `source`
         source: std::num::ParseFloatError,
     },
+
+    #[error("Qty out of range '{0}'")]
+    QtyOutOfRangeError(String),
+
+    #[error("Qty is not a scalar '{0}' (a list?)")]
+    QtyNotScalarError(String),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
@@ -27,12 +33,14 @@ pub struct Scale {
 
 // should be sorted in DESC
 #[rustfmt::skip]
-static SCALES: [Scale;15] = [
+static SCALES: [Scale;17] = [
+    Scale{ label:"Ei", base: 2, pow: 60},
     Scale{ label:"Pi", base: 2, pow: 50},
     Scale{ label:"Ti", base: 2, pow: 40},
     Scale{ label:"Gi", base: 2, pow: 30},
     Scale{ label:"Mi", base: 2, pow: 20},
     Scale{ label:"Ki", base: 2, pow: 10},
+    Scale{ label:"E", base: 10, pow: 18},
     Scale{ label:"P", base: 10, pow: 15},
     Scale{ label:"T", base: 10, pow: 12},
     Scale{ label:"G", base: 10, pow: 9},
@@ -45,12 +53,36 @@ static SCALES: [Scale;15] = [
     Scale{ label:"n", base: 10, pow: -9},
 ];
 
+// scale of the base unit (no prefix), eg of the cores
+static SCALE_UNIT: Scale = Scale {
+    label: "",
+    base: 10,
+    pow: 0,
+};
+
+// scale of counts (pods, gpu,...), never adjusted to a prefix (cf `Qty::adjust_scale`)
+static SCALE_COUNT: Scale = Scale {
+    label: "",
+    base: 1,
+    pow: 0,
+};
+
 impl FromStr for Scale {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         SCALES
             .iter()
             .find(|v| v.label == s)
+            .or_else(|| {
+                // tolerate unusual casing when unambiguous: binary suffixes ("mi", "GI")
+                // and "K" (no scale "K"), but never "m" (milli) vs "M" (mega)
+                let canonical = match s.to_lowercase().as_str() {
+                    "k" => "k".to_string(),
+                    l if l.len() == 2 && l.ends_with('i') => l.to_uppercase().replace('I', "i"),
+                    _ => return None,
+                };
+                SCALES.iter().find(|v| v.label == canonical)
+            })
             .cloned()
             .ok_or_else(|| Error::ScaleParseError(s.to_owned()))
     }
@@ -75,7 +107,7 @@ impl PartialOrd for Scale {
             Some(Ordering::Greater)
         } else if v1 < v2 {
             Some(Ordering::Less)
-        } else if (v1 - v2).abs() < std::f64::EPSILON {
+        } else if (v1 - v2).abs() < f64::EPSILON {
             Some(Ordering::Equal)
         } else {
             None
@@ -84,6 +116,31 @@ impl PartialOrd for Scale {
 }
 
 impl Scale {
+    /// the factor of the scale in milli-units (eg 1024000 for "Ki"), `None` under the milli
+    fn milli_factor(&self) -> Option<i128> {
+        if self.base == 0 || self.pow == 0 {
+            return Some(1000);
+        }
+        let base = i128::from(self.base);
+        if self.pow > 0 {
+            base.checked_pow(self.pow as u32)?.checked_mul(1000)
+        } else if self.pow >= -3 {
+            Some(1000 / base.pow(self.pow.unsigned_abs()))
+        } else {
+            None
+        }
+    }
+
+    /// the factor of the scale in micro-units, only for the scales under the milli: positive to multiply ("u"),
+    /// negative to divide ("n", truncated), `None` otherwise
+    fn micro_factor(&self) -> Option<i128> {
+        match self.pow {
+            -6 => Some(1),
+            -9 => Some(-1000),
+            _ => None,
+        }
+    }
+
     pub fn min(&self, other: &Scale) -> Scale {
         if self < other {
             self.clone()
@@ -93,15 +150,40 @@ impl Scale {
     }
 }
 
+/// Family of unit used to express the base value of a quantity, depends of the kind of resource
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum UnitFamily {
+    /// cpu, base value is in millicores
+    Millicores,
+    /// memory, storage, hugepages,... base value is in bytes
+    Bytes,
+    /// pods, gpu,... base value is in units
+    Count,
+}
+
+impl UnitFamily {
+    pub fn from_kind(kind: &str) -> UnitFamily {
+        match kind {
+            "cpu" => UnitFamily::Millicores,
+            "memory" | "storage" | "ephemeral-storage" => UnitFamily::Bytes,
+            k if k.starts_with("hugepages-") => UnitFamily::Bytes,
+            _ => UnitFamily::Count,
+        }
+    }
+}
+
+/// (the value is in milli-units, on 128 bits to sum exabytes of storage;
+/// the sub-milli part, eg of "1500u", is kept in `micro`, always in `0..1000`)
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct Qty {
-    pub value: i64,
+    pub value: i128,
+    pub micro: i128,
     pub scale: Scale,
 }
 
 impl From<&Qty> for f64 {
     fn from(v: &Qty) -> f64 {
-        (v.value as f64) * 0.001
+        (v.value as f64) * 0.001 + (v.micro as f64) * 0.000001
     }
 }
 
@@ -109,90 +191,315 @@ impl Qty {
     pub fn lowest_positive() -> Qty {
         Qty {
             value: 1,
+            micro: 0,
             scale: Scale::from_str("m").unwrap(),
         }
     }
 
+    /// parse the quantity of a resource, the `kind` is used as a hint:
+    /// counts (pods, gpu,...) without suffix are never displayed with a SI prefix.
+    /// The other kinds are parsed like `Qty::from_str`.
+    pub fn parse_for_kind(s: &str, kind: &str) -> Result<Qty, Error> {
+        let qty = Qty::from_str(s)?;
+        let has_suffix = s
+            .trim_end()
+            .ends_with(|c: char| c.is_alphabetic() && c != 'e' && c != 'E');
+        if UnitFamily::from_kind(kind) == UnitFamily::Count && !has_suffix {
+            Ok(Qty {
+                value: qty.value,
+                micro: qty.micro,
+                scale: SCALE_COUNT.clone(),
+            })
+        } else {
+            Ok(qty)
+        }
+    }
+
     pub fn is_zero(&self) -> bool {
-        self.value == 0
+        self.value == 0 && self.micro == 0
+    }
+
+    /// the quantity of `micros` micro-units (eg "1500u" for 1500)
+    pub fn from_micros(micros: i128, scale: Scale) -> Qty {
+        Qty {
+            value: micros.div_euclid(1000),
+            micro: micros.rem_euclid(1000),
+            scale,
+        }
+    }
+
+    /// the value in micro-units, saturated
+    fn micros(&self) -> i128 {
+        self.value.saturating_mul(1000).saturating_add(self.micro)
+    }
+
+    /// the normalized value of the quantity, expressed into the base unit of the `family`
+    pub fn to_base_value(&self, family: UnitFamily) -> i128 {
+        match family {
+            UnitFamily::Millicores => self.value,
+            UnitFamily::Bytes | UnitFamily::Count => self.value / 1000,
+        }
+    }
+
+    /// the quantity of a value expressed into the base unit of the `family` (reverse of `to_base_value`)
+    pub fn from_base_value(value: i128, family: UnitFamily) -> Qty {
+        match family {
+            UnitFamily::Millicores => Qty {
+                value,
+                micro: 0,
+                scale: SCALE_UNIT.clone(),
+            },
+            // any scale, to allow binary prefixes
+            UnitFamily::Bytes => Qty {
+                value: checked_milli_value(value),
+                micro: 0,
+                scale: Scale::default(),
+            },
+            UnitFamily::Count => Qty {
+                value: checked_milli_value(value),
+                micro: 0,
+                scale: SCALE_COUNT.clone(),
+            },
+        }
     }
 
     pub fn calc_percentage(&self, base100: &Self) -> f64 {
-        if base100.value != 0 {
+        if !base100.is_zero() {
             f64::from(self) * 100f64 / f64::from(base100)
         } else {
-            core::f64::NAN
+            f64::NAN
+        }
+    }
+
+    /// `self - other`, `None` if the result is negative (eg requested over allocatable)
+    pub fn checked_sub(&self, other: &Self) -> Option<Qty> {
+        (self >= other).then(|| self - other)
+    }
+
+    /// the ratio `self / base`, `None` if base is zero
+    pub fn ratio(&self, base: &Self) -> Option<f64> {
+        (!base.is_zero()).then(|| f64::from(self) / f64::from(base))
+    }
+
+    /// the magnitude of the quantity (eg to sort the deltas by size of change)
+    pub fn abs(&self) -> Qty {
+        if self.value < 0 {
+            Qty::from_micros(self.micros().saturating_neg(), self.scale.clone())
+        } else {
+            self.clone()
         }
     }
 
+    /// the largest prefix keeping the magnitude >= 1 (eg "-512.0Mi" for "-0.5Gi")
     pub fn adjust_scale(&self) -> Qty {
-        let valuef64 = f64::from(self);
+        let valuef64 = f64::from(self).abs();
         let scale = SCALES
             .iter()
             .filter(|s| s.base == self.scale.base || self.scale.base == 0)
             .find(|s| f64::from(*s) <= valuef64);
         match scale {
-            Some(scale) => Qty {
-                value: self.value,
-                scale: scale.clone(),
-            },
+            Some(scale) => self.with_scale(scale),
             None => self.clone(),
         }
     }
+
+    /// the same quantity with the prefix `scale` (eg "1024.0Mi" for "1Gi" with the scale of "Mi")
+    pub fn with_scale(&self, scale: &Scale) -> Qty {
+        Qty {
+            value: self.value,
+            micro: self.micro,
+            scale: scale.clone(),
+        }
+    }
+
+    /// the prefix shared by the quantities (eg of a column): the one of `adjust_scale` of the smallest non-zero (by magnitude),
+    /// so the largest prefix keeping every value >= 1. `None` without non-zero quantity.
+    pub fn common_scale<'a>(qtys: impl IntoIterator<Item = &'a Qty>) -> Option<Scale> {
+        qtys.into_iter()
+            .filter(|qty| !qty.is_zero())
+            .min_by_key(|qty| qty.abs())
+            .map(|qty| qty.adjust_scale().scale)
+    }
+
+    /// compact representation, like `adjust_scale` but rounded to the nearest whole unit
+    /// of the prefix and without decimal (eg "1Gi" for "1.04Gi", "2" for "1.5")
+    pub fn to_compact_string(&self) -> String {
+        self.adjust_scale().to_compact_string_in_scale()
+    }
+
+    /// like `to_compact_string`, with the prefix of the quantity (see `with_scale`)
+    pub fn to_compact_string_in_scale(&self) -> String {
+        let v = f64::from(self) / f64::from(&self.scale);
+        format!("{}{}", v.round() as i128, self.scale.label)
+    }
+
+    /// lossless representation, in base units (eg "2147483648" for "2Gi", "1500m" for "1.5", "1500u" for "1.5m"),
+    /// to serialize & to compare, unlike `adjust_scale` (for display)
+    pub fn to_canonical_string(&self) -> String {
+        if self.micro != 0 {
+            format!("{}u", self.micros())
+        } else if self.value % 1000 == 0 {
+            (self.value / 1000).to_string()
+        } else {
+            format!("{}m", self.value)
+        }
+    }
 }
 
 impl FromStr for Qty {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // tolerate spaces around the quantity and between number and suffix (eg " 512 Mi ")
+        let s = s.trim();
+        // a list (eg "1,2", "[1, 2]") is rejected, not read as its first item
+        if s.contains([',', '[', '{', ';']) {
+            return Err(Error::QtyNotScalarError(s.to_owned()));
+        }
         let (num_str, scale_str): (&str, &str) = match s.find(|c: char| {
             !c.is_ascii_digit() && c != 'E' && c != 'e' && c != '+' && c != '-' && c != '.'
         }) {
             Some(pos) => (&s[..pos], &s[pos..]),
             None => (s, ""),
         };
+        // a trailing "E" is the suffix exa ("1E", "1Ei"), not an exponent ("1E3")
+        let (num_str, scale_str) = match num_str.strip_suffix('E') {
+            Some(num) if !num.is_empty() => (num, &s[num.len()..]),
+            _ => (num_str, scale_str),
+        };
         let scale = Scale::from_str(scale_str.trim())?;
+        // exact for integers (eg "1Pi" + "1" byte), unlike the float
+        if let (Ok(num), Some(factor)) = (i128::from_str(num_str), scale.milli_factor()) {
+            return num
+                .checked_mul(factor)
+                .map(|value| Qty {
+                    value,
+                    micro: 0,
+                    scale,
+                })
+                .ok_or_else(|| Error::QtyOutOfRangeError(s.to_owned()));
+        }
+        // exact under the milli too (eg "1500u" as 1m + 500u), "n" truncated to the micro
+        if let (Ok(num), Some(factor)) = (i128::from_str(num_str), scale.micro_factor()) {
+            let micros = if factor > 0 {
+                num.checked_mul(factor)
+            } else {
+                Some(num / -factor)
+            };
+            return micros
+                .map(|micros| Qty::from_micros(micros, scale))
+                .ok_or_else(|| Error::QtyOutOfRangeError(s.to_owned()));
+        }
         let num = f64::from_str(num_str).map_err(|source| Error::QtyNumberParseError {
             input: num_str.to_owned(),
             source,
         })?;
-        let value = (num * f64::from(&scale) * 1000f64) as i64;
-        Ok(Qty { value, scale })
+        // under 1u the value is truncated, but not the float error (eg "4007m" as 4006.9999999999995)
+        let value = num * f64::from(&scale) * 1000f64;
+        let (value, micro) = if is_nearly_whole(value) {
+            (value.round(), 0f64)
+        } else {
+            let micro = (value - value.trunc()) * 1000f64;
+            let micro = if is_nearly_whole(micro) {
+                micro.round()
+            } else {
+                micro.trunc()
+            };
+            (value.trunc(), micro)
+        };
+        // `as i128` would saturate silently (eg "1e400" as i128::MAX)
+        if !value.is_finite() || value.abs() >= i128::MAX as f64 {
+            return Err(Error::QtyOutOfRangeError(s.to_owned()));
+        }
+        let (value, micro) = carry_micros(value as i128, micro as i128);
+        Ok(Qty {
+            value,
+            micro,
+            scale,
+        })
     }
 }
 
+/// `v` is a whole number, but for the float error
+fn is_nearly_whole(v: f64) -> bool {
+    (v.round() - v).abs() <= v.abs().max(1.0) * 1e-12
+}
+
+/// negative quantities (eg deltas) are displayed with a leading `-`, and positive ones with a `+` if requested
+/// by the flag `+` (eg `format!("{:+}", qty)`)
 impl std::fmt::Display for Qty {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{:.1}{}",
-            (self.value as f64 / (f64::from(&self.scale) * 1000f64)),
-            self.scale.label
-        )
+        // 1 decimal, unless a precision is given (eg `{:.2}`)
+        let decimals = f.precision().unwrap_or(1);
+        let v = f64::from(self) / f64::from(&self.scale);
+        if f.sign_plus() {
+            write!(f, "{:+.*}{}", decimals, v, self.scale.label)
+        } else {
+            write!(f, "{:.*}{}", decimals, v, self.scale.label)
+        }
+    }
+}
+
+impl serde::Serialize for Qty {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_canonical_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Qty {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Qty::from_str(&s).map_err(serde::de::Error::custom)
     }
 }
 
 impl PartialOrd for Qty {
-    //TODO optimize accuracy with big number
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let v1 = self.value; // f64::from(self);
-        let v2 = other.value; // f64::from(other);
-        v1.partial_cmp(&v2)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Qty {
-    //TODO optimize accuracy with big number
     fn cmp(&self, other: &Self) -> Ordering {
-        let v1 = self.value; // f64::from(self);
-        let v2 = other.value; // f64::from(other);
-        v1.partial_cmp(&v2).unwrap() // i64 should always be comparable (no NaNs or anything crazy like that)
+        (self.value, self.micro).cmp(&(other.value, other.micro))
     }
 }
 
+/// `a + b`, saturated (with a warning) on overflow: a wrong total is worse than a capped one
+fn checked_add_value(a: i128, b: i128) -> i128 {
+    a.checked_add(b).unwrap_or_else(|| {
+        tracing::warn!(a, b, "overflow of the sum of quantities, saturated");
+        a.saturating_add(b)
+    })
+}
+
+/// the milli-units of `value` (`value * 1000`), saturated (with a warning) on overflow
+fn checked_milli_value(value: i128) -> i128 {
+    value.checked_mul(1000).unwrap_or_else(|| {
+        tracing::warn!(value, "overflow of the quantity, saturated");
+        value.saturating_mul(1000)
+    })
+}
+
+/// `a - b`, saturated (with a warning) on overflow
+fn checked_sub_value(a: i128, b: i128) -> i128 {
+    a.checked_sub(b).unwrap_or_else(|| {
+        tracing::warn!(a, b, "overflow of the difference of quantities, saturated");
+        a.saturating_sub(b)
+    })
+}
+
+/// the sum of the values & of the sub-milli parts `micro`, with the carry (or the borrow) of the micros
+fn carry_micros(value: i128, micro: i128) -> (i128, i128) {
+    (
+        checked_add_value(value, micro.div_euclid(1000)),
+        micro.rem_euclid(1000),
+    )
+}
+
 pub fn select_scale_for_add(v1: &Qty, v2: &Qty) -> Scale {
-    if v2.value == 0 {
+    if v2.is_zero() {
         v1.scale.clone()
-    } else if v1.value == 0 {
+    } else if v1.is_zero() {
         v2.scale.clone()
     } else {
         v1.scale.min(&v2.scale)
@@ -209,8 +516,13 @@ impl std::ops::Add for Qty {
 impl std::ops::Add for &Qty {
     type Output = Qty;
     fn add(self, other: Self) -> Qty {
+        let (value, micro) = carry_micros(
+            checked_add_value(self.value, other.value),
+            self.micro + other.micro,
+        );
         Qty {
-            value: self.value + other.value,
+            value,
+            micro,
             scale: select_scale_for_add(self, other),
         }
     }
@@ -218,10 +530,7 @@ impl std::ops::Add for &Qty {
 
 impl<'b> std::ops::AddAssign<&'b Qty> for Qty {
     fn add_assign(&mut self, other: &'b Self) {
-        *self = Qty {
-            value: self.value + other.value,
-            scale: select_scale_for_add(self, other),
-        }
+        *self = &*self + other
     }
 }
 
@@ -235,8 +544,13 @@ impl std::ops::Sub for Qty {
 impl std::ops::Sub for &Qty {
     type Output = Qty;
     fn sub(self, other: Self) -> Qty {
+        let (value, micro) = carry_micros(
+            checked_sub_value(self.value, other.value),
+            self.micro - other.micro,
+        );
         Qty {
-            value: self.value - other.value,
+            value,
+            micro,
             scale: select_scale_for_add(self, other),
         }
     }
@@ -244,10 +558,28 @@ impl std::ops::Sub for &Qty {
 
 impl<'b> std::ops::SubAssign<&'b Qty> for Qty {
     fn sub_assign(&mut self, other: &'b Self) {
-        *self = Qty {
-            value: self.value - other.value,
-            scale: select_scale_for_add(self, other),
-        };
+        *self = &*self - other;
+    }
+}
+
+impl std::ops::Mul<i64> for &Qty {
+    type Output = Qty;
+    fn mul(self, n: i64) -> Qty {
+        let value = self.value.checked_mul(i128::from(n)).unwrap_or_else(|| {
+            tracing::warn!(
+                value = self.value,
+                n,
+                "overflow of the product of a quantity, saturated"
+            );
+            self.value.saturating_mul(i128::from(n))
+        });
+        // `micro` < 1000, so its product can't overflow
+        let (value, micro) = carry_micros(value, self.micro * i128::from(n));
+        Qty {
+            value,
+            micro,
+            scale: self.scale.clone(),
+        }
     }
 }
 
@@ -262,6 +594,7 @@ mod tests {
             .is_close_to(f64::from(&Qty::from_str("1000000m")?), 0.01);
         assert_that!(Qty::from_str("1Ki")?).is_equal_to(Qty {
             value: 1024000,
+            micro: 0,
             scale: Scale {
                 label: "Ki",
                 base: 2,
@@ -298,6 +631,122 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_checked_sub() -> Result<(), Box<dyn std::error::Error>> {
+        let allocatable = Qty::from_str("4")?;
+        assert_that!(allocatable
+            .checked_sub(&Qty::from_str("1500m")?)
+            .map(|q| q.value))
+        .is_equal_to(Some(2500));
+        assert_that!(allocatable
+            .checked_sub(&Qty::from_str("4")?)
+            .map(|q| q.value))
+        .is_equal_to(Some(0));
+        assert_that!(allocatable.checked_sub(&Qty::from_str("4001m")?).is_none()).is_true();
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_base_value() -> Result<(), Box<dyn std::error::Error>> {
+        let cases = vec![
+            ("cpu", "1500m", "1.5"),
+            ("cpu", "2000", "2.0k"),
+            ("memory", "1Gi", "1.0Gi"),
+            ("pods", "1500", "1500.0"),
+        ];
+        for (kind, input, expected) in cases {
+            let family = UnitFamily::from_kind(kind);
+            let qty = Qty::parse_for_kind(input, kind)?;
+            let back = Qty::from_base_value(qty.to_base_value(family), family);
+            assert_that!(back.value).is_equal_to(qty.value);
+            assert_that!(format!("{}", back.adjust_scale())).is_equal_to(expected.to_string());
+        }
+        assert_that!(Qty::from_base_value(i128::MAX, UnitFamily::Bytes).value)
+            .is_equal_to(i128::MAX);
+        assert_that!(Qty::from_base_value(i128::MIN, UnitFamily::Count).value)
+            .is_equal_to(i128::MIN);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_for_kind() -> Result<(), Box<dyn std::error::Error>> {
+        let cases = vec![
+            ("pods", "110", "110.0"),
+            ("pods", "1500", "1500.0"),
+            ("nvidia.com/gpu", "2000", "2000.0"),
+            ("pods", "2k", "2.0k"),
+            ("memory", "1500", "1.5k"),
+            ("memory", "1Gi", "1.0Gi"),
+            ("cpu", "1500m", "1.5"),
+        ];
+        for (kind, input, expected) in cases {
+            assert_that!(format!(
+                "{}",
+                &Qty::parse_for_kind(input, kind)?.adjust_scale()
+            ))
+            .is_equal_to(expected.to_string());
+        }
+        let sum = &Qty::parse_for_kind("1000", "pods")? + &Qty::parse_for_kind("500", "pods")?;
+        assert_that!(format!("{}", sum.adjust_scale())).is_equal_to("1500.0".to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_compact_string() -> Result<(), Box<dyn std::error::Error>> {
+        let cases = vec![
+            ("1090519040", "1G"),
+            ("1.04Gi", "1Gi"),
+            ("1.5Gi", "2Gi"),
+            ("1.49Gi", "1Gi"),
+            ("1499k", "1M"),
+            ("1999k", "2M"),
+            ("999k", "999k"),
+            ("1500m", "2"),
+            ("1499m", "1"),
+            ("100m", "100m"),
+            ("0", "0"),
+        ];
+        for (input, expected) in cases {
+            assert_that!(Qty::from_str(input)?.to_compact_string())
+                .is_equal_to(expected.to_string());
+        }
+        assert_that!(Qty::parse_for_kind("1500", "pods")?.to_compact_string())
+            .is_equal_to("1500".to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ratio() -> Result<(), Box<dyn std::error::Error>> {
+        assert_that!(Qty::from_str("100m")?.ratio(&Qty::from_str("2")?)).is_equal_to(Some(0.05));
+        assert_that!(Qty::from_str("1Gi")?.ratio(&Qty::from_str("512Mi")?)).is_equal_to(Some(2.0));
+        assert_that!(Qty::from_str("1")?.ratio(&Qty::from_str("0")?)).is_equal_to(None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_str_non_canonical() -> Result<(), Box<dyn std::error::Error>> {
+        let cases = vec![
+            (" 512Mi ", "512Mi"),
+            ("512 Mi", "512Mi"),
+            ("512mi", "512Mi"),
+            ("1GI", "1Gi"),
+            ("2ki", "2Ki"),
+            ("2K", "2k"),
+            ("100m", "100m"),
+            ("100M", "100M"),
+        ];
+        for (input, expected) in cases {
+            assert_that!(Qty::from_str(input)?).is_equal_to(Qty::from_str(expected)?);
+        }
+        // ambiguous or unknown suffixes are rejected with the suffix in the error
+        for input in ["1g", "1mb", "1 2Mi"] {
+            assert_that!(Qty::from_str(input).is_err()).is_true();
+        }
+        assert_that!(Qty::from_str("1mb").unwrap_err().to_string())
+            .is_equal_to("Failed to parse scale in 'mb'".to_string());
+        Ok(())
+    }
+
     #[test]
     fn test_display() -> Result<(), Box<dyn std::error::Error>> {
         let cases = vec![
@@ -318,10 +767,11 @@ mod tests {
             ("1m", "1.0m"),
             ("1000000n", "1000000.0n"),
             // lowest precision is m, under 1m value is trunked
-            ("1u", "0.0u"),
-            ("1μ", "0.0μ"),
+            ("1u", "1.0u"),
+            ("1μ", "1.0μ"),
+            // the nano truncated to the micro
             ("1n", "0.0n"),
-            ("999999n", "0.0n"),
+            ("999999n", "999000.0n"),
         ];
         for input in cases {
             assert_that!(format!("{}", &Qty::from_str(input.0)?)).is_equal_to(input.1.to_string());
@@ -330,6 +780,34 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_signed() -> Result<(), Box<dyn std::error::Error>> {
+        let qty = Qty::from_str("-512Mi")?;
+        assert_that!(format!("{}", qty.adjust_scale())).is_equal_to("-512.0Mi".to_string());
+        assert_that!(format!("{:+}", qty.adjust_scale())).is_equal_to("-512.0Mi".to_string());
+        assert_that!(format!("{}", Qty::from_str("-1.5Gi")?.adjust_scale()))
+            .is_equal_to("-1.5Gi".to_string());
+        assert_that!(format!("{:+}", Qty::from_str("512Mi")?.adjust_scale()))
+            .is_equal_to("+512.0Mi".to_string());
+        assert_that!(Qty::from_str("-512Mi")?.to_compact_string())
+            .is_equal_to("-512Mi".to_string());
+        assert_that!(qty.abs()).is_equal_to(Qty::from_str("512Mi")?);
+
+        let mut deltas = vec![
+            Qty::from_str("100Mi")?,
+            Qty::from_str("-1Gi")?,
+            Qty::from_str("-10Mi")?,
+        ];
+        deltas.sort_by_key(|q| std::cmp::Reverse(q.abs()));
+        assert_that!(deltas.iter().map(|q| q.to_string()).collect::<Vec<_>>()).is_equal_to(vec![
+            "-1.0Gi".to_string(),
+            "100.0Mi".to_string(),
+            "-10.0Mi".to_string(),
+        ]);
+        assert_that!(Qty::common_scale(&deltas).map(|s| s.label)).is_equal_to(Some("Mi"));
+        Ok(())
+    }
+
     #[test]
     fn test_f64_from_scale() -> Result<(), Box<dyn std::error::Error>> {
         assert_that!(f64::from(&Scale::from_str("m")?)).is_close_to(0.001, 0.00001);
@@ -347,6 +825,19 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_to_base_value() -> Result<(), Box<dyn std::error::Error>> {
+        assert_that!(UnitFamily::from_kind("cpu")).is_equal_to(UnitFamily::Millicores);
+        assert_that!(UnitFamily::from_kind("memory")).is_equal_to(UnitFamily::Bytes);
+        assert_that!(UnitFamily::from_kind("hugepages-2Mi")).is_equal_to(UnitFamily::Bytes);
+        assert_that!(UnitFamily::from_kind("nvidia.com/gpu")).is_equal_to(UnitFamily::Count);
+        assert_that!(Qty::from_str("1.5")?.to_base_value(UnitFamily::Millicores)).is_equal_to(1500);
+        assert_that!(Qty::from_str("250m")?.to_base_value(UnitFamily::Millicores)).is_equal_to(250);
+        assert_that!(Qty::from_str("1Ki")?.to_base_value(UnitFamily::Bytes)).is_equal_to(1024);
+        assert_that!(Qty::from_str("110")?.to_base_value(UnitFamily::Count)).is_equal_to(110);
+        Ok(())
+    }
+
     #[test]
     fn test_add() -> Result<(), Box<dyn std::error::Error>> {
         assert_that!(
@@ -369,6 +860,7 @@ mod tests {
             .is_equal_to(&Qty::from_str("2Ki")?);
         assert_that!(&(Qty::from_str("1Ki")? + Qty::from_str("1k")?)).is_equal_to(&Qty {
             value: 2024000,
+            micro: 0,
             scale: Scale {
                 label: "k",
                 base: 10,
@@ -377,4 +869,204 @@ mod tests {
         });
         Ok(())
     }
+
+    #[test]
+    fn test_to_canonical_string() -> Result<(), Box<dyn std::error::Error>> {
+        assert_that!(Qty::from_str("2Gi")?.to_canonical_string())
+            .is_equal_to("2147483648".to_string());
+        assert_that!(Qty::from_str("1.5")?.to_canonical_string()).is_equal_to("1500m".to_string());
+        assert_that!(Qty::from_str("250m")?.to_canonical_string()).is_equal_to("250m".to_string());
+        assert_that!(Qty::from_str("1k")?.to_canonical_string()).is_equal_to("1000".to_string());
+        assert_that!(Qty::default().to_canonical_string()).is_equal_to("0".to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        for input in ["2Gi", "1.5", "250m", "1k", "1001m", "3.7Gi", "110", "0"] {
+            let qty = Qty::from_str(input)?;
+            let json = serde_json::to_string(&qty)?;
+            let back: Qty = serde_json::from_str(&json)?;
+            assert_that!(back.value).is_equal_to(qty.value);
+            assert_that!(Qty::from_str(&qty.to_canonical_string())?.value).is_equal_to(qty.value);
+        }
+        // the display is lossy, not the canonical string
+        let qty = Qty::from_str("2147483647")?;
+        assert_that!(Qty::from_str(&qty.adjust_scale().to_string())?.value != qty.value).is_true();
+        assert_that!(serde_json::to_string(&qty)?).is_equal_to("\"2147483647\"".to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_str_never_panics() -> Result<(), Box<dyn std::error::Error>> {
+        // unusual inputs, like the ones generated by `cargo fuzz run qty_from_str` (see fuzz/)
+        for input in [
+            "",
+            " ",
+            "e",
+            "E5",
+            "+",
+            "-",
+            ".",
+            "1.5.5",
+            "++1",
+            "1e",
+            "1e+",
+            "NaN",
+            "inf",
+            "μ",
+            "1μμ",
+            "1İ",
+            "1ǅi",
+            "1\u{0}",
+            "١",
+            "1 e3",
+            "999999999999999999999999",
+        ] {
+            let _ = Qty::from_str(input)
+                .map(|qty| (qty.adjust_scale().to_string(), qty.to_compact_string()));
+        }
+        for input in [
+            "1e400",
+            "-1e400",
+            "1e30Ei",
+            "999999999999999999999999999999999999999",
+            "1000000000000000000000Ei",
+        ] {
+            assert_that!(matches!(
+                Qty::from_str(input),
+                Err(Error::QtyOutOfRangeError(_))
+            ))
+            .is_true();
+        }
+        assert_that!(Qty::from_str("1e-400")?.value).is_equal_to(0);
+        assert_that!(Qty::from_str("4007m")?.value).is_equal_to(4007);
+        assert_that!(Qty::from_str("1.001")?.value).is_equal_to(1001);
+        assert_that!(Qty::from_str("9000000000000000")?.value)
+            .is_equal_to(9_000_000_000_000_000_000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_quantities() -> Result<(), Box<dyn std::error::Error>> {
+        let pib: i128 = 1 << 50;
+        assert_that!(Qty::from_str("1Pi")?.value).is_equal_to(pib * 1000);
+        assert_that!(Qty::from_str("1Ei")?.value).is_equal_to(pib * 1024 * 1000);
+        assert_that!(Qty::from_str("1E")?.value).is_equal_to(10i128.pow(21));
+        assert_that!(Qty::from_str("1.5E")?.value).is_equal_to(15 * 10i128.pow(20));
+        assert_that!(Qty::from_str("2P")?.value).is_equal_to(2 * 10i128.pow(18));
+        assert_that!(Qty::from_str("1T")?.value).is_equal_to(10i128.pow(15));
+        assert_that!(Qty::from_str("1Ti")?.value).is_equal_to((1i128 << 40) * 1000);
+        // an exponent, not the suffix exa
+        assert_that!(Qty::from_str("1E3")?.value).is_equal_to(1_000_000);
+        let sum = Qty::from_str("1Pi")? + Qty::from_str("512Ti")?;
+        assert_that!(sum.value).is_equal_to(pib * 3 / 2 * 1000);
+        assert_that!(sum.adjust_scale().to_string()).is_equal_to("1.5Pi".to_string());
+        // exact, unlike the float
+        let sum = Qty::from_str("1Ei")? + Qty::from_str("1")?;
+        assert_that!(sum.to_canonical_string()).is_equal_to("1152921504606846977".to_string());
+        // beyond the i64
+        let total = &Qty::from_str("100Pi")? * 100;
+        assert_that!(total.adjust_scale().to_string()).is_equal_to("9.8Ei".to_string());
+        assert_that!(Qty::from_str("2E")?.adjust_scale().to_string())
+            .is_equal_to("2.0E".to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow_is_saturated() -> Result<(), Box<dyn std::error::Error>> {
+        // the memory of a (very) huge cluster
+        let node = Qty::from_str("2Ti")?;
+        let mut total = Qty::default();
+        for _ in 0..10_000 {
+            total += &node;
+        }
+        assert_that!(total.value).is_equal_to((1i128 << 41) * 10_000 * 1000);
+        assert_that!(total.adjust_scale().to_string()).is_equal_to("19.5Pi".to_string());
+        let max = Qty {
+            value: i128::MAX,
+            micro: 0,
+            scale: Scale::default(),
+        };
+        assert_that!((&max + &node).value).is_equal_to(i128::MAX);
+        assert_that!((&Qty::default() - &max - node.clone()).value).is_equal_to(i128::MIN);
+        assert_that!((&max * 2).value).is_equal_to(i128::MAX);
+        let mut sum = max.clone();
+        sum += &max;
+        assert_that!(sum.value).is_equal_to(i128::MAX);
+        Ok(())
+    }
+
+    #[test]
+    fn test_common_scale() -> Result<(), Box<dyn std::error::Error>> {
+        let qtys = ["512Mi", "2Gi", "0", "1536Mi"]
+            .iter()
+            .map(|s| Qty::from_str(s))
+            .collect::<Result<Vec<_>, _>>()?;
+        let scale = Qty::common_scale(&qtys).unwrap();
+        let aligned = qtys
+            .iter()
+            .map(|qty| qty.with_scale(&scale).to_string())
+            .collect::<Vec<_>>();
+        assert_that!(aligned).is_equal_to(vec![
+            "512.0Mi".to_string(),
+            "2048.0Mi".to_string(),
+            "0.0Mi".to_string(),
+            "1536.0Mi".to_string(),
+        ]);
+        assert_that!(qtys[1].with_scale(&scale).to_compact_string_in_scale())
+            .is_equal_to("2048Mi".to_string());
+        assert_that!(Qty::common_scale(&[Qty::default()]).is_none()).is_true();
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_str_rejects_lists() {
+        for input in [
+            "1,2",
+            "500m,1",
+            "[1, 2]",
+            "[\"1\"]",
+            "{\"cpu\":\"1\"}",
+            "1;2",
+        ] {
+            assert_that!(matches!(
+                Qty::from_str(input),
+                Err(Error::QtyNotScalarError(_))
+            ))
+            .is_true();
+        }
+        // neither read as its first item
+        assert_that!(Qty::from_str("1 2").is_err()).is_true();
+    }
+
+    #[test]
+    fn test_micro() -> Result<(), Box<dyn std::error::Error>> {
+        let qty = Qty::from_str("1500u")?;
+        assert_that!((qty.value, qty.micro)).is_equal_to((1, 500));
+        let canonical = |qty: Qty| qty.to_canonical_string();
+        assert_that!(canonical(qty.clone())).is_equal_to("1500u".to_string());
+        assert_that!(canonical(Qty::from_str("0.0015")?)).is_equal_to("1500u".to_string());
+        // the nano truncated to the micro
+        assert_that!(canonical(Qty::from_str("1500999n")?)).is_equal_to("1500u".to_string());
+        assert_that!(canonical(&qty + &qty)).is_equal_to("3m".to_string());
+        assert_that!(canonical(qty.clone() - Qty::from_str("2m")?))
+            .is_equal_to("-500u".to_string());
+        assert_that!(canonical(Qty::from_str("-500u")?.abs())).is_equal_to("500u".to_string());
+        assert_that!(canonical(&qty * 3)).is_equal_to("4500u".to_string());
+        assert_that!(Qty::from_str("1m")? > Qty::from_str("999u")?).is_true();
+        assert_that!(format!("{}", qty.adjust_scale())).is_equal_to("1.5m".to_string());
+        // no drift of the sum of many tiny values
+        let mut sum = Qty::default();
+        for _ in 0..1000 {
+            sum += &Qty::from_str("1u")?;
+        }
+        assert_that!(canonical(sum)).is_equal_to("1m".to_string());
+        let mut sum = Qty::default();
+        for _ in 0..1000 {
+            sum += &Qty::from_str("1m")?;
+        }
+        assert_that!(canonical(sum)).is_equal_to("1".to_string());
+        Ok(())
+    }
 }
@@ -48,22 +48,58 @@ struct TreeNode {
     children: Vec<usize>,
 }
 
-fn level_to_string(level: &[bool]) -> String {
-    const EMPTY: &str = "   ";
-    const EDGE: &str = " └─";
-    const PIPE: &str = " │ ";
-    const BRANCH: &str = " ├─";
+/// The glyphs of the lines of the tree, every one of the same width
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyphs {
+    empty: &'static str,
+    edge: &'static str,
+    pipe: &'static str,
+    branch: &'static str,
+    rule: char,
+}
+
+impl Glyphs {
+    /// box-drawing characters (`├─`, `└─`,...)
+    pub const UNICODE: Glyphs = Glyphs {
+        empty: "   ",
+        edge: " └─",
+        pipe: " │ ",
+        branch: " ├─",
+        rule: '─',
+    };
+
+    /// ascii characters (`|-`, `` `- ``,...), for the terminals & fonts without box-drawing characters
+    pub const ASCII: Glyphs = Glyphs {
+        empty: "   ",
+        edge: " `-",
+        pipe: " | ",
+        branch: " |-",
+        rule: '-',
+    };
+
+    /// the character of the horizontal lines (eg the separators of the groups of a table)
+    pub fn rule(&self) -> char {
+        self.rule
+    }
+}
 
+impl Default for Glyphs {
+    fn default() -> Self {
+        Glyphs::UNICODE
+    }
+}
+
+fn level_to_string(level: &[bool], glyphs: &Glyphs) -> String {
     let mut prefix = String::new();
     if !level.is_empty() {
         let last_col = level.len() - 1;
         for (col, is_last_child) in level.iter().enumerate() {
             let is_last_col = col == last_col;
             let s = match (*is_last_child, is_last_col) {
-                (true, false) => EMPTY,
-                (true, true) => EDGE,
-                (false, false) => PIPE,
-                (false, true) => BRANCH,
+                (true, false) => glyphs.empty,
+                (true, true) => glyphs.edge,
+                (false, false) => glyphs.pipe,
+                (false, true) => glyphs.branch,
             };
             prefix.push_str(s);
         }
@@ -120,6 +156,14 @@ where
 /// - output can be zipped with input ìtems
 /// - is_parent_of(maybe_parent, item)
 pub fn provide_prefix<I, F>(items: &[I], is_parent_of: F) -> Vec<String>
+where
+    F: Fn(&I, &I) -> bool,
+{
+    provide_prefix_with(items, &Glyphs::UNICODE, is_parent_of)
+}
+
+/// like `provide_prefix`, with the lines drawn by `glyphs`
+pub fn provide_prefix_with<I, F>(items: &[I], glyphs: &Glyphs, is_parent_of: F) -> Vec<String>
 where
     F: Fn(&I, &I) -> bool,
 {
@@ -129,7 +173,10 @@ where
         write_tree_level_of_children(&mut nodes, i);
     }
     //dbg!(&nodes);
-    nodes.iter().map(|n| level_to_string(&n.level)).collect()
+    nodes
+        .iter()
+        .map(|n| level_to_string(&n.level, glyphs))
+        .collect()
 }
 
 #[cfg(test)]
@@ -164,4 +211,13 @@ mod tests {
         //dbg!(&actual);
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_ascii() {
+        let items = vec!["1", "1/2", "1/2/3", "1/4"];
+        let prefixes = provide_prefix_with(&items, &Glyphs::ASCII, |parent, item| {
+            item.split('/').count() == parent.split('/').count() + 1 && item.starts_with(parent)
+        });
+        assert_eq!(prefixes, vec!["", " |-", " |  `-", " `-"]);
+    }
 }
@@ -0,0 +1,668 @@
+//! module to keep the display up to date with the watch API (see `--watch`)
+
+use crate::*;
+
+/// Objects (by namespace & name) kept up to date by the watch events,
+/// with the resource version to resume the watch from
+#[derive(Debug, Clone)]
+pub struct WatchCache<K> {
+    pub objects: BTreeMap<(String, String), K>,
+    pub version: String,
+}
+
+impl<K> Default for WatchCache<K> {
+    fn default() -> Self {
+        WatchCache {
+            objects: BTreeMap::new(),
+            version: String::new(),
+        }
+    }
+}
+
+impl<K> WatchCache<K>
+where
+    K: kube::Resource + Clone,
+{
+    fn key_of(o: &K) -> (String, String) {
+        let meta = o.meta();
+        (
+            meta.namespace.clone().unwrap_or_default(),
+            meta.name.clone().unwrap_or_default(),
+        )
+    }
+
+    fn update_version(&mut self, o: &K) {
+        if let Some(version) = &o.meta().resource_version {
+            self.version = version.clone();
+        }
+    }
+
+    /// replace the objects by the result of a (re)list
+    pub fn reset(&mut self, list: ObjectList<K>) {
+        self.objects = list
+            .items
+            .into_iter()
+            .map(|o| (Self::key_of(&o), o))
+            .collect();
+        self.version = list.metadata.resource_version.unwrap_or_default();
+    }
+
+    /// apply the event, returns `true` if the objects changed,
+    /// or the error sent by the server (eg `410 Gone` when the version expired, a relist is required)
+    pub fn apply(&mut self, event: WatchEvent<K>) -> Result<bool, kube::core::ErrorResponse> {
+        match event {
+            WatchEvent::Added(o) | WatchEvent::Modified(o) => {
+                self.update_version(&o);
+                self.objects.insert(Self::key_of(&o), o);
+                Ok(true)
+            }
+            WatchEvent::Deleted(o) => {
+                self.update_version(&o);
+                self.objects.remove(&Self::key_of(&o));
+                Ok(true)
+            }
+            WatchEvent::Bookmark(b) => {
+                self.version = b.metadata.resource_version;
+                Ok(false)
+            }
+            WatchEvent::Error(e) => Err(e),
+        }
+    }
+
+    pub fn to_list(&self) -> ObjectList<K> {
+        ObjectList {
+            metadata: Default::default(),
+            items: self.objects.values().cloned().collect(),
+        }
+    }
+}
+
+enum WatchedEvent {
+    Node(Box<kube::Result<WatchEvent<Node>>>),
+    Pod(Box<kube::Result<WatchEvent<DynamicObject>>>),
+    /// a watch ended (timeout of the server)
+    End,
+}
+
+async fn display_watched(
+    nodes: &WatchCache<Node>,
+    pods: &WatchCache<DynamicObject>,
+    cli_opts: &CliOpts,
+    cluster: &ClusterInfo,
+    min_priority: Option<i32>,
+    stale_since: Option<DateTime<Utc>>,
+) -> Result<(), Error> {
+    let mut resources = vec![];
+    let mut report = CollectReport::default();
+    if cli_opts.collects_nodes() {
+        report
+            .skipped
+            .extend(extract_allocatable_from_nodes(nodes.to_list(), &mut resources).await?);
+        // the watched nodes change (eg a node removed by the autoscaler), not a reason to stop the watch
+        if let Err(err) =
+            apply_node_allocatable_overrides(&mut resources, &cli_opts.node_allocatable_override)
+        {
+            warn!(%err, "allocatable not overridden");
+        }
+        apply_reserve_for_system(&mut resources, &cli_opts.reserve_for_system);
+    }
+    let pods = typed_pods(pods.to_list().items)?;
+    if cli_opts.collects_pods() {
+        let pods = ObjectList {
+            metadata: Default::default(),
+            items: pods.clone(),
+        };
+        report
+            .skipped
+            .extend(extract_allocatable_from_pods(pods, &mut resources).await?);
+    }
+    let mut out = std::io::stdout().lock();
+    if cli_opts.output == Output::table && cli_opts.split_by_node.is_none() {
+        // clear the screen
+        write!(out, "\x1B[2J\x1B[1;1H")?;
+        if let Some(since) = stale_since {
+            // dimmed
+            writeln!(out, "\x1B[2mstale since {}\x1B[0m", since.to_rfc3339())?;
+        }
+    } else if let Some(since) = stale_since {
+        warn!(since = since.to_rfc3339(), "the output is stale");
+    }
+    let collected = Collected {
+        resources,
+        fleet: if cli_opts.collects_nodes() {
+            summarize_nodes(&nodes.to_list().items)
+        } else {
+            FleetSummary::default()
+        },
+        without_requests: find_containers_without_requests(
+            &pods,
+            cli_opts.no_requests.as_deref().unwrap_or_default(),
+        ),
+        blocked: find_blocked_pods(&pods),
+        crashlooping: find_crashlooping_pods(&pods),
+        report,
+        ..Collected::default()
+    };
+    display_collected(cli_opts, cluster, min_priority, collected, &mut out)
+}
+
+/// the apis of the nodes & the pods, untyped like in `collect_from_pods` (for their native sidecars)
+fn make_watched_apis(client: kube::Client, cli_opts: &CliOpts) -> (Api<Node>, Api<DynamicObject>) {
+    let ar = ApiResource::erase::<Pod>(&());
+    let api_pods = if let Some(ns) = &cli_opts.namespace {
+        Api::namespaced_with(client.clone(), ns, &ar)
+    } else {
+        Api::all_with(client.clone(), &ar)
+    };
+    (Api::all(client), api_pods)
+}
+
+/// the kubeconfig files used by the client: `--kubeconfig`, else `$KUBECONFIG` (list of paths),
+/// else `~/.kube/config`
+pub fn kubeconfig_paths(cli_opts: &CliOpts) -> Vec<std::path::PathBuf> {
+    if let Some(ref path) = cli_opts.kubeconfig {
+        return vec![path.clone()];
+    }
+    match std::env::var_os("KUBECONFIG") {
+        Some(paths) if !paths.is_empty() => std::env::split_paths(&paths).collect(),
+        _ => std::env::var_os("HOME")
+            .map(|home| std::path::Path::new(&home).join(".kube").join("config"))
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Detect the modifications of files (by their modification time), eg the rotation of credentials
+#[derive(Debug, Clone, Default)]
+pub struct FilesWatcher {
+    files: Vec<(std::path::PathBuf, Option<std::time::SystemTime>)>,
+}
+
+impl FilesWatcher {
+    pub fn new(paths: Vec<std::path::PathBuf>) -> FilesWatcher {
+        FilesWatcher {
+            files: paths
+                .into_iter()
+                .map(|path| {
+                    let modified = Self::modified(&path);
+                    (path, modified)
+                })
+                .collect(),
+        }
+    }
+
+    fn modified(path: &std::path::Path) -> Option<std::time::SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// at least one file changed since the last call of `changed`
+    pub fn is_changed(&self) -> bool {
+        self.files
+            .iter()
+            .any(|(path, modified)| &Self::modified(path) != modified)
+    }
+
+    /// like `is_changed`, and remember the current modification times
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, modified) in self.files.iter_mut() {
+            let current = Self::modified(path);
+            if &current != modified {
+                debug!(?path, "file changed");
+                *modified = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// the failures of the requests shorter than this are transient (see `Staleness`)
+pub const MAX_STALENESS: Duration = Duration::from_secs(300);
+
+/// the error could be solved by a retry: the API server (or the network) is unavailable or overloaded
+pub fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::KubeError { source, .. } => match source {
+            kube::Error::Api(response) => response.code == 429 || response.code >= 500,
+            kube::Error::HyperError(_) | kube::Error::Service(_) | kube::Error::ReadEvents(_) => {
+                true
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// The failures of the requests since the last success of the watch,
+/// the last good snapshot is displayed as stale until `MAX_STALENESS`
+#[derive(Debug, Clone, Default)]
+pub struct Staleness {
+    pub since: Option<DateTime<Utc>>,
+    pub failures: u32,
+}
+
+impl Staleness {
+    /// the delay before the retry, or the error when it is not transient (or for too long)
+    pub fn failed(&mut self, err: Error, now: DateTime<Utc>) -> Result<Duration, Error> {
+        let since = *self.since.get_or_insert(now);
+        let stale_for = (now - since).to_std().unwrap_or_default();
+        if !is_transient(&err) || stale_for > MAX_STALENESS {
+            return Err(err);
+        }
+        self.failures += 1;
+        warn!(%err, failures = self.failures, "request failed, retry");
+        Ok(Duration::from_secs(2u64.pow(self.failures.min(5))))
+    }
+
+    /// returns `true` if it was stale
+    pub fn recovered(&mut self) -> bool {
+        self.failures = 0;
+        self.since.take().is_some()
+    }
+}
+
+/// The redraws of the watch, at most one per `period`, so a busy cluster is not redrawn on every event
+#[derive(Debug)]
+pub struct Redraw {
+    period: Duration,
+    last: tokio::time::Instant,
+    pending: bool,
+}
+
+impl Redraw {
+    pub fn new(period: Duration) -> Self {
+        Redraw {
+            period,
+            last: tokio::time::Instant::now(),
+            pending: false,
+        }
+    }
+
+    /// the displayed objects changed, redraw on the next `due`
+    pub fn changed(&mut self) {
+        self.pending = true;
+    }
+
+    /// a change is pending, and the last redraw is older than `period`: returns `true` at most once per period
+    pub fn due(&mut self) -> bool {
+        if self.pending && self.last.elapsed() >= self.period {
+            self.drawn();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// the objects were displayed (eg after a relist)
+    pub fn drawn(&mut self) {
+        self.pending = false;
+        self.last = tokio::time::Instant::now();
+    }
+}
+
+/// The next item of `events`, or `None` on a tick of `ticks` (to redraw when the events stop)
+pub async fn next_or_tick<S>(
+    events: &mut S,
+    ticks: &mut tokio::time::Interval,
+) -> Option<Option<S::Item>>
+where
+    S: futures::Stream + Unpin,
+{
+    let tick = ticks.tick();
+    futures::pin_mut!(tick);
+    match futures::future::select(events.next(), tick).await {
+        futures::future::Either::Left((event, _)) => Some(event),
+        futures::future::Either::Right(_) => None,
+    }
+}
+
+/// List nodes & pods once, then keep them up to date with the watch API and display on every change
+/// (at most once per second). The watch is resumed from the last version when the server closes it,
+/// and nodes & pods are relisted when the version expired.
+/// Utilization (metrics) and jobs are not watchable, so they are not collected.
+/// With `--refresh-on-change`, the client is rebuilt when the kubeconfig changes.
+/// When a request fails transiently, the last snapshot is displayed as stale, and the request is retried.
+pub async fn watch_and_display(
+    client: kube::Client,
+    lister: &mut Lister,
+    cli_opts: &CliOpts,
+    cluster: &ClusterInfo,
+    min_priority: Option<i32>,
+) -> Result<(), Error> {
+    if cli_opts.utilization
+        || cli_opts.include_jobs
+        || cli_opts.limit_ranges
+        || cli_opts.compare_to_quota
+        || !cli_opts.extra_allocatable_from.is_empty()
+        || cli_opts.allocated_resources
+        || cli_opts.resource_claims
+    {
+        warn!("utilization, jobs, limitranges, quotas, extra and allocated resources, resource claims are not collected in watch mode");
+    }
+    let (mut api_nodes, mut api_pods) = make_watched_apis(client, cli_opts);
+    let mut kubeconfig_files = FilesWatcher::new(if cli_opts.refresh_on_change {
+        kubeconfig_paths(cli_opts)
+    } else {
+        vec![]
+    });
+    let mut nodes = WatchCache::default();
+    let mut pods = WatchCache::default();
+    let mut relist = true;
+    let mut staleness = Staleness::default();
+    // nothing to display as stale before the first list
+    let mut listed = false;
+    loop {
+        if kubeconfig_files.changed() {
+            info!("kubeconfig changed, rebuild the client");
+            // eg a kubeconfig half-written, retried on its next modification
+            match new_client(cli_opts).await {
+                Ok(client) => (api_nodes, api_pods) = make_watched_apis(client, cli_opts),
+                Err(err) => warn!(?err, "failed to rebuild the client, keep the previous one"),
+            }
+        }
+        let opened =
+            async {
+                if relist {
+                    let node_list = lister
+                        .list(&api_nodes, &ListParams::default())
+                        .await
+                        .map_err(|source| Error::KubeError {
+                            context: "list nodes".to_string(),
+                            source,
+                        })?;
+                    let pod_list = lister
+                        .list(&api_pods, &ListParams::default())
+                        .await
+                        .map_err(|source| Error::KubeError {
+                            context: "list pods".to_string(),
+                            source,
+                        })?;
+                    nodes.reset(node_list);
+                    pods.reset(pod_list);
+                }
+                let lp = ListParams::default().timeout(290);
+                lister.limiter.acquire().await;
+                let node_events = api_nodes
+                    .watch(&lp, &nodes.version)
+                    .await
+                    .map_err(|source| Error::KubeError {
+                        context: "watch nodes".to_string(),
+                        source,
+                    })?;
+                lister.limiter.acquire().await;
+                let pod_events = api_pods.watch(&lp, &pods.version).await.map_err(|source| {
+                    Error::KubeError {
+                        context: "watch pods".to_string(),
+                        source,
+                    }
+                })?;
+                Ok::<_, Error>((node_events, pod_events))
+            }
+            .await;
+        let (node_events, pod_events) = match opened {
+            Ok(events) => events,
+            Err(err) => {
+                let delay = staleness.failed(err, Utc::now())?;
+                if listed {
+                    display_watched(
+                        &nodes,
+                        &pods,
+                        cli_opts,
+                        cluster,
+                        min_priority,
+                        staleness.since,
+                    )
+                    .await?;
+                }
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        };
+        if staleness.recovered() || relist {
+            relist = false;
+            listed = true;
+            display_watched(&nodes, &pods, cli_opts, cluster, min_priority, None).await?;
+        }
+        let node_events = node_events
+            .map(|e| WatchedEvent::Node(Box::new(e)))
+            .chain(futures::stream::once(async { WatchedEvent::End }));
+        let pod_events = pod_events
+            .map(|e| WatchedEvent::Pod(Box::new(e)))
+            .chain(futures::stream::once(async { WatchedEvent::End }));
+        let mut events = futures::stream::select(node_events.boxed(), pod_events.boxed());
+        let mut redraw = Redraw::new(Duration::from_secs(1));
+        let mut ticks = tokio::time::interval(Duration::from_secs(1));
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            // on every pass, not only when idle (the events of a busy cluster would delay the rotation)
+            if kubeconfig_files.is_changed() {
+                // resume the watches with the new client
+                break;
+            }
+            let event = match next_or_tick(&mut events, &mut ticks).await {
+                Some(Some(event)) => Some(event),
+                Some(None) => Some(WatchedEvent::End),
+                None => None,
+            };
+            if let Some(event) = event {
+                let applied = match event {
+                    WatchedEvent::Node(e) => e.map(|e| nodes.apply(e)),
+                    WatchedEvent::Pod(e) => e.map(|e| pods.apply(e)),
+                    WatchedEvent::End => break,
+                };
+                let applied = match applied {
+                    Ok(applied) => applied,
+                    Err(err) => {
+                        warn!(?err, "watch failed, resume it");
+                        break;
+                    }
+                };
+                match applied {
+                    Ok(true) => redraw.changed(),
+                    Ok(false) => {}
+                    Err(e) => {
+                        debug!(?e, "watch error, relist");
+                        relist = true;
+                        break;
+                    }
+                }
+            }
+            // on every pass, so a steady stream of events (never idle for 1s) is still displayed
+            if redraw.due() {
+                display_watched(&nodes, &pods, cli_opts, cluster, min_priority, None).await?;
+            }
+        }
+        if redraw.pending && !relist {
+            display_watched(&nodes, &pods, cli_opts, cluster, min_priority, None).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    fn test_files_watcher() {
+        let path = std::env::temp_dir().join(format!("kubeconfig-{}", std::process::id()));
+        std::fs::write(&path, "apiVersion: v1").unwrap();
+        let cli_opts = CliOpts::parse_from([
+            "kubectl-view-allocations",
+            "--watch",
+            "--refresh-on-change",
+            "--kubeconfig",
+            path.to_str().unwrap(),
+        ]);
+        assert_eq!(kubeconfig_paths(&cli_opts), vec![path.clone()]);
+        let mut watcher = FilesWatcher::new(kubeconfig_paths(&cli_opts));
+        assert!(!watcher.is_changed());
+        assert!(!watcher.changed());
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert!(watcher.is_changed());
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+        assert!(!FilesWatcher::default().changed());
+    }
+
+    #[test]
+    fn test_staleness() {
+        let api_error = |code: u16| Error::KubeError {
+            context: "watch pods".to_string(),
+            source: kube::Error::Api(kube::core::ErrorResponse {
+                status: "Failure".to_string(),
+                message: String::new(),
+                reason: String::new(),
+                code,
+            }),
+        };
+        assert!(is_transient(&api_error(503)));
+        assert!(is_transient(&api_error(429)));
+        assert!(!is_transient(&api_error(403)));
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut staleness = Staleness::default();
+        assert!(!staleness.recovered());
+        assert_eq!(
+            staleness.failed(api_error(503), now).unwrap(),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            staleness
+                .failed(api_error(503), now + chrono::Duration::seconds(2))
+                .unwrap(),
+            Duration::from_secs(4)
+        );
+        assert_eq!(staleness.since, Some(now));
+        // persistent
+        assert!(staleness
+            .failed(api_error(503), now + chrono::Duration::minutes(6))
+            .is_err());
+        assert!(staleness.failed(api_error(403), now).is_err());
+        assert!(staleness.recovered());
+        assert_eq!(staleness.failures, 0);
+        assert_eq!(staleness.since, None);
+    }
+
+    #[test]
+    fn test_watch_cache_apply() {
+        let node = |name: &str, version: &str, cpu: &str| {
+            let mut n = make_node(
+                name,
+                serde_json::json!({}),
+                serde_json::json!({ "cpu": cpu }),
+            );
+            n.metadata.resource_version = Some(version.to_string());
+            n
+        };
+        let mut cache = WatchCache::default();
+        cache.reset(make_node_list(vec![node("n1", "1", "2")]));
+        assert_eq!(cache.objects.len(), 1);
+
+        assert_eq!(
+            cache.apply(WatchEvent::Added(node("n2", "2", "2"))),
+            Ok(true)
+        );
+        assert_eq!(
+            cache.apply(WatchEvent::Modified(node("n1", "3", "4"))),
+            Ok(true)
+        );
+        assert_eq!(cache.objects.len(), 2);
+        assert_eq!(cache.version, "3");
+        let list = cache.to_list();
+        let cpu = list.items[0]
+            .status
+            .as_ref()
+            .unwrap()
+            .allocatable
+            .as_ref()
+            .unwrap()["cpu"]
+            .clone();
+        assert_eq!(cpu.0, "4");
+
+        assert_eq!(
+            cache.apply(WatchEvent::Deleted(node("n2", "4", "2"))),
+            Ok(true)
+        );
+        assert_eq!(cache.objects.len(), 1);
+        let bookmark = serde_json::from_value(serde_json::json!({
+            "type": "BOOKMARK",
+            "object": { "apiVersion": "v1", "kind": "Node", "metadata": { "resourceVersion": "5" } }
+        }))
+        .unwrap();
+        assert_eq!(cache.apply(bookmark), Ok(false));
+        assert_eq!(cache.version, "5");
+
+        let gone = serde_json::from_value(serde_json::json!({
+            "type": "ERROR",
+            "object": { "status": "Failure", "message": "too old resource version", "reason": "Expired", "code": 410 }
+        }))
+        .unwrap();
+        assert_eq!(cache.apply(gone).map_err(|e| e.code), Err(410));
+        assert_eq!(cache.objects.len(), 1);
+    }
+
+    // the clock is paused (advanced only by the sleeps), so the elapsed times are exact
+    #[tokio::test(start_paused = true)]
+    async fn test_redraw_steady_events() {
+        // an event every 100ms for 3.5s: never idle for 1s, still redrawn every second
+        let mut events = futures::stream::unfold(0, |i| async move {
+            if i == 35 {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Some((i, i + 1))
+        })
+        .boxed();
+        let start = tokio::time::Instant::now();
+        let mut redraw = Redraw::new(Duration::from_secs(1));
+        let mut ticks = tokio::time::interval(Duration::from_secs(1));
+        let mut draws = vec![];
+        loop {
+            match next_or_tick(&mut events, &mut ticks).await {
+                Some(Some(_)) => redraw.changed(),
+                Some(None) => break,
+                None => {}
+            }
+            if redraw.due() {
+                draws.push(start.elapsed());
+            }
+        }
+        assert_eq!(
+            draws,
+            vec![
+                Duration::from_secs(1),
+                Duration::from_secs(2),
+                Duration::from_secs(3)
+            ]
+        );
+        // the last events are drawn after the end of the stream
+        assert!(redraw.pending);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_redraw_on_tick() {
+        // a single event, then idle: redrawn on the tick, not on the next event
+        let mut events = futures::stream::once(async {})
+            .chain(futures::stream::pending())
+            .boxed();
+        let start = tokio::time::Instant::now();
+        let mut redraw = Redraw::new(Duration::from_secs(1));
+        let mut ticks = tokio::time::interval(Duration::from_secs(1));
+        ticks.tick().await;
+        assert_eq!(next_or_tick(&mut events, &mut ticks).await, Some(Some(())));
+        redraw.changed();
+        assert!(!redraw.due());
+        assert_eq!(next_or_tick(&mut events, &mut ticks).await, None);
+        assert!(redraw.due());
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        assert!(!redraw.due());
+    }
+}